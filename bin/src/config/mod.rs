@@ -45,18 +45,24 @@ use hickory_proto::{ProtoError, rr::Name, serialize::txt::ParseError};
 use hickory_resolver::recursor::RecursiveConfig;
 #[cfg(feature = "__dnssec")]
 use hickory_server::dnssec::NxProofKind;
-#[cfg(any(feature = "recursor", feature = "sqlite"))]
 use hickory_server::net::runtime::TokioRuntimeProvider;
 #[cfg(feature = "blocklist")]
 use hickory_server::store::blocklist::{BlocklistConfig, BlocklistZoneHandler};
 #[cfg(feature = "resolver")]
 use hickory_server::store::forwarder::{ForwardConfig, ForwardZoneHandler};
+#[cfg(feature = "geo")]
+use hickory_server::store::geo::{GeoConfig, GeoZoneHandler};
 #[cfg(feature = "recursor")]
 use hickory_server::store::recursor::RecursiveZoneHandler;
 #[cfg(feature = "sqlite")]
 use hickory_server::store::sqlite::{SqliteConfig, SqliteZoneHandler};
 use hickory_server::{
-    store::file::{FileConfig, FileZoneHandler},
+    store::{
+        file::{FileConfig, FileZoneHandler},
+        notify::{NotifyConfig, NotifyZoneHandler},
+        secondary::{SecondaryConfig, SecondaryZoneHandler},
+        weighted::{WeightedConfig, WeightedZoneHandler},
+    },
     zone_handler::{AxfrPolicy, ZoneHandler, ZoneType},
 };
 
@@ -297,11 +303,57 @@ impl ZoneConfig {
                                 .await?;
                             Arc::new(handler)
                         }
+
+                        ServerStoreConfig::Secondary(config) => {
+                            if zone_type != ZoneType::Secondary {
+                                return Err(ProtoError::from(SECONDARY_STORE_NOT_SECONDARY));
+                            }
+
+                            SecondaryZoneHandler::try_from_config(
+                                zone_name.clone(),
+                                axfr_policy,
+                                config,
+                                Some(zone_dir),
+                                #[cfg(feature = "__dnssec")]
+                                server_config.nx_proof_kind.clone(),
+                                TokioRuntimeProvider::new(),
+                            )?
+                        }
+
+                        #[cfg(feature = "geo")]
+                        ServerStoreConfig::Geo(config) => {
+                            let inner = handlers.pop().ok_or(GEO_WITHOUT_STORE)?;
+                            Arc::new(GeoZoneHandler::try_from_config(
+                                inner,
+                                config,
+                                Some(zone_dir),
+                            )?)
+                        }
+                        ServerStoreConfig::Weighted(config) => {
+                            let inner = handlers.pop().ok_or(WEIGHTED_WITHOUT_STORE)?;
+                            Arc::new(WeightedZoneHandler::try_from_config(inner, config)?)
+                        }
                         _ => return Err(ProtoError::from(EMPTY_STORES)),
                     };
 
                     handlers.push(handler);
                 }
+
+                if !server_config.notify.is_empty() {
+                    if zone_type != ZoneType::Primary {
+                        return Err(ProtoError::from(NOTIFY_NOT_PRIMARY));
+                    }
+
+                    // updates are handled by the first zone handler of the zone
+                    let inner = handlers.remove(0);
+                    let handler = NotifyZoneHandler::new(
+                        inner,
+                        &server_config.notify,
+                        TokioRuntimeProvider::new(),
+                    );
+                    handler.notify_changed().await;
+                    handlers.insert(0, Arc::new(handler));
+                }
             }
             ZoneTypeConfig::External { stores } => {
                 debug!(
@@ -373,6 +425,12 @@ impl ZoneConfig {
 }
 
 const EMPTY_STORES: &str = "empty [[zones.stores]] in config";
+const NOTIFY_NOT_PRIMARY: &str = "`notify` can only be configured for a primary zone";
+const SECONDARY_STORE_NOT_SECONDARY: &str =
+    "a `secondary` store can only be used in a secondary zone";
+#[cfg(feature = "geo")]
+const GEO_WITHOUT_STORE: &str = "a `geo` store must follow the store it answers for";
+const WEIGHTED_WITHOUT_STORE: &str = "a `weighted` store must follow the store it answers for";
 
 #[derive(Deserialize, Debug)]
 #[serde(tag = "zone_type")]
@@ -418,6 +476,9 @@ pub(crate) struct ServerZoneConfig {
     /// The kind of non-existence proof provided by the nameserver
     #[cfg(feature = "__dnssec")]
     pub nx_proof_kind: Option<NxProofKind>,
+    /// Servers sent NOTIFY when the serial of a primary zone changes
+    #[serde(default)]
+    pub notify: NotifyConfig,
     /// Store configurations.  Note: we specify a default handler to get a Vec containing a
     /// StoreConfig::Default, which is used for authoritative file-based zones and legacy sqlite
     /// configurations. #[serde(default)] cannot be used, because it will invoke Default for Vec,
@@ -439,6 +500,10 @@ impl ServerZoneConfig {
             ServerStoreConfig::File(file_config) => Some(&*file_config.zone_path),
             #[cfg(feature = "sqlite")]
             ServerStoreConfig::Sqlite(sqlite_config) => Some(&*sqlite_config.zone_path),
+            ServerStoreConfig::Secondary(secondary_config) => secondary_config.zone_path.as_deref(),
+            #[cfg(feature = "geo")]
+            ServerStoreConfig::Geo(_) => None,
+            ServerStoreConfig::Weighted(_) => None,
             ServerStoreConfig::Default => None,
         })
     }
@@ -472,6 +537,13 @@ pub(crate) enum ServerStoreConfig {
     /// Sqlite based configuration file
     #[cfg(feature = "sqlite")]
     Sqlite(SqliteConfig),
+    /// Zone transferred from its primaries
+    Secondary(SecondaryConfig),
+    /// Answers chosen by client location, wrapping the store listed before it
+    #[cfg(feature = "geo")]
+    Geo(GeoConfig),
+    /// Weighted subsets of RRsets, wrapping the store listed before it
+    Weighted(WeightedConfig),
     /// This is used by the configuration processing code to represent a deprecated or main-block config without an associated store.
     #[default]
     Default,
@@ -235,6 +235,11 @@ define_test_config!(ipv4_only);
 define_test_config!(ipv6_only);
 #[cfg(feature = "resolver")]
 define_test_config!(example_forwarder);
+#[cfg(feature = "geo")]
+define_test_config!(example_geo);
+define_test_config!(example_notify);
+define_test_config!(example_secondary);
+define_test_config!(example_weighted);
 
 /// Iterator that yields modified TOML tables with an extra field added, and recurses down the
 /// table's values.
@@ -456,6 +461,13 @@ fn test_reject_unknown_fields() {
                     break;
                 }
 
+                #[cfg(not(feature = "geo"))]
+                if _store_type == "geo" {
+                    println!("skipping due to geo store");
+                    skip = true;
+                    break;
+                }
+
                 #[cfg(not(feature = "resolver"))]
                 if _store_type == "forward" {
                     println!("skipping due to forward store");
@@ -600,3 +612,132 @@ fn file_store_zone_path() {
         Err(e) => panic!("expected successful parse: {e:?}"),
     }
 }
+
+#[cfg(feature = "geo")]
+#[test]
+fn geo_rules_are_validated() {
+    let zone_dir =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../tests/test-data/test_configs");
+    let load = |rules: &str| {
+        let config = toml::from_str::<Config>(&format!(
+            r#"[[zones]]
+               zone = "example.com"
+               zone_type = "Primary"
+
+               [[zones.stores]]
+               type = "file"
+               zone_path = "example.com.zone"
+
+               [[zones.stores]]
+               type = "geo"
+               {rules}"#
+        ))
+        .unwrap();
+        let zone = config.zones.into_iter().next().unwrap();
+        futures_executor::block_on(zone.load(&zone_dir)).map(|handlers| handlers.len())
+    };
+
+    let cidr = r#"rules = [{ name = "www", match_type = "cidr", match_value = "10.0.0.0/8", target = "10.0.0.10" }]"#;
+    assert_eq!(load(cidr).unwrap(), 1);
+
+    let bad_network = cidr.replace("/8", "/33");
+    let error = load(&bad_network).unwrap_err().to_string();
+    assert!(error.contains("invalid cidr match value"), "{error}");
+
+    let bad_target = cidr.replace("10.0.0.10", "a..b");
+    let error = load(&bad_target).unwrap_err().to_string();
+    assert!(error.contains("invalid target"), "{error}");
+
+    // the rules file matches countries, which needs a database
+    let error = load(r#"rules_file = "default/example.com.geo.toml""#)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("needs a GeoIP database"), "{error}");
+
+    let error = load(r#"rules_file = "default/missing.geo.toml""#)
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("failed to read geo rules file"), "{error}");
+}
+
+#[test]
+fn weighted_store_wraps_the_store_before_it() {
+    let zone_dir =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../tests/test-data/test_configs");
+    let load = |stores: &str| {
+        let config = toml::from_str::<Config>(&format!(
+            r#"[[zones]]
+               zone = "example.com"
+               zone_type = "Primary"
+               {stores}"#
+        ))
+        .unwrap();
+        let zone = config.zones.into_iter().next().unwrap();
+        futures_executor::block_on(zone.load(&zone_dir)).map(|handlers| handlers.len())
+    };
+
+    let file = r#"[[zones.stores]]
+                  type = "file"
+                  zone_path = "example.com.zone""#;
+    let weighted = r#"[[zones.stores]]
+                      type = "weighted"
+                      records = [{ name = "www", record_type = "A", value = "127.0.0.1", weight = 3 }]"#;
+    assert_eq!(load(&format!("{file}\n{weighted}")).unwrap(), 1);
+
+    let error = load(weighted).unwrap_err().to_string();
+    assert!(error.contains("must follow the store"), "{error}");
+
+    let bad_value = weighted.replace("127.0.0.1", "www");
+    let error = load(&format!("{file}\n{bad_value}"))
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("invalid A value"), "{error}");
+}
+
+#[test]
+fn secondary_store_needs_secondary_zone() {
+    let config = toml::from_str::<Config>(
+        r#"[[zones]]
+           zone = "example.com"
+           zone_type = "Primary"
+
+           [[zones.stores]]
+           type = "secondary"
+           primaries = ["192.0.2.1:53"]"#,
+    )
+    .unwrap();
+
+    let zone = config.zones.into_iter().next().unwrap();
+    let error = futures_executor::block_on(zone.load(Path::new(".")))
+        .err()
+        .expect("expected a secondary store in a primary zone to be rejected")
+        .to_string();
+    assert!(
+        error.contains("only be used in a secondary zone"),
+        "{error}"
+    );
+}
+
+#[test]
+fn notify_needs_primary_zone() {
+    let zone_dir =
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../tests/test-data/test_configs");
+    let config = toml::from_str::<Config>(
+        r#"[[zones]]
+           zone = "example.com"
+           zone_type = "Secondary"
+           file = "example.com.zone"
+           notify = { also_notify = ["192.0.2.2:53"] }"#,
+    )
+    .unwrap();
+
+    let zone = config.zones.into_iter().next().unwrap();
+    let error = futures_executor::block_on(zone.load(&zone_dir))
+        .err()
+        .expect("expected notify in a secondary zone to be rejected")
+        .to_string();
+    assert!(
+        error.contains("only be configured for a primary zone"),
+        "{error}"
+    );
+}
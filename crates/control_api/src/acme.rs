@@ -0,0 +1,448 @@
+//! ACME DNS-01 challenge helper.
+//!
+//! Certificate bots get a credential scoped to a single `_acme-challenge` name and publish their
+//! challenge tokens through either of two client protocols:
+//!
+//! * `acme-dns`: `POST /acme/update` with `X-Api-User`/`X-Api-Key` headers and a
+//!   `{"subdomain", "txt"}` body. As in acme-dns, only the two most recent values are kept.
+//! * lego `httpreq`: `POST /acme/httpreq/present` and `/acme/httpreq/cleanup` with basic auth
+//!   and a `{"fqdn", "value"}` body.
+//!
+//! Challenge records are ordinary TXT records with an expiry; [`spawn_challenge_reaper`] removes
+//! them once their lifetime has passed, whether or not the client cleaned up.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use argon2::{password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString}, Argon2};
+use log::{info, warn};
+use rand_core::OsRng;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Client as PgClient;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::events::{Event, EventBus, RecordChange};
+use crate::orgs;
+use crate::sharing::Permission;
+use crate::AppState;
+
+/// TTL of published challenge records, lowered to the lifetime if that is shorter.
+const CHALLENGE_TTL: i32 = 60;
+
+/// Default lifetime of a challenge record.
+const DEFAULT_LIFETIME_SECS: i32 = 3600;
+
+/// Longest lifetime a credential may be registered with.
+const MAX_LIFETIME_SECS: i32 = 86400;
+
+/// Number of values kept per name by `acme-dns` updates, enough for a name and its wildcard.
+const ACME_DNS_MAX_VALUES: i64 = 2;
+
+/// Label prefixed to the scoped name.
+const CHALLENGE_LABEL: &str = "_acme-challenge";
+
+/// A challenge credential, as loaded after authentication.
+struct Credential {
+    id: String,
+    zone_id: String,
+    /// Record name relative to the zone, e.g. `_acme-challenge.www`
+    name: String,
+    /// Fully qualified name without the trailing dot
+    fqdn: String,
+    lifetime_secs: i32,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct RegisterReq {
+    /// Host the certificates are for, relative to the zone; empty or `@` for the apex.
+    #[serde(default)]
+    name: String,
+    lifetime_secs: Option<i32>,
+}
+
+/// Registration response, shaped like the one of acme-dns.
+#[derive(Serialize, ToSchema)]
+pub struct RegisterResponse {
+    id: String,
+    username: String,
+    password: String,
+    fulldomain: String,
+    subdomain: String,
+    allowfrom: Vec<String>,
+    lifetime_secs: i32,
+}
+
+/// `POST /api/v1/zones/{zone_id}/acme/credentials`: create a credential for one challenge name.
+#[utoipa::path(
+    post, path = "/api/v1/zones/{id}/acme/credentials", tag = "acme", operation_id = "create_acme_credential",
+    params(("id" = String, Path, description = "Zone id")), request_body = RegisterReq,
+    responses((status = 201, body = RegisterResponse), (status = 400), (status = 401), (status = 404)),
+)]
+pub async fn register(path: web::Path<String>, body: web::Json<RegisterReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    let domain: String = match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(z)) => match z.require(Permission::Write) { Ok(()) => z.domain, Err(resp) => return resp },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("acme register error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+
+    let host = body.name.trim_end_matches('.').to_ascii_lowercase();
+    let name = match host.as_str() {
+        "" | "@" => CHALLENGE_LABEL.to_string(),
+        h if valid_host(h) => format!("{}.{}", CHALLENGE_LABEL, h),
+        _ => return HttpResponse::BadRequest().body("invalid name"),
+    };
+    let lifetime_secs = body.lifetime_secs.unwrap_or(DEFAULT_LIFETIME_SECS);
+    if !(1..=MAX_LIFETIME_SECS).contains(&lifetime_secs) {
+        return HttpResponse::BadRequest().body(format!("lifetime_secs must be between 1 and {}", MAX_LIFETIME_SECS));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let username = Uuid::new_v4().to_string();
+    let password = format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple());
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default().hash_password(password.as_bytes(), &salt).unwrap().to_string();
+    let res = data.db.execute(
+        "INSERT INTO acme_credentials (id, zone_id, name, username, password_hash, lifetime_secs) VALUES ($1::text::uuid, $2::text::uuid, $3, $4, $5, $6)",
+        &[&id, &zone_id, &name, &username, &password_hash, &lifetime_secs],
+    ).await;
+    if let Err(e) = res {
+        warn!("acme register error: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Created().json(RegisterResponse {
+        fulldomain: format!("{}.{}", name, domain.trim_end_matches('.')),
+        subdomain: id.clone(),
+        id,
+        username,
+        password,
+        allowfrom: Vec::new(),
+        lifetime_secs,
+    })
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CredentialResponse {
+    id: String,
+    name: String,
+    username: String,
+    lifetime_secs: i32,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// `GET /api/v1/zones/{zone_id}/acme/credentials`
+#[utoipa::path(
+    get, path = "/api/v1/zones/{id}/acme/credentials", tag = "acme", operation_id = "list_acme_credentials",
+    params(("id" = String, Path, description = "Zone id")),
+    responses((status = 200, body = [CredentialResponse]), (status = 401), (status = 404)),
+)]
+pub async fn list_credentials(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("list acme credentials error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    let rows = data.db.query(
+        "SELECT id::text, name, username, lifetime_secs, created_at FROM acme_credentials WHERE zone_id::text = $1 ORDER BY name",
+        &[&zone_id],
+    ).await.unwrap_or_default();
+    let out: Vec<_> = rows.into_iter().map(|r| CredentialResponse {
+        id: r.get(0),
+        name: r.get(1),
+        username: r.get(2),
+        lifetime_secs: r.get(3),
+        created_at: r.get(4),
+    }).collect();
+    HttpResponse::Ok().json(out)
+}
+
+/// `DELETE /api/v1/zones/{zone_id}/acme/credentials/{id}`: revoke a credential.
+///
+/// Challenge records already published stay until they expire.
+#[utoipa::path(
+    delete, path = "/api/v1/zones/{zone_id}/acme/credentials/{id}", tag = "acme", operation_id = "delete_acme_credential",
+    params(("zone_id" = String, Path, description = "Zone id"), ("id" = String, Path, description = "Credential id")),
+    responses((status = 200), (status = 401), (status = 404)),
+)]
+pub async fn delete_credential(path: web::Path<(String, String)>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let (zone_id, id) = path.into_inner();
+    match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(zone)) => if let Err(resp) = zone.require(Permission::Write) { return resp; },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("delete acme credential error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    match data.db.execute("DELETE FROM acme_credentials WHERE id::text = $1 AND zone_id::text = $2", &[&id, &zone_id]).await {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("delete acme credential error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AcmeDnsUpdate {
+    subdomain: String,
+    txt: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AcmeDnsUpdateResponse {
+    txt: String,
+}
+
+/// Error of an acme-dns call, as acme-dns reports it.
+#[derive(Serialize, ToSchema)]
+pub struct AcmeDnsError {
+    error: String,
+}
+
+impl AcmeDnsError {
+    fn new(error: &str) -> Self {
+        Self { error: error.to_string() }
+    }
+}
+
+/// `POST /acme/update`: the acme-dns update call.
+#[utoipa::path(
+    post, path = "/acme/update", tag = "acme", security(()), request_body = AcmeDnsUpdate,
+    params(("X-Api-User" = String, Header, description = "Credential username"), ("X-Api-Key" = String, Header, description = "Credential password")),
+    responses((status = 200, body = AcmeDnsUpdateResponse), (status = 400, body = AcmeDnsError), (status = 401, body = AcmeDnsError)),
+)]
+pub async fn acme_dns_update(body: web::Json<AcmeDnsUpdate>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let user = req.headers().get("X-Api-User").and_then(|h| h.to_str().ok());
+    let key = req.headers().get("X-Api-Key").and_then(|h| h.to_str().ok());
+    let cred = match (user, key) {
+        (Some(user), Some(key)) => authenticate(&data.db, user, key).await,
+        _ => None,
+    };
+    let cred = match cred {
+        Some(c) => c,
+        None => return HttpResponse::Unauthorized().json(AcmeDnsError::new("forbidden")),
+    };
+    if !body.subdomain.eq_ignore_ascii_case(&cred.id) {
+        return HttpResponse::Unauthorized().json(AcmeDnsError::new("forbidden"));
+    }
+    if !valid_token(&body.txt) {
+        return HttpResponse::BadRequest().json(AcmeDnsError::new("bad_txt"));
+    }
+    if let Err(e) = publish(&data, &cred, &body.txt, Some(ACME_DNS_MAX_VALUES)).await {
+        warn!("acme update error: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    HttpResponse::Ok().json(AcmeDnsUpdateResponse { txt: body.txt.clone() })
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct HttpReqBody {
+    fqdn: String,
+    value: String,
+}
+
+/// `POST /acme/httpreq/present`: the lego `httpreq` present call.
+#[utoipa::path(
+    post, path = "/acme/httpreq/present", tag = "acme", security(("basic" = [])), request_body = HttpReqBody,
+    responses((status = 200), (status = 400), (status = 401)),
+)]
+pub async fn httpreq_present(body: web::Json<HttpReqBody>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let cred = match httpreq_credential(&data.db, &req, &body.fqdn).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if !valid_token(&body.value) {
+        return HttpResponse::BadRequest().body("invalid challenge value");
+    }
+    match publish(&data, &cred, &body.value, None).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("acme present error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `POST /acme/httpreq/cleanup`: the lego `httpreq` cleanup call.
+#[utoipa::path(
+    post, path = "/acme/httpreq/cleanup", tag = "acme", security(("basic" = [])), request_body = HttpReqBody,
+    responses((status = 200), (status = 401)),
+)]
+pub async fn httpreq_cleanup(body: web::Json<HttpReqBody>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let cred = match httpreq_credential(&data.db, &req, &body.fqdn).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let rows = data.db.query(
+        "DELETE FROM records WHERE zone_id::text = $1 AND name = $2 AND type = 'TXT' AND value = $3 AND expires_at IS NOT NULL RETURNING id::text",
+        &[&cred.zone_id, &cred.name, &body.value],
+    ).await;
+    match rows {
+        Ok(rows) => {
+            for row in rows {
+                publish_change(&data.events, &data.db, &cred.zone_id, row.get(0), RecordChange::Deleted).await;
+            }
+            HttpResponse::Ok().finish()
+        }
+        Err(e) => { warn!("acme cleanup error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// Authenticate an `httpreq` call and check that it is for the credential's name.
+async fn httpreq_credential(db: &PgClient, req: &HttpRequest, fqdn: &str) -> Result<Credential, HttpResponse> {
+    let (user, pass) = basic_auth(req).ok_or_else(|| {
+        HttpResponse::Unauthorized().insert_header((header::WWW_AUTHENTICATE, "Basic realm=\"acme\"")).finish()
+    })?;
+    let cred = authenticate(db, &user, &pass).await.ok_or_else(|| HttpResponse::Unauthorized().finish())?;
+    if !fqdn.trim_end_matches('.').eq_ignore_ascii_case(&cred.fqdn) {
+        return Err(HttpResponse::Forbidden().body("credential is not valid for this name"));
+    }
+    Ok(cred)
+}
+
+/// Add a challenge value, or extend the lifetime of an existing one.
+///
+/// With `keep`, older values for the name beyond the newest `keep` are removed.
+async fn publish(data: &AppState, cred: &Credential, value: &str, keep: Option<i64>) -> Result<(), tokio_postgres::Error> {
+    let ttl = CHALLENGE_TTL.min(cred.lifetime_secs);
+    let refreshed = data.db.query_opt(
+        "UPDATE records SET expires_at = now() + make_interval(secs => $4), ttl = $5
+         WHERE zone_id::text = $1 AND name = $2 AND type = 'TXT' AND value = $3 AND expires_at IS NOT NULL RETURNING id::text",
+        &[&cred.zone_id, &cred.name, &value, &(cred.lifetime_secs as f64), &ttl],
+    ).await?;
+    let (record_id, change) = match refreshed {
+        Some(row) => (row.get(0), RecordChange::Updated),
+        None => {
+            let id = Uuid::new_v4().to_string();
+            data.db.execute(
+                "INSERT INTO records (id, zone_id, name, type, value, ttl, expires_at)
+                 VALUES ($1::text::uuid, $2::text::uuid, $3, 'TXT', $4, $5, now() + make_interval(secs => $6))",
+                &[&id, &cred.zone_id, &cred.name, &value, &ttl, &(cred.lifetime_secs as f64)],
+            ).await?;
+            (id, RecordChange::Created)
+        }
+    };
+    info!("published acme challenge for {}", cred.fqdn);
+    publish_change(&data.events, &data.db, &cred.zone_id, record_id, change).await;
+
+    if let Some(keep) = keep {
+        let stale = data.db.query(
+            "DELETE FROM records WHERE id IN (
+                 SELECT id FROM records WHERE zone_id::text = $1 AND name = $2 AND type = 'TXT' AND expires_at IS NOT NULL
+                 ORDER BY expires_at DESC OFFSET $3)
+             RETURNING id::text",
+            &[&cred.zone_id, &cred.name, &keep],
+        ).await?;
+        for row in stale {
+            publish_change(&data.events, &data.db, &cred.zone_id, row.get(0), RecordChange::Deleted).await;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) async fn publish_change(events: &EventBus, db: &PgClient, zone_id: &str, record_id: String, change: RecordChange) {
+    let org = crate::zone_org(db, zone_id).await;
+    events.publish(Event::RecordsChanged { zone_id: zone_id.to_string(), record_id, change }, org).await;
+}
+
+async fn authenticate(db: &PgClient, username: &str, password: &str) -> Option<Credential> {
+    let row = db.query_opt(
+        "SELECT c.id::text, c.zone_id::text, c.name, z.domain, c.password_hash, c.lifetime_secs
+         FROM acme_credentials c JOIN zones z ON z.id = c.zone_id WHERE c.username = $1",
+        &[&username],
+    ).await.ok()??;
+    let hash: String = row.get(4);
+    let hash = PasswordHash::new(&hash).ok()?;
+    Argon2::default().verify_password(password.as_bytes(), &hash).ok()?;
+    let name: String = row.get(2);
+    let domain: String = row.get(3);
+    Some(Credential {
+        id: row.get(0),
+        zone_id: row.get(1),
+        fqdn: format!("{}.{}", name, domain.trim_end_matches('.')),
+        name,
+        lifetime_secs: row.get(5),
+    })
+}
+
+/// Credentials from an `Authorization: Basic` header.
+fn basic_auth(req: &HttpRequest) -> Option<(String, String)> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let encoded = value.strip_prefix("Basic ")?;
+    let decoded = data_encoding::BASE64.decode(encoded.trim().as_bytes()).ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (user, pass) = decoded.split_once(':')?;
+    Some((user.to_string(), pass.to_string()))
+}
+
+/// A relative host name; wildcards are covered by the challenge name of their parent.
+fn valid_host(host: &str) -> bool {
+    host.split('.').all(|l| !l.is_empty() && l.len() <= 63 && l.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'))
+}
+
+/// Challenge tokens are unpadded base64url digests (RFC 8555, section 8.4).
+fn valid_token(value: &str) -> bool {
+    !value.is_empty() && value.len() <= 255 && value.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// Spawn the task removing challenge records whose lifetime has passed.
+pub fn spawn_challenge_reaper(db: Arc<PgClient>, events: EventBus) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(15));
+        loop {
+            tick.tick().await;
+            let rows = match db.query("DELETE FROM records WHERE expires_at <= now() RETURNING id::text, zone_id::text", &[]).await {
+                Ok(rows) => rows,
+                Err(e) => { warn!("failed to remove expired records: {}", e); continue; }
+            };
+            for row in rows {
+                let zone_id: String = row.get(1);
+                publish_change(&events, &db, &zone_id, row.get(0), RecordChange::Deleted).await;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn test_valid_token() {
+        assert!(valid_token("LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"));
+        assert!(!valid_token(""));
+        assert!(!valid_token("\"quoted\""));
+        assert!(!valid_token("a b"));
+        assert!(!valid_token(&"a".repeat(256)));
+    }
+
+    #[test]
+    fn test_valid_host() {
+        assert!(valid_host("www"));
+        assert!(valid_host("api.eu"));
+        assert!(!valid_host("*.www"));
+        assert!(!valid_host("www..eu"));
+        assert!(!valid_host(&"a".repeat(64)));
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        // "user:pa:ss"
+        let req = TestRequest::default().insert_header((header::AUTHORIZATION, "Basic dXNlcjpwYTpzcw==")).to_http_request();
+        assert_eq!(basic_auth(&req), Some(("user".to_string(), "pa:ss".to_string())));
+
+        let req = TestRequest::default().insert_header((header::AUTHORIZATION, "Bearer dXNlcjpwYTpzcw==")).to_http_request();
+        assert_eq!(basic_auth(&req), None);
+        let req = TestRequest::default().insert_header((header::AUTHORIZATION, "Basic !!!")).to_http_request();
+        assert_eq!(basic_auth(&req), None);
+    }
+}
@@ -0,0 +1,214 @@
+//! Routes of the API and their OpenAPI 3 description.
+//!
+//! Every route is declared once in [`routes!`], which registers it with actix and lists it for
+//! the test checking that the spec describes exactly the routes served. The spec is served at
+//! `GET /api/v1/openapi.json` and checked in as `openapi.json`, from which the
+//! `control_api_client` crate is generated; run the tests with `UPDATE_OPENAPI=1` to refresh it
+//! after changing a handler or one of its types.
+
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi, ToSchema};
+
+use crate::{acme, backup, events, geo, health, import, orgs, ptr, sharing, templates};
+
+/// Response of the endpoints creating something.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct IdResponse {
+    pub id: String,
+}
+
+/// Declare the routes of the API, for both the server and the spec check.
+macro_rules! routes {
+    ($($method:ident $path:literal => $handler:path,)*) => {
+        /// Register every route of the API.
+        pub fn configure(cfg: &mut web::ServiceConfig) {
+            $(cfg.route($path, web::$method().to($handler));)*
+        }
+
+        /// Method and path of every route registered by [`configure`].
+        #[cfg(test)]
+        const ROUTES: &[(&str, &str)] = &[$((stringify!($method), $path),)*];
+    };
+}
+
+routes! {
+    post "/api/v1/auth/login" => crate::login,
+    post "/api/v1/users" => crate::create_user,
+    get "/api/v1/servers" => crate::list_servers,
+    post "/api/v1/servers" => crate::create_server,
+    get "/api/v1/zones" => crate::list_zones,
+    post "/api/v1/zones" => crate::create_zone,
+    get "/api/v1/zones/{id}" => sharing::get_zone,
+    delete "/api/v1/zones/{id}" => crate::delete_zone,
+    post "/api/v1/zones/{id}/transfer" => sharing::transfer_zone,
+    get "/api/v1/zones/{id}/shares" => sharing::list_shares,
+    post "/api/v1/zones/{id}/shares" => sharing::share_zone,
+    delete "/api/v1/zones/{zone_id}/shares/{id}" => sharing::unshare_zone,
+    post "/api/v1/agents/register" => crate::agent_register,
+    post "/api/v1/agents/heartbeat" => crate::agent_heartbeat,
+    get "/api/v1/agents" => crate::list_agents,
+    post "/api/v1/dns/start" => crate::start_dns_server,
+    post "/api/v1/dns/stop" => crate::stop_dns_server,
+    post "/api/v1/georules" => geo::create_georule,
+    get "/api/v1/georules" => geo::list_georules,
+    post "/api/v1/georules/resolve" => geo::resolve_by_geo,
+    get "/api/v1/zones/{id}/geo/default" => geo::get_geo_default,
+    put "/api/v1/zones/{id}/geo/default" => geo::set_geo_default,
+    delete "/api/v1/zones/{id}/geo/default" => geo::delete_geo_default,
+    get "/api/v1/geo/db" => geo::get_geo_db,
+    post "/api/v1/geo/db/reload" => geo::reload_geo_db_files,
+    post "/api/v1/config/push" => crate::push_config_to_agents,
+    get "/health" => crate::health,
+    get "/metrics" => crate::metrics,
+    get "/api/v1/openapi.json" => openapi_json,
+    get "/api/v1/agents/{id}/config" => crate::agent_get_config,
+    post "/api/v1/agents/{id}/token/rotate" => crate::rotate_agent_token,
+    post "/api/v1/agents/{id}/config/applied" => crate::agent_config_applied,
+    get "/api/v1/events" => events::stream_events,
+    post "/api/v1/webhooks" => events::create_webhook,
+    get "/api/v1/webhooks" => events::list_webhooks,
+    delete "/api/v1/webhooks/{id}" => events::delete_webhook,
+    get "/api/v1/webhooks/{id}/deliveries" => events::list_webhook_deliveries,
+    post "/api/v1/zones/{id}/records" => crate::create_record,
+    get "/api/v1/zones/{id}/records" => crate::list_records,
+    get "/api/v1/records" => crate::search_records,
+    put "/api/v1/zones/{zone_id}/records/{record_id}" => crate::update_record,
+    delete "/api/v1/zones/{zone_id}/records/{record_id}" => crate::delete_record,
+    post "/api/v1/zones/{id}/import" => import::import_records,
+    put "/api/v1/zones/{zone_id}/records/{record_id}/health" => health::put_health_check,
+    get "/api/v1/zones/{zone_id}/records/{record_id}/health" => health::get_health_check,
+    delete "/api/v1/zones/{zone_id}/records/{record_id}/health" => health::delete_health_check,
+    post "/api/v1/zones/{id}/acme/credentials" => acme::register,
+    get "/api/v1/zones/{id}/acme/credentials" => acme::list_credentials,
+    delete "/api/v1/zones/{zone_id}/acme/credentials/{id}" => acme::delete_credential,
+    post "/api/v1/orgs" => orgs::create_org,
+    get "/api/v1/orgs" => orgs::list_orgs,
+    get "/api/v1/orgs/{id}" => orgs::get_org,
+    delete "/api/v1/orgs/{id}" => orgs::delete_org,
+    put "/api/v1/orgs/{id}/quotas" => orgs::set_quotas,
+    get "/api/v1/orgs/{id}/members" => orgs::list_members,
+    post "/api/v1/orgs/{id}/members" => orgs::add_member,
+    delete "/api/v1/orgs/{id}/members/{user_id}" => orgs::remove_member,
+    put "/api/v1/zones/{id}/ptr" => ptr::set_auto_ptr,
+    post "/api/v1/ptr/ranges" => ptr::create_range,
+    get "/api/v1/ptr/ranges" => ptr::list_ranges,
+    delete "/api/v1/ptr/ranges/{id}" => ptr::delete_range,
+    get "/api/v1/ptr/conflicts" => ptr::list_conflicts,
+    post "/api/v1/templates" => templates::create_template,
+    get "/api/v1/templates" => templates::list_templates,
+    get "/api/v1/templates/{id}" => templates::get_template,
+    put "/api/v1/templates/{id}" => templates::update_template,
+    delete "/api/v1/templates/{id}" => templates::delete_template,
+    post "/api/v1/templates/{id}/apply" => templates::apply_template,
+    post "/api/v1/templates/{id}/bulk" => templates::bulk_apply_template,
+    get "/api/v1/templates/{id}/preview" => templates::preview_template,
+    post "/api/v1/templates/{id}/propagate" => templates::propagate_template,
+    get "/api/v1/backup" => backup::export_backup,
+    post "/api/v1/backup/restore" => backup::restore_backup,
+    post "/acme/update" => acme::acme_dns_update,
+    post "/acme/httpreq/present" => acme::httpreq_present,
+    post "/acme/httpreq/cleanup" => acme::httpreq_cleanup,
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Hickory DNS control API", description = "Zones, records and agents of a Hickory DNS deployment.
+
+Requests are authenticated with the JWT returned by `POST /api/v1/auth/login`, agents with the token \
+returned when registering. The organization a request is scoped to can be selected with an `X-Org-Id` header."),
+    paths(
+        crate::login, crate::create_user,
+        crate::list_servers, crate::create_server,
+        crate::list_zones, crate::create_zone, sharing::get_zone, crate::delete_zone,
+        sharing::transfer_zone, sharing::list_shares, sharing::share_zone, sharing::unshare_zone,
+        crate::agent_register, crate::agent_heartbeat, crate::list_agents,
+        crate::agent_get_config, crate::rotate_agent_token, crate::agent_config_applied, crate::push_config_to_agents,
+        crate::start_dns_server, crate::stop_dns_server,
+        geo::create_georule, geo::list_georules, geo::resolve_by_geo,
+        geo::get_geo_default, geo::set_geo_default, geo::delete_geo_default, geo::get_geo_db, geo::reload_geo_db_files,
+        crate::health, crate::metrics, openapi_json,
+        events::stream_events, events::create_webhook, events::list_webhooks, events::delete_webhook, events::list_webhook_deliveries,
+        crate::create_record, crate::list_records, crate::search_records, crate::update_record, crate::delete_record,
+        import::import_records,
+        health::put_health_check, health::get_health_check, health::delete_health_check,
+        acme::register, acme::list_credentials, acme::delete_credential,
+        acme::acme_dns_update, acme::httpreq_present, acme::httpreq_cleanup,
+        orgs::create_org, orgs::list_orgs, orgs::get_org, orgs::delete_org, orgs::set_quotas,
+        orgs::list_members, orgs::add_member, orgs::remove_member,
+        ptr::set_auto_ptr, ptr::create_range, ptr::list_ranges, ptr::delete_range, ptr::list_conflicts,
+        templates::create_template, templates::list_templates, templates::get_template, templates::update_template,
+        templates::delete_template, templates::apply_template, templates::bulk_apply_template,
+        templates::preview_template, templates::propagate_template,
+        backup::export_backup, backup::restore_backup,
+    ),
+    modifiers(&SecuritySchemes),
+    security(("bearer" = [])),
+)]
+pub struct ApiDoc;
+
+/// Adds the authentication schemes referred to by the paths.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("bearer", SecurityScheme::Http(Http::builder().scheme(HttpAuthScheme::Bearer).bearer_format("JWT").build()));
+        components.add_security_scheme("basic", SecurityScheme::Http(Http::new(HttpAuthScheme::Basic)));
+    }
+}
+
+/// This document.
+#[utoipa::path(get, path = "/api/v1/openapi.json", tag = "system", security(()), responses((status = 200, body = Object, description = "The OpenAPI 3 document of the API")))]
+pub async fn openapi_json() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    fn spec() -> String {
+        ApiDoc::openapi().to_pretty_json().unwrap() + "\n"
+    }
+
+    #[test]
+    fn test_routes_match_spec() {
+        let spec: serde_json::Value = serde_json::from_str(&spec()).unwrap();
+        let documented: BTreeSet<(String, String)> = spec["paths"].as_object().unwrap().iter()
+            .flat_map(|(path, item)| item.as_object().unwrap().keys().map(move |method| (method.clone(), path.clone())))
+            .collect();
+        let served: BTreeSet<(String, String)> = ROUTES.iter().map(|(m, p)| (m.to_string(), p.to_string())).collect();
+        assert_eq!(served.len(), ROUTES.len(), "a route is registered twice");
+        let undocumented: Vec<_> = served.difference(&documented).collect();
+        let unserved: Vec<_> = documented.difference(&served).collect();
+        assert!(undocumented.is_empty() && unserved.is_empty(), "routes missing from the spec: {:?}, spec paths not served: {:?}", undocumented, unserved);
+    }
+
+    #[test]
+    fn test_operation_ids_unique() {
+        let spec: serde_json::Value = serde_json::from_str(&spec()).unwrap();
+        let mut seen = BTreeSet::new();
+        for item in spec["paths"].as_object().unwrap().values() {
+            for op in item.as_object().unwrap().values() {
+                let id = op["operationId"].as_str().unwrap();
+                assert!(seen.insert(id.to_string()), "duplicate operationId {}", id);
+            }
+        }
+    }
+
+    /// The checked in spec, which the client is generated from, is up to date.
+    #[test]
+    fn test_checked_in_spec() {
+        let path = concat!(env!("CARGO_MANIFEST_DIR"), "/openapi.json");
+        let spec = spec();
+        if std::env::var_os("UPDATE_OPENAPI").is_some() {
+            std::fs::write(path, &spec).unwrap();
+        }
+        let checked_in = std::fs::read_to_string(path).unwrap_or_default();
+        assert!(checked_in == spec, "openapi.json is out of date, run the tests with UPDATE_OPENAPI=1 to refresh it");
+    }
+}
@@ -0,0 +1,659 @@
+//! Backup and restore of the control plane as a versioned JSON archive.
+//!
+//! An archive holds the configuration of the control plane: users, organizations, servers,
+//! agents, templates, zones, their shares and records, geo rules, health checks, PTR ranges, ACME
+//! credentials and webhooks. Runtime state (events, webhook deliveries, health and presence) is
+//! not included. Password hashes, agent tokens and webhook secrets are only exported when asked
+//! for; restored without them, users must have their password reset, agents their token rotated,
+//! ACME credentials re-registered, and webhooks are disabled and need recreating.
+//!
+//! Archives are exported in a single statement, so they are consistent, and restored in a
+//! transaction on a dedicated connection after being validated: either merged, skipping the rows
+//! already present, or replacing everything. The same is available from the command line:
+//!
+//! ```text
+//! control_api backup [--secrets] [FILE]
+//! control_api restore [--replace] [FILE]
+//! ```
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{Read, Write};
+
+use actix_web::{http::header, web, HttpRequest, HttpResponse, Responder};
+use futures_util::StreamExt;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{Client as PgClient, NoTls};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::{auth_from_header, AppState};
+
+/// Version of the archives written; restoring any other version is refused.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+/// Largest archive accepted by the restore endpoint.
+const MAX_ARCHIVE_BYTES: usize = 256 * 1024 * 1024;
+
+/// A table of the archive, in restore order.
+struct Table {
+    name: &'static str,
+    columns: &'static str,
+    /// Select list of the export; `$1` tells whether to include secrets.
+    export: &'static str,
+    /// Select list of the restore, from the rows of the archive.
+    restore: &'static str,
+}
+
+const TABLES: &[Table] = &[
+    Table {
+        name: "users",
+        columns: "id, username, role, password_hash",
+        export: "id, username, role, CASE WHEN $1 THEN password_hash END AS password_hash",
+        restore: "id, username, role, coalesce(password_hash, '')",
+    },
+    Table {
+        name: "orgs",
+        columns: "id, name, max_zones, max_records, max_georules, created_at",
+        export: "id, name, max_zones, max_records, max_georules, created_at",
+        restore: "id, name, max_zones, max_records, max_georules, created_at",
+    },
+    Table {
+        name: "org_members",
+        columns: "org_id, user_id, role",
+        export: "org_id, user_id, role",
+        restore: "org_id, user_id, role",
+    },
+    Table {
+        name: "servers",
+        columns: "id, name, address, region",
+        export: "id, name, address, region",
+        restore: "id, name, address, region",
+    },
+    Table {
+        name: "agents",
+        columns: "id, name, addr, token_hash",
+        export: "id, name, addr, CASE WHEN $1 THEN token_hash END AS token_hash",
+        restore: "id, name, addr, token_hash",
+    },
+    Table {
+        name: "zone_templates",
+        columns: "id, name, description, records, owner, org_id, created_at, updated_at",
+        export: "id, name, description, records, owner, org_id, created_at, updated_at",
+        restore: "id, name, description, records, owner, org_id, created_at, updated_at",
+    },
+    Table {
+        name: "zones",
+        columns: "id, domain, owner, org_id, template_id, template_params, auto_ptr, geo_default",
+        export: "id, domain, owner, org_id, template_id, template_params, auto_ptr, geo_default",
+        restore: "id, domain, owner, org_id, template_id, template_params, auto_ptr, geo_default",
+    },
+    Table {
+        name: "zone_shares",
+        columns: "id, zone_id, user_id, org_id, permission, created_by, created_at",
+        export: "id, zone_id, user_id, org_id, permission, created_by, created_at",
+        restore: "id, zone_id, user_id, org_id, permission, created_by, created_at",
+    },
+    Table {
+        name: "records",
+        columns: "id, zone_id, name, type, value, ttl, weight, expires_at, from_template, ptr_address, created_at",
+        export: "id, zone_id, name, type, value, ttl, weight, expires_at, from_template, ptr_address, created_at",
+        restore: "id, zone_id, name, type, value, ttl, weight, expires_at, from_template, ptr_address, created_at",
+    },
+    Table {
+        name: "georules",
+        columns: "id, zone_id, priority, match_type, match_value, action, targets, target, created_at",
+        export: "id, zone_id, priority, match_type, match_value, action, targets, target, created_at",
+        restore: "id, zone_id, priority, match_type, match_value, action, targets, target, created_at",
+    },
+    Table {
+        name: "health_checks",
+        columns: "record_id, probe, interval_secs, timeout_ms, rise, fall",
+        export: "record_id, probe, interval_secs, timeout_ms, rise, fall",
+        restore: "record_id, probe, interval_secs, timeout_ms, rise, fall",
+    },
+    Table {
+        name: "health_targets",
+        columns: "id, record_id, value",
+        export: "id, record_id, value",
+        restore: "id, record_id, value",
+    },
+    Table {
+        name: "ptr_ranges",
+        columns: "id, network, zone_id, created_at",
+        export: "id, network, zone_id, created_at",
+        restore: "id, network, zone_id, created_at",
+    },
+    Table {
+        name: "acme_credentials",
+        columns: "id, zone_id, name, username, password_hash, lifetime_secs, created_at",
+        export: "id, zone_id, name, username, CASE WHEN $1 THEN password_hash END AS password_hash, lifetime_secs, created_at",
+        restore: "id, zone_id, name, username, coalesce(password_hash, ''), lifetime_secs, created_at",
+    },
+    Table {
+        name: "webhooks",
+        columns: "id, url, secret, event_types, enabled, created_at",
+        export: "id, url, CASE WHEN $1 THEN secret END AS secret, event_types, enabled, created_at",
+        restore: "id, url, coalesce(secret, ''), event_types, enabled AND secret IS NOT NULL, created_at",
+    },
+];
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct UserRow {
+    pub id: String,
+    pub username: String,
+    pub role: String,
+    /// Only with secrets
+    pub password_hash: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrgRow {
+    pub id: String,
+    pub name: String,
+    pub max_zones: Option<i32>,
+    pub max_records: Option<i32>,
+    pub max_georules: Option<i32>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct OrgMemberRow {
+    pub org_id: String,
+    pub user_id: String,
+    pub role: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ServerRow {
+    pub id: String,
+    pub name: String,
+    pub address: String,
+    pub region: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct AgentRow {
+    pub id: String,
+    pub name: Option<String>,
+    pub addr: Option<String>,
+    /// Only with secrets
+    pub token_hash: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct TemplateRow {
+    pub id: String,
+    pub name: String,
+    pub description: String,
+    pub records: serde_json::Value,
+    pub owner: Option<String>,
+    pub org_id: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ZoneRow {
+    pub id: String,
+    pub domain: String,
+    pub owner: Option<String>,
+    pub org_id: Option<String>,
+    pub template_id: Option<String>,
+    pub template_params: Option<serde_json::Value>,
+    pub auto_ptr: bool,
+    /// Missing from archives written before zones had a geo default
+    #[serde(default)]
+    pub geo_default: Option<serde_json::Value>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct ZoneShareRow {
+    pub id: String,
+    pub zone_id: String,
+    pub user_id: Option<String>,
+    pub org_id: Option<String>,
+    pub permission: String,
+    pub created_by: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct RecordRow {
+    pub id: String,
+    pub zone_id: Option<String>,
+    pub name: Option<String>,
+    #[serde(rename = "type")]
+    pub rtype: Option<String>,
+    pub value: Option<String>,
+    pub ttl: Option<i32>,
+    pub weight: i32,
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub from_template: bool,
+    /// Address a managed PTR was created for
+    pub ptr_address: Option<String>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct GeoRuleRow {
+    pub id: String,
+    pub zone_id: Option<String>,
+    /// Missing from archives written before geo rules had priorities, actions and several targets
+    #[serde(default)]
+    pub priority: i32,
+    pub match_type: Option<String>,
+    pub match_value: Option<String>,
+    #[serde(default = "default_geo_action")]
+    pub action: String,
+    #[serde(default)]
+    pub targets: Option<serde_json::Value>,
+    pub target: Option<String>,
+    #[serde(default = "chrono::Utc::now")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+fn default_geo_action() -> String {
+    "answer".to_string()
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthCheckRow {
+    pub record_id: String,
+    pub probe: serde_json::Value,
+    pub interval_secs: i32,
+    pub timeout_ms: i32,
+    pub rise: i32,
+    pub fall: i32,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct HealthTargetRow {
+    pub id: String,
+    pub record_id: Option<String>,
+    pub value: String,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct PtrRangeRow {
+    pub id: String,
+    pub network: String,
+    pub zone_id: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct AcmeCredentialRow {
+    pub id: String,
+    pub zone_id: Option<String>,
+    pub name: String,
+    pub username: String,
+    /// Only with secrets
+    pub password_hash: Option<String>,
+    pub lifetime_secs: i32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct WebhookRow {
+    pub id: String,
+    pub url: String,
+    /// Only with secrets
+    pub secret: Option<String>,
+    pub event_types: Option<Vec<String>>,
+    pub enabled: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Snapshot of the configuration of the control plane.
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
+pub struct Archive {
+    /// Format of the archive, [`ARCHIVE_VERSION`] when written by this version
+    pub version: u32,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Whether password hashes, agent tokens and webhook secrets are included
+    pub secrets: bool,
+    pub users: Vec<UserRow>,
+    pub orgs: Vec<OrgRow>,
+    pub org_members: Vec<OrgMemberRow>,
+    pub servers: Vec<ServerRow>,
+    pub agents: Vec<AgentRow>,
+    pub zone_templates: Vec<TemplateRow>,
+    pub zones: Vec<ZoneRow>,
+    /// Missing from archives written before zones could be shared
+    #[serde(default)]
+    pub zone_shares: Vec<ZoneShareRow>,
+    pub records: Vec<RecordRow>,
+    pub georules: Vec<GeoRuleRow>,
+    pub health_checks: Vec<HealthCheckRow>,
+    pub health_targets: Vec<HealthTargetRow>,
+    pub ptr_ranges: Vec<PtrRangeRow>,
+    pub acme_credentials: Vec<AcmeCredentialRow>,
+    pub webhooks: Vec<WebhookRow>,
+}
+
+/// How an archive is restored.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    /// Add the rows of the archive, skipping the ones conflicting with existing rows
+    #[default]
+    Merge,
+    /// Delete the configuration of the control plane, then load the archive
+    Replace,
+}
+
+/// Rows restored per table.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct RestoreSummary {
+    pub mode: RestoreMode,
+    pub restored: BTreeMap<String, u64>,
+}
+
+/// Error of a restore.
+#[derive(Debug)]
+pub enum RestoreError {
+    /// The archive is not one that can be restored
+    Invalid(String),
+    Db(tokio_postgres::Error),
+}
+
+impl std::fmt::Display for RestoreError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Invalid(e) => write!(f, "invalid archive: {}", e),
+            Self::Db(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for RestoreError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        Self::Db(e)
+    }
+}
+
+/// Snapshot the control plane, with secrets or not.
+pub async fn export(db: &PgClient, secrets: bool) -> Result<Archive, anyhow::Error> {
+    let tables: Vec<String> = TABLES.iter()
+        .map(|t| format!("'{0}', (SELECT coalesce(json_agg(t), '[]') FROM (SELECT {1} FROM {0}) t)", t.name, t.export))
+        .collect();
+    let row = db.query_one(format!("SELECT json_build_object({})", tables.join(", ")).as_str(), &[&secrets]).await?;
+    let mut archive: serde_json::Value = row.get(0);
+    archive["version"] = ARCHIVE_VERSION.into();
+    archive["created_at"] = chrono::Utc::now().to_rfc3339().into();
+    archive["secrets"] = secrets.into();
+    Ok(serde_json::from_value(archive)?)
+}
+
+/// Check that an archive can be restored: its version is known, and its rows have unique ids
+/// and only refer to rows of the archive.
+pub fn validate(archive: &Archive) -> Result<(), String> {
+    if archive.version != ARCHIVE_VERSION {
+        return Err(format!("unsupported version {}, expected {}", archive.version, ARCHIVE_VERSION));
+    }
+    fn ids<'a>(table: &str, ids: impl Iterator<Item = &'a String>) -> Result<BTreeSet<&'a str>, String> {
+        let mut set = BTreeSet::new();
+        for id in ids {
+            if Uuid::parse_str(id).is_err() {
+                return Err(format!("{}: invalid id {}", table, id));
+            }
+            if !set.insert(id.as_str()) {
+                return Err(format!("{}: duplicate id {}", table, id));
+            }
+        }
+        Ok(set)
+    }
+    fn refers<'a>(table: &str, column: &str, ids: &BTreeSet<&str>, refs: impl Iterator<Item = Option<&'a String>>) -> Result<(), String> {
+        match refs.flatten().find(|r| !ids.contains(r.as_str())) {
+            Some(r) => Err(format!("{}: {} {} is not in the archive", table, column, r)),
+            None => Ok(()),
+        }
+    }
+    let users = ids("users", archive.users.iter().map(|r| &r.id))?;
+    let orgs = ids("orgs", archive.orgs.iter().map(|r| &r.id))?;
+    ids("servers", archive.servers.iter().map(|r| &r.id))?;
+    ids("agents", archive.agents.iter().map(|r| &r.id))?;
+    let templates = ids("zone_templates", archive.zone_templates.iter().map(|r| &r.id))?;
+    let zones = ids("zones", archive.zones.iter().map(|r| &r.id))?;
+    ids("zone_shares", archive.zone_shares.iter().map(|r| &r.id))?;
+    let records = ids("records", archive.records.iter().map(|r| &r.id))?;
+    ids("georules", archive.georules.iter().map(|r| &r.id))?;
+    let checks = ids("health_checks", archive.health_checks.iter().map(|r| &r.record_id))?;
+    ids("health_targets", archive.health_targets.iter().map(|r| &r.id))?;
+    ids("ptr_ranges", archive.ptr_ranges.iter().map(|r| &r.id))?;
+    ids("acme_credentials", archive.acme_credentials.iter().map(|r| &r.id))?;
+    ids("webhooks", archive.webhooks.iter().map(|r| &r.id))?;
+
+    refers("org_members", "org_id", &orgs, archive.org_members.iter().map(|r| Some(&r.org_id)))?;
+    refers("org_members", "user_id", &users, archive.org_members.iter().map(|r| Some(&r.user_id)))?;
+    refers("zone_templates", "org_id", &orgs, archive.zone_templates.iter().map(|r| r.org_id.as_ref()))?;
+    refers("zones", "org_id", &orgs, archive.zones.iter().map(|r| r.org_id.as_ref()))?;
+    refers("zones", "template_id", &templates, archive.zones.iter().map(|r| r.template_id.as_ref()))?;
+    refers("records", "zone_id", &zones, archive.records.iter().map(|r| r.zone_id.as_ref()))?;
+    refers("zone_shares", "zone_id", &zones, archive.zone_shares.iter().map(|r| Some(&r.zone_id)))?;
+    refers("zone_shares", "user_id", &users, archive.zone_shares.iter().map(|r| r.user_id.as_ref()))?;
+    refers("zone_shares", "org_id", &orgs, archive.zone_shares.iter().map(|r| r.org_id.as_ref()))?;
+    refers("georules", "zone_id", &zones, archive.georules.iter().map(|r| r.zone_id.as_ref()))?;
+    refers("health_checks", "record_id", &records, archive.health_checks.iter().map(|r| Some(&r.record_id)))?;
+    refers("health_targets", "record_id", &checks, archive.health_targets.iter().map(|r| r.record_id.as_ref()))?;
+    refers("ptr_ranges", "zone_id", &zones, archive.ptr_ranges.iter().map(|r| Some(&r.zone_id)))?;
+    refers("acme_credentials", "zone_id", &zones, archive.acme_credentials.iter().map(|r| r.zone_id.as_ref()))?;
+    Ok(())
+}
+
+/// Validate an archive and restore it in a transaction.
+pub async fn restore(db: &mut PgClient, archive: &Archive, mode: RestoreMode) -> Result<RestoreSummary, RestoreError> {
+    validate(archive).map_err(RestoreError::Invalid)?;
+    let rows = serde_json::to_value(archive).map_err(|e| RestoreError::Invalid(e.to_string()))?;
+    let tx = db.transaction().await?;
+    if mode == RestoreMode::Replace {
+        let names: Vec<&str> = TABLES.iter().map(|t| t.name).collect();
+        tx.batch_execute(&format!("TRUNCATE {} CASCADE", names.join(", "))).await?;
+    }
+    let conflict = if mode == RestoreMode::Merge { " ON CONFLICT DO NOTHING" } else { "" };
+    let mut restored = BTreeMap::new();
+    for t in TABLES {
+        let sql = format!("INSERT INTO {0} ({1}) SELECT {2} FROM jsonb_populate_recordset(NULL::{0}, $1::jsonb){3}", t.name, t.columns, t.restore, conflict);
+        let n = tx.execute(sql.as_str(), &[&rows[t.name]]).await?;
+        restored.insert(t.name.to_string(), n);
+    }
+    tx.commit().await?;
+    Ok(RestoreSummary { mode, restored })
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct BackupQuery {
+    /// Include password hashes, agent tokens and webhook secrets
+    #[serde(default)]
+    pub secrets: bool,
+}
+
+/// Export the control plane as an archive (admins).
+#[utoipa::path(
+    get, path = "/api/v1/backup", tag = "backup", params(BackupQuery),
+    responses((status = 200, body = Archive), (status = 401), (status = 403)),
+)]
+pub async fn export_backup(query: web::Query<BackupQuery>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    match export(&data.db, query.secrets).await {
+        Ok(archive) => {
+            let file = format!("control-plane-{}.json", archive.created_at.format("%Y%m%dT%H%M%SZ"));
+            HttpResponse::Ok().insert_header((header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file))).json(archive)
+        }
+        Err(e) => { warn!("export_backup error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RestoreQuery {
+    #[serde(default)]
+    pub mode: RestoreMode,
+}
+
+/// Restore an archive (admins).
+///
+/// The archive is validated first; nothing is changed when it is refused or when a row cannot be
+/// restored.
+#[utoipa::path(
+    post, path = "/api/v1/backup/restore", tag = "backup", params(RestoreQuery), request_body = Archive,
+    responses((status = 200, body = RestoreSummary), (status = 400, description = "The archive is invalid or conflicts with existing rows"), (status = 401), (status = 403), (status = 413)),
+)]
+pub async fn restore_backup(query: web::Query<RestoreQuery>, mut payload: web::Payload, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let tok = match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => tok,
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(chunk) if body.len() + chunk.len() <= MAX_ARCHIVE_BYTES => body.extend_from_slice(&chunk),
+            Ok(_) => return HttpResponse::PayloadTooLarge().finish(),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        }
+    }
+    let archive: Archive = match serde_json::from_slice(&body) {
+        Ok(archive) => archive,
+        Err(e) => return HttpResponse::BadRequest().body(format!("invalid archive: {}", e)),
+    };
+    // restoring needs a transaction, which the shared connection cannot hold
+    let mut db = match connect(&data.database_url).await {
+        Ok(db) => db,
+        Err(e) => { warn!("restore_backup error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    match restore(&mut db, &archive, query.mode).await {
+        Ok(summary) => {
+            info!("archive of {} restored by {} ({:?})", archive.created_at, tok.claims.sub, query.mode);
+            HttpResponse::Ok().json(summary)
+        }
+        Err(RestoreError::Invalid(e)) => HttpResponse::BadRequest().body(format!("invalid archive: {}", e)),
+        Err(RestoreError::Db(e)) if e.as_db_error().is_some() => HttpResponse::BadRequest().body(e.to_string()),
+        Err(e) => { warn!("restore_backup error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+async fn connect(database_url: &str) -> Result<PgClient, tokio_postgres::Error> {
+    let (client, connection) = tokio_postgres::connect(database_url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            warn!("postgres connection error: {}", e);
+        }
+    });
+    Ok(client)
+}
+
+const USAGE: &str = "usage: control_api backup [--secrets] [FILE] | control_api restore [--replace] [FILE]";
+
+/// Run the `backup` or `restore` command, reading or writing `FILE`, stdin/stdout when not given.
+pub async fn run_cli(db: &mut PgClient, args: &[String]) -> Result<(), anyhow::Error> {
+    let (flags, files): (Vec<&String>, Vec<&String>) = args[1..].iter().partition(|a| a.starts_with("--"));
+    if files.len() > 1 {
+        anyhow::bail!(USAGE);
+    }
+    let file = files.first();
+    match (args[0].as_str(), flags.as_slice()) {
+        ("backup", [] | [_]) => {
+            let secrets = match flags.first() {
+                Some(f) if f.as_str() == "--secrets" => true,
+                Some(_) => anyhow::bail!(USAGE),
+                None => false,
+            };
+            let archive = export(db, secrets).await?;
+            let json = serde_json::to_vec_pretty(&archive)?;
+            match file {
+                Some(path) => std::fs::write(path, json)?,
+                None => std::io::stdout().write_all(&json)?,
+            }
+            eprintln!("exported {} zones and {} records", archive.zones.len(), archive.records.len());
+            Ok(())
+        }
+        ("restore", [] | [_]) => {
+            let mode = match flags.first() {
+                Some(f) if f.as_str() == "--replace" => RestoreMode::Replace,
+                Some(_) => anyhow::bail!(USAGE),
+                None => RestoreMode::Merge,
+            };
+            let json = match file {
+                Some(path) => std::fs::read(path)?,
+                None => {
+                    let mut json = Vec::new();
+                    std::io::stdin().read_to_end(&mut json)?;
+                    json
+                }
+            };
+            let archive: Archive = serde_json::from_slice(&json)?;
+            let summary = restore(db, &archive, mode).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+            for (table, n) in summary.restored {
+                eprintln!("{}: {} rows restored", table, n);
+            }
+            Ok(())
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn archive() -> Archive {
+        let now = chrono::Utc::now();
+        let org = Uuid::new_v4().to_string();
+        let zone = Uuid::new_v4().to_string();
+        Archive {
+            version: ARCHIVE_VERSION, created_at: now, secrets: false,
+            users: vec![], org_members: vec![], servers: vec![], agents: vec![], zone_templates: vec![],
+            orgs: vec![OrgRow { id: org.clone(), name: "acme".into(), max_zones: None, max_records: None, max_georules: None, created_at: now }],
+            zones: vec![ZoneRow { id: zone.clone(), domain: "example.com".into(), owner: None, org_id: Some(org.clone()), template_id: None, template_params: None, auto_ptr: false, geo_default: None }],
+            zone_shares: vec![ZoneShareRow {
+                id: Uuid::new_v4().to_string(), zone_id: zone.clone(), user_id: None, org_id: Some(org), permission: "read".into(), created_by: None, created_at: now,
+            }],
+            records: vec![RecordRow {
+                id: Uuid::new_v4().to_string(), zone_id: Some(zone), name: Some("www".into()), rtype: Some("A".into()), value: Some("192.0.2.1".into()),
+                ttl: Some(300), weight: 1, expires_at: None, from_template: false, ptr_address: None, created_at: now,
+            }],
+            georules: vec![], health_checks: vec![], health_targets: vec![], ptr_ranges: vec![], acme_credentials: vec![], webhooks: vec![],
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate(&archive()), Ok(()));
+
+        let mut newer = archive();
+        newer.version = ARCHIVE_VERSION + 1;
+        assert!(validate(&newer).unwrap_err().contains("unsupported version"));
+
+        let mut duplicate = archive();
+        duplicate.records.push(duplicate.records[0].clone());
+        assert!(validate(&duplicate).unwrap_err().starts_with("records: duplicate id"));
+
+        let mut dangling = archive();
+        dangling.zones.clear();
+        assert!(validate(&dangling).unwrap_err().starts_with("records: zone_id"));
+
+        let mut invalid = archive();
+        invalid.orgs[0].id = "acme".into();
+        assert!(validate(&invalid).unwrap_err().starts_with("orgs: invalid id"));
+    }
+
+    /// Archives use the column names, which the restore maps rows by.
+    #[test]
+    fn test_archive_columns() {
+        let archive = serde_json::to_value(archive()).unwrap();
+        for t in TABLES {
+            let columns: BTreeSet<&str> = t.columns.split(", ").collect();
+            for row in archive[t.name].as_array().unwrap() {
+                let fields: BTreeSet<&str> = row.as_object().unwrap().keys().map(String::as_str).collect();
+                assert_eq!(fields, columns, "{}", t.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,685 @@
+//! Typed change events emitted by the control API.
+//!
+//! Every mutation that downstream systems care about is published on an [`EventBus`]. Events are
+//! persisted in the `events` table, fanned out to `GET /api/v1/events` server-sent-events
+//! subscribers, and delivered to the configured outbound webhooks.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_web::{http::header, web, web::Bytes, HttpRequest, HttpResponse, Responder};
+use futures_util::stream;
+use hmac::{Hmac, Mac};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::sync::{broadcast, Notify};
+use tokio::task::JoinHandle;
+use tokio_postgres::Client as PgClient;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+use crate::orgs::{self, Caller};
+use crate::{auth_from_header, decode_token, AppState};
+
+/// Agents that have not sent a heartbeat for this many seconds are considered offline.
+pub const AGENT_OFFLINE_AFTER_SECS: i64 = 120;
+
+/// Number of delivery attempts made for each webhook before giving up.
+const WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+
+/// Delay before the first webhook retry, doubled after every failed attempt.
+const WEBHOOK_INITIAL_BACKOFF: Duration = Duration::from_secs(2);
+
+/// Interval at which webhooks are checked for events without a wake-up, e.g. events published by
+/// another instance of the control API.
+const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of persisted events read at a time, when replaying them to an SSE client reconnecting
+/// with `Last-Event-ID` or delivering them to a webhook.
+const EVENT_PAGE_SIZE: i64 = 1000;
+
+/// Header carrying the hex encoded HMAC-SHA256 of the webhook body.
+pub const SIGNATURE_HEADER: &str = "X-Hickory-Signature";
+
+/// A change in the control plane.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ZoneCreated { zone_id: String, domain: String },
+    ZoneDeleted { zone_id: String, domain: String },
+    RecordsChanged { zone_id: String, record_id: String, change: RecordChange },
+    AgentOnline { agent_id: String },
+    AgentOffline { agent_id: String },
+    ConfigApplied { agent_id: String, version: Option<i64> },
+    TargetHealthChanged { zone_id: String, record_id: String, target: String, healthy: bool },
+    // changes to who may access a zone, `by` is the user who made them
+    ZoneTransferred {
+        zone_id: String,
+        domain: String,
+        from_owner: Option<String>,
+        to_owner: String,
+        from_org: Option<String>,
+        to_org: Option<String>,
+        by: String,
+    },
+    ZoneShared { zone_id: String, domain: String, share_id: String, user_id: Option<String>, org_id: Option<String>, permission: String, by: String },
+    ZoneUnshared { zone_id: String, domain: String, share_id: String, user_id: Option<String>, org_id: Option<String>, by: String },
+}
+
+impl Event {
+    /// The name of the event, as used for the SSE `event:` field and webhook filters.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::ZoneCreated { .. } => "zone_created",
+            Self::ZoneDeleted { .. } => "zone_deleted",
+            Self::RecordsChanged { .. } => "records_changed",
+            Self::AgentOnline { .. } => "agent_online",
+            Self::AgentOffline { .. } => "agent_offline",
+            Self::ConfigApplied { .. } => "config_applied",
+            Self::TargetHealthChanged { .. } => "target_health_changed",
+            Self::ZoneTransferred { .. } => "zone_transferred",
+            Self::ZoneShared { .. } => "zone_shared",
+            Self::ZoneUnshared { .. } => "zone_unshared",
+        }
+    }
+}
+
+/// Event types which change the configuration served to agents.
+pub const CONFIG_EVENT_TYPES: [&str; 4] = ["zone_created", "zone_deleted", "records_changed", "target_health_changed"];
+
+/// The current agent configuration version: the id of the latest config-changing event.
+pub async fn config_version(db: &PgClient) -> i64 {
+    let row = db.query_one("SELECT COALESCE(max(id), 0) FROM events WHERE type = ANY($1)", &[&&CONFIG_EVENT_TYPES[..]]).await;
+    match row {
+        Ok(r) => r.get(0),
+        Err(e) => { warn!("failed to read config version: {}", e); 0 }
+    }
+}
+
+/// The kind of change made to a record.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordChange {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/// A published event along with its sequence number.
+#[derive(Clone, Debug, Serialize)]
+pub struct EventEnvelope {
+    pub id: i64,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    #[serde(flatten)]
+    pub event: Event,
+    /// Organization of the zone the event refers to; `None` for events only visible to admins.
+    #[serde(skip)]
+    pub org_id: Option<String>,
+}
+
+impl EventEnvelope {
+    /// Whether a caller is allowed to see this event.
+    fn visible_to(&self, caller: &Caller) -> bool {
+        caller.all || self.org_id.as_ref().is_some_and(|o| caller.orgs.contains(o))
+    }
+
+    /// Encode the event as a server-sent-events frame.
+    fn to_sse_frame(&self) -> String {
+        let data = serde_json::to_string(self).unwrap_or_default();
+        format!("id: {}\nevent: {}\ndata: {}\n\n", self.id, self.event.kind(), data)
+    }
+}
+
+/// Persists events and broadcasts them to SSE subscribers and the webhook dispatcher.
+#[derive(Clone)]
+pub struct EventBus {
+    db: Arc<PgClient>,
+    tx: broadcast::Sender<Arc<EventEnvelope>>,
+}
+
+impl EventBus {
+    pub fn new(db: Arc<PgClient>) -> Self {
+        let (tx, _) = broadcast::channel(1024);
+        Self { db, tx }
+    }
+
+    /// Record an event and notify all subscribers.
+    ///
+    /// `org_id` is the organization of the zone the event is about, used to scope the SSE stream.
+    pub async fn publish(&self, event: Event, org_id: Option<String>) {
+        let payload = match serde_json::to_value(&event) {
+            Ok(p) => p,
+            Err(e) => { warn!("failed to encode {} event: {}", event.kind(), e); return; }
+        };
+        let row = self.db.query_one(
+            "INSERT INTO events (type, payload, org_id) VALUES ($1, $2, $3::text::uuid) RETURNING id, created_at",
+            &[&event.kind(), &payload, &org_id],
+        ).await;
+        let row = match row {
+            Ok(r) => r,
+            Err(e) => { warn!("failed to persist {} event: {}", event.kind(), e); return; }
+        };
+        let envelope = EventEnvelope { id: row.get(0), timestamp: row.get(1), event, org_id };
+        // no subscribers is not an error
+        let _ = self.tx.send(Arc::new(envelope));
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<EventEnvelope>> {
+        self.tx.subscribe()
+    }
+
+}
+
+/// A page of the events persisted after `last_id`, oldest first, along with the id of the last
+/// event read; `None` once there are no more events.
+async fn since(db: &PgClient, last_id: i64) -> Result<(Vec<EventEnvelope>, Option<i64>), tokio_postgres::Error> {
+    let rows = db.query(
+        "SELECT id, created_at, payload, org_id::text FROM events WHERE id > $1 ORDER BY id LIMIT $2",
+        &[&last_id, &EVENT_PAGE_SIZE],
+    ).await?;
+    let last = rows.last().map(|r| r.get(0));
+    let events = rows.into_iter().filter_map(|r| {
+        let event = serde_json::from_value(r.get(2)).ok()?;
+        Some(EventEnvelope { id: r.get(0), timestamp: r.get(1), event, org_id: r.get(3) })
+    }).collect();
+    Ok((events, last))
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EventStreamQuery {
+    /// JWT, for `EventSource` clients which cannot set an `Authorization` header.
+    token: Option<String>,
+    /// Comma separated list of event types to receive; all types if unset.
+    types: Option<String>,
+}
+
+/// `GET /api/v1/events`: stream events as server-sent events.
+///
+/// Admins receive every event, other users only events about zones of their organizations,
+/// narrowed to one with `X-Org-Id`. Clients that reconnect with a `Last-Event-ID` header first
+/// receive all the events they missed. A client too slow to keep up with the live events is
+/// disconnected, and replays the ones it missed when it reconnects.
+#[utoipa::path(
+    get, path = "/api/v1/events", tag = "events", params(EventStreamQuery),
+    responses((status = 200, content_type = "text/event-stream", body = String), (status = 401)),
+)]
+pub async fn stream_events(query: web::Query<EventStreamQuery>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let claims = match auth_from_header(&req, &data.jwt_secret)
+        .or_else(|| query.token.as_deref().and_then(|t| decode_token(t, &data.jwt_secret)))
+    {
+        Some(tok) => tok.claims,
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+    let caller = match orgs::scope(&data.db, &claims, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let types: Option<Vec<String>> = query.types.as_ref().map(|t| t.split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect());
+    let wanted = move |env: &EventEnvelope| {
+        env.visible_to(&caller)
+            && types.as_ref().is_none_or(|t| t.iter().any(|k| k == env.event.kind()))
+    };
+
+    // subscribe before replaying so nothing published in between is lost
+    let rx = data.events.subscribe();
+    let last_id = req.headers().get("Last-Event-ID").and_then(|h| h.to_str().ok()).and_then(|s| s.parse::<i64>().ok());
+    let db = data.db.clone();
+
+    // The backlog is replayed a page at a time until a page comes back empty, by then every
+    // event after it is on the live stream. Live events are only compared against the backlog:
+    // concurrent publishers can broadcast an event after one with a higher id, so the cutoff must
+    // not follow the live stream.
+    let state = Subscription { rx, replay: last_id, replayed: last_id.unwrap_or(0) };
+    let events = stream::unfold(state, move |mut state| {
+        let wanted = wanted.clone();
+        let db = db.clone();
+        async move {
+            if let Some(cursor) = state.replay {
+                let (backlog, last) = match since(&db, cursor).await {
+                    Ok(page) => page,
+                    Err(e) => { warn!("failed to replay events: {}", e); return None; }
+                };
+                let frames: String = backlog.iter().filter(|e| wanted(e)).map(EventEnvelope::to_sse_frame).collect();
+                state.replay = last;
+                state.replayed = last.unwrap_or(state.replayed);
+                return Some((Ok::<_, actix_web::Error>(Bytes::from(frames)), state));
+            }
+            loop {
+                tokio::select! {
+                    msg = state.rx.recv() => match msg {
+                        Ok(env) => {
+                            if env.id <= state.replayed || !wanted(&env) { continue; }
+                            return Some((Ok(Bytes::from(env.to_sse_frame())), state));
+                        }
+                        Err(broadcast::error::RecvError::Lagged(n)) => {
+                            // the client reconnects with its `Last-Event-ID` and replays the gap
+                            warn!("event stream subscriber lagged by {} events, closing it", n);
+                            return None;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    },
+                    _ = tokio::time::sleep(Duration::from_secs(15)) => {
+                        return Some((Ok(Bytes::from_static(b": keep-alive\n\n")), state));
+                    }
+                }
+            }
+        }
+    });
+    let body = futures_util::StreamExt::chain(stream::once(async { Ok::<_, actix_web::Error>(Bytes::from_static(b"retry: 5000\n\n")) }), events);
+
+    HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(body)
+}
+
+/// State of an SSE response: the backlog is replayed after `replay` until it is caught up, then
+/// live events after `replayed` are sent.
+struct Subscription {
+    rx: broadcast::Receiver<Arc<EventEnvelope>>,
+    replay: Option<i64>,
+    replayed: i64,
+}
+
+// ============================================================================
+// WEBHOOKS
+// ============================================================================
+
+#[derive(Clone, Debug)]
+struct Webhook {
+    id: String,
+    url: String,
+    secret: String,
+    event_types: Vec<String>,
+}
+
+impl Webhook {
+    fn accepts(&self, event: &Event) -> bool {
+        self.event_types.is_empty() || self.event_types.iter().any(|t| t == event.kind())
+    }
+}
+
+/// Compute the value of the [`SIGNATURE_HEADER`] for a webhook body.
+pub fn sign_payload(secret: &[u8], body: &[u8]) -> String {
+    // HMAC accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC key of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Spawn the task delivering published events to the configured webhooks.
+///
+/// Every enabled webhook has a worker delivering the events after its `last_event_id` cursor, in
+/// order. Published events only wake the workers up, the events themselves are read from the
+/// `events` table, so none are lost when the broadcast channel lags or the control API restarts.
+pub fn spawn_webhook_dispatcher(bus: EventBus) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(10))
+        .build()
+        .expect("webhook http client");
+    let mut rx = bus.subscribe();
+    tokio::spawn(async move {
+        let mut workers: HashMap<String, (Arc<Notify>, JoinHandle<()>)> = HashMap::new();
+        loop {
+            workers.retain(|_, (_, task)| !task.is_finished());
+            let rows = match bus.db.query("SELECT id::text FROM webhooks WHERE enabled", &[]).await {
+                Ok(rows) => rows,
+                Err(e) => { warn!("failed to list webhooks: {}", e); vec![] }
+            };
+            for r in rows {
+                let id: String = r.get(0);
+                let (wake, _) = workers.entry(id.clone()).or_insert_with(|| {
+                    let wake = Arc::new(Notify::new());
+                    (wake.clone(), tokio::spawn(run_webhook(bus.db.clone(), client.clone(), id, wake)))
+                });
+                wake.notify_one();
+            }
+
+            tokio::select! {
+                msg = rx.recv() => match msg {
+                    // missed events are still in the table, this is only a wake-up
+                    Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {}
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                _ = tokio::time::sleep(WEBHOOK_POLL_INTERVAL) => {}
+            }
+        }
+    });
+}
+
+/// Deliver the events after the cursor of the webhook `id`, each time `wake` is notified, until
+/// the webhook is deleted or disabled.
+///
+/// The cursor only moves past an event once it was delivered or its attempts ran out, so
+/// deliveries still pending when the control API stops are retried after a restart.
+async fn run_webhook(db: Arc<PgClient>, client: reqwest::Client, id: String, wake: Arc<Notify>) {
+    loop {
+        let row = db.query_opt("SELECT url, secret, event_types, last_event_id FROM webhooks WHERE id::text = $1 AND enabled", &[&id]).await;
+        let (hook, cursor) = match row {
+            Ok(Some(r)) => {
+                let hook = Webhook {
+                    id: id.clone(),
+                    url: r.get(0),
+                    secret: r.get(1),
+                    event_types: r.get::<usize, Option<Vec<String>>>(2).unwrap_or_default(),
+                };
+                (hook, r.get::<usize, i64>(3))
+            }
+            Ok(None) => return,
+            Err(e) => { warn!("failed to read webhook {}: {}", id, e); wake.notified().await; continue; }
+        };
+        let (events, last) = match since(&db, cursor).await {
+            Ok(page) => page,
+            Err(e) => { warn!("failed to read events for webhook {}: {}", id, e); wake.notified().await; continue; }
+        };
+        let Some(last) = last else {
+            wake.notified().await;
+            continue;
+        };
+
+        for env in events.iter().filter(|e| hook.accepts(&e.event)) {
+            deliver(&db, &client, &hook, env).await;
+            advance(&db, &id, env.id).await;
+        }
+        advance(&db, &id, last).await;
+    }
+}
+
+/// Move the cursor of a webhook to `event_id`.
+async fn advance(db: &PgClient, webhook_id: &str, event_id: i64) {
+    let res = db.execute("UPDATE webhooks SET last_event_id = $2 WHERE id::text = $1 AND last_event_id < $2", &[&webhook_id, &event_id]).await;
+    if let Err(e) = res {
+        warn!("failed to advance webhook {}: {}", webhook_id, e);
+    }
+}
+
+/// Deliver one event to one webhook, retrying with exponential backoff.
+///
+/// Attempts recorded before a restart count towards [`WEBHOOK_MAX_ATTEMPTS`].
+async fn deliver(db: &PgClient, client: &reqwest::Client, hook: &Webhook, env: &EventEnvelope) {
+    let body = match serde_json::to_vec(env) {
+        Ok(b) => b,
+        Err(e) => { warn!("failed to encode event {}: {}", env.id, e); return; }
+    };
+    let previous = db.query_one(
+        "SELECT COALESCE(max(attempt), 0), COALESCE(bool_or(success), false) FROM webhook_deliveries WHERE webhook_id = $1::text::uuid AND event_id = $2",
+        &[&hook.id, &env.id],
+    ).await;
+    let (made, delivered) = match previous {
+        Ok(r) => (r.get::<usize, i32>(0) as u32, r.get::<usize, bool>(1)),
+        Err(e) => { warn!("failed to read webhook deliveries: {}", e); (0, false) }
+    };
+    if delivered {
+        return;
+    }
+    let signature = sign_payload(hook.secret.as_bytes(), &body);
+    let mut backoff = WEBHOOK_INITIAL_BACKOFF * 2u32.pow(made.saturating_sub(1));
+    for attempt in made + 1..=WEBHOOK_MAX_ATTEMPTS {
+        if attempt > 1 {
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+        }
+        let res = client.post(&hook.url)
+            .header(header::CONTENT_TYPE.as_str(), "application/json")
+            .header(SIGNATURE_HEADER, &signature)
+            .header("X-Hickory-Event", env.event.kind())
+            .header("X-Hickory-Delivery", env.id.to_string())
+            .body(body.clone())
+            .send()
+            .await;
+        let (status, error) = match res {
+            Ok(resp) if resp.status().is_success() => (Some(resp.status().as_u16() as i32), None),
+            Ok(resp) => (Some(resp.status().as_u16() as i32), Some(format!("unexpected status {}", resp.status()))),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        let success = error.is_none();
+        let attempt_no = attempt as i32;
+        let logged = db.execute(
+            "INSERT INTO webhook_deliveries (id, webhook_id, event_id, event_type, attempt, status_code, error, success) VALUES ($1::text::uuid, $2::text::uuid, $3, $4, $5, $6, $7, $8)",
+            &[&Uuid::new_v4().to_string(), &hook.id, &env.id, &env.event.kind(), &attempt_no, &status, &error, &success],
+        ).await;
+        if let Err(e) = logged {
+            warn!("failed to record webhook delivery: {}", e);
+        }
+        if success {
+            return;
+        }
+    }
+    warn!("giving up delivering event {} to webhook {}", env.id, hook.id);
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateWebhookReq {
+    url: String,
+    /// Shared secret for the signature; generated when not provided.
+    secret: Option<String>,
+    /// Event types to deliver; all types when empty.
+    #[serde(default)]
+    event_types: Vec<String>,
+}
+
+const EVENT_TYPES: [&str; 10] = [
+    "zone_created", "zone_deleted", "records_changed", "agent_online", "agent_offline", "config_applied", "target_health_changed",
+    "zone_transferred", "zone_shared", "zone_unshared",
+];
+
+#[derive(Serialize, ToSchema)]
+pub struct CreateWebhookResponse {
+    id: String,
+    /// Key of the `X-Hickory-Signature` HMAC
+    secret: String,
+}
+
+/// Deliver events to a URL (admins).
+#[utoipa::path(
+    post, path = "/api/v1/webhooks", tag = "webhooks", request_body = CreateWebhookReq,
+    responses((status = 201, body = CreateWebhookResponse), (status = 400), (status = 401), (status = 403)),
+)]
+pub async fn create_webhook(body: web::Json<CreateWebhookReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    if !(body.url.starts_with("http://") || body.url.starts_with("https://")) {
+        return HttpResponse::BadRequest().body("url must be http or https");
+    }
+    if let Some(t) = body.event_types.iter().find(|t| !EVENT_TYPES.contains(&t.as_str())) {
+        return HttpResponse::BadRequest().body(format!("unknown event type: {}", t));
+    }
+    let id = Uuid::new_v4().to_string();
+    let secret = body.secret.clone().unwrap_or_else(|| format!("{}{}", Uuid::new_v4().to_simple(), Uuid::new_v4().to_simple()));
+    let res = data.db.execute(
+        "INSERT INTO webhooks (id, url, secret, event_types, last_event_id) VALUES ($1::text::uuid, $2, $3, $4, (SELECT COALESCE(max(id), 0) FROM events))",
+        &[&id, &body.url, &secret, &body.event_types],
+    ).await;
+    match res {
+        Ok(_) => {
+            info!("created webhook {} for {}", id, body.url);
+            HttpResponse::Created().json(CreateWebhookResponse { id, secret })
+        }
+        Err(e) => { warn!("create_webhook error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct WebhookResponse {
+    id: String,
+    url: String,
+    event_types: Vec<String>,
+    enabled: bool,
+    /// Id of the last event delivered, or given up on, events after it are still to be delivered
+    last_event_id: i64,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[utoipa::path(get, path = "/api/v1/webhooks", tag = "webhooks", responses((status = 200, body = [WebhookResponse]), (status = 401), (status = 403)))]
+pub async fn list_webhooks(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    let rows = data.db.query("SELECT id::text, url, event_types, enabled, last_event_id, created_at FROM webhooks ORDER BY created_at", &[]).await.unwrap_or_default();
+    let hooks: Vec<_> = rows.into_iter().map(|r| WebhookResponse {
+        id: r.get(0),
+        url: r.get(1),
+        event_types: r.get::<usize, Option<Vec<String>>>(2).unwrap_or_default(),
+        enabled: r.get(3),
+        last_event_id: r.get(4),
+        created_at: r.get(5),
+    }).collect();
+    HttpResponse::Ok().json(hooks)
+}
+
+#[utoipa::path(
+    delete, path = "/api/v1/webhooks/{id}", tag = "webhooks", params(("id" = String, Path, description = "Webhook id")),
+    responses((status = 200), (status = 401), (status = 403), (status = 404)),
+)]
+pub async fn delete_webhook(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    let id = path.into_inner();
+    match data.db.execute("DELETE FROM webhooks WHERE id::text = $1", &[&id]).await {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("delete_webhook error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeliveriesQuery {
+    limit: Option<i64>,
+    /// Only return failed (`false`) or successful (`true`) attempts.
+    success: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DeliveryResponse {
+    id: String,
+    event_id: i64,
+    event_type: String,
+    attempt: i32,
+    status_code: Option<i32>,
+    error: Option<String>,
+    success: bool,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// `GET /api/v1/webhooks/{id}/deliveries`: delivery attempts for a webhook, newest first.
+#[utoipa::path(
+    get, path = "/api/v1/webhooks/{id}/deliveries", tag = "webhooks", params(("id" = String, Path, description = "Webhook id"), DeliveriesQuery),
+    responses((status = 200, body = [DeliveryResponse]), (status = 401), (status = 403)),
+)]
+pub async fn list_webhook_deliveries(path: web::Path<String>, query: web::Query<DeliveriesQuery>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    let id = path.into_inner();
+    let limit = query.limit.unwrap_or(100).clamp(1, 1000);
+    let rows = data.db.query(
+        "SELECT id::text, event_id, event_type, attempt, status_code, error, success, created_at FROM webhook_deliveries
+         WHERE webhook_id::text = $1 AND ($2::bool IS NULL OR success = $2) ORDER BY created_at DESC LIMIT $3",
+        &[&id, &query.success, &limit],
+    ).await.unwrap_or_default();
+    let out: Vec<_> = rows.into_iter().map(|r| DeliveryResponse {
+        id: r.get(0),
+        event_id: r.get(1),
+        event_type: r.get(2),
+        attempt: r.get(3),
+        status_code: r.get(4),
+        error: r.get(5),
+        success: r.get(6),
+        created_at: r.get(7),
+    }).collect();
+    HttpResponse::Ok().json(out)
+}
+
+/// Spawn the task marking agents offline once their heartbeats stop.
+pub fn spawn_agent_presence_monitor(bus: EventBus) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(30));
+        loop {
+            interval.tick().await;
+            let rows = bus.db.query(
+                "UPDATE agents SET online = false WHERE online AND last_heartbeat < now() - make_interval(secs => $1) RETURNING id::text",
+                &[&(AGENT_OFFLINE_AFTER_SECS as f64)],
+            ).await;
+            match rows {
+                Ok(rows) => for r in rows {
+                    bus.publish(Event::AgentOffline { agent_id: r.get(0) }, None).await;
+                },
+                Err(e) => warn!("agent presence check failed: {}", e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_payload() {
+        // RFC 4231, test case 2
+        assert_eq!(
+            sign_payload(b"Jefe", b"what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn test_event_encoding() {
+        let env = EventEnvelope {
+            id: 7,
+            timestamp: chrono::DateTime::from_timestamp(0, 0).unwrap(),
+            event: Event::RecordsChanged { zone_id: "z".into(), record_id: "r".into(), change: RecordChange::Deleted },
+            org_id: Some("o".into()),
+        };
+        let value = serde_json::to_value(&env).unwrap();
+        assert_eq!(value["type"], "records_changed");
+        assert_eq!(value["change"], "deleted");
+        assert!(value.get("org_id").is_none());
+
+        let frame = env.to_sse_frame();
+        assert!(frame.starts_with("id: 7\nevent: records_changed\ndata: {"));
+        assert!(frame.ends_with("}\n\n"));
+    }
+
+    #[test]
+    fn test_event_visibility() {
+        let env = EventEnvelope {
+            id: 1,
+            timestamp: chrono::Utc::now(),
+            event: Event::AgentOnline { agent_id: "a".into() },
+            org_id: None,
+        };
+        let caller = |all: bool, orgs: &[&str]| Caller { sub: "u".into(), admin: all, all, orgs: orgs.iter().map(|o| o.to_string()).collect() };
+        assert!(env.visible_to(&caller(true, &[])));
+        assert!(!env.visible_to(&caller(false, &["o1"])));
+
+        let env = EventEnvelope { org_id: Some("o1".into()), ..env };
+        assert!(env.visible_to(&caller(false, &["o2", "o1"])));
+        assert!(!env.visible_to(&caller(false, &["o2"])));
+    }
+
+    #[test]
+    fn test_webhook_filter() {
+        let mut hook = Webhook { id: String::new(), url: String::new(), secret: String::new(), event_types: vec![] };
+        let event = Event::ZoneCreated { zone_id: "z".into(), domain: "example.com".into() };
+        assert!(hook.accepts(&event));
+        hook.event_types = vec!["zone_deleted".into()];
+        assert!(!hook.accepts(&event));
+        hook.event_types.push("zone_created".into());
+        assert!(hook.accepts(&event));
+    }
+}
@@ -0,0 +1,710 @@
+//! Geo rules: what a zone answers depending on where its clients are.
+//!
+//! Rules match a client by location (`country`, `continent`, `subdivision`, `city`, `asn`) or by
+//! network (`cidr`). They are evaluated by ascending `priority`, the oldest first among rules of
+//! the same priority; the first match decides. A rule answers with one of its targets, chosen by
+//! weight, or with NXDOMAIN. When no rule matches, the zone's default applies, if it has one.
+//!
+//! Targets are typed by the record they are answered with: `A`, `AAAA` or `CNAME`. The type is
+//! inferred from the value when not given.
+//!
+//! Clients are located with the MaxMind databases of `GEOIP_DB_PATH` (City or Country) and
+//! `GEOIP_ASN_DB_PATH`. They are reloaded when their files change, checked every
+//! `GEOIP_RELOAD_INTERVAL_SECS` (60 by default, 0 to never check), or with
+//! `POST /api/v1/geo/db/reload`. New databases are validated before they replace the current
+//! ones, which are kept if they are not valid.
+//!
+//! `POST /api/v1/georules/resolve` with `"explain": true` also tells where the client was
+//! located, and for each rule in the order they are evaluated whether and why it matched.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use chrono::{DateTime, TimeZone, Utc};
+use geodns::{GeoAction, GeoDbError, GeoDbInfo, GeoDecision, GeoExplanation, GeoRule, GeoRuleEngine, GeoTarget, Location, ReloadableGeoDB, RuleOutcome, WeightedTarget};
+use ipnet::IpNet;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Client as PgClient;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::sharing::{self, Permission};
+use crate::{metrics, orgs, FullState, IdResponse};
+
+/// A target of a rule or default.
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+pub struct GeoTargetBody {
+    /// `A`, `AAAA` or `CNAME`, inferred from the value when missing
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    record_type: Option<String>,
+    /// An address, or a hostname relative to the zone unless it ends with a dot
+    value: String,
+    /// Relative weight; targets of weight 0 are only answered when all are
+    #[serde(default = "default_weight")]
+    weight: u32,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+/// What a rule or default answers.
+#[derive(Clone, Copy, Default, PartialEq, Deserialize, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum GeoActionKind {
+    /// One of the targets
+    #[default]
+    Answer,
+    /// NXDOMAIN: the name does not exist for the client
+    Nxdomain,
+}
+
+impl GeoActionKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Answer => "answer",
+            Self::Nxdomain => "nxdomain",
+        }
+    }
+}
+
+/// Validate an action and its targets; `target` is a shorthand for a single target of weight 1.
+fn geo_action(kind: GeoActionKind, target: Option<&str>, targets: &[GeoTargetBody]) -> Result<GeoAction, String> {
+    let single = target.map(|value| GeoTargetBody { record_type: None, value: value.to_string(), weight: 1 });
+    let targets = single.iter().chain(targets)
+        .map(|t| GeoTarget::new(t.record_type.as_deref(), &t.value).map(|target| WeightedTarget { target, weight: t.weight }))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    match kind {
+        GeoActionKind::Nxdomain if !targets.is_empty() => Err("an nxdomain action has no targets".to_string()),
+        GeoActionKind::Nxdomain => Ok(GeoAction::NxDomain),
+        GeoActionKind::Answer => GeoAction::answer(targets).map_err(|e| e.to_string()),
+    }
+}
+
+/// The kind and the targets, with their type, of a validated action.
+fn action_body(action: &GeoAction) -> (GeoActionKind, Vec<GeoTargetBody>) {
+    let kind = match action {
+        GeoAction::Answer(_) => GeoActionKind::Answer,
+        GeoAction::NxDomain => GeoActionKind::Nxdomain,
+    };
+    let targets = action.targets().iter()
+        .map(|t| GeoTargetBody { record_type: Some(t.target.record_type().to_string()), value: t.target.to_string(), weight: t.weight })
+        .collect();
+    (kind, targets)
+}
+
+/// A stored action, `targets` falling back to the single `target` of rules created before rules
+/// had several.
+fn stored_action(action: Option<&str>, targets: Option<serde_json::Value>, target: Option<&str>) -> Result<GeoAction, String> {
+    let kind = match action {
+        Some("nxdomain") => GeoActionKind::Nxdomain,
+        _ => GeoActionKind::Answer,
+    };
+    match targets {
+        Some(targets) => {
+            let targets: Vec<GeoTargetBody> = serde_json::from_value(targets).map_err(|e| e.to_string())?;
+            geo_action(kind, None, &targets)
+        }
+        None => geo_action(kind, target, &[]),
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateGeoRuleReq {
+    zone_id: String,
+    /// Rules are evaluated by ascending priority
+    #[serde(default)]
+    priority: i32,
+    /// One of `country`, `continent`, `subdivision`, `city`, `asn` or `cidr`
+    match_type: String,
+    /// e.g. `US`, `EU`, `US-TX` (ISO 3166-2), `Berlin` or a GeoNames id, `AS64500`, `192.0.2.0/24`
+    match_value: String,
+    #[serde(default)]
+    action: GeoActionKind,
+    /// A single target of weight 1, added to `targets`
+    target: Option<String>,
+    #[serde(default)]
+    targets: Vec<GeoTargetBody>,
+}
+
+#[utoipa::path(
+    post, path = "/api/v1/georules", tag = "georules", request_body = CreateGeoRuleReq,
+    responses((status = 201, body = IdResponse), (status = 400), (status = 403, body = orgs::QuotaExceeded), (status = 404)),
+)]
+pub async fn create_georule(body: web::Json<CreateGeoRuleReq>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data.inner, &req).await { Ok(c) => c, Err(resp) => return resp };
+    let id = Uuid::new_v4();
+    let zone_uuid = match Uuid::parse_str(&body.zone_id) {
+        Ok(z) => z,
+        Err(_) => return HttpResponse::BadRequest().body("invalid zone_id"),
+    };
+    if body.priority < 0 {
+        return HttpResponse::BadRequest().body("priority must not be negative");
+    }
+    let action = match geo_action(body.action, body.target.as_deref(), &body.targets) {
+        Ok(action) => action,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let rule = body.match_type.parse().and_then(|match_type| GeoRule::new(id.to_string(), match_type, body.match_value.trim(), action));
+    let rule = match rule {
+        Ok(rule) => rule,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+    let id_str = id.to_string();
+    let zone_str = zone_uuid.to_string();
+    let zone = match orgs::scoped_zone(&data.inner.db, &caller, &zone_str).await {
+        Ok(Some(z)) => z,
+        Ok(None) => return HttpResponse::NotFound().body("zone not found"),
+        Err(e) => { warn!("create_georule error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    if let Err(resp) = zone.require(Permission::Write) {
+        return resp;
+    }
+    let (kind, targets) = action_body(&rule.action);
+    // the first target is kept in `target` too, for the readers of rules with a single target
+    let first = targets.first().map(|t| t.value.clone());
+    let targets = serde_json::to_value(&targets).expect("targets serialize");
+    let res = data.inner.db.execute(
+        &format!(
+            "INSERT INTO georules (id, zone_id, priority, match_type, match_value, action, targets, target)
+             SELECT $1::text::uuid, $2::text::uuid, $3, $4, $5, $6, $7, $8 WHERE (SELECT {})",
+            orgs::Quota::Georules.allows("$9::text::uuid", "1"),
+        ),
+        &[&id_str, &zone_str, &body.priority, &rule.match_type.as_str(), &rule.match_value, &kind.as_str(), &targets, &first, &zone.org_id],
+    ).await;
+    match res {
+        Ok(0) => orgs::quota_refused(&data.inner.db, &[(zone.org_id.as_deref(), orgs::Quota::Georules, 1)]).await,
+        Ok(_) => HttpResponse::Created().json(IdResponse { id: id.to_string() }),
+        Err(e) => { warn!("create_georule error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct GeoRuleResponse {
+    id: String,
+    zone_id: String,
+    priority: i32,
+    match_type: String,
+    match_value: String,
+    action: GeoActionKind,
+    targets: Vec<GeoTargetBody>,
+}
+
+/// Geo rules of the zones of the caller's organizations and of the zones shared with them, in the
+/// order they are evaluated.
+#[utoipa::path(get, path = "/api/v1/georules", tag = "georules", responses((status = 200, body = [GeoRuleResponse]), (status = 401)))]
+pub async fn list_georules(data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data.inner, &req).await { Ok(c) => c, Err(resp) => return resp };
+    let rows = data.inner.db.query(
+        &format!(
+            "SELECT g.id::text, g.zone_id::text, g.priority, g.match_type, g.match_value, g.action, g.targets, g.target
+             FROM georules g JOIN zones z ON z.id = g.zone_id WHERE $1 OR z.org_id::text = ANY($2) OR {}
+             ORDER BY g.zone_id, g.priority, g.created_at, g.id",
+            sharing::shared_sql("z.id", "$3", "$2"),
+        ),
+        &[&caller.all, &caller.orgs, &caller.sub],
+    ).await.unwrap_or_default();
+    let out: Vec<_> = rows.into_iter().map(|r| {
+        // rules stored before targets were validated are listed as they are
+        let (action, targets) = match stored_action(r.get(5), r.get(6), r.get(7)) {
+            Ok(action) => action_body(&action),
+            Err(_) => (GeoActionKind::Answer, r.get::<_, Option<String>>(7).map(|value| GeoTargetBody { record_type: None, value, weight: 1 }).into_iter().collect()),
+        };
+        GeoRuleResponse { id: r.get(0), zone_id: r.get(1), priority: r.get(2), match_type: r.get::<_, Option<String>>(3).unwrap_or_default(), match_value: r.get::<_, Option<String>>(4).unwrap_or_default(), action, targets }
+    }).collect();
+    HttpResponse::Ok().json(out)
+}
+
+/// The answer of a zone when none of its geo rules match.
+#[derive(Clone, Deserialize, Serialize, ToSchema)]
+pub struct GeoDefault {
+    #[serde(default)]
+    action: GeoActionKind,
+    /// A single target of weight 1, added to `targets`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    target: Option<String>,
+    #[serde(default)]
+    targets: Vec<GeoTargetBody>,
+}
+
+/// The zone `zone_id` if the caller has `permission` on it, or the response refusing the request.
+async fn geo_zone(data: &FullState, req: &HttpRequest, zone_id: &str, permission: Permission) -> Result<(), HttpResponse> {
+    let caller = orgs::caller(&data.inner, req).await?;
+    match orgs::scoped_zone(&data.inner.db, &caller, zone_id).await {
+        Ok(Some(zone)) => zone.require(permission),
+        Ok(None) => Err(HttpResponse::NotFound().body("zone not found")),
+        Err(e) => { warn!("geo zone error: {}", e); Err(HttpResponse::InternalServerError().finish()) }
+    }
+}
+
+/// `GET /api/v1/zones/{id}/geo/default`: the default answer of a zone, if it has one.
+#[utoipa::path(
+    get, path = "/api/v1/zones/{id}/geo/default", tag = "georules", params(("id" = String, Path, description = "Zone id")),
+    responses((status = 200, body = GeoDefault), (status = 401), (status = 404, description = "No such zone, or it has no default")),
+)]
+pub async fn get_geo_default(path: web::Path<String>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    let zone_id = path.into_inner();
+    if let Err(resp) = geo_zone(&data, &req, &zone_id, Permission::Read).await {
+        return resp;
+    }
+    match data.inner.db.query_opt("SELECT geo_default FROM zones WHERE id::text = $1 AND geo_default IS NOT NULL", &[&zone_id]).await {
+        Ok(Some(row)) => HttpResponse::Ok().json(row.get::<_, serde_json::Value>(0)),
+        Ok(None) => HttpResponse::NotFound().body("no geo default"),
+        Err(e) => { warn!("get_geo_default error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `PUT /api/v1/zones/{id}/geo/default`: set what a zone answers when none of its geo rules match.
+#[utoipa::path(
+    put, path = "/api/v1/zones/{id}/geo/default", tag = "georules", params(("id" = String, Path, description = "Zone id")), request_body = GeoDefault,
+    responses((status = 200, body = GeoDefault), (status = 400), (status = 401), (status = 403), (status = 404)),
+)]
+pub async fn set_geo_default(path: web::Path<String>, body: web::Json<GeoDefault>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    let zone_id = path.into_inner();
+    let action = match geo_action(body.action, body.target.as_deref(), &body.targets) {
+        Ok(action) => action,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    if let Err(resp) = geo_zone(&data, &req, &zone_id, Permission::Write).await {
+        return resp;
+    }
+    let (action, targets) = action_body(&action);
+    let default = GeoDefault { action, target: None, targets };
+    let value = serde_json::to_value(&default).expect("geo default serializes");
+    match data.inner.db.execute("UPDATE zones SET geo_default = $2 WHERE id::text = $1", &[&zone_id, &value]).await {
+        Ok(_) => HttpResponse::Ok().json(default),
+        Err(e) => { warn!("set_geo_default error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `DELETE /api/v1/zones/{id}/geo/default`: remove the default answer of a zone.
+#[utoipa::path(
+    delete, path = "/api/v1/zones/{id}/geo/default", tag = "georules", params(("id" = String, Path, description = "Zone id")),
+    responses((status = 200), (status = 401), (status = 403), (status = 404)),
+)]
+pub async fn delete_geo_default(path: web::Path<String>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    let zone_id = path.into_inner();
+    if let Err(resp) = geo_zone(&data, &req, &zone_id, Permission::Write).await {
+        return resp;
+    }
+    match data.inner.db.execute("UPDATE zones SET geo_default = NULL WHERE id::text = $1", &[&zone_id]).await {
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("delete_geo_default error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// The rules and default of a zone, in an engine locating clients with `db`.
+///
+/// Rules stored before their match type or targets were validated never match; they are skipped.
+async fn zone_engine(db: &PgClient, zone_id: &str, geo_db: Option<geodns::GeoDB>) -> Result<GeoRuleEngine, tokio_postgres::Error> {
+    let rows = db.query(
+        "SELECT id::text, priority, match_type, match_value, action, targets, target FROM georules WHERE zone_id::text = $1 ORDER BY priority, created_at, id",
+        &[&zone_id],
+    ).await?;
+    let mut engine = GeoRuleEngine::new(geo_db);
+    for r in rows {
+        let id = r.get::<_, String>(0);
+        let priority = r.get::<_, i32>(1);
+        let rule = stored_action(r.get(4), r.get(5), r.get(6)).and_then(|action| {
+            r.get::<_, Option<String>>(2).unwrap_or_default().parse()
+                .and_then(|match_type| GeoRule::new(id.as_str(), match_type, r.get::<_, Option<String>>(3).unwrap_or_default(), action))
+                .map_err(|e| e.to_string())
+        });
+        match rule {
+            Ok(rule) => engine.add_rule(rule.with_priority(priority.max(0) as u32)),
+            Err(e) => warn!("skipping geo rule {}: {}", id, e),
+        }
+    }
+
+    let default = db.query_opt("SELECT geo_default FROM zones WHERE id::text = $1 AND geo_default IS NOT NULL", &[&zone_id]).await?;
+    if let Some(default) = default.map(|r| r.get::<_, serde_json::Value>(0)) {
+        let action = serde_json::from_value::<GeoDefault>(default).map_err(|e| e.to_string())
+            .and_then(|d| geo_action(d.action, d.target.as_deref(), &d.targets));
+        match action {
+            Ok(action) => engine.set_default(Some(action)),
+            Err(e) => warn!("skipping geo default of zone {}: {}", zone_id, e),
+        }
+    }
+    Ok(engine)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct GeoResolveRequest {
+    zone_id: String,
+    client_ip: String,
+    /// EDNS Client Subnet of the query, e.g. `"198.51.100.0/24"`; the client is located by its
+    /// network address instead of `client_ip`
+    #[serde(default)]
+    client_subnet: Option<String>,
+    /// Explain how the decision was made
+    #[serde(default)]
+    explain: bool,
+}
+
+/// Where a client was located
+#[derive(Serialize, ToSchema)]
+pub struct GeoLocationBody {
+    country: Option<String>,
+    continent: Option<String>,
+    subdivisions: Vec<String>,
+    city: Option<String>,
+    city_id: Option<u32>,
+    asn: Option<u32>,
+    /// Prefix length of the database network the address is in
+    prefix_len: u8,
+}
+
+impl From<Location> for GeoLocationBody {
+    fn from(l: Location) -> Self {
+        Self { country: l.country, continent: l.continent, subdivisions: l.subdivisions, city: l.city, city_id: l.city_id, asn: l.asn, prefix_len: l.prefix_len }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum GeoRuleOutcome {
+    Matched,
+    NotMatched,
+    /// A rule evaluated before matched
+    NotEvaluated,
+}
+
+/// A rule considered for a client
+#[derive(Serialize, ToSchema)]
+pub struct GeoRuleCheck {
+    rule_id: String,
+    priority: u32,
+    match_type: String,
+    match_value: String,
+    outcome: GeoRuleOutcome,
+    /// Why the rule matched or not
+    reason: String,
+}
+
+/// Where the client was located and how each rule fared.
+#[derive(Serialize, ToSchema)]
+pub struct GeoExplanationBody {
+    /// Address the client was located by
+    address: String,
+    /// `client_subnet` or `client_ip`
+    source: String,
+    /// Prefix length of `client_subnet`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source_prefix: Option<u8>,
+    /// `None` without a GeoIP database or if the address is in none
+    location: Option<GeoLocationBody>,
+    /// Every rule of the zone, in the order they are evaluated
+    rules: Vec<GeoRuleCheck>,
+    /// `default` when no rule matched and the zone default applies, `none` when there is no
+    /// default either
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fallback: Option<String>,
+}
+
+impl GeoExplanationBody {
+    fn new(explanation: GeoExplanation, address: IpAddr, subnet: Option<IpNet>) -> Self {
+        let rules = explanation.rules.into_iter().map(|check| GeoRuleCheck {
+            rule_id: check.rule_id,
+            priority: check.priority,
+            match_type: check.match_type.to_string(),
+            match_value: check.match_value,
+            outcome: match check.outcome {
+                RuleOutcome::Matched => GeoRuleOutcome::Matched,
+                RuleOutcome::NotMatched => GeoRuleOutcome::NotMatched,
+                RuleOutcome::NotEvaluated => GeoRuleOutcome::NotEvaluated,
+            },
+            reason: check.reason,
+        }).collect();
+        let fallback = match (&explanation.decision.rule_id, &explanation.decision.action) {
+            (Some(_), _) => None,
+            (None, Some(_)) => Some("default".to_string()),
+            (None, None) => Some("none".to_string()),
+        };
+        Self {
+            address: address.to_string(),
+            source: if subnet.is_some() { "client_subnet" } else { "client_ip" }.to_string(),
+            source_prefix: subnet.map(|s| s.prefix_len()),
+            location: explanation.location.map(GeoLocationBody::from),
+            rules,
+            fallback,
+        }
+    }
+}
+
+/// The decision of the rules of a zone for a client.
+#[derive(Serialize, ToSchema)]
+pub struct GeoResolveResponse {
+    /// Rule that matched, `None` when the default applies or there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rule_id: Option<String>,
+    /// `None` when no rule matches and the zone has no default
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<GeoActionKind>,
+    /// Targets of the answer
+    targets: Vec<GeoTargetBody>,
+    /// Target chosen for the client among `targets`, by weight
+    target: Option<String>,
+    /// Prefix length of the client networks the decision holds for, `None` if it depends on a
+    /// location that is not known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    /// With `explain`, how the decision was made
+    #[serde(skip_serializing_if = "Option::is_none")]
+    explanation: Option<GeoExplanationBody>,
+}
+
+impl GeoResolveResponse {
+    fn new(decision: GeoDecision, client_ip: IpAddr) -> Self {
+        let mut hasher = DefaultHasher::new();
+        client_ip.hash(&mut hasher);
+        let target = decision.action.as_ref().and_then(|action| geodns::pick_weighted(action.targets(), hasher.finish())).map(|t| t.to_string());
+        let (action, targets) = decision.action.as_ref().map(action_body).unzip();
+        let message = match &decision.action {
+            None => Some("no matching geo rule".to_string()),
+            Some(_) => None,
+        };
+        Self { rule_id: decision.rule_id, action, targets: targets.unwrap_or_default(), target, scope: decision.scope, message, explanation: None }
+    }
+}
+
+/// Resolve a DNS response for a zone based on the client's geographic location.
+/// Uses the zone's geo rules and default to decide what to answer; the caller needs read access
+/// to the zone, whose rules the answer and its explanation reveal.
+#[utoipa::path(
+    post, path = "/api/v1/georules/resolve", tag = "georules", request_body = GeoResolveRequest,
+    responses((status = 200, body = GeoResolveResponse), (status = 400), (status = 401), (status = 404)),
+)]
+pub async fn resolve_by_geo(body: web::Json<GeoResolveRequest>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    if let Err(resp) = geo_zone(&data, &req, &body.zone_id, Permission::Read).await {
+        return resp;
+    }
+    let client_ip = match body.client_ip.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => return HttpResponse::BadRequest().body("invalid client IP"),
+    };
+    let subnet = match body.client_subnet.as_deref().map(str::parse::<IpNet>).transpose() {
+        Ok(subnet) => subnet,
+        Err(_) => return HttpResponse::BadRequest().body("invalid client subnet"),
+    };
+    let address = subnet.map(|s| s.network()).unwrap_or(client_ip);
+
+    let geo_db = data.geo.get();
+    let engine = match zone_engine(&data.inner.db, &body.zone_id, geo_db).await {
+        Ok(engine) => engine,
+        Err(e) => { warn!("resolve_by_geo error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    HttpResponse::Ok().json(resolve(&engine, address, subnet, body.explain))
+}
+
+fn resolve(engine: &GeoRuleEngine, address: IpAddr, subnet: Option<IpNet>, explain: bool) -> GeoResolveResponse {
+    if !explain {
+        return GeoResolveResponse::new(engine.evaluate(address), address);
+    }
+    let explanation = engine.explain(address);
+    let mut response = GeoResolveResponse::new(explanation.decision.clone(), address);
+    response.explanation = Some(GeoExplanationBody::new(explanation, address, subnet));
+    response
+}
+
+/// Reload the GeoIP databases, logging and counting the outcome.
+pub fn reload_geo_db(db: &ReloadableGeoDB) -> Result<Vec<GeoDbInfo>, GeoDbError> {
+    let res = db.reload();
+    record_reload(&res);
+    res
+}
+
+fn record_reload(res: &Result<Vec<GeoDbInfo>, GeoDbError>) {
+    match res {
+        Ok(databases) => {
+            let loaded: Vec<_> = databases.iter().map(|db| format!("{} built {}", db.database_type, db.build_epoch)).collect();
+            info!("loaded GeoIP databases: {}", loaded.join(", "));
+            metrics::set_geoip_databases(databases);
+            metrics::GEOIP_RELOADS.with_label_values(&["success"]).inc();
+        }
+        Err(e) => {
+            warn!("GeoIP databases not reloaded: {}", e);
+            metrics::GEOIP_RELOADS.with_label_values(&["failure"]).inc();
+        }
+    }
+}
+
+/// Spawn the task reloading the GeoIP databases when their files change.
+pub fn spawn_geo_db_watcher(db: ReloadableGeoDB, interval: Duration) {
+    if db.paths().is_empty() || interval.is_zero() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(interval);
+        loop {
+            tick.tick().await;
+            let watched = db.clone();
+            match web::block(move || watched.reload_if_changed()).await {
+                Ok(Some(res)) => record_reload(&res),
+                Ok(None) => {}
+                Err(e) => warn!("GeoIP database watcher error: {}", e),
+            }
+        }
+    });
+}
+
+/// A loaded GeoIP database.
+#[derive(Serialize, ToSchema)]
+pub struct GeoDbFile {
+    path: String,
+    /// e.g. `GeoLite2-City`
+    database_type: String,
+    /// When the database was built, in seconds since the Unix epoch
+    build_epoch: u64,
+    build_time: Option<DateTime<Utc>>,
+}
+
+/// The GeoIP databases clients are located with.
+#[derive(Serialize, ToSchema)]
+pub struct GeoDbStatus {
+    databases: Vec<GeoDbFile>,
+    /// When the databases were loaded, `None` if they never were
+    loaded_at: Option<DateTime<Utc>>,
+}
+
+impl GeoDbStatus {
+    fn new(db: &ReloadableGeoDB) -> Self {
+        let databases = db.paths().iter().zip(db.info())
+            .map(|(path, info)| GeoDbFile {
+                path: path.display().to_string(),
+                build_time: Utc.timestamp_opt(info.build_epoch as i64, 0).single(),
+                database_type: info.database_type,
+                build_epoch: info.build_epoch,
+            })
+            .collect();
+        Self { databases, loaded_at: db.loaded_at().map(DateTime::<Utc>::from) }
+    }
+}
+
+/// `GET /api/v1/geo/db`: type and version of the GeoIP databases in use.
+#[utoipa::path(get, path = "/api/v1/geo/db", tag = "georules", responses((status = 200, body = GeoDbStatus), (status = 401)))]
+pub async fn get_geo_db(data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    if let Err(resp) = orgs::caller(&data.inner, &req).await {
+        return resp;
+    }
+    HttpResponse::Ok().json(GeoDbStatus::new(&data.geo))
+}
+
+/// `POST /api/v1/geo/db/reload`: read the GeoIP databases again (admins).
+///
+/// The current databases are kept if one of the files cannot be read, is not a valid database, or
+/// holds another type of database than before.
+#[utoipa::path(
+    post, path = "/api/v1/geo/db/reload", tag = "georules",
+    responses((status = 200, body = GeoDbStatus), (status = 401), (status = 403), (status = 422, description = "The databases are not valid; the current ones are kept")),
+)]
+pub async fn reload_geo_db_files(data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
+    match orgs::caller(&data.inner, &req).await {
+        Ok(caller) if caller.admin => {}
+        Ok(_) => return HttpResponse::Forbidden().body("admin role required"),
+        Err(resp) => return resp,
+    }
+    let db = data.geo.clone();
+    match web::block(move || reload_geo_db(&db)).await {
+        Ok(Ok(_)) => HttpResponse::Ok().json(GeoDbStatus::new(&data.geo)),
+        Ok(Err(e)) => HttpResponse::UnprocessableEntity().body(e.to_string()),
+        Err(e) => { warn!("reload_geo_db_files error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use geodns::testing::{asn, country, MmdbWriter};
+    use geodns::MatchType;
+
+    use super::*;
+
+    fn write_db(path: &PathBuf, database_type: &str, build_epoch: u64) {
+        let mut writer = MmdbWriter::new(database_type, build_epoch);
+        match database_type.contains("ASN") {
+            true => writer.insert("192.0.2.0".parse().unwrap(), 24, &asn(64500, "Example")),
+            false => writer.insert("192.0.2.0".parse().unwrap(), 24, &country("DE", "EU")),
+        };
+        std::fs::write(path, writer.build()).unwrap();
+    }
+
+    #[test]
+    fn test_reload_geo_db() {
+        let path = std::env::temp_dir().join(format!("control-api-geo-{}.mmdb", Uuid::new_v4()));
+        let db = ReloadableGeoDB::new(vec![path.clone()]);
+        assert!(db.reload().is_err());
+        assert!(db.get().is_none());
+
+        write_db(&path, "GeoLite2-Country", 1_700_000_000);
+        assert!(db.reload_if_changed().unwrap().is_ok());
+        assert!(db.reload_if_changed().is_none());
+        let status = GeoDbStatus::new(&db);
+        assert_eq!(status.databases.len(), 1);
+        assert_eq!(status.databases[0].database_type, "GeoLite2-Country");
+        assert_eq!(status.databases[0].build_time.unwrap().to_rfc3339(), "2023-11-14T22:13:20+00:00");
+        assert!(status.loaded_at.is_some());
+
+        // not a database, or another type of database: the current one is kept
+        std::fs::write(&path, b"not a database").unwrap();
+        assert!(matches!(db.reload_if_changed(), Some(Err(GeoDbError::Invalid(..)))));
+        assert!(db.reload_if_changed().is_none());
+        write_db(&path, "GeoLite2-ASN", 1_700_000_001);
+        assert!(matches!(db.reload(), Err(GeoDbError::TypeChanged { .. })));
+        let ip = "192.0.2.1".parse().unwrap();
+        assert_eq!(db.get().unwrap().country(ip).as_deref(), Some("DE"));
+        assert_eq!(db.info()[0].build_epoch, 1_700_000_000);
+
+        write_db(&path, "GeoLite2-Country", 1_700_000_001);
+        assert_eq!(db.reload().unwrap()[0].build_epoch, 1_700_000_001);
+        assert_eq!(db.get().unwrap().info()[0].build_epoch, 1_700_000_001);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut writer = MmdbWriter::new("GeoLite2-Country", 1_700_000_000);
+        writer.insert("198.51.100.0".parse().unwrap(), 24, &country("BR", "SA"));
+        let geo_db = geodns::GeoDB::open_from_bytes(writer.build()).unwrap();
+        let mut engine = GeoRuleEngine::new(Some(geo_db));
+        let answer = |target: &str| GeoAction::Answer(vec![WeightedTarget::new(target.parse().unwrap())]);
+        engine.add_rule(GeoRule::new("us", MatchType::Country, "US", answer("192.0.2.1")).unwrap());
+        engine.add_rule(GeoRule::new("sa", MatchType::Continent, "SA", answer("192.0.2.2")).unwrap());
+        engine.add_rule(GeoRule::new("br", MatchType::Country, "BR", answer("192.0.2.3")).unwrap());
+        engine.add_rule(GeoRule::new("lan", MatchType::Cidr, "10.0.0.0/8", answer("192.0.2.4")).unwrap());
+
+        let subnet = "198.51.100.0/24".parse::<IpNet>().unwrap();
+        let response = resolve(&engine, subnet.network(), Some(subnet), true);
+        assert_eq!(response.rule_id.as_deref(), Some("sa"));
+        assert_eq!(response.target.as_deref(), Some("192.0.2.2"));
+        let explanation = serde_json::to_value(response.explanation.unwrap()).unwrap();
+        assert_eq!(explanation["address"], "198.51.100.0");
+        assert_eq!(explanation["source"], "client_subnet");
+        assert_eq!(explanation["source_prefix"], 24);
+        assert_eq!(explanation["location"]["country"], "BR");
+        assert_eq!(explanation["location"]["continent"], "SA");
+        assert!(explanation.get("fallback").is_none());
+        let rules = explanation["rules"].as_array().unwrap();
+        let outcomes = rules.iter().map(|r| (r["rule_id"].as_str().unwrap(), r["outcome"].as_str().unwrap())).collect::<Vec<_>>();
+        assert_eq!(outcomes, [("lan", "not_matched"), ("us", "not_matched"), ("sa", "matched"), ("br", "not_evaluated")]);
+        assert_eq!(rules[1]["reason"], "country is BR, not US");
+        assert_eq!(rules[3]["reason"], "rule sa matched first");
+
+        let client_ip = "203.0.113.1".parse().unwrap();
+        let response = resolve(&engine, client_ip, None, true);
+        assert!(response.action.is_none());
+        let explanation = serde_json::to_value(response.explanation.unwrap()).unwrap();
+        assert_eq!(explanation["source"], "client_ip");
+        assert!(explanation["location"].is_null());
+        assert_eq!(explanation["fallback"], "none");
+        assert_eq!(explanation["rules"][1]["reason"], "203.0.113.1 is not in the GeoIP database");
+        assert!(resolve(&engine, client_ip, None, false).explanation.is_none());
+    }
+}
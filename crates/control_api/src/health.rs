@@ -0,0 +1,587 @@
+//! Health-checked failover records.
+//!
+//! A record can be given a pool of targets and a probe. The checker task runs the probe against
+//! every target on the configured interval; a target is pulled from the served record set after
+//! `fall` consecutive failures and added back after `rise` consecutive successes. Agents pick up
+//! the change through `GET /api/v1/agents/{id}/config`, which serves [`served_records`].
+
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures_util::future::join_all;
+use hickory_server::proto::op::{Message, Query, ResponseCode};
+use hickory_server::proto::rr::{Name, RecordType};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_postgres::Client as PgClient;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::events::{Event, EventBus};
+use crate::sharing::Permission;
+use crate::{orgs, zone_org, AppState, ZoneRecord};
+
+/// How a target is probed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Probe {
+    /// The target accepts a TCP connection on `port`.
+    Tcp { port: u16 },
+    /// An HTTP `GET` of `path` on the target returns `expected_status`.
+    Http {
+        #[serde(default = "default_http_port")]
+        port: u16,
+        #[serde(default = "default_http_path")]
+        path: String,
+        #[serde(default = "default_http_status")]
+        expected_status: u16,
+        /// `Host` header to send, the target itself if unset.
+        host: Option<String>,
+    },
+    /// The target, a DNS server, answers `query_name` with `NOERROR` and at least one record.
+    Dns {
+        #[serde(default = "default_dns_port")]
+        port: u16,
+        query_name: String,
+        #[serde(default = "default_dns_query_type")]
+        query_type: String,
+    },
+}
+
+fn default_http_port() -> u16 { 80 }
+fn default_http_path() -> String { "/".to_string() }
+fn default_http_status() -> u16 { 200 }
+fn default_dns_port() -> u16 { 53 }
+fn default_dns_query_type() -> String { "A".to_string() }
+
+impl Probe {
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Http { path, .. } if !path.starts_with('/') => Err("http path must start with '/'".to_string()),
+            Self::Dns { query_name, query_type, .. } => {
+                Name::from_str(query_name).map_err(|e| format!("invalid query_name: {}", e))?;
+                RecordType::from_str(query_type).map_err(|e| format!("invalid query_type: {}", e))?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Probe a single target, returning why it is unhealthy on failure.
+    pub async fn run(&self, target: &str, timeout: Duration) -> Result<(), String> {
+        match tokio::time::timeout(timeout, self.run_inner(target)).await {
+            Ok(res) => res,
+            Err(_) => Err(format!("timed out after {}ms", timeout.as_millis())),
+        }
+    }
+
+    async fn run_inner(&self, target: &str) -> Result<(), String> {
+        match self {
+            Self::Tcp { port } => {
+                TcpStream::connect((target, *port)).await.map_err(|e| e.to_string())?;
+                Ok(())
+            }
+            Self::Http { port, path, expected_status, host } => {
+                let mut stream = TcpStream::connect((target, *port)).await.map_err(|e| e.to_string())?;
+                let host = host.as_deref().unwrap_or(target);
+                let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: hickory-control-api\r\nConnection: close\r\n\r\n", path, host);
+                stream.write_all(request.as_bytes()).await.map_err(|e| e.to_string())?;
+                // only the status line is of interest
+                let mut buf = [0u8; 64];
+                let mut len = 0;
+                while len < buf.len() && !buf[..len].contains(&b'\n') {
+                    match stream.read(&mut buf[len..]).await.map_err(|e| e.to_string())? {
+                        0 => break,
+                        n => len += n,
+                    }
+                }
+                let status = parse_status_line(&buf[..len]).ok_or("malformed HTTP response")?;
+                if status == *expected_status {
+                    Ok(())
+                } else {
+                    Err(format!("unexpected status {}", status))
+                }
+            }
+            Self::Dns { port, query_name, query_type } => {
+                let ip = IpAddr::from_str(target).map_err(|_| "dns targets must be IP addresses")?;
+                let name = Name::from_str(query_name).map_err(|e| e.to_string())?;
+                let rtype = RecordType::from_str(query_type).map_err(|e| e.to_string())?;
+                let mut query = Message::query();
+                query.add_query(Query::query(name, rtype));
+                let bytes = query.to_vec().map_err(|e| e.to_string())?;
+
+                let local: SocketAddr = if ip.is_ipv4() { ([0, 0, 0, 0], 0).into() } else { ([0u16; 8], 0).into() };
+                let socket = UdpSocket::bind(local).await.map_err(|e| e.to_string())?;
+                socket.connect((ip, *port)).await.map_err(|e| e.to_string())?;
+                socket.send(&bytes).await.map_err(|e| e.to_string())?;
+                let mut buf = vec![0u8; 4096];
+                loop {
+                    let n = socket.recv(&mut buf).await.map_err(|e| e.to_string())?;
+                    let response = match Message::from_vec(&buf[..n]) {
+                        Ok(m) if m.id() == query.id() => m,
+                        // ignore stray datagrams
+                        _ => continue,
+                    };
+                    return match response.response_code() {
+                        ResponseCode::NoError if !response.answers().is_empty() => Ok(()),
+                        ResponseCode::NoError => Err("empty answer".to_string()),
+                        code => Err(format!("response code {}", code)),
+                    };
+                }
+            }
+        }
+    }
+}
+
+fn parse_status_line(buf: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(buf).ok()?.lines().next()?;
+    let mut parts = line.split_whitespace();
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    parts.next()?.parse().ok()
+}
+
+/// Consecutive probe results for one target.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TargetState {
+    pub healthy: bool,
+    pub successes: i32,
+    pub failures: i32,
+}
+
+impl TargetState {
+    /// Account for a probe result; returns true if the target changed health.
+    pub fn record(&mut self, success: bool, rise: i32, fall: i32) -> bool {
+        if success {
+            self.successes = self.successes.saturating_add(1);
+            self.failures = 0;
+            if !self.healthy && self.successes >= rise {
+                self.healthy = true;
+                return true;
+            }
+        } else {
+            self.failures = self.failures.saturating_add(1);
+            self.successes = 0;
+            if self.healthy && self.failures >= fall {
+                self.healthy = false;
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// The records of a zone as they should be served.
+///
+/// Records with a health-checked pool are expanded to one record per healthy target. If every
+/// target of a pool is down, all of them are served rather than none.
+pub async fn served_records(db: &PgClient, zone_id: &str) -> Vec<ZoneRecord> {
+    let rows = db.query(
+        "SELECT r.name, r.type, COALESCE(t.value, r.value), r.ttl, r.weight FROM records r
+         LEFT JOIN health_checks c ON c.record_id = r.id
+         LEFT JOIN health_targets t ON t.record_id = c.record_id
+             AND (t.healthy OR NOT EXISTS (SELECT 1 FROM health_targets h WHERE h.record_id = c.record_id AND h.healthy))
+         WHERE r.zone_id::text = $1 ORDER BY r.name, r.type",
+        &[&zone_id],
+    ).await.unwrap_or_default();
+    rows.into_iter().map(|r| ZoneRecord {
+        name: r.get(0),
+        record_type: r.get(1),
+        value: r.get(2),
+        ttl: r.get::<usize, i32>(3) as u32,
+        weight: r.get::<usize, i32>(4) as u32,
+    }).collect()
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct HealthCheckReq {
+    targets: Vec<String>,
+    probe: Probe,
+    #[serde(default = "default_interval")]
+    interval_secs: i32,
+    #[serde(default = "default_timeout")]
+    timeout_ms: i32,
+    #[serde(default = "default_threshold")]
+    rise: i32,
+    #[serde(default = "default_threshold")]
+    fall: i32,
+}
+
+fn default_interval() -> i32 { 30 }
+fn default_timeout() -> i32 { 2000 }
+fn default_threshold() -> i32 { 3 }
+
+/// Check that a pool target is valid rdata for the record type.
+fn validate_target(record_type: &str, target: &str) -> Result<(), String> {
+    let ok = match record_type.to_ascii_uppercase().as_str() {
+        "A" => target.parse::<std::net::Ipv4Addr>().is_ok(),
+        "AAAA" => target.parse::<std::net::Ipv6Addr>().is_ok(),
+        "CNAME" => Name::from_str(target).is_ok(),
+        other => return Err(format!("health checks are not supported for {} records", other)),
+    };
+    if ok { Ok(()) } else { Err(format!("invalid {} target: {}", record_type, target)) }
+}
+
+/// `PUT /api/v1/zones/{zone_id}/records/{record_id}/health`: attach or replace a target pool.
+#[utoipa::path(
+    put, path = "/api/v1/zones/{zone_id}/records/{record_id}/health", tag = "health-checks", params(("zone_id" = String, Path, description = "Zone id"), ("record_id" = String, Path, description = "Record id")),
+    request_body = HealthCheckReq, responses((status = 200), (status = 400), (status = 401), (status = 404)),
+)]
+pub async fn put_health_check(path: web::Path<(String, String)>, body: web::Json<HealthCheckReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let (zone_id, record_id) = path.into_inner();
+    match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(zone)) => if let Err(resp) = zone.require(Permission::Write) { return resp; },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("put_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    let record_type: String = match data.db.query_opt("SELECT type FROM records WHERE id::text = $1 AND zone_id::text = $2", &[&record_id, &zone_id]).await {
+        Ok(Some(row)) => row.get(0),
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("put_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+
+    if body.targets.is_empty() {
+        return HttpResponse::BadRequest().body("at least one target is required");
+    }
+    if let Some(err) = body.targets.iter().find_map(|t| validate_target(&record_type, t).err()) {
+        return HttpResponse::BadRequest().body(err);
+    }
+    if let Err(e) = body.probe.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    if body.interval_secs < 1 || body.timeout_ms < 1 || body.rise < 1 || body.fall < 1 {
+        return HttpResponse::BadRequest().body("interval_secs, timeout_ms, rise and fall must be positive");
+    }
+
+    let probe = match serde_json::to_value(&body.probe) {
+        Ok(p) => p,
+        Err(e) => { warn!("put_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let res = data.db.execute(
+        "INSERT INTO health_checks (record_id, probe, interval_secs, timeout_ms, rise, fall) VALUES ($1::text::uuid, $2, $3, $4, $5, $6)
+         ON CONFLICT (record_id) DO UPDATE SET probe = $2, interval_secs = $3, timeout_ms = $4, rise = $5, fall = $6, next_run = now()",
+        &[&record_id, &probe, &body.interval_secs, &body.timeout_ms, &body.rise, &body.fall],
+    ).await;
+    if let Err(e) = res {
+        warn!("put_health_check error: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    // keep the state of targets which stay in the pool; new targets start out healthy
+    let res = data.db.execute("DELETE FROM health_targets WHERE record_id::text = $1 AND NOT (value = ANY($2))", &[&record_id, &body.targets]).await;
+    if let Err(e) = res {
+        warn!("put_health_check error: {}", e);
+        return HttpResponse::InternalServerError().finish();
+    }
+    for target in &body.targets {
+        let res = data.db.execute(
+            "INSERT INTO health_targets (id, record_id, value) VALUES ($1::text::uuid, $2::text::uuid, $3) ON CONFLICT (record_id, value) DO NOTHING",
+            &[&Uuid::new_v4().to_string(), &record_id, target],
+        ).await;
+        if let Err(e) = res {
+            warn!("put_health_check error: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let org = zone_org(&data.db, &zone_id).await;
+    data.events.publish(Event::RecordsChanged { zone_id, record_id, change: crate::events::RecordChange::Updated }, org).await;
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TargetStatus {
+    value: String,
+    healthy: bool,
+    successes: i32,
+    failures: i32,
+    last_checked: Option<chrono::DateTime<chrono::Utc>>,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct HealthCheckResponse {
+    record_id: String,
+    probe: Probe,
+    interval_secs: i32,
+    timeout_ms: i32,
+    rise: i32,
+    fall: i32,
+    targets: Vec<TargetStatus>,
+}
+
+/// `GET /api/v1/zones/{zone_id}/records/{record_id}/health`: the probe and per-target state.
+#[utoipa::path(
+    get, path = "/api/v1/zones/{zone_id}/records/{record_id}/health", tag = "health-checks", params(("zone_id" = String, Path, description = "Zone id"), ("record_id" = String, Path, description = "Record id")),
+    responses((status = 200, body = HealthCheckResponse), (status = 401), (status = 404)),
+)]
+pub async fn get_health_check(path: web::Path<(String, String)>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let (zone_id, record_id) = path.into_inner();
+    match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("get_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    let check = data.db.query_opt(
+        "SELECT c.probe, c.interval_secs, c.timeout_ms, c.rise, c.fall FROM health_checks c JOIN records r ON r.id = c.record_id
+         WHERE c.record_id::text = $1 AND r.zone_id::text = $2",
+        &[&record_id, &zone_id],
+    ).await;
+    let check = match check {
+        Ok(Some(row)) => row,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("get_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let rows = data.db.query(
+        "SELECT value, healthy, successes, failures, last_checked, last_error FROM health_targets WHERE record_id::text = $1 ORDER BY value",
+        &[&record_id],
+    ).await.unwrap_or_default();
+    let targets: Vec<TargetStatus> = rows.into_iter().map(|r| TargetStatus {
+        value: r.get(0),
+        healthy: r.get(1),
+        successes: r.get(2),
+        failures: r.get(3),
+        last_checked: r.get(4),
+        last_error: r.get(5),
+    }).collect();
+    let probe = match serde_json::from_value(check.get(0)) {
+        Ok(p) => p,
+        Err(e) => { warn!("get_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    HttpResponse::Ok().json(HealthCheckResponse {
+        record_id,
+        probe,
+        interval_secs: check.get(1),
+        timeout_ms: check.get(2),
+        rise: check.get(3),
+        fall: check.get(4),
+        targets,
+    })
+}
+
+/// `DELETE /api/v1/zones/{zone_id}/records/{record_id}/health`: serve the record's own value again.
+#[utoipa::path(
+    delete, path = "/api/v1/zones/{zone_id}/records/{record_id}/health", tag = "health-checks", params(("zone_id" = String, Path, description = "Zone id"), ("record_id" = String, Path, description = "Record id")),
+    responses((status = 200), (status = 401), (status = 404)),
+)]
+pub async fn delete_health_check(path: web::Path<(String, String)>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let (zone_id, record_id) = path.into_inner();
+    match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(zone)) => if let Err(resp) = zone.require(Permission::Write) { return resp; },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("delete_health_check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    let res = data.db.execute(
+        "DELETE FROM health_checks c USING records r WHERE r.id = c.record_id AND c.record_id::text = $1 AND r.zone_id::text = $2",
+        &[&record_id, &zone_id],
+    ).await;
+    match res {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => {
+            let org = zone_org(&data.db, &zone_id).await;
+            data.events.publish(Event::RecordsChanged { zone_id, record_id, change: crate::events::RecordChange::Updated }, org).await;
+            HttpResponse::Ok().finish()
+        }
+        Err(e) => { warn!("delete_health_check error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// Spawn the task running due health checks.
+pub fn spawn_health_checker(db: std::sync::Arc<PgClient>, events: EventBus) {
+    tokio::spawn(async move {
+        let mut tick = tokio::time::interval(Duration::from_secs(1));
+        loop {
+            tick.tick().await;
+            // claim due checks by pushing their next run out
+            let due = db.query(
+                "UPDATE health_checks SET next_run = now() + make_interval(secs => interval_secs) WHERE next_run <= now()
+                 RETURNING record_id::text, probe, timeout_ms, rise, fall",
+                &[],
+            ).await;
+            let due = match due {
+                Ok(rows) => rows,
+                Err(e) => { warn!("health checker query failed: {}", e); continue; }
+            };
+            for row in due {
+                let record_id: String = row.get(0);
+                let probe: Probe = match serde_json::from_value(row.get(1)) {
+                    Ok(p) => p,
+                    Err(e) => { warn!("invalid probe for record {}: {}", record_id, e); continue; }
+                };
+                let timeout = Duration::from_millis(row.get::<usize, i32>(2) as u64);
+                let (rise, fall): (i32, i32) = (row.get(3), row.get(4));
+                tokio::spawn(run_check(db.clone(), events.clone(), record_id, probe, timeout, rise, fall));
+            }
+        }
+    });
+}
+
+async fn run_check(db: std::sync::Arc<PgClient>, events: EventBus, record_id: String, probe: Probe, timeout: Duration, rise: i32, fall: i32) {
+    let rows = db.query(
+        "SELECT t.value, t.healthy, t.successes, t.failures, r.zone_id::text FROM health_targets t JOIN records r ON r.id = t.record_id WHERE t.record_id::text = $1",
+        &[&record_id],
+    ).await.unwrap_or_default();
+    let results = join_all(rows.iter().map(|r| {
+        let value: String = r.get(0);
+        let probe = &probe;
+        async move { probe.run(&value, timeout).await }
+    })).await;
+
+    for (row, result) in rows.iter().zip(results) {
+        let value: String = row.get(0);
+        let zone_id: String = row.get(4);
+        let mut state = TargetState { healthy: row.get(1), successes: row.get(2), failures: row.get(3) };
+        let changed = state.record(result.is_ok(), rise, fall);
+        let error = result.err();
+        let res = db.execute(
+            "UPDATE health_targets SET healthy = $1, successes = $2, failures = $3, last_checked = now(), last_error = $4 WHERE record_id::text = $5 AND value = $6",
+            &[&state.healthy, &state.successes, &state.failures, &error, &record_id, &value],
+        ).await;
+        if let Err(e) = res {
+            warn!("failed to store health of {} for record {}: {}", value, record_id, e);
+            continue;
+        }
+        if changed {
+            info!("target {} of record {} is now {}", value, record_id, if state.healthy { "healthy" } else { "unhealthy" });
+            let org = zone_org(&db, &zone_id).await;
+            events.publish(Event::TargetHealthChanged { zone_id, record_id: record_id.clone(), target: value, healthy: state.healthy }, org).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn test_rise_and_fall() {
+        let mut state = TargetState { healthy: true, successes: 0, failures: 0 };
+        assert!(!state.record(false, 2, 3));
+        assert!(!state.record(false, 2, 3));
+        assert!(state.record(false, 2, 3));
+        assert!(!state.healthy);
+        assert!(!state.record(false, 2, 3));
+
+        assert!(!state.record(true, 2, 3));
+        // a failure resets the count towards rise
+        assert!(!state.record(false, 2, 3));
+        assert!(!state.record(true, 2, 3));
+        assert!(state.record(true, 2, 3));
+        assert!(state.healthy);
+    }
+
+    #[test]
+    fn test_validate_target() {
+        assert!(validate_target("A", "192.0.2.1").is_ok());
+        assert!(validate_target("a", "2001:db8::1").is_err());
+        assert!(validate_target("AAAA", "2001:db8::1").is_ok());
+        assert!(validate_target("CNAME", "pop1.example.com.").is_ok());
+        assert!(validate_target("MX", "10 mail.example.com.").is_err());
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(parse_status_line(b"HTTP/1.1 503 Service Unavailable\r\n"), Some(503));
+        assert_eq!(parse_status_line(b"HTTP/1.0 200 OK"), Some(200));
+        assert_eq!(parse_status_line(b"SSH-2.0-OpenSSH\r\n"), None);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let probe = Probe::Tcp { port };
+        assert!(probe.run("127.0.0.1", TIMEOUT).await.is_ok());
+
+        drop(listener);
+        assert!(probe.run("127.0.0.1", TIMEOUT).await.is_err());
+    }
+
+    async fn http_stand_in(status: &'static str) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_http_probe() {
+        let port = http_stand_in("200 OK").await;
+        let probe = Probe::Http { port, path: "/healthz".to_string(), expected_status: 200, host: None };
+        assert!(probe.run("127.0.0.1", TIMEOUT).await.is_ok());
+
+        let port = http_stand_in("503 Service Unavailable").await;
+        let probe = Probe::Http { port, path: "/healthz".to_string(), expected_status: 200, host: None };
+        assert_eq!(probe.run("127.0.0.1", TIMEOUT).await, Err("unexpected status 503".to_string()));
+    }
+
+    async fn dns_stand_in(answer: bool) -> u16 {
+        use hickory_server::proto::rr::{rdata::A, RData, Record};
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, src)) = socket.recv_from(&mut buf).await {
+                let query = Message::from_vec(&buf[..n]).unwrap();
+                let mut response = Message::response(query.id(), query.op_code());
+                response.add_query(query.queries()[0].clone());
+                if answer {
+                    let name = query.queries()[0].name().clone();
+                    response.add_answer(Record::from_rdata(name, 60, RData::A(A::new(192, 0, 2, 1))));
+                } else {
+                    response.set_response_code(ResponseCode::NXDomain);
+                }
+                socket.send_to(&response.to_vec().unwrap(), src).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[tokio::test]
+    async fn test_dns_probe() {
+        let port = dns_stand_in(true).await;
+        let probe = Probe::Dns { port, query_name: "www.example.com.".to_string(), query_type: "A".to_string() };
+        assert!(probe.run("127.0.0.1", TIMEOUT).await.is_ok());
+
+        let port = dns_stand_in(false).await;
+        let probe = Probe::Dns { port, query_name: "www.example.com.".to_string(), query_type: "A".to_string() };
+        assert!(probe.run("127.0.0.1", TIMEOUT).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_probe_timeout() {
+        // a bound socket which never answers
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let port = socket.local_addr().unwrap().port();
+        let probe = Probe::Dns { port, query_name: "example.com.".to_string(), query_type: "SOA".to_string() };
+        let res = probe.run("127.0.0.1", Duration::from_millis(100)).await;
+        assert_eq!(res, Err("timed out after 100ms".to_string()));
+    }
+}
@@ -0,0 +1,732 @@
+//! Import of the records of a zone from the export of another DNS provider.
+//!
+//! A [`Format`] adapter reads an export into [`SourceRecord`]s, names and values as the provider
+//! wrote them. They are then normalized against the origin of the zone: owner names become
+//! relative (`@` for the apex), host names in values absolute and TXT strings quoted. Every value
+//! is parsed with hickory-proto, so that only records the name servers can load are imported.
+//! Provider specific types, such as alias records or web redirects, are reported along with the
+//! standard record which can replace them, if there is one.
+
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use futures_util::StreamExt;
+use hickory_server::proto::rr::{Name, RData, RecordType};
+use hickory_server::proto::serialize::txt::RDataParser;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::events::{Event, RecordChange};
+use crate::orgs::{self, Quota};
+use crate::sharing::Permission;
+use crate::{default_weight, ptr, AppState, ZoneRecord};
+
+/// Largest export accepted.
+const MAX_IMPORT_BYTES: usize = 16 << 20;
+
+/// TTL of the records an export gives none for, or `auto`.
+const DEFAULT_TTL: u32 = 3600;
+
+/// A record as read from an export.
+#[derive(Debug, PartialEq)]
+pub struct SourceRecord {
+    /// 1-based line of a CSV export, entry of a JSON one.
+    pub position: usize,
+    pub name: String,
+    pub record_type: String,
+    pub ttl: Option<u32>,
+    pub value: String,
+    pub weight: Option<u32>,
+}
+
+/// Reader of the exports of one provider.
+pub trait Format: Sync {
+    /// Name of the format in import requests.
+    fn name(&self) -> &'static str;
+
+    /// Whether `input` looks like an export in this format.
+    fn detect(&self, input: &str) -> bool;
+
+    /// Whether host names in values are absolute even without a trailing dot, as most web consoles
+    /// show them, rather than relative to the origin as in zone files.
+    fn absolute_hosts(&self) -> bool {
+        false
+    }
+
+    fn parse(&self, input: &str) -> Result<Vec<SourceRecord>, String>;
+}
+
+/// Known formats, in the order they are tried when the format of an export is not given.
+pub static FORMATS: &[&dyn Format] = &[&Route53, &Cloudflare, &GoogleCloud, &Csv];
+
+fn format(name: &str) -> Option<&'static dyn Format> {
+    FORMATS.iter().copied().find(|f| f.name() == name)
+}
+
+fn detect(input: &str) -> Option<&'static dyn Format> {
+    FORMATS.iter().copied().find(|f| f.detect(input))
+}
+
+/// The entries of a JSON export: the array under `key`, or the document itself if it is an array.
+fn json_entries(input: &str, key: &str) -> Option<Vec<Value>> {
+    match serde_json::from_str(input).ok()? {
+        Value::Array(entries) => Some(entries),
+        Value::Object(mut doc) => match doc.remove(key)? {
+            Value::Array(entries) => Some(entries),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn first_has(entries: Option<Vec<Value>>, fields: &[&str]) -> bool {
+    entries.and_then(|e| e.into_iter().next()).is_some_and(|e| fields.iter().all(|f| e.get(f).is_some()))
+}
+
+fn str_field(entry: &Value, field: &str, position: usize) -> Result<String, String> {
+    entry.get(field).and_then(Value::as_str).map(str::to_string).ok_or_else(|| format!("entry {}: missing {}", position, field))
+}
+
+fn u32_field(entry: &Value, field: &str, position: usize) -> Result<Option<u32>, String> {
+    match entry.get(field) {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v.as_u64().and_then(|v| u32::try_from(v).ok()).map(Some).ok_or_else(|| format!("entry {}: invalid {}", position, field)),
+    }
+}
+
+/// Prefix the priority the provider keeps apart to MX and SRV values which lack it.
+fn with_priority(record_type: &str, priority: Option<u64>, value: &str) -> String {
+    let fields = match record_type.to_ascii_uppercase().as_str() {
+        "MX" => 2,
+        "SRV" => 4,
+        _ => return value.to_string(),
+    };
+    match priority {
+        Some(p) if value.split_whitespace().count() < fields => format!("{} {}", p, value),
+        _ => value.to_string(),
+    }
+}
+
+/// `aws route53 list-resource-record-sets`.
+pub struct Route53;
+
+impl Route53 {
+    /// Route 53 escapes characters other than letters, digits, `-` and `_` in names as `\ooo`,
+    /// e.g. `\052` for the `*` of wildcards.
+    fn unescape(name: &str) -> String {
+        let bytes = name.as_bytes();
+        let mut out = String::with_capacity(name.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let octal = bytes.get(i + 1..i + 4).and_then(|d| std::str::from_utf8(d).ok()).and_then(|d| u8::from_str_radix(d, 8).ok());
+            match (bytes[i], octal) {
+                (b'\\', Some(c)) => {
+                    out.push(c as char);
+                    i += 4;
+                }
+                (c, _) => {
+                    out.push(c as char);
+                    i += 1;
+                }
+            }
+        }
+        out
+    }
+}
+
+impl Format for Route53 {
+    fn name(&self) -> &'static str {
+        "route53"
+    }
+
+    fn detect(&self, input: &str) -> bool {
+        first_has(json_entries(input, "ResourceRecordSets"), &["Name", "Type"])
+    }
+
+    fn parse(&self, input: &str) -> Result<Vec<SourceRecord>, String> {
+        let sets = json_entries(input, "ResourceRecordSets").ok_or("expected ResourceRecordSets")?;
+        let mut records = Vec::new();
+        for (i, set) in sets.iter().enumerate() {
+            let position = i + 1;
+            let name = Self::unescape(&str_field(set, "Name", position)?);
+            let record_type = str_field(set, "Type", position)?;
+            let ttl = u32_field(set, "TTL", position)?;
+            let weight = u32_field(set, "Weight", position)?;
+            if let Some(alias) = set.get("AliasTarget") {
+                let target = str_field(alias, "DNSName", position)?;
+                records.push(SourceRecord { position, name, record_type: "ALIAS".to_string(), ttl, value: target, weight });
+                continue;
+            }
+            for rr in set.get("ResourceRecords").and_then(Value::as_array).into_iter().flatten() {
+                let value = str_field(rr, "Value", position)?;
+                records.push(SourceRecord { position, name: name.clone(), record_type: record_type.clone(), ttl, value, weight });
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// The `dns_records` list of the Cloudflare API.
+pub struct Cloudflare;
+
+impl Format for Cloudflare {
+    fn name(&self) -> &'static str {
+        "cloudflare"
+    }
+
+    fn detect(&self, input: &str) -> bool {
+        first_has(json_entries(input, "result"), &["name", "type", "content"])
+    }
+
+    fn absolute_hosts(&self) -> bool {
+        true
+    }
+
+    fn parse(&self, input: &str) -> Result<Vec<SourceRecord>, String> {
+        let entries = json_entries(input, "result").ok_or("expected a result array")?;
+        entries.iter().enumerate().map(|(i, entry)| {
+            let position = i + 1;
+            let record_type = str_field(entry, "type", position)?;
+            let content = str_field(entry, "content", position)?;
+            let priority = entry.get("priority").and_then(Value::as_u64);
+            Ok(SourceRecord {
+                position,
+                name: str_field(entry, "name", position)?,
+                value: with_priority(&record_type, priority, &content),
+                record_type,
+                // 1 is "automatic"
+                ttl: u32_field(entry, "ttl", position)?.filter(|&ttl| ttl != 1),
+                weight: None,
+            })
+        }).collect()
+    }
+}
+
+/// `gcloud dns record-sets list --format=json`.
+pub struct GoogleCloud;
+
+impl Format for GoogleCloud {
+    fn name(&self) -> &'static str {
+        "gcloud"
+    }
+
+    fn detect(&self, input: &str) -> bool {
+        first_has(json_entries(input, "rrsets"), &["name", "type", "rrdatas"])
+    }
+
+    fn parse(&self, input: &str) -> Result<Vec<SourceRecord>, String> {
+        let sets = json_entries(input, "rrsets").ok_or("expected an array of record sets")?;
+        let mut records = Vec::new();
+        for (i, set) in sets.iter().enumerate() {
+            let position = i + 1;
+            let name = str_field(set, "name", position)?;
+            let record_type = str_field(set, "type", position)?;
+            let ttl = u32_field(set, "ttl", position)?;
+            for value in set.get("rrdatas").and_then(Value::as_array).into_iter().flatten() {
+                let value = value.as_str().ok_or_else(|| format!("entry {}: invalid rrdatas", position))?;
+                records.push(SourceRecord { position, name: name.clone(), record_type: record_type.clone(), ttl, value: value.to_string(), weight: None });
+            }
+        }
+        Ok(records)
+    }
+}
+
+/// CSV with a header row, as exported by most web consoles. Columns are recognized by name.
+pub struct Csv;
+
+impl Csv {
+    const NAME: &'static [&'static str] = &["name", "host", "hostname", "record", "record name"];
+    const TYPE: &'static [&'static str] = &["type", "record type", "rrtype"];
+    const TTL: &'static [&'static str] = &["ttl"];
+    const VALUE: &'static [&'static str] = &["value", "content", "data", "rdata", "answer", "points to", "target", "address"];
+    const PRIORITY: &'static [&'static str] = &["priority", "prio", "preference", "pref"];
+
+    fn column(header: &[String], aliases: &[&str]) -> Option<usize> {
+        header.iter().position(|h| aliases.contains(&h.trim().to_ascii_lowercase().as_str()))
+    }
+
+    /// Rows of an RFC 4180 document, along with the line each starts on. Blank lines are skipped.
+    fn rows(input: &str) -> Result<Vec<(usize, Vec<String>)>, String> {
+        let mut rows = Vec::new();
+        let (mut row, mut field) = (Vec::new(), String::new());
+        let (mut line, mut start) = (1, 1);
+        let mut quoted = false;
+        let mut chars = input.trim_start_matches('\u{feff}').chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                ('"', true) => quoted = false,
+                ('"', false) if field.is_empty() => quoted = true,
+                (',', false) => row.push(std::mem::take(&mut field)),
+                ('\r', false) if chars.peek() == Some(&'\n') => {}
+                ('\n', false) => {
+                    row.push(std::mem::take(&mut field));
+                    if row.iter().any(|f| !f.trim().is_empty()) {
+                        rows.push((start, std::mem::take(&mut row)));
+                    }
+                    row.clear();
+                    line += 1;
+                    start = line;
+                }
+                (c, _) => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+        }
+        if quoted {
+            return Err(format!("line {}: unterminated quoted field", start));
+        }
+        row.push(field);
+        if row.iter().any(|f| !f.trim().is_empty()) {
+            rows.push((start, row));
+        }
+        Ok(rows)
+    }
+}
+
+impl Format for Csv {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn detect(&self, input: &str) -> bool {
+        match Self::rows(input).ok().and_then(|rows| rows.into_iter().next()) {
+            Some((_, header)) => [Self::NAME, Self::TYPE, Self::VALUE].iter().all(|aliases| Self::column(&header, aliases).is_some()),
+            None => false,
+        }
+    }
+
+    fn absolute_hosts(&self) -> bool {
+        true
+    }
+
+    fn parse(&self, input: &str) -> Result<Vec<SourceRecord>, String> {
+        let mut rows = Self::rows(input)?.into_iter();
+        let (_, header) = rows.next().ok_or("empty export")?;
+        let required = |aliases: &[&str]| Self::column(&header, aliases).ok_or_else(|| format!("no {} column", aliases[0]));
+        let (name, record_type, value) = (required(Self::NAME)?, required(Self::TYPE)?, required(Self::VALUE)?);
+        let (ttl, priority) = (Self::column(&header, Self::TTL), Self::column(&header, Self::PRIORITY));
+        rows.map(|(line, row)| {
+            let field = |i: Option<usize>| i.and_then(|i| row.get(i)).map(|f| f.trim()).filter(|f| !f.is_empty());
+            let number = |i: Option<usize>, what: &str| match field(i) {
+                None => Ok(None),
+                Some(f) if f.eq_ignore_ascii_case("auto") => Ok(None),
+                Some(f) => f.parse().map(Some).map_err(|_| format!("line {}: invalid {} {:?}", line, what, f)),
+            };
+            let record_type = field(Some(record_type)).unwrap_or_default().to_string();
+            let ttl: Option<u32> = number(ttl, "TTL")?;
+            let priority = number(priority, "priority")?.map(u64::from);
+            Ok(SourceRecord {
+                position: line,
+                name: field(Some(name)).unwrap_or("@").to_string(),
+                value: with_priority(&record_type, priority, field(Some(value)).unwrap_or_default()),
+                record_type,
+                ttl,
+                weight: None,
+            })
+        }).collect()
+    }
+}
+
+/// What is wrong with a record of an export.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// The record cannot be parsed, or is outside the zone.
+    Invalid,
+    /// The type is specific to the previous provider; the replacement, if any, is a standard one.
+    Unsupported,
+    /// The record is left out on purpose, e.g. the SOA or an existing record.
+    Skipped,
+}
+
+/// A record of an export which is not imported.
+#[derive(Serialize, ToSchema)]
+pub struct ImportIssue {
+    /// 1-based line of a CSV export, entry of a JSON one
+    position: usize,
+    name: String,
+    record_type: String,
+    value: String,
+    kind: IssueKind,
+    message: String,
+    /// Standard record which can replace an unsupported one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replacement: Option<ZoneRecord>,
+}
+
+/// Records of an export, normalized, and those which cannot be imported.
+#[derive(Default)]
+pub struct Normalized {
+    /// Records which can be imported, with their position in the export
+    records: Vec<(usize, ZoneRecord)>,
+    issues: Vec<ImportIssue>,
+}
+
+/// Normalize `source` against the zone `origin`, e.g. `example.com`.
+pub fn normalize(source: Vec<SourceRecord>, origin: &str, format: &dyn Format, default_ttl: u32) -> Normalized {
+    let origin = origin.trim_end_matches('.').to_ascii_lowercase();
+    let mut out = Normalized::default();
+    for r in source {
+        let issue = |kind, message: String, replacement| ImportIssue {
+            position: r.position, name: r.name.clone(), record_type: r.record_type.clone(), value: r.value.clone(), kind, message, replacement,
+        };
+        match normalize_record(&r, &origin, format.absolute_hosts(), default_ttl) {
+            Ok(record) => out.records.push((r.position, record)),
+            Err((kind, message, replacement)) => out.issues.push(issue(kind, message, replacement)),
+        }
+    }
+    out
+}
+
+type Rejection = (IssueKind, String, Option<ZoneRecord>);
+
+fn normalize_record(r: &SourceRecord, origin: &str, absolute_hosts: bool, default_ttl: u32) -> Result<ZoneRecord, Rejection> {
+    let invalid = |message: String| (IssueKind::Invalid, message, None);
+    let name = relative_name(&r.name, origin).map_err(invalid)?;
+    let ttl = r.ttl.unwrap_or(default_ttl);
+    let weight = r.weight.unwrap_or_else(default_weight);
+    if ttl > i32::MAX as u32 || weight > i32::MAX as u32 {
+        return Err(invalid("ttl or weight out of range".to_string()));
+    }
+    let record_type = r.record_type.trim().to_ascii_uppercase();
+    let record = |record_type: &str| -> Result<ZoneRecord, String> {
+        let parsed = RecordType::from_str(record_type).map_err(|_| format!("unknown record type {}", record_type))?;
+        let value = normalize_value(parsed, r.value.trim(), origin, absolute_hosts);
+        RData::try_from_str(parsed, &value).map_err(|e| format!("invalid {} value: {}", record_type, e))?;
+        Ok(ZoneRecord { name: name.clone(), record_type: record_type.to_string(), value, ttl, weight })
+    };
+    let unsupported = |message: &str, replacement: &str| (IssueKind::Unsupported, message.to_string(), record(replacement).ok());
+
+    match record_type.as_str() {
+        "SOA" => Err((IssueKind::Skipped, "the SOA record is generated by the name servers".to_string(), None)),
+        "RRSIG" | "NSEC" | "NSEC3" | "NSEC3PARAM" | "DNSKEY" | "CDS" | "CDNSKEY" => {
+            Err((IssueKind::Skipped, "DNSSEC records of the previous provider are not imported".to_string(), None))
+        }
+        "ALIAS" if name == "@" => Err(unsupported("alias records are provider specific, ANAME resolves the target at the apex", "ANAME")),
+        "ALIAS" => Err(unsupported("alias records are provider specific", "CNAME")),
+        "CNAME" if name == "@" => Err(unsupported("CNAME is not allowed at the apex (RFC 1034 section 3.6.2)", "ANAME")),
+        "SPF" => Err(unsupported("the SPF type is obsolete (RFC 7208 section 3.1), SPF policies are TXT records", "TXT")),
+        "URL" | "URL301" | "URL302" | "REDIRECT" | "FWD" | "FRAME" => Err((
+            IssueKind::Unsupported,
+            "web redirects of the provider have no DNS equivalent, point the name at an HTTP redirect service instead".to_string(),
+            None,
+        )),
+        _ => record(&record_type).map_err(invalid),
+    }
+}
+
+/// The name relative to `origin`, `@` for the apex. Absolute names must be in the zone; others
+/// are relative to the origin already.
+fn relative_name(name: &str, origin: &str) -> Result<String, String> {
+    let name = name.trim().to_ascii_lowercase();
+    let (bare, absolute) = match name.strip_suffix('.') {
+        Some(bare) => (bare, true),
+        None => (name.as_str(), false),
+    };
+    let relative = if bare.is_empty() || bare == "@" || bare == origin {
+        "@"
+    } else if let Some(label) = bare.strip_suffix(origin).and_then(|l| l.strip_suffix('.')) {
+        label
+    } else if absolute {
+        return Err(format!("{} is outside of the zone {}", name, origin));
+    } else {
+        bare
+    };
+    let fqdn = if relative == "@" { format!("{}.", origin) } else { format!("{}.{}.", relative, origin) };
+    Name::from_ascii(&fqdn).map_err(|e| format!("invalid name {}: {}", name, e))?;
+    Ok(relative.to_string())
+}
+
+/// Quote TXT strings given bare and make the host names of values absolute.
+fn normalize_value(record_type: RecordType, value: &str, origin: &str, absolute_hosts: bool) -> String {
+    let host = match record_type {
+        RecordType::TXT if !value.starts_with('"') => return quote_txt(value),
+        RecordType::CNAME | RecordType::ANAME | RecordType::NS | RecordType::PTR => 0,
+        RecordType::MX => 1,
+        RecordType::SRV => 3,
+        _ => return value.to_string(),
+    };
+    let mut fields: Vec<String> = value.split_whitespace().map(str::to_string).collect();
+    if let Some(field) = fields.get_mut(host) {
+        *field = absolute_host(field, origin, absolute_hosts);
+    }
+    fields.join(" ")
+}
+
+fn absolute_host(host: &str, origin: &str, absolute_hosts: bool) -> String {
+    if host == "@" {
+        format!("{}.", origin)
+    } else if host.ends_with('.') || (absolute_hosts && host.contains('.')) {
+        format!("{}.", host.trim_end_matches('.'))
+    } else {
+        format!("{}.{}.", host, origin)
+    }
+}
+
+/// A TXT value of quoted character strings, split at their 255 bytes limit.
+fn quote_txt(text: &str) -> String {
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        if current.len() + c.len_utf8() > 255 {
+            strings.push(std::mem::take(&mut current));
+        }
+        current.push(c);
+    }
+    strings.push(current);
+    strings.iter().map(|s| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))).collect::<Vec<_>>().join(" ")
+}
+
+/// Request of `POST /api/v1/zones/{id}/import`.
+#[derive(Deserialize, ToSchema)]
+pub struct ImportReq {
+    /// The export, as downloaded from the previous provider
+    pub data: String,
+    /// `route53`, `cloudflare`, `gcloud` or `csv`; detected when not given
+    #[serde(default)]
+    pub format: Option<String>,
+    /// TTL of the records the export gives none for
+    #[serde(default)]
+    pub default_ttl: Option<u32>,
+    /// Only report what would be imported
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Import the valid records even if others are invalid or unsupported
+    #[serde(default)]
+    pub skip_invalid: bool,
+}
+
+/// Outcome of an import.
+#[derive(Serialize, ToSchema)]
+pub struct ImportReport {
+    format: String,
+    /// Records created, 0 for a dry run or a refused import
+    imported: usize,
+    /// Normalized records of the export which can be imported
+    records: Vec<ZoneRecord>,
+    issues: Vec<ImportIssue>,
+}
+
+/// Import records from the export of another provider.
+///
+/// Nothing is imported when a record is invalid or unsupported, unless `skip_invalid` is set;
+/// records which are already in the zone are skipped.
+#[utoipa::path(
+    post, path = "/api/v1/zones/{id}/import", tag = "records", params(("id" = String, Path, description = "Zone id")), request_body = ImportReq,
+    responses(
+        (status = 200, body = ImportReport),
+        (status = 400, description = "The export cannot be read, or has invalid or unsupported records, listed in a report body"),
+        (status = 401), (status = 403, description = "Record quota exceeded"), (status = 404), (status = 413),
+    ),
+)]
+pub async fn import_records(zone_id: web::Path<String>, mut payload: web::Payload, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = zone_id.into_inner();
+    let zone = match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(z)) => z,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("import_records error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    if let Err(resp) = zone.require(Permission::Write) {
+        return resp;
+    }
+    let mut body = web::BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        match chunk {
+            Ok(chunk) if body.len() + chunk.len() <= MAX_IMPORT_BYTES => body.extend_from_slice(&chunk),
+            Ok(_) => return HttpResponse::PayloadTooLarge().finish(),
+            Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+        }
+    }
+    let body: ImportReq = match serde_json::from_slice(&body) {
+        Ok(body) => body,
+        Err(e) => return HttpResponse::BadRequest().body(format!("invalid request: {}", e)),
+    };
+    let format = match body.format.as_deref() {
+        Some(name) => match format(name) {
+            Some(f) => f,
+            None => {
+                let known: Vec<_> = FORMATS.iter().map(|f| f.name()).collect();
+                return HttpResponse::BadRequest().body(format!("unknown format {:?}, expected one of {}", name, known.join(", ")));
+            }
+        },
+        None => match detect(&body.data) {
+            Some(f) => f,
+            None => return HttpResponse::BadRequest().body("the format of the export cannot be detected"),
+        },
+    };
+    let source = match format.parse(&body.data) {
+        Ok(source) => source,
+        Err(e) => return HttpResponse::BadRequest().body(format!("invalid {} export: {}", format.name(), e)),
+    };
+    let mut normalized = normalize(source, &zone.domain, format, body.default_ttl.unwrap_or(DEFAULT_TTL));
+
+    // importing an export twice, or one which overlaps the zone, does not duplicate records
+    let rows = match data.db.query("SELECT name, type, value FROM records WHERE zone_id = $1::text::uuid", &[&zone_id]).await {
+        Ok(rows) => rows,
+        Err(e) => { warn!("import_records error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let mut seen: HashSet<(String, String, String)> = rows.iter().map(|r| (r.get(0), r.get::<_, String>(1).to_ascii_uppercase(), r.get(2))).collect();
+    let mut records = Vec::with_capacity(normalized.records.len());
+    for (position, r) in normalized.records {
+        if seen.insert((r.name.clone(), r.record_type.clone(), r.value.clone())) {
+            records.push(r);
+        } else {
+            normalized.issues.push(ImportIssue {
+                position, name: r.name, record_type: r.record_type, value: r.value, kind: IssueKind::Skipped,
+                message: "the record is already in the zone".to_string(), replacement: None,
+            });
+        }
+    }
+    let mut report = ImportReport { format: format.name().to_string(), imported: 0, records, issues: normalized.issues };
+
+    if !body.skip_invalid && report.issues.iter().any(|i| i.kind != IssueKind::Skipped) {
+        return HttpResponse::BadRequest().json(report);
+    }
+    if body.dry_run || report.records.is_empty() {
+        return HttpResponse::Ok().json(report);
+    }
+
+    let ids: Vec<String> = report.records.iter().map(|_| Uuid::new_v4().to_string()).collect();
+    let rows: Vec<Value> = report.records.iter().zip(&ids).map(|(r, id)| serde_json::json!({
+        "id": id, "zone_id": zone_id, "name": r.name, "type": r.record_type, "value": r.value, "ttl": r.ttl, "weight": r.weight,
+    })).collect();
+    let adding = ids.len() as i64;
+    let res = data.db.execute(
+        &format!(
+            "INSERT INTO records (id, zone_id, name, type, value, ttl, weight)
+             SELECT r.id, r.zone_id, r.name, r.type, r.value, r.ttl, r.weight
+             FROM jsonb_to_recordset($1) AS r(id uuid, zone_id uuid, name text, type text, value text, ttl int, weight int)
+             WHERE (SELECT {})",
+            Quota::Records.allows("$2::text::uuid", "$3"),
+        ),
+        &[&Value::from(rows), &zone.org_id, &adding],
+    ).await;
+    match res {
+        Ok(0) => return orgs::quota_refused(&data.db, &[(zone.org_id.as_deref(), Quota::Records, adding)]).await,
+        Ok(_) => {}
+        Err(e) => { warn!("import_records error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    info!("{} records imported into {} from a {} export by {}", ids.len(), zone.domain, format.name(), caller.sub);
+    for record_id in ids {
+        data.events.publish(Event::RecordsChanged { zone_id: zone_id.clone(), record_id, change: RecordChange::Created }, zone.org_id.clone()).await;
+    }
+    ptr::sync_records(&data, report.records.iter().map(|r| (r.record_type.as_str(), r.value.as_str()))).await;
+    report.imported = report.records.len();
+    HttpResponse::Ok().json(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn import(format: &dyn Format, input: &str) -> Normalized {
+        assert!(format.detect(input), "{} not detected", format.name());
+        assert_eq!(detect(input).map(|f| f.name()), Some(format.name()));
+        normalize(format.parse(input).unwrap(), "example.com", format, DEFAULT_TTL)
+    }
+
+    fn values(n: &Normalized) -> Vec<(&str, &str, &str, u32)> {
+        n.records.iter().map(|(_, r)| (r.name.as_str(), r.record_type.as_str(), r.value.as_str(), r.ttl)).collect()
+    }
+
+    #[test]
+    fn test_route53() {
+        let n = import(&Route53, r#"{"ResourceRecordSets": [
+            {"Name": "example.com.", "Type": "SOA", "TTL": 900, "ResourceRecords": [{"Value": "ns-1.awsdns-00.com. awsdns-hostmaster.amazon.com. 1 7200 900 1209600 86400"}]},
+            {"Name": "example.com.", "Type": "A", "AliasTarget": {"HostedZoneId": "Z1", "DNSName": "lb-1.eu-west-1.elb.amazonaws.com.", "EvaluateTargetHealth": false}},
+            {"Name": "\\052.example.com.", "Type": "MX", "TTL": 300, "ResourceRecords": [{"Value": "10 mx1.example.com."}, {"Value": "20 mx2.example.net."}]},
+            {"Name": "www.example.com.", "Type": "A", "SetIdentifier": "blue", "Weight": 3, "TTL": 60, "ResourceRecords": [{"Value": "192.0.2.1"}]},
+            {"Name": "_dmarc.example.com.", "Type": "TXT", "TTL": 300, "ResourceRecords": [{"Value": "\"v=DMARC1; p=none\""}]},
+            {"Name": "other.example.org.", "Type": "A", "TTL": 300, "ResourceRecords": [{"Value": "192.0.2.2"}]}
+        ]}"#);
+        assert_eq!(values(&n), vec![
+            ("*", "MX", "10 mx1.example.com.", 300),
+            ("*", "MX", "20 mx2.example.net.", 300),
+            ("www", "A", "192.0.2.1", 60),
+            ("_dmarc", "TXT", "\"v=DMARC1; p=none\"", 300),
+        ]);
+        assert_eq!(n.records[2].0, 4);
+        assert_eq!(n.records[2].1.weight, 3);
+        let kinds: Vec<_> = n.issues.iter().map(|i| (i.position, i.kind)).collect();
+        assert_eq!(kinds, vec![(1, IssueKind::Skipped), (2, IssueKind::Unsupported), (6, IssueKind::Invalid)]);
+        let replacement = n.issues[1].replacement.as_ref().unwrap();
+        assert_eq!((replacement.name.as_str(), replacement.record_type.as_str(), replacement.value.as_str()), ("@", "ANAME", "lb-1.eu-west-1.elb.amazonaws.com."));
+    }
+
+    #[test]
+    fn test_cloudflare() {
+        let n = import(&Cloudflare, r#"{"success": true, "result": [
+            {"name": "example.com", "type": "MX", "content": "mail.example.com", "priority": 10, "ttl": 1},
+            {"name": "example.com", "type": "TXT", "content": "v=spf1 include:_spf.example.net ~all", "ttl": 300},
+            {"name": "_sip._tcp.example.com", "type": "SRV", "content": "5 5060 sip", "priority": 0, "ttl": 120},
+            {"name": "blog.example.com", "type": "CNAME", "content": "example.github.io", "ttl": 1, "proxied": true},
+            {"name": "example.com", "type": "CNAME", "content": "www.example.com", "ttl": 1},
+            {"name": "bad.example.com", "type": "AAAA", "content": "192.0.2.1", "ttl": 1}
+        ]}"#);
+        assert_eq!(values(&n), vec![
+            ("@", "MX", "10 mail.example.com.", DEFAULT_TTL),
+            ("@", "TXT", "\"v=spf1 include:_spf.example.net ~all\"", 300),
+            ("_sip._tcp", "SRV", "0 5 5060 sip.example.com.", 120),
+            ("blog", "CNAME", "example.github.io.", DEFAULT_TTL),
+        ]);
+        assert_eq!(n.issues.len(), 2);
+        assert_eq!(n.issues[0].kind, IssueKind::Unsupported);
+        assert_eq!(n.issues[0].replacement.as_ref().unwrap().record_type, "ANAME");
+        assert_eq!((n.issues[1].position, n.issues[1].kind), (6, IssueKind::Invalid));
+    }
+
+    #[test]
+    fn test_gcloud() {
+        let n = import(&GoogleCloud, r#"[
+            {"kind": "dns#resourceRecordSet", "name": "example.com.", "type": "CAA", "ttl": 300, "rrdatas": ["0 issue \"letsencrypt.org\""]},
+            {"kind": "dns#resourceRecordSet", "name": "ftp.example.com.", "type": "CNAME", "ttl": 300, "rrdatas": ["www"]}
+        ]"#);
+        assert_eq!(values(&n), vec![
+            ("@", "CAA", "0 issue \"letsencrypt.org\"", 300),
+            ("ftp", "CNAME", "www.example.com.", 300),
+        ]);
+        assert!(n.issues.is_empty());
+    }
+
+    #[test]
+    fn test_csv() {
+        let n = import(&Csv, "\u{feff}Type,Host,Value,Priority,TTL\r\n\
+            A,@,192.0.2.1,,Auto\r\n\
+            MX,@,mail,10,3600\r\n\
+            \r\n\
+            TXT,www,\"say \"\"hi\"\", then\nbye\",,600\r\n\
+            URL,go,https://example.net/,,\r\n\
+            SPF,@,v=spf1 -all,,\r\n");
+        assert_eq!(values(&n), vec![
+            ("@", "A", "192.0.2.1", DEFAULT_TTL),
+            ("@", "MX", "10 mail.example.com.", 3600),
+            ("www", "TXT", "\"say \\\"hi\\\", then\nbye\"", 600),
+        ]);
+        let issues: Vec<_> = n.issues.iter().map(|i| (i.position, i.kind, i.replacement.as_ref().map(|r| r.value.as_str()))).collect();
+        assert_eq!(issues, vec![(7, IssueKind::Unsupported, None), (8, IssueKind::Unsupported, Some("\"v=spf1 -all\""))]);
+        assert!(Csv.parse("name,type\nwww,A\n").is_err());
+        assert!(Csv.parse("name,type,value\nwww,A,\"192.0.2.1\n").is_err());
+    }
+
+    #[test]
+    fn test_quote_txt() {
+        let long = "a".repeat(300);
+        assert_eq!(quote_txt(&long), format!("\"{}\" \"{}\"", "a".repeat(255), "a".repeat(45)));
+        RData::try_from_str(RecordType::TXT, &quote_txt(&long)).unwrap();
+    }
+}
@@ -12,6 +12,25 @@ use jsonwebtoken::{EncodingKey, DecodingKey, Header, Validation, encode, decode,
 use argon2::{Argon2, password_hash::{SaltString, PasswordHasher, PasswordVerifier, PasswordHash}};
 use rand_core::OsRng;
 use chrono::TimeZone;
+use utoipa::ToSchema;
+
+mod acme;
+mod api;
+mod backup;
+mod events;
+mod geo;
+mod health;
+mod import;
+mod metrics;
+mod orgs;
+mod paging;
+mod ptr;
+mod sharing;
+mod templates;
+
+use api::IdResponse;
+use events::{Event, EventBus, RecordChange};
+use sharing::Permission;
 
 #[derive(Clone, Serialize, Deserialize)]
 struct Claims {
@@ -20,7 +39,7 @@ struct Claims {
     exp: usize,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 struct ServerInfo {
     id: String,
     name: String,
@@ -28,40 +47,65 @@ struct ServerInfo {
     region: Option<String>,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 struct ZoneRecord {
     name: String,
     record_type: String,
     value: String,
     ttl: u32,
+    /// Relative share of answers for this value among the records with the same name and type
+    #[serde(default = "default_weight")]
+    weight: u32,
 }
 
-#[derive(Clone, Serialize, Deserialize)]
+fn default_weight() -> u32 { 1 }
+
+#[derive(Clone, Serialize, Deserialize, ToSchema)]
 struct Zone {
     id: String,
     domain: String,
     records: Vec<ZoneRecord>,
+    /// Template the zone was created from
+    template_id: Option<String>,
+    /// Organization the zone belongs to
+    org_id: Option<String>,
 }
 
 #[derive(Clone)]
 struct AppState {
     db: std::sync::Arc<PgClient>,
+    /// For the connections needing a transaction, which the shared one cannot hold
+    database_url: String,
     jwt_secret: String,
-}
-
-struct GeoState {
-    db: Option<geodns::GeoDB>,
+    events: EventBus,
 }
 
 #[derive(Clone)]
 struct FullState {
     inner: AppState,
-    geo: std::sync::Arc<tokio::sync::Mutex<GeoState>>,
+    geo: geodns::ReloadableGeoDB,
     processes: std::sync::Arc<tokio::sync::Mutex<HashMap<String, std::process::Child>>>,
 }
 
+#[derive(Serialize, ToSchema)]
+struct StatusResponse {
+    status: String,
+}
+
+/// Liveness probe.
+#[utoipa::path(get, path = "/health", tag = "system", security(()), responses((status = 200, body = StatusResponse)))]
 async fn health() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({"status":"ok"}))
+    HttpResponse::Ok().json(StatusResponse { status: "ok".to_string() })
+}
+
+/// Prometheus metrics of the process.
+#[utoipa::path(get, path = "/metrics", tag = "system", security(()), responses((status = 200, content_type = "text/plain", body = String)))]
+async fn metrics() -> impl Responder {
+    let encoder = TextEncoder::new();
+    let metric_families = gather();
+    let mut buffer = Vec::new();
+    encoder.encode(&metric_families, &mut buffer).unwrap_or(());
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(buffer)
 }
 
 async fn migrate_db(client: &PgClient) -> Result<(), tokio_postgres::Error> {
@@ -75,22 +119,105 @@ async fn migrate_db(client: &PgClient) -> Result<(), tokio_postgres::Error> {
     ).await?;
     // Backfill: ensure token_hash column exists for older DBs
     client.batch_execute("ALTER TABLE agents ADD COLUMN IF NOT EXISTS token_hash TEXT;").await.ok();
+    client.batch_execute(
+        "ALTER TABLE agents ADD COLUMN IF NOT EXISTS online BOOLEAN NOT NULL DEFAULT false;
+         CREATE TABLE IF NOT EXISTS events (id BIGSERIAL PRIMARY KEY, type TEXT NOT NULL, payload JSONB NOT NULL, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE TABLE IF NOT EXISTS webhooks (id UUID PRIMARY KEY, url TEXT NOT NULL, secret TEXT NOT NULL, event_types TEXT[], enabled BOOLEAN NOT NULL DEFAULT true, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE TABLE IF NOT EXISTS webhook_deliveries (id UUID PRIMARY KEY, webhook_id UUID REFERENCES webhooks(id) ON DELETE CASCADE, event_id BIGINT NOT NULL, event_type TEXT NOT NULL, attempt INT NOT NULL, status_code INT, error TEXT, success BOOLEAN NOT NULL, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE INDEX IF NOT EXISTS webhook_deliveries_webhook_idx ON webhook_deliveries (webhook_id, created_at);
+         CREATE INDEX IF NOT EXISTS events_type_idx ON events (type, id);
+         ALTER TABLE webhooks ADD COLUMN IF NOT EXISTS last_event_id BIGINT;
+         UPDATE webhooks SET last_event_id = (SELECT COALESCE(max(id), 0) FROM events) WHERE last_event_id IS NULL;
+         ALTER TABLE webhooks ALTER COLUMN last_event_id SET NOT NULL;
+         CREATE INDEX IF NOT EXISTS webhook_deliveries_event_idx ON webhook_deliveries (webhook_id, event_id);
+         ALTER TABLE records ADD COLUMN IF NOT EXISTS weight INT NOT NULL DEFAULT 1;
+         ALTER TABLE records ADD COLUMN IF NOT EXISTS expires_at TIMESTAMP WITH TIME ZONE;
+         CREATE INDEX IF NOT EXISTS records_expires_idx ON records (expires_at) WHERE expires_at IS NOT NULL;
+         CREATE TABLE IF NOT EXISTS zone_templates (id UUID PRIMARY KEY, name TEXT NOT NULL, description TEXT NOT NULL DEFAULT '', records JSONB NOT NULL, owner UUID, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(), updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         ALTER TABLE zones ADD COLUMN IF NOT EXISTS template_id UUID REFERENCES zone_templates(id) ON DELETE SET NULL;
+         ALTER TABLE zones ADD COLUMN IF NOT EXISTS template_params JSONB;
+         ALTER TABLE records ADD COLUMN IF NOT EXISTS from_template BOOLEAN NOT NULL DEFAULT false;
+         CREATE TABLE IF NOT EXISTS acme_credentials (id UUID PRIMARY KEY, zone_id UUID REFERENCES zones(id) ON DELETE CASCADE, name TEXT NOT NULL, username TEXT UNIQUE NOT NULL, password_hash TEXT NOT NULL, lifetime_secs INT NOT NULL, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE TABLE IF NOT EXISTS health_checks (record_id UUID PRIMARY KEY REFERENCES records(id) ON DELETE CASCADE, probe JSONB NOT NULL, interval_secs INT NOT NULL, timeout_ms INT NOT NULL, rise INT NOT NULL, fall INT NOT NULL, next_run TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE TABLE IF NOT EXISTS health_targets (id UUID PRIMARY KEY, record_id UUID REFERENCES health_checks(record_id) ON DELETE CASCADE, value TEXT NOT NULL, healthy BOOLEAN NOT NULL DEFAULT true, successes INT NOT NULL DEFAULT 0, failures INT NOT NULL DEFAULT 0, last_checked TIMESTAMP WITH TIME ZONE, last_error TEXT, UNIQUE (record_id, value));
+         ALTER TABLE zones ADD COLUMN IF NOT EXISTS auto_ptr BOOLEAN NOT NULL DEFAULT false;
+         ALTER TABLE records ADD COLUMN IF NOT EXISTS created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now();
+         ALTER TABLE records ADD COLUMN IF NOT EXISTS ptr_address INET;
+         CREATE INDEX IF NOT EXISTS records_ptr_address_idx ON records (ptr_address) WHERE ptr_address IS NOT NULL;
+         CREATE TABLE IF NOT EXISTS ptr_ranges (id UUID PRIMARY KEY, network CIDR UNIQUE NOT NULL, zone_id UUID NOT NULL REFERENCES zones(id) ON DELETE CASCADE, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE OR REPLACE FUNCTION try_inet(v TEXT) RETURNS INET LANGUAGE plpgsql IMMUTABLE AS $$
+         BEGIN RETURN v::inet; EXCEPTION WHEN others THEN RETURN NULL; END $$;
+         ALTER TABLE records ADD COLUMN IF NOT EXISTS address INET GENERATED ALWAYS AS (CASE WHEN type IN ('A', 'AAAA') THEN try_inet(value) END) STORED;
+         CREATE INDEX IF NOT EXISTS records_address_idx ON records (address) WHERE address IS NOT NULL;
+         CREATE TABLE IF NOT EXISTS orgs (id UUID PRIMARY KEY, name TEXT UNIQUE NOT NULL, max_zones INT, max_records INT, max_georules INT, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now());
+         CREATE TABLE IF NOT EXISTS org_members (org_id UUID REFERENCES orgs(id) ON DELETE CASCADE, user_id UUID REFERENCES users(id) ON DELETE CASCADE, role TEXT NOT NULL, PRIMARY KEY (org_id, user_id));
+         ALTER TABLE zones ADD COLUMN IF NOT EXISTS org_id UUID REFERENCES orgs(id);
+         ALTER TABLE zone_templates ADD COLUMN IF NOT EXISTS org_id UUID REFERENCES orgs(id);
+         ALTER TABLE events ADD COLUMN IF NOT EXISTS org_id UUID;
+         CREATE INDEX IF NOT EXISTS zones_org_idx ON zones (org_id);
+         CREATE INDEX IF NOT EXISTS org_members_user_idx ON org_members (user_id);
+         CREATE INDEX IF NOT EXISTS records_zone_name_idx ON records (zone_id, name, id);
+         CREATE INDEX IF NOT EXISTS records_name_idx ON records (name, id);
+         CREATE INDEX IF NOT EXISTS records_zone_lower_name_idx ON records (zone_id, lower(name) text_pattern_ops);
+         CREATE INDEX IF NOT EXISTS records_lower_name_idx ON records (lower(name) text_pattern_ops);
+         CREATE INDEX IF NOT EXISTS zones_domain_idx ON zones (domain, id);
+         CREATE INDEX IF NOT EXISTS events_config_applied_idx ON events ((payload->>'agent_id'), id) WHERE type = 'config_applied';
+         CREATE TABLE IF NOT EXISTS zone_shares (id UUID PRIMARY KEY, zone_id UUID NOT NULL REFERENCES zones(id) ON DELETE CASCADE, user_id UUID REFERENCES users(id) ON DELETE CASCADE, org_id UUID REFERENCES orgs(id) ON DELETE CASCADE, permission TEXT NOT NULL, created_by UUID, created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(), UNIQUE (zone_id, user_id), UNIQUE (zone_id, org_id), CHECK ((user_id IS NULL) <> (org_id IS NULL)));
+         CREATE INDEX IF NOT EXISTS zone_shares_user_idx ON zone_shares (user_id);
+         CREATE INDEX IF NOT EXISTS zone_shares_org_idx ON zone_shares (org_id);
+         CREATE INDEX IF NOT EXISTS events_zone_idx ON events ((payload->>'zone_id'), id);
+         ALTER TABLE georules ADD COLUMN IF NOT EXISTS priority INT NOT NULL DEFAULT 0;
+         ALTER TABLE georules ADD COLUMN IF NOT EXISTS action TEXT NOT NULL DEFAULT 'answer';
+         ALTER TABLE georules ADD COLUMN IF NOT EXISTS targets JSONB;
+         ALTER TABLE georules ADD COLUMN IF NOT EXISTS created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now();
+         ALTER TABLE zones ADD COLUMN IF NOT EXISTS geo_default JSONB;
+         CREATE OR REPLACE FUNCTION quota_usage(org UUID, resource TEXT) RETURNS BIGINT LANGUAGE sql STABLE AS $$
+         SELECT CASE resource
+             WHEN 'zones' THEN (SELECT count(*) FROM zones z WHERE z.org_id = org)
+             WHEN 'records' THEN (SELECT count(*) FROM records r JOIN zones z ON z.id = r.zone_id WHERE z.org_id = org)
+             ELSE (SELECT count(*) FROM georules g JOIN zones z ON z.id = g.zone_id WHERE z.org_id = org) END $$;
+         CREATE OR REPLACE FUNCTION quota_allows(org UUID, resource TEXT, adding BIGINT) RETURNS BOOLEAN LANGUAGE plpgsql AS $$
+         DECLARE lim INT;
+         BEGIN
+             -- the lock serializes the writes of the organization, and the count below runs with
+             -- a snapshot taken after it, so it sees the writes committed while waiting for it
+             SELECT CASE resource WHEN 'zones' THEN max_zones WHEN 'records' THEN max_records ELSE max_georules END INTO lim
+             FROM orgs WHERE id = org FOR NO KEY UPDATE;
+             RETURN lim IS NULL OR adding <= 0 OR quota_usage(org, resource) + adding <= lim;
+         END $$;",
+    ).await?;
+    // Backfill: zones and templates from before organizations move to a personal organization
+    // of their owner, with the owner as its admin.
+    client.batch_execute(
+        "WITH owners AS (SELECT owner FROM zones WHERE org_id IS NULL AND owner IS NOT NULL UNION SELECT owner FROM zone_templates WHERE org_id IS NULL AND owner IS NOT NULL),
+              created AS (INSERT INTO orgs (id, name) SELECT o.owner, coalesce(u.username, o.owner::text) FROM owners o LEFT JOIN users u ON u.id = o.owner ON CONFLICT DO NOTHING RETURNING id)
+         INSERT INTO org_members (org_id, user_id, role) SELECT c.id, u.id, 'admin' FROM created c JOIN users u ON u.id = c.id;
+         UPDATE zones SET org_id = owner WHERE org_id IS NULL AND owner IN (SELECT id FROM orgs);
+         UPDATE zone_templates SET org_id = owner WHERE org_id IS NULL AND owner IN (SELECT id FROM orgs);",
+    ).await?;
+    // Backfill: "region" geo rules always compared the country code.
+    client.batch_execute("UPDATE georules SET match_type = 'country' WHERE match_type = 'region';").await?;
     Ok(())
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct LoginRequest {
     username: String,
     password: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct LoginResponse {
     token: String,
 }
 
+/// Exchange a username and password for a JWT valid for 8 hours.
+#[utoipa::path(
+    post, path = "/api/v1/auth/login", tag = "auth", security(()), request_body = LoginRequest,
+    responses((status = 200, body = LoginResponse), (status = 401, description = "Invalid credentials")),
+)]
 async fn login(body: web::Json<LoginRequest>, data: web::Data<AppState>) -> impl Responder {
-    if let Ok(row) = (&*data.db).query_one("SELECT id::text, password_hash, role FROM users WHERE username = $1", &[&body.username]).await {
+    if let Ok(row) = data.db.query_one("SELECT id::text, password_hash, role FROM users WHERE username = $1", &[&body.username]).await {
         let id_str: String = row.get(0);
         let id = id_str.clone();
         let password_hash: String = row.get(1);
@@ -104,9 +231,12 @@ async fn login(body: web::Json<LoginRequest>, data: web::Data<AppState>) -> impl
             }
         }
     }
+    metrics::FAILED_LOGINS.inc();
     HttpResponse::Unauthorized().finish()
 }
 
+/// Create a user with the `user` role.
+#[utoipa::path(post, path = "/api/v1/users", tag = "auth", security(()), request_body = LoginRequest, responses((status = 201, body = IdResponse)))]
 async fn create_user(req: web::Json<LoginRequest>, data: web::Data<AppState>) -> impl Responder {
     let mut rng = OsRng;
     let salt = SaltString::generate(&mut rng);
@@ -114,11 +244,11 @@ async fn create_user(req: web::Json<LoginRequest>, data: web::Data<AppState>) ->
     let password_hash = argon2.hash_password(req.password.as_bytes(), &salt).unwrap().to_string();
     let id = Uuid::new_v4();
     let role = "user";
-    // pass a UUID directly to avoid parameter serialization errors
+    // bind the id as text and cast it: a String cannot be serialized into a UUID parameter
     let id_str = id.to_string();
-    let res = (&*data.db).execute("INSERT INTO users (id, username, password_hash, role) VALUES ($1, $2, $3, $4)", &[&id_str, &req.username, &password_hash, &role]).await;
+    let res = data.db.execute("INSERT INTO users (id, username, password_hash, role) VALUES ($1::text::uuid, $2, $3, $4)", &[&id_str, &req.username, &password_hash, &role]).await;
     match res {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({"id": id.to_string()})),
+        Ok(_) => HttpResponse::Created().json(IdResponse { id: id.to_string() }),
         Err(e) => {
             warn!("create_user error: {}", e);
             HttpResponse::InternalServerError().finish()
@@ -129,33 +259,40 @@ async fn create_user(req: web::Json<LoginRequest>, data: web::Data<AppState>) ->
 fn auth_from_header(req: &HttpRequest, secret: &str) -> Option<TokenData<Claims>> {
     if let Some(auth) = req.headers().get(header::AUTHORIZATION) {
         if let Ok(s) = auth.to_str() {
-            if s.starts_with("Bearer ") {
-                let token = &s[7..];
-                if let Ok(data) = decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default()) {
-                    return Some(data);
-                }
+            if let Some(token) = s.strip_prefix("Bearer ") {
+                return decode_token(token, secret);
             }
         }
     }
     None
 }
 
+fn decode_token(token: &str, secret: &str) -> Option<TokenData<Claims>> {
+    decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default()).ok()
+}
+
+#[utoipa::path(get, path = "/api/v1/servers", tag = "servers", responses((status = 200, body = [ServerInfo]), (status = 401)))]
 async fn list_servers(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     if auth_from_header(&req, &data.jwt_secret).is_none() {
         return HttpResponse::Unauthorized().finish();
     }
-    let rows = (&*data.db).query("SELECT id::text, name, address, region FROM servers", &[]).await.unwrap_or_default();
+    let rows = data.db.query("SELECT id::text, name, address, region FROM servers", &[]).await.unwrap_or_default();
     let servers: Vec<ServerInfo> = rows.into_iter().map(|r| ServerInfo { id: r.get::<usize, String>(0), name: r.get(1), address: r.get(2), region: r.get(3) }).collect();
     HttpResponse::Ok().json(servers)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct CreateServerReq {
     name: String,
     address: String,
     region: Option<String>,
 }
 
+/// Register a DNS server (admins).
+#[utoipa::path(
+    post, path = "/api/v1/servers", tag = "servers", request_body = CreateServerReq,
+    responses((status = 201, body = IdResponse), (status = 401), (status = 403)),
+)]
 async fn create_server(body: web::Json<CreateServerReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     if let Some(tok) = auth_from_header(&req, &data.jwt_secret) {
         if tok.claims.role != "admin" {
@@ -166,9 +303,9 @@ async fn create_server(body: web::Json<CreateServerReq>, data: web::Data<AppStat
     }
     let id = Uuid::new_v4();
     let id_str = id.to_string();
-    let res = (&*data.db).execute("INSERT INTO servers (id, name, address, region) VALUES ($1, $2, $3, $4)", &[&id_str, &body.name, &body.address, &body.region]).await;
+    let res = data.db.execute("INSERT INTO servers (id, name, address, region) VALUES ($1, $2, $3, $4)", &[&id_str, &body.name, &body.address, &body.region]).await;
     match res {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({"id": id.to_string()})),
+        Ok(_) => HttpResponse::Created().json(IdResponse { id: id.to_string() }),
         Err(e) => {
             warn!("create_server error: {}", e);
             HttpResponse::InternalServerError().finish()
@@ -176,30 +313,32 @@ async fn create_server(body: web::Json<CreateServerReq>, data: web::Data<AppStat
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct AgentRegistration {
     name: String,
     addr: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct AgentRegisterResponse {
     id: String,
     token: String,
 }
 
+/// Register an agent; the returned token authenticates its later calls.
+#[utoipa::path(post, path = "/api/v1/agents/register", tag = "agents", security(()), request_body = AgentRegistration, responses((status = 201, body = AgentRegisterResponse)))]
 async fn agent_register(body: web::Json<AgentRegistration>, data: web::Data<AppState>) -> impl Responder {
     // create agent id and a secure token
     let id = Uuid::new_v4();
     let id_str = id.to_string();
     // token: combine two UUIDs for sufficient entropy
-    let token_plain = format!("{}{}", Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+    let token_plain = format!("{}{}", Uuid::new_v4(), Uuid::new_v4());
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
     let token_hash = argon2.hash_password(token_plain.as_bytes(), &salt).unwrap().to_string();
 
-    let res = (&*data.db).execute(
-        "INSERT INTO agents (id, name, addr, token_hash) VALUES ($1, $2, $3, $4)",
+    let res = data.db.execute(
+        "INSERT INTO agents (id, name, addr, token_hash) VALUES ($1::text::uuid, $2, $3, $4)",
         &[&id_str, &body.name, &body.addr, &token_hash]
     ).await;
     match res {
@@ -211,6 +350,11 @@ async fn agent_register(body: web::Json<AgentRegistration>, data: web::Data<AppS
     }
 }
 
+/// Mark the agent with the given address as alive, authenticated with its token.
+#[utoipa::path(
+    post, path = "/api/v1/agents/heartbeat", tag = "agents", request_body = AgentRegistration,
+    responses((status = 200), (status = 401), (status = 404, description = "No agent with this address")),
+)]
 async fn agent_heartbeat(body: web::Json<AgentRegistration>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     // require agent token in Authorization header
     let token = req.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok()).and_then(|s| s.strip_prefix("Bearer ")).map(|s| s.to_string());
@@ -220,15 +364,25 @@ async fn agent_heartbeat(body: web::Json<AgentRegistration>, data: web::Data<App
     let token = token.unwrap();
 
     // find agent by addr
-    if let Ok(row) = (&*data.db).query_one("SELECT id::text, token_hash FROM agents WHERE addr = $1", &[&body.addr]).await {
+    if let Ok(row) = data.db.query_one("SELECT id::text, token_hash FROM agents WHERE addr = $1", &[&body.addr]).await {
         let id_str: String = row.get(0);
         let token_hash: Option<String> = row.get(1);
         if let Some(th) = token_hash {
             if let Ok(ph) = PasswordHash::new(&th) {
                 if Argon2::default().verify_password(token.as_bytes(), &ph).is_ok() {
-                    let res = (&*data.db).execute("UPDATE agents SET last_heartbeat = now() WHERE id::text = $1", &[&id_str]).await;
+                    // join against the old row to learn whether the agent was offline before
+                    let res = data.db.query_one(
+                        "UPDATE agents a SET last_heartbeat = now(), online = true FROM agents old WHERE a.id = old.id AND a.id::text = $1 RETURNING old.online",
+                        &[&id_str],
+                    ).await;
                     return match res {
-                        Ok(_) => HttpResponse::Ok().finish(),
+                        Ok(row) => {
+                            let was_online: bool = row.get(0);
+                            if !was_online {
+                                data.events.publish(Event::AgentOnline { agent_id: id_str }, None).await;
+                            }
+                            HttpResponse::Ok().finish()
+                        }
                         Err(e) => { warn!("agent_heartbeat error: {}", e); HttpResponse::InternalServerError().finish() }
                     };
                 }
@@ -239,7 +393,26 @@ async fn agent_heartbeat(body: web::Json<AgentRegistration>, data: web::Data<App
     HttpResponse::NotFound().finish()
 }
 
-// Agent fetch config (agents call this with their token)
+/// A zone and the records an agent serves for it.
+#[derive(Serialize, ToSchema)]
+struct AgentZone {
+    id: String,
+    domain: String,
+    records: Vec<ZoneRecord>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AgentConfig {
+    /// Reported back to `config/applied` once loaded
+    version: i64,
+    zones: Vec<AgentZone>,
+}
+
+/// The configuration an agent serves, fetched with its token.
+#[utoipa::path(
+    get, path = "/api/v1/agents/{id}/config", tag = "agents", params(("id" = String, Path, description = "Agent id")),
+    responses((status = 200, body = AgentConfig), (status = 401), (status = 404)),
+)]
 async fn agent_get_config(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     let agent_id = path.into_inner();
     let token = req.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok()).and_then(|s| s.strip_prefix("Bearer ")).map(|s| s.to_string());
@@ -248,15 +421,21 @@ async fn agent_get_config(path: web::Path<String>, data: web::Data<AppState>, re
     }
     let token = token.unwrap();
 
-    if let Ok(row) = (&*data.db).query_one("SELECT token_hash FROM agents WHERE id::text = $1", &[&agent_id]).await {
+    if let Ok(row) = data.db.query_one("SELECT token_hash FROM agents WHERE id::text = $1", &[&agent_id]).await {
         let token_hash: Option<String> = row.get(0);
         if let Some(th) = token_hash {
             if let Ok(ph) = PasswordHash::new(&th) {
                 if Argon2::default().verify_password(token.as_bytes(), &ph).is_ok() {
-                    // In production, return signed config blob. For now, return zone list assigned to control plane.
-                    let zones = (&*data.db).query("SELECT id::text, domain FROM zones", &[]).await.unwrap_or_default();
-                    let z: Vec<_> = zones.into_iter().map(|r| serde_json::json!({"id": r.get::<usize, String>(0), "domain": r.get::<usize, String>(1)})).collect();
-                    return HttpResponse::Ok().json(serde_json::json!({"zones": z}));
+                    // In production, return signed config blob. For now, return all zones with the records to serve.
+                    let version = events::config_version(&data.db).await;
+                    let zones = data.db.query("SELECT id::text, domain FROM zones", &[]).await.unwrap_or_default();
+                    let mut z = Vec::with_capacity(zones.len());
+                    for r in zones {
+                        let id: String = r.get(0);
+                        let records = health::served_records(&data.db, &id).await;
+                        z.push(AgentZone { id, domain: r.get(1), records });
+                    }
+                    return HttpResponse::Ok().json(AgentConfig { version, zones: z });
                 }
             }
         }
@@ -265,7 +444,46 @@ async fn agent_get_config(path: web::Path<String>, data: web::Data<AppState>, re
     HttpResponse::NotFound().finish()
 }
 
-// Admin-only: rotate agent token
+#[derive(Deserialize, ToSchema)]
+struct ConfigAppliedReq {
+    version: Option<i64>,
+}
+
+/// Agents report back once a fetched config has been loaded.
+#[utoipa::path(
+    post, path = "/api/v1/agents/{id}/config/applied", tag = "agents", params(("id" = String, Path, description = "Agent id")),
+    request_body = ConfigAppliedReq, responses((status = 200), (status = 401), (status = 404)),
+)]
+async fn agent_config_applied(path: web::Path<String>, body: web::Json<ConfigAppliedReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let agent_id = path.into_inner();
+    let token = match req.headers().get(header::AUTHORIZATION).and_then(|h| h.to_str().ok()).and_then(|s| s.strip_prefix("Bearer ")) {
+        Some(t) => t.to_string(),
+        None => return HttpResponse::Unauthorized().finish(),
+    };
+
+    let row = match data.db.query_one("SELECT token_hash FROM agents WHERE id::text = $1", &[&agent_id]).await {
+        Ok(row) => row,
+        Err(_) => return HttpResponse::NotFound().finish(),
+    };
+    let token_hash: Option<String> = row.get(0);
+    let verified = token_hash.as_deref().and_then(|th| PasswordHash::new(th).ok()).is_some_and(|ph| Argon2::default().verify_password(token.as_bytes(), &ph).is_ok());
+    if !verified {
+        return HttpResponse::Unauthorized().finish();
+    }
+    data.events.publish(Event::ConfigApplied { agent_id, version: body.version }, None).await;
+    HttpResponse::Ok().finish()
+}
+
+#[derive(Serialize, ToSchema)]
+struct AgentTokenResponse {
+    token: String,
+}
+
+/// Replace the token of an agent (admins).
+#[utoipa::path(
+    post, path = "/api/v1/agents/{id}/token/rotate", tag = "agents", params(("id" = String, Path, description = "Agent id")),
+    responses((status = 200, body = AgentTokenResponse), (status = 401), (status = 403), (status = 404)),
+)]
 async fn rotate_agent_token(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     // require admin role via JWT
     if let Some(tok) = auth_from_header(&req, &data.jwt_secret) {
@@ -276,17 +494,29 @@ async fn rotate_agent_token(path: web::Path<String>, data: web::Data<AppState>,
         return HttpResponse::Unauthorized().finish();
     }
     let agent_id = path.into_inner();
-    let token_plain = format!("{}{}", Uuid::new_v4().to_string(), Uuid::new_v4().to_string());
+    let token_plain = format!("{}{}", Uuid::new_v4(), Uuid::new_v4());
     let salt = SaltString::generate(&mut OsRng);
     let argon2 = Argon2::default();
     let token_hash = argon2.hash_password(token_plain.as_bytes(), &salt).unwrap().to_string();
-    let res = (&*data.db).execute("UPDATE agents SET token_hash = $1 WHERE id::text = $2", &[&token_hash, &agent_id]).await;
+    let res = data.db.execute("UPDATE agents SET token_hash = $1 WHERE id::text = $2", &[&token_hash, &agent_id]).await;
     match res {
-        Ok(r) => if r == 0 { HttpResponse::NotFound().finish() } else { HttpResponse::Ok().json(serde_json::json!({"token": token_plain})) },
+        Ok(r) => if r == 0 { HttpResponse::NotFound().finish() } else { HttpResponse::Ok().json(AgentTokenResponse { token: token_plain }) },
         Err(e) => { warn!("rotate_agent_token error: {}", e); HttpResponse::InternalServerError().finish() }
     }
 }
 
+#[derive(Serialize, ToSchema)]
+struct AgentInfo {
+    id: String,
+    name: String,
+    addr: String,
+    /// RFC 3339 timestamp
+    last_heartbeat: String,
+    online: bool,
+}
+
+/// Registered agents and whether they are online (admins).
+#[utoipa::path(get, path = "/api/v1/agents", tag = "agents", responses((status = 200, body = [AgentInfo]), (status = 401), (status = 403)))]
 async fn list_agents(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
     if let Some(tok) = auth_from_header(&req, &data.jwt_secret) {
         if tok.claims.role != "admin" {
@@ -295,27 +525,38 @@ async fn list_agents(data: web::Data<AppState>, req: HttpRequest) -> impl Respon
     } else {
         return HttpResponse::Unauthorized().finish();
     }
-    let rows = (&*data.db).query("SELECT id::text, name, addr, EXTRACT(EPOCH FROM last_heartbeat) as epoch FROM agents", &[]).await.unwrap_or_default();
-    let agents: Vec<_> = rows.into_iter().map(|r| {
+    let rows = data.db.query("SELECT id::text, name, addr, EXTRACT(EPOCH FROM last_heartbeat) as epoch FROM agents", &[]).await.unwrap_or_default();
+    let agents: Vec<AgentInfo> = rows.into_iter().map(|r| {
         let id: String = r.get(0);
         let name: String = r.get(1);
         let addr: String = r.get(2);
         let epoch: f64 = r.get(3);
-        let last_dt = chrono::Utc.timestamp_opt(epoch as i64, ((epoch.fract() * 1e9) as u32)).single().unwrap_or(chrono::Utc::now());
+        let last_dt = chrono::Utc.timestamp_opt(epoch as i64, (epoch.fract() * 1e9) as u32).single().unwrap_or(chrono::Utc::now());
         let age = chrono::Utc::now().signed_duration_since(last_dt).num_seconds();
-        let online = age < 120;
-        serde_json::json!({"id": id, "name": name, "addr": addr, "last_heartbeat": last_dt.to_rfc3339(), "online": online})
+        let online = age < events::AGENT_OFFLINE_AFTER_SECS;
+        AgentInfo { id, name, addr, last_heartbeat: last_dt.to_rfc3339(), online }
     }).collect();
     HttpResponse::Ok().json(agents)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[allow(dead_code)]
 struct StartDnsReq {
     id: String,
     bind: String,
 }
 
+#[derive(Serialize, ToSchema)]
+struct DnsProcessResponse {
+    status: String,
+    server_id: String,
+}
+
+/// Write the zones to disk and start a local `hickory-dns` process serving them (admins).
+#[utoipa::path(
+    post, path = "/api/v1/dns/start", tag = "dns", request_body = StartDnsReq,
+    responses((status = 200, body = DnsProcessResponse), (status = 401), (status = 403)),
+)]
 async fn start_dns_server(body: web::Json<StartDnsReq>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
     // require admin
     if let Some(tok) = auth_from_header(&req, &data.inner.jwt_secret) {
@@ -334,38 +575,45 @@ async fn start_dns_server(body: web::Json<StartDnsReq>, data: web::Data<FullStat
         return HttpResponse::InternalServerError().body("failed to create zonedir");
     }
 
-    // write zone files from DB
-    let zones = (&*data.inner.db).query("SELECT id::text, domain FROM zones", &[]).await.unwrap_or_default();
+    // write zone files from DB, and the configuration serving them with their record weights
+    let zones = data.inner.db.query("SELECT id::text, domain FROM zones", &[]).await.unwrap_or_default();
+    let mut config = String::new();
     for z in zones.into_iter() {
         let zid: String = z.get(0);
         let domain: String = z.get(1);
-        let fname = format!("{}/{}.zone", zonedir, domain.replace('.', "_"));
-        if let Ok(mut f) = std::fs::File::create(&fname) {
+        let zone_file = format!("{}.zone", domain.replace('.', "_"));
+        let records = health::served_records(&data.inner.db, &zid).await;
+        if let Ok(mut f) = std::fs::File::create(format!("{}/{}", zonedir, zone_file)) {
             use std::io::Write;
             let soa = format!("@ 3600 IN SOA ns.{} hostmaster.{} 1 3600 3600 604800 3600\n", domain, domain);
             let _ = f.write_all(soa.as_bytes());
-            let recs = (&*data.inner.db).query("SELECT name, type, value, ttl FROM records WHERE zone_id::text = $1", &[&zid]).await.unwrap_or_default();
-            for r in recs {
-                let name: String = r.get(0);
-                let typ: String = r.get(1);
-                let value: String = r.get(2);
-                let ttl: i32 = r.get(3);
-                let rr = format!("{} {} IN {} {}\n", if name.is_empty() { "@" } else { &name }, ttl, typ, value);
+            for r in &records {
+                let name = if r.name.is_empty() { "@" } else { &r.name };
+                let rr = format!("{} {} IN {} {}\n", name, r.ttl, r.record_type, r.value);
                 let _ = f.write_all(rr.as_bytes());
             }
         }
+        config.push_str(&zone_config(&domain, &zone_file, &records));
+    }
+    let config_path = format!("{}/named.toml", zonedir);
+    if let Err(e) = std::fs::write(&config_path, config) {
+        warn!("failed writing {}: {}", config_path, e);
+        return HttpResponse::InternalServerError().body("failed to write dns config");
     }
 
     let bin = std::env::var("HICKORY_DNS_BIN").unwrap_or_else(|_| "./target/debug/hickory-dns".to_string());
     let port_arg = if body.bind.is_empty() { "0".to_string() } else { body.bind.clone() };
     let mut cmd = Command::new(bin);
-    cmd.arg("-d").arg(format!("--zonedir={}", zonedir)).arg(format!("--port={}", port_arg));
+    cmd.arg("-d")
+        .arg(format!("--config={}", config_path))
+        .arg(format!("--zonedir={}", zonedir))
+        .arg(format!("--port={}", port_arg));
 
     match cmd.spawn() {
         Ok(child) => {
             let mut procs = data.processes.lock().await;
             procs.insert(server_id.clone(), child);
-            HttpResponse::Ok().json(serde_json::json!({"status":"started","server_id": server_id}))
+            HttpResponse::Ok().json(DnsProcessResponse { status: "started".to_string(), server_id })
         }
         Err(e) => {
             warn!("failed spawning dns process: {}", e);
@@ -374,11 +622,54 @@ async fn start_dns_server(body: web::Json<StartDnsReq>, data: web::Data<FullStat
     }
 }
 
-#[derive(Deserialize)]
+/// The `hickory-dns` configuration of a zone served from `zone_file`.
+///
+/// RRsets with a record weight other than the default are answered through a `weighted` store
+/// wrapping the zone file; other RRsets are answered in full.
+fn zone_config(domain: &str, zone_file: &str, records: &[ZoneRecord]) -> String {
+    // JSON strings are valid TOML basic strings
+    let quote = |s: &str| serde_json::to_string(s).unwrap_or_default();
+    let mut config = format!(
+        "[[zones]]\nzone = {}\nzone_type = \"Primary\"\n\n[[zones.stores]]\ntype = \"file\"\nzone_path = {}\n\n",
+        quote(domain),
+        quote(zone_file),
+    );
+
+    let rrset_key = |r: &ZoneRecord| (r.name.to_ascii_lowercase(), r.record_type.to_ascii_uppercase());
+    let weighted: std::collections::HashSet<_> = records.iter().filter(|r| r.weight != default_weight()).map(rrset_key).collect();
+    if weighted.is_empty() {
+        return config;
+    }
+
+    config.push_str("[[zones.stores]]\ntype = \"weighted\"\nrecords = [\n");
+    for r in records.iter().filter(|r| weighted.contains(&rrset_key(r))) {
+        let name = match r.name.as_str() {
+            "" | "@" => format!("{}.", domain.trim_end_matches('.')),
+            name => name.to_string(),
+        };
+        config.push_str(&format!(
+            "    {{ name = {}, record_type = {}, value = {}, weight = {} }},\n",
+            quote(&name),
+            quote(&r.record_type.to_ascii_uppercase()),
+            quote(&r.value),
+            r.weight,
+        ));
+    }
+    config.push_str("]\n\n");
+    config
+}
+
+#[derive(Deserialize, ToSchema)]
 #[allow(dead_code)]
 struct StopDnsReq {
     id: String,
 }
+
+/// Stop a process started with `dns/start` (admins).
+#[utoipa::path(
+    post, path = "/api/v1/dns/stop", tag = "dns", request_body = StopDnsReq,
+    responses((status = 200, body = DnsProcessResponse), (status = 401), (status = 403), (status = 404)),
+)]
 async fn stop_dns_server(body: web::Json<StopDnsReq>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
     // require admin via JWT
     if let Some(tok) = auth_from_header(&req, &data.inner.jwt_secret) {
@@ -393,7 +684,7 @@ async fn stop_dns_server(body: web::Json<StopDnsReq>, data: web::Data<FullState>
     let mut procs = data.processes.lock().await;
     if let Some(mut child) = procs.remove(&server_id) {
         match child.kill() {
-            Ok(_) => HttpResponse::Ok().json(serde_json::json!({"status":"stopped","server_id": server_id})),
+            Ok(_) => HttpResponse::Ok().json(DnsProcessResponse { status: "stopped".to_string(), server_id }),
             Err(e) => {
                 warn!("failed killing process {}: {}", server_id, e);
                 HttpResponse::InternalServerError().body("failed to stop process")
@@ -404,79 +695,85 @@ async fn stop_dns_server(body: web::Json<StopDnsReq>, data: web::Data<FullState>
     }
 }
 
-#[derive(Deserialize)]
-struct CreateGeoRuleReq {
-    zone_id: String,
-    match_type: String,
-    match_value: String,
-    target: String,
-}
-
-async fn create_georule(body: web::Json<CreateGeoRuleReq>, data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
-    if auth_from_header(&req, &data.inner.jwt_secret).is_none() { return HttpResponse::Unauthorized().finish(); }
-    let id = Uuid::new_v4();
-    let zone_uuid = match Uuid::parse_str(&body.zone_id) {
-        Ok(z) => z,
-        Err(_) => return HttpResponse::BadRequest().body("invalid zone_id"),
-    };
-    let id_str = id.to_string();
-    let zone_str = zone_uuid.to_string();
-    let res = (&*data.inner.db).execute("INSERT INTO georules (id, zone_id, match_type, match_value, target) VALUES ($1, $2, $3, $4, $5)", &[&id_str, &zone_str, &body.match_type, &body.match_value, &body.target]).await;
-    match res {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({"id": id.to_string()})),
-        Err(e) => { warn!("create_georule error: {}", e); HttpResponse::InternalServerError().finish() }
-    }
-}
-
-async fn list_georules(data: web::Data<FullState>, req: HttpRequest) -> impl Responder {
-    if auth_from_header(&req, &data.inner.jwt_secret).is_none() { return HttpResponse::Unauthorized().finish(); }
-    let rows = (&*data.inner.db).query("SELECT id::text, zone_id::text, match_type, match_value, target FROM georules", &[]).await.unwrap_or_default();
-    let out: Vec<_> = rows.into_iter().map(|r| serde_json::json!({"id": r.get::<usize, String>(0), "zone_id": r.get::<usize, String>(1), "match_type": r.get::<usize, String>(2), "match_value": r.get::<usize, String>(3), "target": r.get::<usize, String>(4)})).collect();
-    HttpResponse::Ok().json(out)
-}
-
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 struct CreateZoneReq {
     domain: String,
 }
 
-async fn list_zones(data: web::Data<AppState>, _req: HttpRequest) -> impl Responder {
-    // show all zones if admin, otherwise only user-owned zones
-    let mut q = "SELECT id::text, domain, owner::text FROM zones".to_string();
-    let mut params: Vec<&(dyn tokio_postgres::types::ToSql + Sync)> = vec![];
-    let req = _req;
-    if let Some(tok) = auth_from_header(&req, &data.jwt_secret) {
-        if tok.claims.role != "admin" {
-            q = "SELECT id::text, domain, owner::text FROM zones WHERE owner::text = $1".to_string();
-            let owner_str = tok.claims.sub.clone();
-            params.push(&owner_str);
-            let rows = (&*data.db).query(q.as_str(), params.as_slice()).await.unwrap_or_default();
-            let zones: Vec<Zone> = rows.into_iter().map(|r| Zone { id: r.get::<usize, String>(0), domain: r.get(1), records: vec![] }).collect();
-            return HttpResponse::Ok().json(zones);
-        }
-    } else {
-        return HttpResponse::Unauthorized().finish();
+/// Zones of the caller's organizations and zones shared with them, ordered by domain.
+#[utoipa::path(
+    get, path = "/api/v1/zones", tag = "zones", params(paging::ZoneFilter),
+    responses((status = 200, body = [Zone], headers(
+        ("X-Next-Cursor" = String, description = "Cursor of the next page, absent on the last page"),
+        ("X-Total-Count" = i64, description = "Matching zones, on the first page only"),
+    )), (status = 400), (status = 401)),
+)]
+async fn list_zones(query: web::Query<paging::ZoneFilter>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    // show all zones if admin, otherwise only zones of the caller's organizations
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let limit = match paging::page_limit(query.limit) {
+        Ok(l) => l,
+        Err(e) => return HttpResponse::BadRequest().body(e),
+    };
+    let mut conds = paging::Conditions::default();
+    let (all, orgs, sub) = (conds.bind(caller.all), conds.bind(caller.orgs.clone()), conds.bind(caller.sub.clone()));
+    conds.push(format!("({} OR z.org_id::text = ANY({}) OR {})", all, orgs, sharing::shared_sql("z.id", &sub, &orgs)));
+    query.apply(&mut conds);
+    let total = match query.cursor {
+        None => match data.db.query_one(format!("SELECT count(*) FROM zones z {}", conds.where_clause()).as_str(), &conds.params()).await {
+            Ok(row) => Some(row.get::<usize, i64>(0)),
+            Err(e) => { warn!("list_zones error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        },
+        Some(_) => None,
+    };
+    if let Err(e) = paging::after_cursor(&mut conds, query.cursor.as_deref(), "z.domain", "z.id") {
+        return HttpResponse::BadRequest().body(e);
     }
-    let rows = (&*data.db).query(q.as_str(), &[]).await.unwrap_or_default();
-    let zones: Vec<Zone> = rows.into_iter().map(|r| Zone { id: r.get::<usize, String>(0), domain: r.get(1), records: vec![] }).collect();
-    HttpResponse::Ok().json(zones)
+    let sql = format!(
+        "SELECT z.id::text, z.domain, z.template_id::text, z.org_id::text FROM zones z {} ORDER BY z.domain, z.id LIMIT {}",
+        conds.where_clause(), limit + 1,
+    );
+    let rows = match data.db.query(sql.as_str(), &conds.params()).await {
+        Ok(rows) => rows,
+        Err(e) => { warn!("list_zones error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let zones: Vec<Zone> = rows.into_iter().map(|r| Zone { id: r.get::<usize, String>(0), domain: r.get(1), records: vec![], template_id: r.get(2), org_id: r.get(3) }).collect();
+    paging::page_response(zones, limit, total, |z| paging::Cursor { key: z.domain.clone(), id: z.id.clone() })
 }
 
+/// Create a zone in the caller's organization.
+#[utoipa::path(
+    post, path = "/api/v1/zones", tag = "zones", request_body = CreateZoneReq,
+    responses((status = 201, body = IdResponse), (status = 400), (status = 401), (status = 403, body = orgs::QuotaExceeded)),
+)]
 async fn create_zone(body: web::Json<CreateZoneReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
-    if auth_from_header(&req, &data.jwt_secret).is_none() {
-        return HttpResponse::Unauthorized().finish();
-    }
-    let tok = auth_from_header(&req, &data.jwt_secret).unwrap();
-    let owner = tok.claims.sub.clone();
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let org_id = match caller.org_for_create() {
+        Ok(o) => o.to_string(),
+        Err(resp) => return resp,
+    };
+    let owner = caller.sub.clone();
     let id = Uuid::new_v4();
     let id_str = id.to_string();
-    let safe_domain = body.domain.replace("'", "''");
-    let safe_owner = owner.replace("'", "''");
-    let insert_sql = format!("INSERT INTO zones (id, domain, owner) VALUES ('{}', '{}', '{}')", id_str, safe_domain, safe_owner);
-    info!("create_zone (formatted SQL): {}", insert_sql);
-    let res = (&*data.db).execute(insert_sql.as_str(), &[]).await;
+    let res = data.db.execute(
+        &format!(
+            "INSERT INTO zones (id, domain, owner, org_id) SELECT $1::text::uuid, $2, $3::text::uuid, $4::text::uuid WHERE (SELECT {})",
+            orgs::Quota::Zones.allows("$4::text::uuid", "1"),
+        ),
+        &[&id_str, &body.domain, &owner, &org_id],
+    ).await;
     match res {
-        Ok(_) => HttpResponse::Created().json(serde_json::json!({"id": id.to_string()})),
+        Ok(0) => orgs::quota_refused(&data.db, &[(Some(&org_id), orgs::Quota::Zones, 1)]).await,
+        Ok(_) => {
+            data.events.publish(Event::ZoneCreated { zone_id: id_str, domain: body.domain.clone() }, Some(org_id)).await;
+            HttpResponse::Created().json(IdResponse { id: id.to_string() })
+        }
         Err(e) => {
             warn!("create_zone error: {}", e);
             HttpResponse::InternalServerError().finish()
@@ -484,62 +781,48 @@ async fn create_zone(body: web::Json<CreateZoneReq>, data: web::Data<AppState>,
     }
 }
 
-#[derive(Deserialize)]
-struct GeoResolveRequest {
-    zone_id: String,
-    client_ip: String,
-}
-
-/// Resolve a DNS response for a zone based on client's geographic location.
-/// Uses GeoRules to determine which target address to return.
-async fn resolve_by_geo(body: web::Json<GeoResolveRequest>, data: web::Data<FullState>) -> impl Responder {
-    // Parse client IP
-    let client_ip = match body.client_ip.parse::<std::net::IpAddr>() {
-        Ok(ip) => ip,
-        Err(_) => return HttpResponse::BadRequest().body("invalid client IP"),
+#[utoipa::path(
+    delete, path = "/api/v1/zones/{id}", tag = "zones", params(("id" = String, Path, description = "Zone id")),
+    responses((status = 200), (status = 401), (status = 404)),
+)]
+async fn delete_zone(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
     };
-
-    // Fetch georules for this zone from DB
-    let rows = (&*data.inner.db)
-        .query(
-            "SELECT id::text, match_type, match_value, target FROM georules WHERE zone_id::text = $1",
-            &[&body.zone_id],
-        )
-        .await
-        .unwrap_or_default();
-
-    let rules: Vec<geodns::GeoRule> = rows
-        .into_iter()
-        .map(|r| geodns::GeoRule {
-            id: r.get::<usize, String>(0),
-            match_type: r.get::<usize, String>(1),
-            match_value: r.get::<usize, String>(2),
-            target: r.get::<usize, String>(3),
-        })
-        .collect();
-
-    // Get GeoIP DB from state
-    let geo_state = data.geo.lock().await;
-    let db = geo_state.db.as_ref().cloned();
-
-    // Create rule engine and evaluate
-    let mut engine = geodns::GeoRuleEngine::new(db);
-    engine.set_rules(rules);
-
-    // Evaluate and return target
-    match engine.evaluate(client_ip) {
-        Some(target) => HttpResponse::Ok().json(serde_json::json!({"target": target})),
-        None => HttpResponse::Ok().json(serde_json::json!({"target": None::<String>, "message": "no matching geo rule"})),
+    let zone_id = path.into_inner();
+    // non-admins may only delete zones of their organizations
+    let res = data.db.query_opt(
+        "DELETE FROM zones WHERE id::text = $1 AND ($2 OR org_id::text = ANY($3)) RETURNING domain, org_id::text",
+        &[&zone_id, &caller.all, &caller.orgs],
+    ).await;
+    match res {
+        Ok(Some(row)) => {
+            let domain: String = row.get(0);
+            ptr::sync_zone(&data, &zone_id, &domain).await;
+            data.events.publish(Event::ZoneDeleted { zone_id, domain }, row.get(1)).await;
+            HttpResponse::Ok().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            warn!("delete_zone error: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
     }
 }
 
+/// Organization of a zone, used to scope the events published about it.
+async fn zone_org(db: &PgClient, zone_id: &str) -> Option<String> {
+    db.query_opt("SELECT org_id::text FROM zones WHERE id::text = $1", &[&zone_id]).await.ok().flatten().and_then(|r| r.get(0))
+}
+
 // Placeholder: function to push configuration to agents (secure HTTPS/gRPC in production)
 #[allow(dead_code)]
 async fn push_config_to_agent(_agent_id: &str) {
     // TODO: implement secure config push
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[allow(dead_code)]
 struct ConfigPushRequest {
     agent_id: String,
@@ -547,7 +830,7 @@ struct ConfigPushRequest {
     zone_config: serde_json::Value,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct ConfigPushResponse {
     success: bool,
     message: String,
@@ -555,6 +838,10 @@ struct ConfigPushResponse {
 
 /// Push DNS zone configuration to an agent securely (mTLS placeholder)
 /// In production: use rustls with client certificates for authentication
+#[utoipa::path(
+    post, path = "/api/v1/config/push", tag = "agents", request_body = ConfigPushRequest,
+    responses((status = 200, body = ConfigPushResponse), (status = 401), (status = 403), (status = 404)),
+)]
 async fn push_config_to_agents(
     body: web::Json<ConfigPushRequest>,
     data: web::Data<FullState>,
@@ -570,7 +857,7 @@ async fn push_config_to_agents(
     }
 
     // Fetch agent details from DB
-    let rows = (&*data.inner.db)
+    let rows = data.inner.db
         .query("SELECT id::text, addr FROM agents WHERE id::text = $1", &[&body.agent_id])
         .await
         .unwrap_or_default();
@@ -603,15 +890,17 @@ async fn push_config_to_agents(
     // RECORDS CRUD ENDPOINTS
     // ============================================================================
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, ToSchema)]
     struct CreateRecordReq {
         name: String,
         record_type: String,
         value: String,
         ttl: u32,
+        #[serde(default = "default_weight")]
+        weight: u32,
     }
 
-    #[derive(Serialize, Clone)]
+    #[derive(Serialize, Clone, ToSchema)]
     struct RecordResponse {
         id: String,
         zone_id: String,
@@ -619,30 +908,59 @@ async fn push_config_to_agents(
         record_type: String,
         value: String,
         ttl: u32,
+        weight: u32,
     }
 
+    #[utoipa::path(
+        post, path = "/api/v1/zones/{id}/records", tag = "records", params(("id" = String, Path, description = "Zone id")),
+        request_body = CreateRecordReq,
+        responses((status = 201, body = IdResponse), (status = 400), (status = 401), (status = 403, body = orgs::QuotaExceeded), (status = 404)),
+    )]
     async fn create_record(
         zone_id: web::Path<String>,
         body: web::Json<CreateRecordReq>,
         data: web::Data<AppState>,
         req: HttpRequest,
     ) -> impl Responder {
-        if auth_from_header(&req, &data.jwt_secret).is_none() {
-            return HttpResponse::Unauthorized().finish();
-        }
+        let caller = match orgs::caller(&data, &req).await {
+            Ok(c) => c,
+            Err(resp) => return resp,
+        };
     
         let id = Uuid::new_v4();
         let id_str = id.to_string();
         let zone_id_str = zone_id.into_inner();
+        let zone = match orgs::scoped_zone(&data.db, &caller, &zone_id_str).await {
+            Ok(Some(z)) => z,
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(e) => { warn!("create_record error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        };
+        if let Err(resp) = zone.require(Permission::Write) {
+            return resp;
+        }
     
-        let safe_name = body.name.replace("'", "''");
-        let safe_value = body.value.replace("'", "''");
-        let insert_sql = format!("INSERT INTO records (id, zone_id, name, type, value, ttl) VALUES ('{}', '{}', '{}', '{}', '{}', {})", id_str, zone_id_str, safe_name, body.record_type, safe_value, body.ttl);
-        info!("create_record (formatted SQL): {}", insert_sql);
-        let res = (&*data.db).execute(insert_sql.as_str(), &[]).await;
+        if body.weight > i32::MAX as u32 {
+            return HttpResponse::BadRequest().body("weight out of range");
+        }
+        if body.ttl > i32::MAX as u32 {
+            return HttpResponse::BadRequest().body("ttl out of range");
+        }
+        let res = data.db.execute(
+            &format!(
+                "INSERT INTO records (id, zone_id, name, type, value, ttl, weight) SELECT $1::text::uuid, $2::text::uuid, $3, $4, $5, $6, $7 WHERE (SELECT {})",
+                orgs::Quota::Records.allows("$8::text::uuid", "1"),
+            ),
+            &[&id_str, &zone_id_str, &body.name, &body.record_type, &body.value, &(body.ttl as i32), &(body.weight as i32), &zone.org_id],
+        ).await;
     
         match res {
-            Ok(_) => HttpResponse::Created().json(serde_json::json!({"id": id.to_string()})),
+            Ok(0) => orgs::quota_refused(&data.db, &[(zone.org_id.as_deref(), orgs::Quota::Records, 1)]).await,
+            Ok(_) => {
+                let org = zone_org(&data.db, &zone_id_str).await;
+                data.events.publish(Event::RecordsChanged { zone_id: zone_id_str, record_id: id_str, change: RecordChange::Created }, org).await;
+                ptr::sync_records(&data, [(body.record_type.as_str(), body.value.as_str())]).await;
+                HttpResponse::Created().json(IdResponse { id: id.to_string() })
+            }
             Err(e) => {
                 warn!("create_record error: {}", e);
                 HttpResponse::InternalServerError().finish()
@@ -650,23 +968,87 @@ async fn push_config_to_agents(
         }
     }
 
+    /// Records of a zone, ordered by name.
+    #[utoipa::path(
+        get, path = "/api/v1/zones/{id}/records", tag = "records", params(("id" = String, Path, description = "Zone id"), paging::RecordFilter),
+        responses((status = 200, body = [RecordResponse], headers(
+            ("X-Next-Cursor" = String, description = "Cursor of the next page, absent on the last page"),
+            ("X-Total-Count" = i64, description = "Matching records, on the first page only and not with `value_contains`"),
+        )), (status = 400), (status = 401), (status = 404)),
+    )]
     async fn list_records(
         zone_id: web::Path<String>,
+        query: web::Query<paging::RecordFilter>,
         data: web::Data<AppState>,
         req: HttpRequest,
     ) -> impl Responder {
-        if auth_from_header(&req, &data.jwt_secret).is_none() {
-            return HttpResponse::Unauthorized().finish();
-        }
+        let caller = match orgs::caller(&data, &req).await {
+            Ok(c) => c,
+            Err(resp) => return resp,
+        };
     
         let zone_id_str = zone_id.into_inner();
-        let rows = (&*data.db)
-            .query(
-                "SELECT id::text, zone_id::text, name, type, value, ttl FROM records WHERE zone_id::text = $1 ORDER BY name",
-                &[&zone_id_str]
-            )
-            .await
-            .unwrap_or_default();
+        match orgs::scoped_zone(&data.db, &caller, &zone_id_str).await {
+            Ok(Some(_)) => {}
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(e) => { warn!("list_records error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        }
+        let mut conds = paging::Conditions::default();
+        let zone = conds.bind(zone_id_str);
+        conds.push(format!("r.zone_id = {}::text::uuid", zone));
+        record_page(&data, conds, &query).await
+    }
+
+    /// `GET /api/v1/records`: search records across the zones of the caller's organizations.
+    #[utoipa::path(
+        get, path = "/api/v1/records", tag = "records", params(paging::RecordFilter),
+        responses((status = 200, body = [RecordResponse], headers(
+            ("X-Next-Cursor" = String, description = "Cursor of the next page, absent on the last page"),
+            ("X-Total-Count" = i64, description = "Matching records, on the first page only and not with `value_contains`"),
+        )), (status = 400), (status = 401)),
+    )]
+    async fn search_records(
+        query: web::Query<paging::RecordFilter>,
+        data: web::Data<AppState>,
+        req: HttpRequest,
+    ) -> impl Responder {
+        let caller = match orgs::caller(&data, &req).await {
+            Ok(c) => c,
+            Err(resp) => return resp,
+        };
+        let mut conds = paging::Conditions::default();
+        let (all, orgs, sub) = (conds.bind(caller.all), conds.bind(caller.orgs.clone()), conds.bind(caller.sub.clone()));
+        conds.push(format!("r.zone_id IN (SELECT z.id FROM zones z WHERE {} OR z.org_id::text = ANY({}) OR {})", all, orgs, sharing::shared_sql("z.id", &sub, &orgs)));
+        record_page(&data, conds, &query).await
+    }
+
+    /// One page of the records matching `conds` and the filters, ordered by name.
+    async fn record_page(data: &AppState, mut conds: paging::Conditions, query: &paging::RecordFilter) -> HttpResponse {
+        let limit = match paging::page_limit(query.limit) {
+            Ok(l) => l,
+            Err(e) => return HttpResponse::BadRequest().body(e),
+        };
+        query.apply(&mut conds);
+        let total = match query.cursor {
+            // a substring of the value can only be counted by reading every record in scope
+            None if query.value_contains.as_deref().is_some_and(|v| !v.is_empty()) => None,
+            None => match data.db.query_one(format!("SELECT count(*) FROM records r {}", conds.where_clause()).as_str(), &conds.params()).await {
+                Ok(row) => Some(row.get::<usize, i64>(0)),
+                Err(e) => { warn!("list_records error: {}", e); return HttpResponse::InternalServerError().finish(); }
+            },
+            Some(_) => None,
+        };
+        if let Err(e) = paging::after_cursor(&mut conds, query.cursor.as_deref(), "r.name", "r.id") {
+            return HttpResponse::BadRequest().body(e);
+        }
+        let sql = format!(
+            "SELECT r.id::text, r.zone_id::text, r.name, r.type, r.value, r.ttl, r.weight FROM records r {} ORDER BY r.name, r.id LIMIT {}",
+            conds.where_clause(), limit + 1,
+        );
+        let rows = match data.db.query(sql.as_str(), &conds.params()).await {
+            Ok(rows) => rows,
+            Err(e) => { warn!("list_records error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        };
     
         let records: Vec<RecordResponse> = rows.into_iter().map(|r| RecordResponse {
             id: r.get::<usize, String>(0),
@@ -675,30 +1057,44 @@ async fn push_config_to_agents(
             record_type: r.get::<usize, String>(3),
             value: r.get::<usize, String>(4),
             ttl: r.get::<usize, i32>(5) as u32,
+            weight: r.get::<usize, i32>(6) as u32,
         }).collect();
     
-        HttpResponse::Ok().json(records)
+        paging::page_response(records, limit, total, |r| paging::Cursor { key: r.name.clone(), id: r.id.clone() })
     }
 
-    #[derive(Deserialize)]
+    #[derive(Deserialize, ToSchema)]
     struct UpdateRecordReq {
         name: Option<String>,
         record_type: Option<String>,
         value: Option<String>,
         ttl: Option<u32>,
+        weight: Option<u32>,
     }
 
+    /// Change some fields of a record.
+    #[utoipa::path(
+        put, path = "/api/v1/zones/{zone_id}/records/{record_id}", tag = "records",
+        params(("zone_id" = String, Path, description = "Zone id"), ("record_id" = String, Path, description = "Record id")),
+        request_body = UpdateRecordReq, responses((status = 200), (status = 400), (status = 401), (status = 404)),
+    )]
     async fn update_record(
         path: web::Path<(String, String)>,
         body: web::Json<UpdateRecordReq>,
         data: web::Data<AppState>,
         req: HttpRequest,
     ) -> impl Responder {
-        if auth_from_header(&req, &data.jwt_secret).is_none() {
-            return HttpResponse::Unauthorized().finish();
-        }
+        let caller = match orgs::caller(&data, &req).await {
+            Ok(c) => c,
+            Err(resp) => return resp,
+        };
     
         let (zone_id, record_id) = path.into_inner();
+        match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+            Ok(Some(zone)) => if let Err(resp) = zone.require(Permission::Write) { return resp; },
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(e) => { warn!("update_record error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        }
     
         // Build dynamic update query
         let mut updates = vec![];
@@ -720,11 +1116,21 @@ async fn push_config_to_agents(
             params.push(value);
             param_idx += 1;
         }
-        let ttl_str;
-        if let Some(ttl) = body.ttl {
-            ttl_str = ttl.to_string();
+        let ttl;
+        if let Some(t) = body.ttl {
+            ttl = t as i32;
             updates.push(format!("ttl = ${}", param_idx));
-            params.push(&ttl_str);
+            params.push(&ttl);
+            param_idx += 1;
+        }
+        let weight;
+        if let Some(w) = body.weight {
+            weight = match i32::try_from(w) {
+                Ok(w) => w,
+                Err(_) => return HttpResponse::BadRequest().body("weight out of range"),
+            };
+            updates.push(format!("weight = ${}", param_idx));
+            params.push(&weight);
             param_idx += 1;
         }
     
@@ -732,23 +1138,26 @@ async fn push_config_to_agents(
             return HttpResponse::BadRequest().body("no fields to update");
         }
     
+        // the old type and value come back too, so that the PTR of a previous address is cleaned up
         let query = format!(
-            "UPDATE records SET {} WHERE id::text = ${} AND zone_id::text = ${}",
+            "UPDATE records SET {} FROM records old WHERE old.id = records.id AND records.id::text = ${} AND records.zone_id::text = ${}
+             RETURNING old.type, old.value, records.type, records.value",
             updates.join(", "), param_idx, param_idx + 1
         );
     
         params.push(&record_id);
         params.push(&zone_id);
     
-        let res = (&*data.db).execute(query.as_str(), params.as_slice()).await;
+        let res = data.db.query_opt(query.as_str(), params.as_slice()).await;
     
         match res {
-            Ok(count) => {
-                if count == 0 {
-                    HttpResponse::NotFound().finish()
-                } else {
-                    HttpResponse::Ok().finish()
-                }
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(row)) => {
+                let org = zone_org(&data.db, &zone_id).await;
+                data.events.publish(Event::RecordsChanged { zone_id, record_id, change: RecordChange::Updated }, org).await;
+                let changed: Vec<(String, String)> = vec![(row.get(0), row.get(1)), (row.get(2), row.get(3))];
+                ptr::sync_records(&data, changed.iter().map(|(t, v)| (t.as_str(), v.as_str()))).await;
+                HttpResponse::Ok().finish()
             }
             Err(e) => {
                 warn!("update_record error: {}", e);
@@ -757,29 +1166,40 @@ async fn push_config_to_agents(
         }
     }
 
+    #[utoipa::path(
+        delete, path = "/api/v1/zones/{zone_id}/records/{record_id}", tag = "records",
+        params(("zone_id" = String, Path, description = "Zone id"), ("record_id" = String, Path, description = "Record id")),
+        responses((status = 200), (status = 401), (status = 404)),
+    )]
     async fn delete_record(
         path: web::Path<(String, String)>,
         data: web::Data<AppState>,
         req: HttpRequest,
     ) -> impl Responder {
-        if auth_from_header(&req, &data.jwt_secret).is_none() {
-            return HttpResponse::Unauthorized().finish();
-        }
+        let caller = match orgs::caller(&data, &req).await {
+            Ok(c) => c,
+            Err(resp) => return resp,
+        };
     
         let (zone_id, record_id) = path.into_inner();
+        match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+            Ok(Some(zone)) => if let Err(resp) = zone.require(Permission::Write) { return resp; },
+            Ok(None) => return HttpResponse::NotFound().finish(),
+            Err(e) => { warn!("delete_record error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        }
     
-        let res = (&*data.db).execute(
-            "DELETE FROM records WHERE id::text = $1 AND zone_id::text = $2",
+        let res = data.db.query_opt(
+            "DELETE FROM records WHERE id::text = $1 AND zone_id::text = $2 RETURNING type, value",
             &[&record_id, &zone_id]
         ).await;
     
         match res {
-            Ok(count) => {
-                if count == 0 {
-                    HttpResponse::NotFound().finish()
-                } else {
-                    HttpResponse::Ok().finish()
-                }
+            Ok(None) => HttpResponse::NotFound().finish(),
+            Ok(Some(row)) => {
+                let org = zone_org(&data.db, &zone_id).await;
+                data.events.publish(Event::RecordsChanged { zone_id, record_id, change: RecordChange::Deleted }, org).await;
+                ptr::sync_records(&data, [(row.get(0), row.get(1))]).await;
+                HttpResponse::Ok().finish()
             }
             Err(e) => {
                 warn!("delete_record error: {}", e);
@@ -795,7 +1215,7 @@ async fn main() -> std::io::Result<()> {
     let database_url = std::env::var("DATABASE_URL").unwrap_or_else(|_| "host=db user=postgres password=password dbname=hickory".to_string());
     let jwt_secret = std::env::var("JWT_SECRET").unwrap_or_else(|_| "replace_with_a_super_secret".to_string());
 
-    let (client, connection) = tokio_postgres::connect(&database_url, NoTls).await.expect("cannot connect to db");
+    let (mut client, connection) = tokio_postgres::connect(&database_url, NoTls).await.expect("cannot connect to db");
     // spawn connection driver
     tokio::spawn(async move {
         if let Err(e) = connection.await {
@@ -804,10 +1224,19 @@ async fn main() -> std::io::Result<()> {
     });
     migrate_db(&client).await.expect("db migrate failed");
 
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() {
+        if let Err(e) = backup::run_cli(&mut client, &args).await {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     // Bootstrap admin user if environment variables are set
     if let (Ok(admin_user), Ok(admin_password)) = (std::env::var("ADMIN_USERNAME"), std::env::var("ADMIN_PASSWORD")) {
         // check if a user exists with that username
-        if let Ok(rows) = (&client).query("SELECT id::text, password_hash FROM users WHERE username = $1 LIMIT 1", &[&admin_user]).await {
+        if let Ok(rows) = client.query("SELECT id::text, password_hash FROM users WHERE username = $1 LIMIT 1", &[&admin_user]).await {
             if rows.is_empty() {
                 // create new admin
                 let mut rng = OsRng;
@@ -817,7 +1246,7 @@ async fn main() -> std::io::Result<()> {
                 let id = Uuid::new_v4();
                 let id_str = id.to_string();
                 let role = "admin";
-                match (&client).execute("INSERT INTO users (id, username, password_hash, role) VALUES ($1, $2, $3, $4)", &[&id_str, &admin_user, &password_hash, &role]).await {
+                match client.execute("INSERT INTO users (id, username, password_hash, role) VALUES ($1::text::uuid, $2, $3, $4)", &[&id_str, &admin_user, &password_hash, &role]).await {
                     Ok(_) => info!("Bootstrapped admin user '{}'", admin_user),
                     Err(e) => warn!("Failed to create admin user '{}': {}", admin_user, e),
                 }
@@ -829,7 +1258,7 @@ async fn main() -> std::io::Result<()> {
                     let salt = SaltString::generate(&mut rng);
                     let argon2 = Argon2::default();
                     let password_hash = argon2.hash_password(admin_password.as_bytes(), &salt).unwrap().to_string();
-                    match (&client).execute("UPDATE users SET password_hash = $1 WHERE username = $2", &[&password_hash, &admin_user]).await {
+                    match client.execute("UPDATE users SET password_hash = $1 WHERE username = $2", &[&password_hash, &admin_user]).await {
                         Ok(_) => info!("Updated admin user '{}' password hash", admin_user),
                         Err(e) => warn!("Failed to update admin user '{}': {}", admin_user, e),
                     }
@@ -842,17 +1271,26 @@ async fn main() -> std::io::Result<()> {
         }
     }
 
-    let app_state = AppState { db: std::sync::Arc::new(client), jwt_secret: jwt_secret.clone() };
+    let db = std::sync::Arc::new(client);
+    let events = EventBus::new(db.clone());
+    events::spawn_webhook_dispatcher(events.clone());
+    events::spawn_agent_presence_monitor(events.clone());
+    health::spawn_health_checker(db.clone(), events.clone());
+    acme::spawn_challenge_reaper(db.clone(), events.clone());
+    metrics::spawn_metrics_refresher(db.clone());
+    let app_state = AppState { db, database_url, jwt_secret: jwt_secret.clone(), events };
 
-    // Load GeoIP DB if provided
-    let geo_db = std::env::var("GEOIP_DB_PATH").ok().and_then(|p| {
-        std::fs::read(p).ok().and_then(|b| geodns::GeoDB::open_from_bytes(b).ok())
-    });
+    // Load GeoIP DB if provided, along with an ASN database for `asn` rules
+    let geo_paths = ["GEOIP_DB_PATH", "GEOIP_ASN_DB_PATH"].iter().filter_map(|var| std::env::var(var).ok()).map(std::path::PathBuf::from).collect();
+    let geo_db = geodns::ReloadableGeoDB::new(geo_paths);
+    let _ = geo::reload_geo_db(&geo_db);
+    let reload_secs = std::env::var("GEOIP_RELOAD_INTERVAL_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(60);
+    geo::spawn_geo_db_watcher(geo_db.clone(), std::time::Duration::from_secs(reload_secs));
 
-    let full_state = FullState { inner: app_state.clone(), geo: std::sync::Arc::new(tokio::sync::Mutex::new(GeoState { db: geo_db })), processes: std::sync::Arc::new(tokio::sync::Mutex::new(HashMap::new())) };
+    let full_state = FullState { inner: app_state.clone(), geo: geo_db, processes: std::sync::Arc::new(tokio::sync::Mutex::new(HashMap::new())) };
 
-    // Prometheus metrics middleware
-    let prometheus = PrometheusMetricsBuilder::new("control_api").endpoint("/metrics").build().expect("prometheus builder");
+    // Prometheus metrics middleware, serving the domain metrics along with its own
+    let prometheus = PrometheusMetricsBuilder::new("control_api").endpoint("/metrics").registry(prometheus::default_registry().clone()).build().expect("prometheus builder");
 
     let app_data = web::Data::new(app_state.clone());
     let full_data = web::Data::new(full_state.clone());
@@ -863,38 +1301,40 @@ async fn main() -> std::io::Result<()> {
                 .wrap(prometheus.clone())
                 .app_data(app_data.clone())
                 .app_data(full_data.clone())
-            .route("/api/v1/auth/login", web::post().to(login))
-            .route("/api/v1/users", web::post().to(create_user))
-            .route("/api/v1/servers", web::get().to(list_servers))
-            .route("/api/v1/servers", web::post().to(create_server))
-                    .route("/api/v1/zones", web::get().to(list_zones))
-                    .route("/api/v1/zones", web::post().to(create_zone))
-            .route("/api/v1/agents/register", web::post().to(agent_register))
-                            .route("/api/v1/agents/heartbeat", web::post().to(agent_heartbeat))
-                            .route("/api/v1/agents", web::get().to(list_agents))
-            .route("/api/v1/dns/start", web::post().to(start_dns_server))
-            .route("/api/v1/dns/stop", web::post().to(stop_dns_server))
-            .route("/api/v1/georules", web::post().to(create_georule))
-            .route("/api/v1/georules", web::get().to(list_georules))
-            .route("/api/v1/georules/resolve", web::post().to(resolve_by_geo))
-            .route("/api/v1/config/push", web::post().to(push_config_to_agents))
-            .route("/health", web::get().to(health))
-            .route("/api/v1/agents/{id}/config", web::get().to(agent_get_config))
-            .route("/api/v1/agents/{id}/token/rotate", web::post().to(rotate_agent_token))
-            // explicit metrics handler (in addition to middleware-exposed endpoint)
-            .route("/metrics", web::get().to(|| async move {
-                let encoder = TextEncoder::new();
-                let metric_families = gather();
-                let mut buffer = Vec::new();
-                encoder.encode(&metric_families, &mut buffer).unwrap_or(());
-                HttpResponse::Ok().content_type("text/plain; version=0.0.4").body(buffer)
-            }))
-                .route("/api/v1/zones/{id}/records", web::post().to(create_record))
-                .route("/api/v1/zones/{id}/records", web::get().to(list_records))
-                .route("/api/v1/zones/{zone_id}/records/{record_id}", web::put().to(update_record))
-                .route("/api/v1/zones/{zone_id}/records/{record_id}", web::delete().to(delete_record))
+                .configure(api::configure)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(name: &str, value: &str, weight: u32) -> ZoneRecord {
+        ZoneRecord { name: name.to_string(), record_type: "A".to_string(), value: value.to_string(), ttl: 300, weight }
+    }
+
+    #[test]
+    fn test_zone_config_weights() {
+        let unweighted = [record("www", "192.0.2.1", 1), record("www", "192.0.2.2", 1)];
+        let config = zone_config("example.com", "example_com.zone", &unweighted);
+        assert!(config.contains("zone_path = \"example_com.zone\""));
+        assert!(!config.contains("weighted"));
+
+        // every record of a weighted RRset is listed, other RRsets are left alone
+        let records = [
+            record("www", "192.0.2.1", 3),
+            record("WWW", "192.0.2.2", 1),
+            record("", "192.0.2.3", 0),
+            record("mail", "192.0.2.4", 1),
+        ];
+        let config = zone_config("example.com", "example_com.zone", &records);
+        assert!(config.contains("type = \"weighted\""));
+        assert!(config.contains(r#"{ name = "www", record_type = "A", value = "192.0.2.1", weight = 3 }"#));
+        assert!(config.contains(r#"{ name = "WWW", record_type = "A", value = "192.0.2.2", weight = 1 }"#));
+        assert!(config.contains(r#"{ name = "example.com.", record_type = "A", value = "192.0.2.3", weight = 0 }"#));
+        assert!(!config.contains("192.0.2.4"));
+    }
+}
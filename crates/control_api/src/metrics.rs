@@ -0,0 +1,216 @@
+//! Domain metrics of the control plane, served on `/metrics` along with the HTTP metrics.
+//!
+//! The gauges are refreshed from the database every [`REFRESH_INTERVAL`] by
+//! [`spawn_metrics_refresher`], so that scrapes never query it; counters are incremented where
+//! the counted thing happens.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::Duration;
+
+use log::warn;
+use once_cell::sync::Lazy;
+use prometheus::core::Collector;
+use prometheus::{register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec};
+use tokio_postgres::Client as PgClient;
+
+use crate::events::{AGENT_OFFLINE_AFTER_SECS, CONFIG_EVENT_TYPES};
+
+/// How often the gauges are recomputed.
+pub const REFRESH_INTERVAL: Duration = Duration::from_secs(15);
+
+static ZONES: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("control_api_zones", "Zones per organization", &["org"]).unwrap()
+});
+
+static RECORDS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("control_api_records", "Records per organization", &["org"]).unwrap()
+});
+
+static AGENTS: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!("control_api_agents", "Registered agents, by whether they are online", &["state"]).unwrap()
+});
+
+static AGENT_CONFIG_LAG: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "control_api_agent_config_lag",
+        "Configuration changes made since the version the agent last applied",
+        &["agent_id", "agent"]
+    ).unwrap()
+});
+
+static PENDING_PUSHES: Lazy<IntGauge> = Lazy::new(|| {
+    register_int_gauge!("control_api_config_pushes_pending", "Agents which have not applied the current configuration yet").unwrap()
+});
+
+static HEALTH_TARGET_UP: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "control_api_health_target_up",
+        "Whether a health checked target is healthy (1) or not (0)",
+        &["zone", "record", "record_id", "target"]
+    ).unwrap()
+});
+
+pub static FAILED_LOGINS: Lazy<IntCounter> = Lazy::new(|| {
+    register_int_counter!("control_api_failed_logins_total", "Logins refused for an unknown user or a wrong password").unwrap()
+});
+
+static GEOIP_BUILD_EPOCH: Lazy<IntGaugeVec> = Lazy::new(|| {
+    register_int_gauge_vec!(
+        "control_api_geoip_build_epoch",
+        "Build time of the loaded GeoIP databases, in seconds since the Unix epoch",
+        &["database_type"]
+    ).unwrap()
+});
+
+pub static GEOIP_RELOADS: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!("control_api_geoip_reloads_total", "GeoIP database reloads, by whether they succeeded", &["result"]).unwrap()
+});
+
+/// Label of the zones and records not belonging to any organization.
+const NO_ORG: &str = "";
+
+/// Register the metrics, so that they are exported before the first refresh.
+pub fn register() {
+    Lazy::force(&ZONES);
+    Lazy::force(&RECORDS);
+    Lazy::force(&AGENTS);
+    Lazy::force(&AGENT_CONFIG_LAG);
+    Lazy::force(&PENDING_PUSHES);
+    Lazy::force(&HEALTH_TARGET_UP);
+    Lazy::force(&FAILED_LOGINS);
+    Lazy::force(&GEOIP_BUILD_EPOCH);
+    Lazy::force(&GEOIP_RELOADS);
+}
+
+/// Export the versions of the GeoIP databases just loaded.
+pub fn set_geoip_databases(databases: &[geodns::GeoDbInfo]) {
+    replace(&GEOIP_BUILD_EPOCH, databases.iter().map(|db| (vec![db.database_type.as_str()], db.build_epoch as i64)));
+}
+
+/// Set the gauges of `vec` to `values`, given as `(label values, value)`, then drop the label
+/// sets not among them.
+///
+/// Unlike `reset()` followed by `set()`, a scrape in between sees each label set with either its
+/// old or its new value, never an empty vector.
+fn replace<'a>(vec: &IntGaugeVec, values: impl IntoIterator<Item = (Vec<&'a str>, i64)>) {
+    let names = vec.desc()[0].variable_labels.clone();
+    let mut kept = HashSet::new();
+    for (labels, value) in values {
+        vec.with_label_values(&labels).set(value);
+        kept.insert(names.iter().map(String::as_str).zip(labels).collect::<BTreeMap<_, _>>());
+    }
+    for family in vec.collect() {
+        for metric in family.get_metric() {
+            let labels: BTreeMap<&str, &str> = metric.get_label().iter().map(|l| (l.get_name(), l.get_value())).collect();
+            if !kept.contains(&labels) {
+                // the label set was just read from the vector, so it is there to remove
+                let _ = vec.remove(&labels.into_iter().collect::<HashMap<_, _>>());
+            }
+        }
+    }
+}
+
+/// Spawn the task refreshing the gauges.
+pub fn spawn_metrics_refresher(db: std::sync::Arc<PgClient>) {
+    register();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = refresh(&db).await {
+                warn!("metrics refresh failed: {}", e);
+            }
+        }
+    });
+}
+
+/// Recompute the gauges. Everything is read before any gauge is touched, and the gauge vectors
+/// are updated label set by label set with [`replace`], so that a scrape never sees a vector
+/// emptied for the refresh; label sets which went away (deleted organizations, agents or
+/// targets) are dropped.
+async fn refresh(db: &PgClient) -> Result<(), tokio_postgres::Error> {
+    // every organization, with or without zones, and the zones outside of any
+    let per_org = db.query(
+        "SELECT o.name, count(DISTINCT z.id), count(r.id)
+         FROM orgs o LEFT JOIN zones z ON z.org_id = o.id LEFT JOIN records r ON r.zone_id = z.id
+         GROUP BY o.id, o.name
+         UNION ALL
+         SELECT $1, count(DISTINCT z.id), count(r.id)
+         FROM zones z LEFT JOIN records r ON r.zone_id = z.id WHERE z.org_id IS NULL
+         HAVING count(z.id) > 0",
+        &[&NO_ORG],
+    ).await?;
+    let agents = db.query(
+        "SELECT a.id::text, coalesce(a.name, ''), a.online AND a.last_heartbeat >= now() - make_interval(secs => $2),
+                (SELECT count(*) FROM events e WHERE e.type = ANY($1) AND e.id > coalesce(ap.version, 0))
+         FROM agents a LEFT JOIN LATERAL (
+             SELECT max((payload->>'version')::bigint) AS version FROM events
+             WHERE type = 'config_applied' AND payload->>'agent_id' = a.id::text
+         ) ap ON true",
+        &[&&CONFIG_EVENT_TYPES[..], &(AGENT_OFFLINE_AFTER_SECS as f64)],
+    ).await?;
+    let targets = db.query(
+        "SELECT z.domain, coalesce(r.name, ''), r.id::text, t.value, t.healthy
+         FROM health_targets t JOIN records r ON r.id = t.record_id JOIN zones z ON z.id = r.zone_id",
+        &[],
+    ).await?;
+
+    replace(&ZONES, per_org.iter().map(|row| (vec![row.get(0)], row.get(1))));
+    replace(&RECORDS, per_org.iter().map(|row| (vec![row.get(0)], row.get(2))));
+
+    let (mut online, mut offline, mut pending) = (0, 0, 0);
+    let mut lags = Vec::with_capacity(agents.len());
+    for row in &agents {
+        let (id, name): (&str, &str) = (row.get(0), row.get(1));
+        let lag: i64 = row.get(3);
+        if row.get(2) { online += 1 } else { offline += 1 }
+        if lag > 0 {
+            pending += 1;
+        }
+        lags.push((vec![id, name], lag));
+    }
+    replace(&AGENT_CONFIG_LAG, lags);
+    AGENTS.with_label_values(&["online"]).set(online);
+    AGENTS.with_label_values(&["offline"]).set(offline);
+    PENDING_PUSHES.set(pending);
+
+    replace(&HEALTH_TARGET_UP, targets.iter().map(|row| {
+        let healthy: bool = row.get(4);
+        (vec![row.get(0), row.get(1), row.get(2), row.get(3)], healthy as i64)
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register() {
+        register();
+        register();
+        FAILED_LOGINS.inc();
+        let names: Vec<String> = prometheus::gather().into_iter().map(|f| f.get_name().to_string()).collect();
+        // vectors without any label set are not gathered
+        for name in ["control_api_config_pushes_pending", "control_api_failed_logins_total"] {
+            assert!(names.iter().any(|n| n == name), "{} not exported: {:?}", name, names);
+        }
+    }
+
+    #[test]
+    fn test_replace() {
+        let vec = IntGaugeVec::new(prometheus::Opts::new("test_replace", "test"), &["org", "zone"]).unwrap();
+        let exported = |vec: &IntGaugeVec| -> Vec<(Vec<String>, i64)> {
+            let mut out: Vec<_> = vec.collect()[0].get_metric().iter()
+                .map(|m| (m.get_label().iter().map(|l| l.get_value().to_string()).collect(), m.get_gauge().get_value() as i64))
+                .collect();
+            out.sort();
+            out
+        };
+        replace(&vec, [(vec!["a", "x"], 1), (vec!["b", "y"], 2)]);
+        replace(&vec, [(vec!["b", "y"], 3), (vec!["c", "z"], 4)]);
+        assert_eq!(exported(&vec), vec![(vec!["b".to_string(), "y".to_string()], 3), (vec!["c".to_string(), "z".to_string()], 4)]);
+        replace(&vec, []);
+        assert!(vec.collect()[0].get_metric().is_empty());
+    }
+}
@@ -0,0 +1,535 @@
+//! Organizations, their members and quotas.
+//!
+//! Zones and templates belong to an organization, and users see and change what belongs to the
+//! organizations they are members of. A request is scoped to one organization with the
+//! `X-Org-Id` header; without it, a user's requests are scoped to all their organizations, and
+//! creating something requires them to be in exactly one.
+//!
+//! Platform admins (`role == "admin"` in the token) create organizations and set their quotas.
+//! Without `X-Org-Id` their requests see every organization; with it, only the selected one.
+//! Organization admins manage their organization's members.
+//!
+//! Zones can also be shared with users and organizations outside their own, see
+//! [`crate::sharing`].
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::error::SqlState;
+use tokio_postgres::Client as PgClient;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::IdResponse;
+use crate::sharing::{self, Permission};
+use crate::{auth_from_header, AppState, Claims};
+
+/// Header selecting the organization a request is scoped to.
+pub const ORG_HEADER: &str = "X-Org-Id";
+
+/// Roles of organization members.
+const MEMBER_ROLES: [&str; 2] = ["admin", "member"];
+
+/// The authenticated user of a request and the organizations in its scope.
+#[derive(Clone)]
+pub struct Caller {
+    pub sub: String,
+    /// Platform admin
+    pub admin: bool,
+    /// Everything is in scope: a platform admin without a selected organization
+    pub all: bool,
+    /// Organizations in scope
+    pub orgs: Vec<String>,
+}
+
+impl Caller {
+    /// The organization new zones and templates are created in.
+    pub fn org_for_create(&self) -> Result<&str, HttpResponse> {
+        match self.orgs.as_slice() {
+            [org] => Ok(org),
+            [] => Err(HttpResponse::Forbidden().body("not a member of any organization")),
+            _ => Err(HttpResponse::BadRequest().body(format!("select an organization with the {} header", ORG_HEADER))),
+        }
+    }
+}
+
+/// Authenticate a request and resolve its organization scope.
+pub async fn caller(data: &AppState, req: &HttpRequest) -> Result<Caller, HttpResponse> {
+    let tok = auth_from_header(req, &data.jwt_secret).ok_or_else(|| HttpResponse::Unauthorized().finish())?;
+    scope(&data.db, &tok.claims, req).await
+}
+
+/// Resolve the organization scope of already authenticated claims.
+pub async fn scope(db: &PgClient, claims: &Claims, req: &HttpRequest) -> Result<Caller, HttpResponse> {
+    let admin = claims.role == "admin";
+    let selected = req.headers().get(ORG_HEADER).and_then(|h| h.to_str().ok()).map(|s| s.trim().to_ascii_lowercase());
+    let rows = match &selected {
+        Some(org) if admin => db.query("SELECT id::text FROM orgs WHERE id::text = $1", &[org]).await,
+        _ => db.query("SELECT org_id::text FROM org_members WHERE user_id::text = $1 ORDER BY org_id", &[&claims.sub]).await,
+    };
+    let mut orgs: Vec<String> = match rows {
+        Ok(rows) => rows.into_iter().map(|r| r.get(0)).collect(),
+        Err(e) => { warn!("org scope error: {}", e); return Err(HttpResponse::InternalServerError().finish()); }
+    };
+    if let Some(org) = &selected {
+        if !orgs.contains(org) {
+            return Err(HttpResponse::Forbidden().body("not a member of the selected organization"));
+        }
+        orgs = vec![org.clone()];
+    }
+    Ok(Caller { sub: claims.sub.clone(), admin, all: admin && selected.is_none(), orgs })
+}
+
+/// A zone visible to a caller.
+pub struct ScopedZone {
+    pub domain: String,
+    pub org_id: Option<String>,
+    /// What the caller may do: everything in their organizations, what it was shared with otherwise
+    pub permission: Permission,
+}
+
+impl ScopedZone {
+    /// Refuse a request needing more than the caller's permission on the zone.
+    pub fn require(&self, permission: Permission) -> Result<(), HttpResponse> {
+        match self.permission >= permission {
+            true => Ok(()),
+            false => Err(HttpResponse::Forbidden().body(format!("{} permission on the zone required", permission.as_str()))),
+        }
+    }
+}
+
+/// The zone `zone_id`, if it exists and is in the caller's scope or shared with them.
+pub async fn scoped_zone(db: &PgClient, caller: &Caller, zone_id: &str) -> Result<Option<ScopedZone>, tokio_postgres::Error> {
+    let row = db.query_opt(
+        &format!(
+            "SELECT z.domain, z.org_id::text, $2 OR z.org_id::text = ANY($3), {} FROM zones z WHERE z.id::text = $1",
+            sharing::permission_sql("z.id", "$4", "$3"),
+        ),
+        &[&zone_id, &caller.all, &caller.orgs, &caller.sub],
+    ).await?;
+    Ok(row.and_then(|r| {
+        let permission = match r.get(2) {
+            true => Permission::Admin,
+            false => Permission::parse(r.get::<_, Option<&str>>(3)?)?,
+        };
+        Some(ScopedZone { domain: r.get(0), org_id: r.get(1), permission })
+    }))
+}
+
+/// Resources capped by organization quotas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Quota {
+    Zones,
+    Records,
+    Georules,
+}
+
+impl Quota {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Zones => "zones",
+            Self::Records => "records",
+            Self::Georules => "georules",
+        }
+    }
+
+    /// Query for `(limit, usage)` of an organization.
+    fn usage_sql(self) -> &'static str {
+        match self {
+            Self::Zones => "SELECT o.max_zones, quota_usage(o.id, 'zones') FROM orgs o WHERE o.id::text = $1",
+            Self::Records => "SELECT o.max_records, quota_usage(o.id, 'records') FROM orgs o WHERE o.id::text = $1",
+            Self::Georules => "SELECT o.max_georules, quota_usage(o.id, 'georules') FROM orgs o WHERE o.id::text = $1",
+        }
+    }
+
+    /// SQL condition allowing a write that adds `adding` of the resource to the organization `org`,
+    /// both SQL expressions.
+    ///
+    /// The organization stays locked until the write commits, so that concurrent writes are
+    /// counted one after the other. Wrap it in a subquery, `WHERE (SELECT ...)`, so that it is
+    /// evaluated once per statement rather than once per row. Zones outside any organization are
+    /// not limited. Records published by the server itself, such as managed PTRs and ACME
+    /// challenges, count towards the usage but are written without this condition.
+    pub fn allows(self, org: &str, adding: &str) -> String {
+        format!("quota_allows({}, '{}', {})", org, self.name(), adding)
+    }
+}
+
+/// Whether `adding` more of a resource fits in the organization's quota.
+fn within_quota(limit: Option<i32>, usage: i64, adding: i64) -> bool {
+    limit.is_none_or(|limit| usage + adding <= i64::from(limit))
+}
+
+/// Response refusing a creation over quota.
+#[derive(Serialize, ToSchema)]
+pub struct QuotaExceeded {
+    /// Always `quota exceeded`
+    error: String,
+    /// `zones`, `records` or `georules`
+    resource: String,
+    limit: Option<i32>,
+    usage: i64,
+}
+
+/// The quota `adding` more of a resource would exceed, if any.
+async fn over_quota(db: &PgClient, org_id: &str, quota: Quota, adding: i64) -> Result<Option<QuotaExceeded>, tokio_postgres::Error> {
+    let row = match db.query_opt(quota.usage_sql(), &[&org_id]).await? {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let (limit, usage): (Option<i32>, i64) = (row.get(0), row.get(1));
+    Ok((!within_quota(limit, usage, adding)).then(|| QuotaExceeded {
+        error: "quota exceeded".to_string(),
+        resource: quota.name().to_string(),
+        limit,
+        usage,
+    }))
+}
+
+/// The response to a write refused by [`Quota::allows`], given what it would have added as
+/// `(org_id, quota, adding)`.
+pub async fn quota_refused(db: &PgClient, wanted: &[(Option<&str>, Quota, i64)]) -> HttpResponse {
+    for &(org_id, quota, adding) in wanted {
+        let Some(org_id) = org_id else { continue };
+        match over_quota(db, org_id, quota, adding).await {
+            Ok(Some(exceeded)) => return HttpResponse::Forbidden().json(exceeded),
+            Ok(None) => {}
+            Err(e) => { warn!("quota check error: {}", e); return HttpResponse::InternalServerError().finish(); }
+        }
+    }
+    // the usage went down again since the write was refused
+    HttpResponse::Conflict().body("quota exceeded, retry")
+}
+
+#[derive(Deserialize, Serialize, Default, ToSchema)]
+pub struct Quotas {
+    /// `None` for no limit
+    max_zones: Option<i32>,
+    max_records: Option<i32>,
+    max_georules: Option<i32>,
+}
+
+impl Quotas {
+    fn validate(&self) -> Result<(), &'static str> {
+        match [self.max_zones, self.max_records, self.max_georules].iter().flatten().any(|&q| q < 0) {
+            true => Err("quotas must not be negative"),
+            false => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateOrgReq {
+    name: String,
+    #[serde(flatten)]
+    quotas: Quotas,
+    /// Users made admins of the new organization
+    #[serde(default)]
+    admins: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct OrgResponse {
+    id: String,
+    name: String,
+    /// Role of the caller in the organization, `None` for platform admins who are not members
+    role: Option<String>,
+    quotas: Quotas,
+    usage: Usage,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Usage {
+    zones: i64,
+    records: i64,
+    georules: i64,
+}
+
+const ORG_COLUMNS: &str =
+    "o.id::text, o.name, (SELECT m.role FROM org_members m WHERE m.org_id = o.id AND m.user_id::text = $1), o.max_zones, o.max_records, o.max_georules,
+     (SELECT count(*) FROM zones z WHERE z.org_id = o.id),
+     (SELECT count(*) FROM records r JOIN zones z ON z.id = r.zone_id WHERE z.org_id = o.id),
+     (SELECT count(*) FROM georules g JOIN zones z ON z.id = g.zone_id WHERE z.org_id = o.id)";
+
+fn org_response(r: &tokio_postgres::Row) -> OrgResponse {
+    OrgResponse {
+        id: r.get(0),
+        name: r.get(1),
+        role: r.get(2),
+        quotas: Quotas { max_zones: r.get(3), max_records: r.get(4), max_georules: r.get(5) },
+        usage: Usage { zones: r.get(6), records: r.get(7), georules: r.get(8) },
+    }
+}
+
+/// `POST /api/v1/orgs`: create an organization (platform admins).
+#[utoipa::path(
+    post, path = "/api/v1/orgs", tag = "orgs", request_body = CreateOrgReq,
+    responses((status = 201, body = IdResponse), (status = 400), (status = 401), (status = 403), (status = 409)),
+)]
+pub async fn create_org(body: web::Json<CreateOrgReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    let name = body.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().body("name is required");
+    }
+    if let Err(e) = body.quotas.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let id = Uuid::new_v4().to_string();
+    let res = data.db.query_one(
+        "WITH org AS (INSERT INTO orgs (id, name, max_zones, max_records, max_georules) VALUES ($1::text::uuid, $2, $3, $4, $5) RETURNING id),
+              admins AS (INSERT INTO org_members (org_id, user_id, role) SELECT org.id, u.id, 'admin' FROM org, users u WHERE u.id::text = ANY($6) RETURNING user_id)
+         SELECT count(*) FROM admins",
+        &[&id, &name, &body.quotas.max_zones, &body.quotas.max_records, &body.quotas.max_georules, &body.admins],
+    ).await;
+    match res {
+        Ok(row) if row.get::<usize, i64>(0) as usize != body.admins.len() => {
+            // unknown admins: undo rather than leave an organization nobody manages
+            data.db.execute("DELETE FROM orgs WHERE id::text = $1", &[&id]).await.ok();
+            HttpResponse::BadRequest().body("unknown user in admins")
+        }
+        Ok(_) => {
+            info!("created organization {}", name);
+            HttpResponse::Created().json(IdResponse { id })
+        }
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => HttpResponse::Conflict().body("an organization with this name exists"),
+        Err(e) => { warn!("create_org error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `GET /api/v1/orgs`: the caller's organizations, with quotas and usage.
+#[utoipa::path(get, path = "/api/v1/orgs", tag = "orgs", responses((status = 200, body = [OrgResponse]), (status = 401)))]
+pub async fn list_orgs(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let rows = data.db.query(
+        &format!("SELECT {} FROM orgs o WHERE $2 OR o.id::text = ANY($3) ORDER BY o.name", ORG_COLUMNS),
+        &[&caller.sub, &caller.all, &caller.orgs],
+    ).await;
+    match rows {
+        Ok(rows) => HttpResponse::Ok().json(rows.iter().map(org_response).collect::<Vec<_>>()),
+        Err(e) => { warn!("list_orgs error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `GET /api/v1/orgs/{id}`
+#[utoipa::path(get, path = "/api/v1/orgs/{id}", tag = "orgs", params(("id" = String, Path, description = "Organization id")), responses((status = 200, body = OrgResponse), (status = 401), (status = 404)))]
+pub async fn get_org(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let id = path.into_inner();
+    let row = data.db.query_opt(
+        &format!("SELECT {} FROM orgs o WHERE o.id::text = $4 AND ($2 OR o.id::text = ANY($3))", ORG_COLUMNS),
+        &[&caller.sub, &caller.all, &caller.orgs, &id],
+    ).await;
+    match row {
+        Ok(Some(row)) => HttpResponse::Ok().json(org_response(&row)),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("get_org error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `PUT /api/v1/orgs/{id}/quotas`: replace the quotas of an organization (platform admins).
+///
+/// Lowering a quota below the current usage only prevents further growth.
+#[utoipa::path(
+    put, path = "/api/v1/orgs/{id}/quotas", tag = "orgs", params(("id" = String, Path, description = "Organization id")), request_body = Quotas,
+    responses((status = 200), (status = 400), (status = 401), (status = 403), (status = 404)),
+)]
+pub async fn set_quotas(path: web::Path<String>, body: web::Json<Quotas>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    if let Err(e) = body.validate() {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let res = data.db.execute(
+        "UPDATE orgs SET max_zones = $2, max_records = $3, max_georules = $4 WHERE id::text = $1",
+        &[&path.into_inner(), &body.max_zones, &body.max_records, &body.max_georules],
+    ).await;
+    match res {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("set_quotas error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `DELETE /api/v1/orgs/{id}`: delete an organization without zones (platform admins).
+#[utoipa::path(
+    delete, path = "/api/v1/orgs/{id}", tag = "orgs", params(("id" = String, Path, description = "Organization id")),
+    responses((status = 200), (status = 401), (status = 403), (status = 404), (status = 409, description = "The organization still has zones or templates")),
+)]
+pub async fn delete_org(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    match auth_from_header(&req, &data.jwt_secret) {
+        Some(tok) if tok.claims.role == "admin" => {}
+        Some(_) => return HttpResponse::Forbidden().finish(),
+        None => return HttpResponse::Unauthorized().finish(),
+    }
+    match data.db.execute("DELETE FROM orgs WHERE id::text = $1", &[&path.into_inner()]).await {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) if e.code() == Some(&SqlState::FOREIGN_KEY_VIOLATION) => HttpResponse::Conflict().body("organization still has zones or templates"),
+        Err(e) => { warn!("delete_org error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// Check that the caller may manage the members of an organization: its admins and platform
+/// admins may.
+async fn check_org_admin(data: &AppState, req: &HttpRequest, org_id: &str) -> Result<(), HttpResponse> {
+    let tok = auth_from_header(req, &data.jwt_secret).ok_or_else(|| HttpResponse::Unauthorized().finish())?;
+    let row = data.db.query_opt(
+        "SELECT $2 OR EXISTS (SELECT 1 FROM org_members m WHERE m.org_id = o.id AND m.user_id::text = $3 AND m.role = 'admin') FROM orgs o WHERE o.id::text = $1",
+        &[&org_id, &(tok.claims.role == "admin"), &tok.claims.sub],
+    ).await;
+    match row {
+        Ok(Some(row)) if row.get(0) => Ok(()),
+        Ok(Some(_)) => Err(HttpResponse::Forbidden().finish()),
+        Ok(None) => Err(HttpResponse::NotFound().finish()),
+        Err(e) => { warn!("org admin check error: {}", e); Err(HttpResponse::InternalServerError().finish()) }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Member {
+    user_id: String,
+    username: String,
+    role: String,
+}
+
+/// `GET /api/v1/orgs/{id}/members`: visible to all members.
+#[utoipa::path(get, path = "/api/v1/orgs/{id}/members", tag = "orgs", params(("id" = String, Path, description = "Organization id")), responses((status = 200, body = [Member]), (status = 401), (status = 404)))]
+pub async fn list_members(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let org_id = path.into_inner();
+    if !caller.all && !caller.orgs.contains(&org_id) {
+        return HttpResponse::NotFound().finish();
+    }
+    let rows = data.db.query(
+        "SELECT u.id::text, u.username, m.role FROM org_members m JOIN users u ON u.id = m.user_id WHERE m.org_id::text = $1 ORDER BY u.username",
+        &[&org_id],
+    ).await;
+    match rows {
+        Ok(rows) => {
+            let out: Vec<_> = rows.into_iter().map(|r| Member { user_id: r.get(0), username: r.get(1), role: r.get(2) }).collect();
+            HttpResponse::Ok().json(out)
+        }
+        Err(e) => { warn!("list_members error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AddMemberReq {
+    username: String,
+    #[serde(default = "default_member_role")]
+    role: String,
+}
+
+fn default_member_role() -> String {
+    "member".to_string()
+}
+
+/// `POST /api/v1/orgs/{id}/members`: add a user to an organization, or change their role.
+#[utoipa::path(
+    post, path = "/api/v1/orgs/{id}/members", tag = "orgs", params(("id" = String, Path, description = "Organization id")), request_body = AddMemberReq,
+    responses((status = 200, body = Member), (status = 400), (status = 401), (status = 403), (status = 404)),
+)]
+pub async fn add_member(path: web::Path<String>, body: web::Json<AddMemberReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let org_id = path.into_inner();
+    if let Err(resp) = check_org_admin(&data, &req, &org_id).await {
+        return resp;
+    }
+    if !MEMBER_ROLES.contains(&body.role.as_str()) {
+        return HttpResponse::BadRequest().body("role must be admin or member");
+    }
+    let row = data.db.query_opt(
+        "INSERT INTO org_members (org_id, user_id, role) SELECT $1::text::uuid, id, $3 FROM users WHERE username = $2
+         ON CONFLICT (org_id, user_id) DO UPDATE SET role = $3 RETURNING user_id::text",
+        &[&org_id, &body.username, &body.role],
+    ).await;
+    match row {
+        Ok(Some(row)) => HttpResponse::Ok().json(Member { user_id: row.get(0), username: body.username.clone(), role: body.role.clone() }),
+        Ok(None) => HttpResponse::NotFound().body("unknown user"),
+        Err(e) => { warn!("add_member error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `DELETE /api/v1/orgs/{id}/members/{user_id}`
+///
+/// The last admin of an organization cannot be removed.
+#[utoipa::path(
+    delete, path = "/api/v1/orgs/{id}/members/{user_id}", tag = "orgs",
+    params(("id" = String, Path, description = "Organization id"), ("user_id" = String, Path, description = "User id")),
+    responses((status = 200), (status = 401), (status = 403), (status = 404), (status = 409, description = "The user is the last admin")),
+)]
+pub async fn remove_member(path: web::Path<(String, String)>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let (org_id, user_id) = path.into_inner();
+    if let Err(resp) = check_org_admin(&data, &req, &org_id).await {
+        return resp;
+    }
+    let res = data.db.query_opt(
+        "DELETE FROM org_members m WHERE m.org_id::text = $1 AND m.user_id::text = $2
+         AND (m.role <> 'admin' OR EXISTS (SELECT 1 FROM org_members o WHERE o.org_id = m.org_id AND o.user_id <> m.user_id AND o.role = 'admin'))
+         RETURNING m.role",
+        &[&org_id, &user_id],
+    ).await;
+    match res {
+        Ok(Some(_)) => HttpResponse::Ok().finish(),
+        Ok(None) => {
+            let exists = data.db.query_opt(
+                "SELECT 1 FROM org_members WHERE org_id::text = $1 AND user_id::text = $2",
+                &[&org_id, &user_id],
+            ).await;
+            match exists {
+                Ok(Some(_)) => HttpResponse::Conflict().body("cannot remove the last admin of an organization"),
+                Ok(None) => HttpResponse::NotFound().finish(),
+                Err(e) => { warn!("remove_member error: {}", e); HttpResponse::InternalServerError().finish() }
+            }
+        }
+        Err(e) => { warn!("remove_member error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caller(orgs: &[&str]) -> Caller {
+        Caller { sub: "u".into(), admin: false, all: false, orgs: orgs.iter().map(|o| o.to_string()).collect() }
+    }
+
+    #[test]
+    fn test_org_for_create() {
+        assert_eq!(caller(&["a"]).org_for_create().ok(), Some("a"));
+        assert_eq!(caller(&[]).org_for_create().unwrap_err().status(), 403);
+        assert_eq!(caller(&["a", "b"]).org_for_create().unwrap_err().status(), 400);
+    }
+
+    #[test]
+    fn test_within_quota() {
+        assert!(within_quota(None, 1_000_000, 1));
+        assert!(within_quota(Some(10), 9, 1));
+        assert!(!within_quota(Some(10), 10, 1));
+        assert!(!within_quota(Some(10), 5, 6));
+        assert!(within_quota(Some(0), 0, 0));
+    }
+
+    #[test]
+    fn test_quotas_validate() {
+        assert!(Quotas::default().validate().is_ok());
+        assert!(Quotas { max_zones: Some(0), ..Quotas::default() }.validate().is_ok());
+        assert!(Quotas { max_records: Some(-1), ..Quotas::default() }.validate().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+//! Cursor pagination and filters for list endpoints.
+//!
+//! Listings are ordered by a unique key (name then id) and paged with an opaque cursor holding
+//! the key of the last row returned, so an unfiltered page costs an index range scan however deep
+//! it is. Name prefixes are looked up in an index of the lowercased names; the other filters
+//! (name suffix, type, TTL, value) are checked on the rows read, so a selective one can read many
+//! rows per page. Bodies stay plain JSON arrays; paging information travels in headers:
+//!
+//! * `X-Next-Cursor`: pass back as `cursor` for the next page, absent on the last page,
+//! * `X-Total-Count`: rows matching the filters, computed for the first page only so that
+//!   walking a large listing counts it once. Records filtered by a value substring are not
+//!   counted: that reads every record in scope.
+
+use actix_web::HttpResponse;
+use data_encoding::BASE64URL_NOPAD;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::types::ToSql;
+use utoipa::IntoParams;
+
+/// Page size when `limit` is not given.
+pub const DEFAULT_LIMIT: i64 = 100;
+
+/// Largest page size.
+pub const MAX_LIMIT: i64 = 1000;
+
+pub const NEXT_CURSOR_HEADER: &str = "X-Next-Cursor";
+pub const TOTAL_COUNT_HEADER: &str = "X-Total-Count";
+
+/// A `WHERE` clause under construction, with its parameters.
+#[derive(Default)]
+pub struct Conditions {
+    clauses: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl Conditions {
+    /// Add a parameter, returning its placeholder.
+    pub fn bind<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.params.push(Box::new(value));
+        format!("${}", self.params.len())
+    }
+
+    pub fn push(&mut self, clause: String) {
+        self.clauses.push(clause);
+    }
+
+    pub fn where_clause(&self) -> String {
+        match self.clauses.is_empty() {
+            true => String::new(),
+            false => format!("WHERE {}", self.clauses.join(" AND ")),
+        }
+    }
+
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|p| p.as_ref() as &(dyn ToSql + Sync)).collect()
+    }
+}
+
+/// Escape `%`, `_` and `\` for use in a `LIKE` pattern.
+pub fn like_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Position in a listing: the sort key and id of the last row of a page.
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub key: String,
+    pub id: String,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        BASE64URL_NOPAD.encode(&serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn decode(s: &str) -> Result<Self, String> {
+        let bytes = BASE64URL_NOPAD.decode(s.as_bytes()).map_err(|_| "invalid cursor".to_string())?;
+        serde_json::from_slice(&bytes).map_err(|_| "invalid cursor".to_string())
+    }
+}
+
+/// The page size for a requested `limit`.
+pub fn page_limit(limit: Option<i64>) -> Result<i64, String> {
+    match limit.unwrap_or(DEFAULT_LIMIT) {
+        l if (1..=MAX_LIMIT).contains(&l) => Ok(l),
+        _ => Err(format!("limit must be between 1 and {}", MAX_LIMIT)),
+    }
+}
+
+/// Restrict `conds` to rows after `cursor` in `(key, id)` order.
+pub fn after_cursor(conds: &mut Conditions, cursor: Option<&str>, key: &str, id: &str) -> Result<(), String> {
+    if let Some(cursor) = cursor {
+        let cursor = Cursor::decode(cursor)?;
+        let (k, i) = (conds.bind(cursor.key), conds.bind(cursor.id));
+        conds.push(format!("({}, {}) > ({}, {}::text::uuid)", key, id, k, i));
+    }
+    Ok(())
+}
+
+/// Filters of record listings and the record search.
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecordFilter {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub name_prefix: Option<String>,
+    pub name_suffix: Option<String>,
+    /// One type or a comma separated list
+    #[serde(rename = "type")]
+    pub record_type: Option<String>,
+    pub ttl_min: Option<i32>,
+    pub ttl_max: Option<i32>,
+    /// Case-insensitive substring of the value
+    pub value_contains: Option<String>,
+}
+
+impl RecordFilter {
+    /// Add the filters, for the `records` table aliased as `r`.
+    pub fn apply(&self, conds: &mut Conditions) {
+        if let Some(prefix) = self.name_prefix.as_deref().filter(|s| !s.is_empty()) {
+            let p = conds.bind(format!("{}%", like_escape(&prefix.to_ascii_lowercase())));
+            conds.push(format!("lower(r.name) LIKE {}", p));
+        }
+        if let Some(suffix) = self.name_suffix.as_deref().filter(|s| !s.is_empty()) {
+            let p = conds.bind(format!("%{}", like_escape(&suffix.to_ascii_lowercase())));
+            conds.push(format!("lower(r.name) LIKE {}", p));
+        }
+        if let Some(types) = &self.record_type {
+            let types: Vec<String> = types.split(',').map(|t| t.trim().to_ascii_uppercase()).filter(|t| !t.is_empty()).collect();
+            if !types.is_empty() {
+                let p = conds.bind(types);
+                conds.push(format!("upper(r.type) = ANY({})", p));
+            }
+        }
+        if let Some(min) = self.ttl_min {
+            let p = conds.bind(min);
+            conds.push(format!("r.ttl >= {}", p));
+        }
+        if let Some(max) = self.ttl_max {
+            let p = conds.bind(max);
+            conds.push(format!("r.ttl <= {}", p));
+        }
+        if let Some(value) = self.value_contains.as_deref().filter(|s| !s.is_empty()) {
+            let p = conds.bind(format!("%{}%", like_escape(value)));
+            conds.push(format!("r.value ILIKE {}", p));
+        }
+    }
+}
+
+/// Filters of the zone listing.
+#[derive(Deserialize, Default, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ZoneFilter {
+    pub limit: Option<i64>,
+    pub cursor: Option<String>,
+    pub name_prefix: Option<String>,
+    pub name_suffix: Option<String>,
+}
+
+impl ZoneFilter {
+    /// Add the filters, for the `zones` table aliased as `z`.
+    pub fn apply(&self, conds: &mut Conditions) {
+        if let Some(prefix) = self.name_prefix.as_deref().filter(|s| !s.is_empty()) {
+            let p = conds.bind(format!("{}%", like_escape(&prefix.to_ascii_lowercase())));
+            conds.push(format!("lower(z.domain) LIKE {}", p));
+        }
+        if let Some(suffix) = self.name_suffix.as_deref().filter(|s| !s.is_empty()) {
+            let p = conds.bind(format!("%{}", like_escape(&suffix.to_ascii_lowercase())));
+            conds.push(format!("lower(z.domain) LIKE {}", p));
+        }
+    }
+}
+
+/// Respond with one page of a listing.
+///
+/// `items` holds up to one row more than `limit`, telling whether there is a next page;
+/// `key` gives the cursor of a row.
+pub fn page_response<T: Serialize>(mut items: Vec<T>, limit: i64, total: Option<i64>, key: impl Fn(&T) -> Cursor) -> HttpResponse {
+    let mut resp = HttpResponse::Ok();
+    if items.len() as i64 > limit {
+        items.truncate(limit as usize);
+        if let Some(last) = items.last() {
+            resp.insert_header((NEXT_CURSOR_HEADER, key(last).encode()));
+        }
+    }
+    if let Some(total) = total {
+        resp.insert_header((TOTAL_COUNT_HEADER, total.to_string()));
+    }
+    resp.json(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let cursor = Cursor { key: "www".into(), id: "2b1f0f7e-3f1a-4a39-9b8e-2f5c8d9a0b1c".into() };
+        let encoded = cursor.encode();
+        assert!(!encoded.contains('='));
+        assert_eq!(Cursor::decode(&encoded).unwrap(), cursor);
+        assert!(Cursor::decode("not a cursor").is_err());
+        assert!(Cursor::decode(&BASE64URL_NOPAD.encode(b"[1]")).is_err());
+    }
+
+    #[test]
+    fn test_page_limit() {
+        assert_eq!(page_limit(None), Ok(DEFAULT_LIMIT));
+        assert_eq!(page_limit(Some(MAX_LIMIT)), Ok(MAX_LIMIT));
+        assert!(page_limit(Some(0)).is_err());
+        assert!(page_limit(Some(MAX_LIMIT + 1)).is_err());
+    }
+
+    #[test]
+    fn test_like_escape() {
+        assert_eq!(like_escape("100%_a\\b"), "100\\%\\_a\\\\b");
+        assert_eq!(like_escape("www"), "www");
+    }
+
+    #[test]
+    fn test_record_filter() {
+        let filter = RecordFilter {
+            name_prefix: Some("WWW".into()),
+            record_type: Some("a, aaaa,".into()),
+            ttl_min: Some(60),
+            value_contains: Some("10.0".into()),
+            ..RecordFilter::default()
+        };
+        let mut conds = Conditions::default();
+        conds.push("r.zone_id = $1::text::uuid".into());
+        conds.bind("zone".to_string());
+        filter.apply(&mut conds);
+        after_cursor(&mut conds, Some(&Cursor { key: "a".into(), id: "b".into() }.encode()), "r.name", "r.id").unwrap();
+        assert_eq!(
+            conds.where_clause(),
+            "WHERE r.zone_id = $1::text::uuid AND lower(r.name) LIKE $2 AND upper(r.type) = ANY($3) AND r.ttl >= $4 AND r.value ILIKE $5 AND (r.name, r.id) > ($6, $7::text::uuid)",
+        );
+        assert_eq!(conds.params().len(), 7);
+    }
+
+    #[test]
+    fn test_page_response() {
+        let resp = page_response(vec![1, 2, 3], 2, Some(10), |i| Cursor { key: i.to_string(), id: String::new() });
+        let next = resp.headers().get(NEXT_CURSOR_HEADER).unwrap().to_str().unwrap();
+        assert_eq!(Cursor::decode(next).unwrap().key, "2");
+        assert_eq!(resp.headers().get(TOTAL_COUNT_HEADER).unwrap(), "10");
+
+        let resp = page_response(vec![1, 2], 2, None, |i| Cursor { key: i.to_string(), id: String::new() });
+        assert!(resp.headers().get(NEXT_CURSOR_HEADER).is_none());
+        assert!(resp.headers().get(TOTAL_COUNT_HEADER).is_none());
+    }
+}
@@ -0,0 +1,450 @@
+//! Automatic reverse (PTR) record management.
+//!
+//! Forward zones opt in with `auto_ptr`. Address ranges are mapped to reverse zones
+//! (`in-addr.arpa`/`ip6.arpa`); the most specific range containing an address decides which zone
+//! holds its PTR. Whenever A/AAAA records change, the affected addresses are re-synced:
+//!
+//! * one forward name claims the address: its PTR is created or pointed at it,
+//! * several names claim it: this is a conflict, reported by `GET /api/v1/ptr/conflicts`. The
+//!   current PTR is kept if it still points at one of them, otherwise the oldest claim wins,
+//! * no name claims it anymore: the PTR is removed.
+//!
+//! Managed PTRs carry the address they were created for in `records.ptr_address`. PTRs added by
+//! hand are never touched; an address with a manual PTR is left alone.
+
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use hickory_server::proto::rr::Name;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::error::SqlState;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::acme::publish_change;
+use crate::events::RecordChange;
+use crate::sharing::Permission;
+use crate::{orgs, AppState};
+
+/// Forward names claiming addresses, as `(address inet, fqdn with trailing dot, ttl, org_id)`.
+///
+/// `records.address` is the parsed value of A/AAAA records, generated on write and indexed.
+const CLAIMS: &str =
+    "SELECT r.address,
+            lower(CASE WHEN r.name IN ('', '@') THEN rtrim(z.domain, '.') ELSE r.name || '.' || rtrim(z.domain, '.') END) || '.' AS fqdn,
+            r.ttl, z.org_id, r.created_at, r.id
+     FROM records r JOIN zones z ON z.id = r.zone_id
+     WHERE z.auto_ptr AND r.address IS NOT NULL";
+
+/// Addresses of the A/AAAA records among `records`, given as `(type, value)` pairs.
+fn addresses<'a>(records: impl IntoIterator<Item = (&'a str, &'a str)>) -> BTreeSet<IpAddr> {
+    records.into_iter()
+        .filter_map(|(rtype, value)| match (rtype.to_ascii_uppercase().as_str(), IpAddr::from_str(value.trim())) {
+            ("A", Ok(ip @ IpAddr::V4(_))) | ("AAAA", Ok(ip @ IpAddr::V6(_))) => Some(ip),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Re-sync the PTRs of the addresses of the given `(type, value)` records.
+///
+/// Failures are logged: the forward change has already been made and PTRs can be re-synced by
+/// toggling `auto_ptr` on the zone.
+pub async fn sync_records<'a>(data: &AppState, records: impl IntoIterator<Item = (&'a str, &'a str)>) {
+    for ip in addresses(records) {
+        if let Err(e) = sync_address(data, ip).await {
+            warn!("ptr sync error for {}: {}", ip, e);
+        }
+    }
+}
+
+/// Re-sync the addresses of a forward zone and those with PTRs pointing into it.
+pub async fn sync_zone(data: &AppState, zone_id: &str, domain: &str) {
+    let suffix = format!("{}.", domain.trim_end_matches('.').to_ascii_lowercase());
+    let rows = data.db.query(
+        "SELECT host(address) FROM records WHERE zone_id = $1::text::uuid AND address IS NOT NULL
+         UNION SELECT host(ptr_address) FROM records WHERE ptr_address IS NOT NULL AND (lower(value) = $2 OR lower(value) LIKE '%.' || $2)",
+        &[&zone_id, &suffix],
+    ).await;
+    sync_rows(data, rows).await;
+}
+
+/// Re-sync every address inside `network` that is claimed or has a managed PTR.
+async fn sync_network(data: &AppState, network: &str) {
+    let rows = data.db.query(
+        &format!("SELECT DISTINCT host(address) FROM ({}) c WHERE address <<= $1::text::cidr
+                  UNION SELECT host(ptr_address) FROM records WHERE ptr_address <<= $1::text::cidr", CLAIMS),
+        &[&network],
+    ).await;
+    sync_rows(data, rows).await;
+}
+
+async fn sync_rows(data: &AppState, rows: Result<Vec<tokio_postgres::Row>, tokio_postgres::Error>) {
+    match rows {
+        Ok(rows) => {
+            let hosts: Vec<String> = rows.into_iter().map(|r| r.get(0)).collect();
+            sync_records(data, hosts.iter().map(|h| (if h.contains(':') { "AAAA" } else { "A" }, h.as_str()))).await;
+        }
+        Err(e) => warn!("ptr sync error: {}", e),
+    }
+}
+
+/// Bring the PTR of one address in line with the forward records claiming it.
+async fn sync_address(data: &AppState, ip: IpAddr) -> Result<(), tokio_postgres::Error> {
+    let address = ip.to_string();
+    let range = data.db.query_opt(
+        "SELECT z.id::text, z.domain FROM ptr_ranges p JOIN zones z ON z.id = p.zone_id
+         WHERE p.network >>= $1::text::inet ORDER BY masklen(p.network) DESC LIMIT 1",
+        &[&address],
+    ).await?;
+    let target = range.as_ref().and_then(|r| {
+        let domain: String = r.get(1);
+        let name = relative_name(ip, &domain);
+        if name.is_none() {
+            warn!("{} is mapped to {}, which is not one of its reverse zones", ip, domain);
+        }
+        Some((r.get::<usize, String>(0), name?))
+    });
+
+    // managed PTRs left in another zone, e.g. after the ranges changed
+    let stale = data.db.query(
+        "DELETE FROM records WHERE ptr_address = $1::text::inet AND ($2::text IS NULL OR zone_id::text <> $2 OR lower(name) <> $3)
+         RETURNING id::text, zone_id::text",
+        &[&address, &target.as_ref().map(|(z, _)| z.as_str()), &target.as_ref().map(|(_, n)| n.as_str()).unwrap_or_default()],
+    ).await?;
+    for row in stale {
+        publish_change(&data.events, &data.db, &row.get::<usize, String>(1), row.get(0), RecordChange::Deleted).await;
+    }
+    let (zone_id, name) = match target {
+        Some(t) => t,
+        None => return Ok(()),
+    };
+
+    let claims = data.db.query(
+        &format!("SELECT fqdn, ttl FROM ({}) c WHERE address = $1::text::inet ORDER BY created_at, id", CLAIMS),
+        &[&address],
+    ).await?;
+    let claimants: Vec<String> = claims.iter().map(|r| r.get(0)).collect();
+    let existing = data.db.query(
+        "SELECT id::text, value, ptr_address IS NOT NULL FROM records WHERE zone_id::text = $1 AND lower(name) = $2 AND type = 'PTR'",
+        &[&zone_id, &name],
+    ).await?;
+    if existing.iter().any(|r| !r.get::<usize, bool>(2)) {
+        // a PTR maintained by hand takes precedence
+        if !claimants.is_empty() {
+            info!("not managing the PTR of {}: it has a manual PTR", ip);
+        }
+        for row in existing.iter().filter(|r| r.get::<usize, bool>(2)) {
+            let id: String = row.get(0);
+            data.db.execute("DELETE FROM records WHERE id::text = $1", &[&id]).await?;
+            publish_change(&data.events, &data.db, &zone_id, id, RecordChange::Deleted).await;
+        }
+        return Ok(());
+    }
+
+    let current = existing.first().map(|r| (r.get::<usize, String>(0), r.get::<usize, String>(1)));
+    match (choose_target(&claimants, current.as_ref().map(|(_, v)| v.as_str())), current) {
+        (None, None) => {}
+        (None, Some((id, _))) => {
+            data.db.execute("DELETE FROM records WHERE id::text = $1", &[&id]).await?;
+            publish_change(&data.events, &data.db, &zone_id, id, RecordChange::Deleted).await;
+        }
+        (Some(fqdn), None) => {
+            let ttl: i32 = claims.iter().find(|r| r.get::<usize, String>(0) == fqdn).map(|r| r.get(1)).unwrap_or(3600);
+            let id = Uuid::new_v4().to_string();
+            data.db.execute(
+                "INSERT INTO records (id, zone_id, name, type, value, ttl, ptr_address) VALUES ($1::text::uuid, $2::text::uuid, $3, 'PTR', $4, $5, $6::text::inet)",
+                &[&id, &zone_id, &name, &fqdn, &ttl, &address],
+            ).await?;
+            publish_change(&data.events, &data.db, &zone_id, id, RecordChange::Created).await;
+        }
+        (Some(fqdn), Some((id, value))) => {
+            if value != fqdn {
+                data.db.execute("UPDATE records SET value = $2 WHERE id::text = $1", &[&id, &fqdn]).await?;
+                publish_change(&data.events, &data.db, &zone_id, id, RecordChange::Updated).await;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The name a PTR for `ip` should point at, given the names claiming it, oldest first.
+///
+/// The current target is kept while it is still claimed so that conflicts do not flap.
+fn choose_target(claimants: &[String], current: Option<&str>) -> Option<String> {
+    current
+        .and_then(|c| claimants.iter().find(|n| n.eq_ignore_ascii_case(c)))
+        .or_else(|| claimants.first())
+        .cloned()
+}
+
+/// Name of the PTR for `ip` relative to the reverse zone `domain`, `None` if the zone does not
+/// contain it. IPv6 addresses use the nibble format of `ip6.arpa`.
+fn relative_name(ip: IpAddr, domain: &str) -> Option<String> {
+    let reverse = Name::from(ip);
+    let zone = Name::from_ascii(format!("{}.", domain.trim_end_matches('.'))).ok()?;
+    if !zone.zone_of(&reverse) {
+        return None;
+    }
+    let labels = reverse.iter().count() - zone.iter().count();
+    if labels == 0 {
+        return Some("@".to_string());
+    }
+    let name: Vec<_> = reverse.iter().take(labels).map(String::from_utf8_lossy).collect();
+    Some(name.join(".").to_ascii_lowercase())
+}
+
+/// The network covered by a reverse zone, e.g. `192.0.2.0/24` for `2.0.192.in-addr.arpa`.
+fn zone_network(domain: &str) -> Option<String> {
+    let name = Name::from_ascii(format!("{}.", domain.trim_end_matches('.'))).ok()?;
+    name.parse_arpa_name().ok().map(|net| net.to_string())
+}
+
+/// Whether `network` is an address with a prefix length, e.g. `192.0.2.0/25`.
+fn valid_network(network: &str) -> bool {
+    let (addr, len) = match network.split_once('/') {
+        Some(parts) => parts,
+        None => return false,
+    };
+    match (IpAddr::from_str(addr), len.parse::<u8>()) {
+        (Ok(IpAddr::V4(_)), Ok(len)) => len <= 32,
+        (Ok(IpAddr::V6(_)), Ok(len)) => len <= 128,
+        _ => false,
+    }
+}
+
+#[derive(Deserialize, Serialize, ToSchema)]
+pub struct AutoPtr {
+    enabled: bool,
+}
+
+/// `PUT /api/v1/zones/{id}/ptr`: turn automatic PTR management on or off for a forward zone.
+///
+/// Existing addresses are synced right away, so turning it off removes the zone's managed PTRs.
+#[utoipa::path(
+    put, path = "/api/v1/zones/{id}/ptr", tag = "ptr", params(("id" = String, Path, description = "Zone id")), request_body = AutoPtr,
+    responses((status = 200, body = AutoPtr), (status = 401), (status = 404)),
+)]
+pub async fn set_auto_ptr(path: web::Path<String>, body: web::Json<AutoPtr>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    let res = data.db.query_opt(
+        "UPDATE zones SET auto_ptr = $4 WHERE id::text = $1 AND ($2 OR org_id::text = ANY($3)) RETURNING domain",
+        &[&zone_id, &caller.all, &caller.orgs, &body.enabled],
+    ).await;
+    match res {
+        Ok(Some(row)) => {
+            let domain: String = row.get(0);
+            sync_zone(&data, &zone_id, &domain).await;
+            HttpResponse::Ok().json(body.into_inner())
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("set_auto_ptr error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct CreateRangeReq {
+    /// Reverse zone holding the PTRs of the range
+    zone_id: String,
+    /// Range in CIDR notation, defaults to the whole reverse zone
+    network: Option<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct RangeResponse {
+    id: String,
+    network: String,
+    zone_id: String,
+    domain: String,
+}
+
+/// `POST /api/v1/ptr/ranges`: map an address range to a reverse zone.
+///
+/// The caller must manage the reverse zone, and the range must lie inside it.
+#[utoipa::path(
+    post, path = "/api/v1/ptr/ranges", tag = "ptr", request_body = CreateRangeReq,
+    responses((status = 201, body = RangeResponse), (status = 400), (status = 401), (status = 404), (status = 409, description = "The network is already mapped")),
+)]
+pub async fn create_range(body: web::Json<CreateRangeReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let domain = match orgs::scoped_zone(&data.db, &caller, &body.zone_id).await {
+        Ok(Some(zone)) => match zone.require(Permission::Write) { Ok(()) => zone.domain, Err(resp) => return resp },
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("create_range error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let zone_net = match zone_network(&domain) {
+        Some(n) => n,
+        None => return HttpResponse::BadRequest().body("zone is not an in-addr.arpa or ip6.arpa zone"),
+    };
+    let network = body.network.clone().unwrap_or_else(|| zone_net.clone());
+    if !valid_network(&network) {
+        return HttpResponse::BadRequest().body("invalid network");
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let res = data.db.query_opt(
+        "INSERT INTO ptr_ranges (id, network, zone_id)
+         SELECT $1::text::uuid, network($2::text::inet), $3::text::uuid WHERE network($2::text::inet) <<= $4::text::cidr
+         RETURNING network::text",
+        &[&id, &network, &body.zone_id, &zone_net],
+    ).await;
+    match res {
+        Ok(Some(row)) => {
+            let network: String = row.get(0);
+            info!("mapped {} to reverse zone {}", network, domain);
+            sync_network(&data, &network).await;
+            HttpResponse::Created().json(RangeResponse { id, network, zone_id: body.zone_id.clone(), domain })
+        }
+        Ok(None) => HttpResponse::BadRequest().body(format!("network is not inside {}", zone_net)),
+        Err(e) if e.code() == Some(&SqlState::UNIQUE_VIOLATION) => HttpResponse::Conflict().body("network is already mapped"),
+        Err(e) => { warn!("create_range error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `GET /api/v1/ptr/ranges`: ranges mapped to reverse zones of the caller's organizations.
+#[utoipa::path(get, path = "/api/v1/ptr/ranges", tag = "ptr", responses((status = 200, body = [RangeResponse]), (status = 401)))]
+pub async fn list_ranges(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let rows = data.db.query(
+        "SELECT p.id::text, p.network::text, p.zone_id::text, z.domain FROM ptr_ranges p JOIN zones z ON z.id = p.zone_id
+         WHERE $1 OR z.org_id::text = ANY($2) ORDER BY p.network",
+        &[&caller.all, &caller.orgs],
+    ).await.unwrap_or_default();
+    let out: Vec<_> = rows.into_iter().map(|r| RangeResponse { id: r.get(0), network: r.get(1), zone_id: r.get(2), domain: r.get(3) }).collect();
+    HttpResponse::Ok().json(out)
+}
+
+/// `DELETE /api/v1/ptr/ranges/{id}`: unmap a range.
+///
+/// Its addresses fall back to a less specific range, or lose their managed PTRs.
+#[utoipa::path(
+    delete, path = "/api/v1/ptr/ranges/{id}", tag = "ptr", params(("id" = String, Path, description = "Range id")),
+    responses((status = 200), (status = 401), (status = 404)),
+)]
+pub async fn delete_range(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let id = path.into_inner();
+    let res = data.db.query_opt(
+        "DELETE FROM ptr_ranges p USING zones z WHERE z.id = p.zone_id AND p.id::text = $1 AND ($2 OR z.org_id::text = ANY($3))
+         RETURNING p.network::text",
+        &[&id, &caller.all, &caller.orgs],
+    ).await;
+    match res {
+        Ok(Some(row)) => {
+            let network: String = row.get(0);
+            sync_network(&data, &network).await;
+            HttpResponse::Ok().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("delete_range error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Conflict {
+    address: String,
+    /// Forward names claiming the address
+    names: Vec<String>,
+    /// Current target of the managed PTR, if any
+    ptr: Option<String>,
+}
+
+/// `GET /api/v1/ptr/conflicts`: addresses claimed by more than one forward name.
+///
+/// Non-admins see the conflicts involving a zone of their organizations.
+#[utoipa::path(get, path = "/api/v1/ptr/conflicts", tag = "ptr", responses((status = 200, body = [Conflict]), (status = 401)))]
+pub async fn list_conflicts(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let rows = data.db.query(
+        &format!(
+            "SELECT host(c.address), array_agg(DISTINCT c.fqdn), (SELECT p.value FROM records p WHERE p.ptr_address = c.address LIMIT 1)
+             FROM ({}) c GROUP BY c.address
+             HAVING count(DISTINCT c.fqdn) > 1 AND ($1 OR bool_or(c.org_id::text = ANY($2)))
+             ORDER BY c.address",
+            CLAIMS,
+        ),
+        &[&caller.all, &caller.orgs],
+    ).await;
+    match rows {
+        Ok(rows) => {
+            let out: Vec<_> = rows.into_iter().map(|r| Conflict { address: r.get(0), names: r.get(1), ptr: r.get(2) }).collect();
+            HttpResponse::Ok().json(out)
+        }
+        Err(e) => { warn!("list_conflicts error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_relative_name_ipv4() {
+        let ip = IpAddr::from_str("192.0.2.5").unwrap();
+        assert_eq!(relative_name(ip, "2.0.192.in-addr.arpa").as_deref(), Some("5"));
+        assert_eq!(relative_name(ip, "0.192.in-addr.arpa.").as_deref(), Some("5.2"));
+        assert_eq!(relative_name(ip, "5.2.0.192.IN-ADDR.ARPA").as_deref(), Some("@"));
+        assert_eq!(relative_name(ip, "3.0.192.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn test_relative_name_ipv6() {
+        let ip = IpAddr::from_str("2001:db8::1").unwrap();
+        assert_eq!(
+            relative_name(ip, "8.b.d.0.1.0.0.2.ip6.arpa").as_deref(),
+            Some("1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0"),
+        );
+        assert_eq!(relative_name(ip, "2.0.192.in-addr.arpa"), None);
+    }
+
+    #[test]
+    fn test_zone_network() {
+        assert_eq!(zone_network("2.0.192.in-addr.arpa").as_deref(), Some("192.0.2.0/24"));
+        assert_eq!(zone_network("8.b.d.0.1.0.0.2.ip6.arpa.").as_deref(), Some("2001:db8::/32"));
+        assert_eq!(zone_network("example.com"), None);
+    }
+
+    #[test]
+    fn test_valid_network() {
+        assert!(valid_network("192.0.2.0/25"));
+        assert!(valid_network("2001:db8::/48"));
+        assert!(!valid_network("192.0.2.0/33"));
+        assert!(!valid_network("192.0.2.0"));
+        assert!(!valid_network("example.com/24"));
+    }
+
+    #[test]
+    fn test_addresses() {
+        let ips = addresses([("A", "192.0.2.1"), ("aaaa", "2001:db8::1"), ("A", "2001:db8::2"), ("TXT", "192.0.2.3"), ("A", "bogus")]);
+        let ips: Vec<_> = ips.into_iter().map(|ip| ip.to_string()).collect();
+        assert_eq!(ips, vec!["192.0.2.1", "2001:db8::1"]);
+    }
+
+    #[test]
+    fn test_choose_target() {
+        let claimants = vec!["a.example.com.".to_string(), "b.example.com.".to_string()];
+        assert_eq!(choose_target(&claimants, None).as_deref(), Some("a.example.com."));
+        assert_eq!(choose_target(&claimants, Some("b.example.com.")).as_deref(), Some("b.example.com."));
+        assert_eq!(choose_target(&claimants, Some("c.example.com.")).as_deref(), Some("a.example.com."));
+        assert_eq!(choose_target(&[], Some("a.example.com.")), None);
+    }
+}
@@ -0,0 +1,459 @@
+//! Zone ownership transfer and sharing.
+//!
+//! A zone belongs to an organization and has an owner, the user who created it. The owner, the
+//! admins of the organization and platform admins can transfer the zone to another user, along
+//! with moving it to another organization. Zones can also be shared with users or organizations
+//! outside their own at a [`Permission`] level; members of the zone's organization keep full
+//! access.
+//!
+//! Transfers and share changes are published as events, which makes them part of the audit
+//! trail, and are listed in the detail view of the zone.
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Client as PgClient;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::IdResponse;
+use crate::events::Event;
+use crate::orgs::{self, Caller, Quota};
+use crate::AppState;
+
+/// Event types of the access changes shown in the detail view of a zone.
+const ACCESS_EVENT_TYPES: [&str; 3] = ["zone_transferred", "zone_shared", "zone_unshared"];
+
+/// Access changes listed in the detail view of a zone.
+const HISTORY_LIMIT: i64 = 100;
+
+/// What a user may do with a zone, from least to most.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// See the zone and its records
+    Read,
+    /// Also change its records, geo rules, health checks and ACME credentials
+    Write,
+    /// Also share it
+    Admin,
+}
+
+impl Permission {
+    const ALL: [Self; 3] = [Self::Read, Self::Write, Self::Admin];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Admin => "admin",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.as_str() == s)
+    }
+}
+
+/// SQL expression of the highest permission the zone `zone` is shared with to the user `user` or
+/// one of the organizations `orgs`, NULL if it is not shared with them.
+pub fn permission_sql(zone: &str, user: &str, orgs: &str) -> String {
+    format!(
+        "(SELECT s.permission FROM zone_shares s WHERE s.zone_id = {} AND (s.user_id::text = {} OR s.org_id::text = ANY({}))
+          ORDER BY array_position(ARRAY['read', 'write', 'admin'], s.permission) DESC LIMIT 1)",
+        zone, user, orgs,
+    )
+}
+
+/// SQL condition that the zone `zone` is shared with the user `user` or one of the organizations
+/// `orgs`, for lists which include shared zones.
+pub fn shared_sql(zone: &str, user: &str, orgs: &str) -> String {
+    format!("EXISTS (SELECT 1 FROM zone_shares s WHERE s.zone_id = {} AND (s.user_id::text = {} OR s.org_id::text = ANY({})))", zone, user, orgs)
+}
+
+/// A user referred to by a zone.
+#[derive(Serialize, ToSchema)]
+pub struct UserRef {
+    id: String,
+    /// `None` if the user was deleted
+    username: Option<String>,
+}
+
+/// A user or an organization a zone is shared with.
+#[derive(Serialize, ToSchema)]
+pub struct ZoneShare {
+    id: String,
+    user_id: Option<String>,
+    username: Option<String>,
+    org_id: Option<String>,
+    org_name: Option<String>,
+    permission: Permission,
+    /// User who shared the zone
+    created_by: Option<String>,
+    created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// An ownership transfer or share change, from the event log.
+#[derive(Serialize, ToSchema)]
+pub struct AccessChange {
+    /// Id of the event
+    id: i64,
+    timestamp: chrono::DateTime<chrono::Utc>,
+    /// `zone_transferred`, `zone_shared` or `zone_unshared`
+    #[serde(rename = "type")]
+    kind: String,
+    /// The event, as published
+    #[schema(value_type = Object)]
+    details: serde_json::Value,
+}
+
+/// A zone with its owner and who it is shared with.
+#[derive(Serialize, ToSchema)]
+pub struct ZoneDetail {
+    id: String,
+    domain: String,
+    /// Organization the zone belongs to
+    org_id: Option<String>,
+    org_name: Option<String>,
+    owner: Option<UserRef>,
+    /// Template the zone was created from
+    template_id: Option<String>,
+    /// Permission of the caller on the zone
+    permission: Permission,
+    records: i64,
+    shares: Vec<ZoneShare>,
+    /// Ownership transfers and share changes, latest first
+    history: Vec<AccessChange>,
+}
+
+async fn shares(db: &PgClient, zone_id: &str) -> Result<Vec<ZoneShare>, tokio_postgres::Error> {
+    let rows = db.query(
+        "SELECT s.id::text, s.user_id::text, u.username, s.org_id::text, o.name, s.permission, s.created_by::text, s.created_at
+         FROM zone_shares s LEFT JOIN users u ON u.id = s.user_id LEFT JOIN orgs o ON o.id = s.org_id
+         WHERE s.zone_id::text = $1 ORDER BY s.created_at, s.id",
+        &[&zone_id],
+    ).await?;
+    Ok(rows.into_iter().map(|r| ZoneShare {
+        id: r.get(0),
+        user_id: r.get(1),
+        username: r.get(2),
+        org_id: r.get(3),
+        org_name: r.get(4),
+        permission: Permission::parse(r.get(5)).unwrap_or(Permission::Read),
+        created_by: r.get(6),
+        created_at: r.get(7),
+    }).collect())
+}
+
+async fn zone_detail(db: &PgClient, zone_id: &str, permission: Permission) -> Result<Option<ZoneDetail>, tokio_postgres::Error> {
+    let row = db.query_opt(
+        "SELECT z.id::text, z.domain, z.org_id::text, o.name, z.owner::text, u.username, z.template_id::text,
+                (SELECT count(*) FROM records r WHERE r.zone_id = z.id)
+         FROM zones z LEFT JOIN orgs o ON o.id = z.org_id LEFT JOIN users u ON u.id = z.owner WHERE z.id::text = $1",
+        &[&zone_id],
+    ).await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    let history = db.query(
+        "SELECT id, created_at, type, payload FROM events WHERE payload->>'zone_id' = $1 AND type = ANY($2) ORDER BY id DESC LIMIT $3",
+        &[&zone_id, &&ACCESS_EVENT_TYPES[..], &HISTORY_LIMIT],
+    ).await?;
+    Ok(Some(ZoneDetail {
+        id: row.get(0),
+        domain: row.get(1),
+        org_id: row.get(2),
+        org_name: row.get(3),
+        owner: row.get::<_, Option<String>>(4).map(|id| UserRef { id, username: row.get(5) }),
+        template_id: row.get(6),
+        permission,
+        records: row.get(7),
+        shares: shares(db, zone_id).await?,
+        history: history.into_iter().map(|r| AccessChange { id: r.get(0), timestamp: r.get(1), kind: r.get(2), details: r.get(3) }).collect(),
+    }))
+}
+
+/// A zone with its owner, shares and access history.
+#[utoipa::path(
+    get, path = "/api/v1/zones/{id}", tag = "zones", params(("id" = String, Path, description = "Zone id")),
+    responses((status = 200, body = ZoneDetail), (status = 401), (status = 404)),
+)]
+pub async fn get_zone(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    let zone = match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(z)) => z,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("get_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    match zone_detail(&data.db, &zone_id, zone.permission).await {
+        Ok(Some(detail)) => HttpResponse::Ok().json(detail),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("get_zone error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TransferReq {
+    /// Username of the new owner
+    username: String,
+    /// Organization to move the zone to, which the new owner must be a member of; the zone stays
+    /// in its organization when not given
+    #[serde(default)]
+    org_id: Option<String>,
+}
+
+/// Whether the caller may manage an organization: platform admins and its admins may.
+async fn is_org_admin(db: &PgClient, caller: &Caller, org_id: Option<&str>) -> Result<bool, tokio_postgres::Error> {
+    if caller.admin {
+        return Ok(true);
+    }
+    let row = db.query_opt(
+        "SELECT 1 FROM org_members WHERE org_id::text = $1 AND user_id::text = $2 AND role = 'admin'",
+        &[&org_id, &caller.sub],
+    ).await?;
+    Ok(row.is_some())
+}
+
+/// Transfer a zone to another owner, possibly in another organization.
+///
+/// Allowed to the owner of the zone and the admins of its organization; moving it to another
+/// organization also requires being an admin of that one.
+#[utoipa::path(
+    post, path = "/api/v1/zones/{id}/transfer", tag = "zones", params(("id" = String, Path, description = "Zone id")), request_body = TransferReq,
+    responses(
+        (status = 200), (status = 400, description = "The new owner is not a member of the organization"),
+        (status = 401), (status = 403, body = orgs::QuotaExceeded), (status = 404),
+    ),
+)]
+pub async fn transfer_zone(path: web::Path<String>, body: web::Json<TransferReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    let zone = match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(z)) => z,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("transfer_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let owner: Option<String> = match data.db.query_one("SELECT owner::text FROM zones WHERE id::text = $1", &[&zone_id]).await {
+        Ok(row) => row.get(0),
+        Err(e) => { warn!("transfer_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let to_org = body.org_id.clone().map(|o| o.trim().to_ascii_lowercase()).or_else(|| zone.org_id.clone());
+    let moving = to_org != zone.org_id;
+    let allowed = async {
+        let source = owner.as_deref() == Some(caller.sub.as_str()) || is_org_admin(&data.db, &caller, zone.org_id.as_deref()).await?;
+        Ok::<_, tokio_postgres::Error>(source && (!moving || is_org_admin(&data.db, &caller, to_org.as_deref()).await?))
+    };
+    match allowed.await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::Forbidden().finish(),
+        Err(e) => { warn!("transfer_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+
+    let new_owner = data.db.query_opt(
+        "SELECT u.id::text, $2::text IS NULL OR EXISTS (SELECT 1 FROM org_members m WHERE m.user_id = u.id AND m.org_id::text = $2) FROM users u WHERE u.username = $1",
+        &[&body.username, &to_org],
+    ).await;
+    let new_owner: String = match new_owner {
+        Ok(Some(row)) if row.get(1) => row.get(0),
+        Ok(Some(_)) => return HttpResponse::BadRequest().body("the new owner is not a member of the organization"),
+        Ok(None) => return HttpResponse::BadRequest().body("unknown user"),
+        Err(e) => { warn!("transfer_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    // the zone and its records count towards the quotas of the organization it moves to
+    let res = data.db.query_one(
+        &format!(
+            "WITH moved AS (
+                 UPDATE zones SET owner = $2::text::uuid, org_id = $3::text::uuid
+                 WHERE id = $1::text::uuid AND (NOT $4 OR (SELECT {} AND {}))
+                 RETURNING id
+             )
+             SELECT count(*), (SELECT count(*) FROM records WHERE zone_id = $1::text::uuid) FROM moved",
+            Quota::Zones.allows("$3::text::uuid", "1"),
+            Quota::Records.allows("$3::text::uuid", "(SELECT count(*) FROM records WHERE zone_id = $1::text::uuid)"),
+        ),
+        &[&zone_id, &new_owner, &to_org, &moving],
+    ).await;
+    match res {
+        Ok(row) if row.get::<_, i64>(0) == 0 => {
+            let records = row.get::<_, i64>(1);
+            return orgs::quota_refused(&data.db, &[(to_org.as_deref(), Quota::Zones, 1), (to_org.as_deref(), Quota::Records, records)]).await;
+        }
+        Ok(_) => {}
+        Err(e) => { warn!("transfer_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    info!("zone {} transferred to {} by {}", zone.domain, body.username, caller.sub);
+    let event = Event::ZoneTransferred {
+        zone_id,
+        domain: zone.domain,
+        from_owner: owner,
+        to_owner: new_owner,
+        from_org: zone.org_id,
+        to_org: to_org.clone(),
+        by: caller.sub,
+    };
+    data.events.publish(event, to_org).await;
+    HttpResponse::Ok().finish()
+}
+
+/// Who a zone is shared with.
+#[utoipa::path(
+    get, path = "/api/v1/zones/{id}/shares", tag = "zones", params(("id" = String, Path, description = "Zone id")),
+    responses((status = 200, body = [ZoneShare]), (status = 401), (status = 404)),
+)]
+pub async fn list_shares(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("list_shares error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    match shares(&data.db, &zone_id).await {
+        Ok(shares) => HttpResponse::Ok().json(shares),
+        Err(e) => { warn!("list_shares error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// Request sharing a zone with either a user or an organization.
+#[derive(Deserialize, ToSchema)]
+pub struct ShareReq {
+    /// User to share the zone with
+    #[serde(default)]
+    username: Option<String>,
+    /// Organization to share the zone with
+    #[serde(default)]
+    org_id: Option<String>,
+    permission: Permission,
+}
+
+/// Share a zone with a user or an organization, or change the permission it is shared with.
+#[utoipa::path(
+    post, path = "/api/v1/zones/{id}/shares", tag = "zones", params(("id" = String, Path, description = "Zone id")), request_body = ShareReq,
+    responses(
+        (status = 201, body = IdResponse, description = "Shared"), (status = 200, body = IdResponse, description = "Permission changed"),
+        (status = 400), (status = 401), (status = 403), (status = 404),
+    ),
+)]
+pub async fn share_zone(path: web::Path<String>, body: web::Json<ShareReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let zone_id = path.into_inner();
+    let zone = match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(z)) => z,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("share_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    if let Err(resp) = zone.require(Permission::Admin) {
+        return resp;
+    }
+    let org_id = body.org_id.as_ref().map(|o| o.trim().to_ascii_lowercase());
+    let (sql, grantee) = match (&body.username, &org_id) {
+        (Some(username), None) => (
+            "INSERT INTO zone_shares (id, zone_id, user_id, permission, created_by)
+             SELECT $1::text::uuid, $2::text::uuid, u.id, $4, $5::text::uuid FROM users u WHERE u.username = $3
+             ON CONFLICT (zone_id, user_id) DO UPDATE SET permission = $4
+             RETURNING id::text, xmax = 0, user_id::text, org_id::text",
+            username,
+        ),
+        (None, Some(org_id)) if Some(org_id) == zone.org_id.as_ref() => {
+            return HttpResponse::BadRequest().body("the zone belongs to this organization");
+        }
+        (None, Some(org_id)) => (
+            "INSERT INTO zone_shares (id, zone_id, org_id, permission, created_by)
+             SELECT $1::text::uuid, $2::text::uuid, o.id, $4, $5::text::uuid FROM orgs o WHERE o.id::text = $3
+             ON CONFLICT (zone_id, org_id) DO UPDATE SET permission = $4
+             RETURNING id::text, xmax = 0, user_id::text, org_id::text",
+            org_id,
+        ),
+        _ => return HttpResponse::BadRequest().body("either username or org_id is required"),
+    };
+    let id = Uuid::new_v4().to_string();
+    let row = data.db.query_opt(sql, &[&id, &zone_id, grantee, &body.permission.as_str(), &caller.sub]).await;
+    let row = match row {
+        Ok(Some(row)) => row,
+        Ok(None) => return HttpResponse::BadRequest().body("unknown user or organization"),
+        Err(e) => { warn!("share_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let (id, created): (String, bool) = (row.get(0), row.get(1));
+    info!("zone {} shared with {} ({}) by {}", zone.domain, grantee, body.permission.as_str(), caller.sub);
+    let event = Event::ZoneShared {
+        zone_id,
+        domain: zone.domain,
+        share_id: id.clone(),
+        user_id: row.get(2),
+        org_id: row.get(3),
+        permission: body.permission.as_str().to_string(),
+        by: caller.sub,
+    };
+    data.events.publish(event, zone.org_id).await;
+    match created {
+        true => HttpResponse::Created().json(IdResponse { id }),
+        false => HttpResponse::Ok().json(IdResponse { id }),
+    }
+}
+
+/// Stop sharing a zone with a user or an organization.
+#[utoipa::path(
+    delete, path = "/api/v1/zones/{zone_id}/shares/{id}", tag = "zones",
+    params(("zone_id" = String, Path, description = "Zone id"), ("id" = String, Path, description = "Share id")),
+    responses((status = 200), (status = 401), (status = 403), (status = 404)),
+)]
+pub async fn unshare_zone(path: web::Path<(String, String)>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let (zone_id, id) = path.into_inner();
+    let zone = match orgs::scoped_zone(&data.db, &caller, &zone_id).await {
+        Ok(Some(z)) => z,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("unshare_zone error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    if let Err(resp) = zone.require(Permission::Admin) {
+        return resp;
+    }
+    let row = data.db.query_opt(
+        "DELETE FROM zone_shares WHERE id::text = $1 AND zone_id::text = $2 RETURNING user_id::text, org_id::text",
+        &[&id, &zone_id],
+    ).await;
+    match row {
+        Ok(Some(row)) => {
+            let event = Event::ZoneUnshared { zone_id, domain: zone.domain, share_id: id, user_id: row.get(0), org_id: row.get(1), by: caller.sub };
+            data.events.publish(event, zone.org_id).await;
+            HttpResponse::Ok().finish()
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("unshare_zone error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_permission_order() {
+        assert!(Permission::Read < Permission::Write && Permission::Write < Permission::Admin);
+        for p in Permission::ALL {
+            assert_eq!(Permission::parse(p.as_str()), Some(p));
+            assert_eq!(serde_json::to_value(p).unwrap(), p.as_str());
+        }
+        assert_eq!(Permission::parse("owner"), None);
+        // the SQL ordering of shares must agree with the Rust one
+        let ranked: Vec<_> = Permission::ALL.iter().map(|p| format!("'{}'", p.as_str())).collect();
+        assert!(permission_sql("z.id", "$1", "$2").contains(&format!("ARRAY[{}]", ranked.join(", "))));
+    }
+}
@@ -0,0 +1,620 @@
+//! Zone templates.
+//!
+//! A template is a list of records whose names and values may refer to `{{domain}}` and to
+//! per-zone parameters, e.g. `{{mx_host}}`. Zones created from a template remember it along with
+//! their parameters, so that a changed template can be previewed and propagated to them. Only the
+//! records a zone got from its template are touched by propagation.
+//!
+//! Writes spanning several zones are each issued as a single statement, which makes them atomic
+//! without having to hold a transaction open on the shared connection.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Client as PgClient;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+use crate::api::IdResponse;
+use crate::events::{Event, RecordChange};
+use crate::orgs::{self, Caller, Quota};
+use crate::{AppState, ZoneRecord};
+
+/// Most zones created by a single bulk request.
+const MAX_BULK_ZONES: usize = 1000;
+
+/// Substitute `{{name}}` variables in `input`.
+///
+/// Whitespace inside the braces is ignored. Referring to a variable which is not defined is an
+/// error, so a typo cannot silently end up in a served record.
+pub fn render(input: &str, vars: &HashMap<String, String>) -> Result<String, String> {
+    substitute(input, |var| vars.get(var).map(String::as_str))
+}
+
+fn substitute<'v>(input: &str, lookup: impl Fn(&str) -> Option<&'v str>) -> Result<String, String> {
+    let mut out = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after.find("}}").ok_or_else(|| format!("unterminated variable in {:?}", input))?;
+        let var = after[..end].trim();
+        out.push_str(lookup(var).ok_or_else(|| format!("undefined variable {:?}", var))?);
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Render all records of a template for one zone.
+fn render_records(records: &[ZoneRecord], domain: &str, params: &HashMap<String, String>) -> Result<Vec<ZoneRecord>, String> {
+    let mut vars = params.clone();
+    vars.insert("domain".to_string(), domain.trim_end_matches('.').to_string());
+    records.iter().map(|r| Ok(ZoneRecord {
+        name: render(&r.name, &vars)?,
+        record_type: r.record_type.clone(),
+        value: render(&r.value, &vars)?,
+        ttl: r.ttl,
+        weight: r.weight,
+    })).collect()
+}
+
+/// The records to add and remove to bring `current` in line with `wanted`.
+fn diff(current: &[(String, ZoneRecord)], wanted: &[ZoneRecord]) -> (Vec<ZoneRecord>, Vec<(String, ZoneRecord)>) {
+    let key = |r: &ZoneRecord| (r.name.clone(), r.record_type.to_ascii_uppercase(), r.value.clone(), r.ttl, r.weight);
+    let mut unmatched: BTreeMap<_, Vec<&(String, ZoneRecord)>> = BTreeMap::new();
+    for c in current {
+        unmatched.entry(key(&c.1)).or_default().push(c);
+    }
+    let mut add = Vec::new();
+    for w in wanted {
+        match unmatched.get_mut(&key(w)).and_then(Vec::pop) {
+            Some(_) => {}
+            None => add.push(w.clone()),
+        }
+    }
+    let remove = unmatched.into_values().flatten().cloned().collect();
+    (add, remove)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TemplateReq {
+    name: String,
+    #[serde(default)]
+    description: String,
+    records: Vec<ZoneRecord>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Template {
+    id: String,
+    name: String,
+    description: String,
+    records: Vec<ZoneRecord>,
+    zones: i64,
+}
+
+/// Check that a template's records are usable before storing it.
+fn validate_template(body: &TemplateReq) -> Result<(), String> {
+    if body.name.trim().is_empty() {
+        return Err("name is required".to_string());
+    }
+    if body.records.is_empty() {
+        return Err("a template needs at least one record".to_string());
+    }
+    for r in &body.records {
+        if r.record_type.is_empty() || r.value.is_empty() {
+            return Err("records need a type and a value".to_string());
+        }
+        if r.weight > i32::MAX as u32 || r.ttl > i32::MAX as u32 {
+            return Err("ttl or weight out of range".to_string());
+        }
+        // catch syntax errors now rather than when the template is applied
+        substitute(&r.name, |_| Some(""))?;
+        substitute(&r.value, |_| Some(""))?;
+    }
+    Ok(())
+}
+
+/// `POST /api/v1/templates`
+#[utoipa::path(post, path = "/api/v1/templates", tag = "templates", request_body = TemplateReq, responses((status = 201, body = IdResponse), (status = 400), (status = 401)))]
+pub async fn create_template(body: web::Json<TemplateReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if let Err(e) = validate_template(&body) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let org_id = match caller.org_for_create() {
+        Ok(o) => o,
+        Err(resp) => return resp,
+    };
+    let id = Uuid::new_v4().to_string();
+    let records = serde_json::to_value(&body.records).unwrap_or_default();
+    let res = data.db.execute(
+        "INSERT INTO zone_templates (id, name, description, records, owner, org_id) VALUES ($1::text::uuid, $2, $3, $4, $5::text::uuid, $6::text::uuid)",
+        &[&id, &body.name, &body.description, &records, &caller.sub, &org_id],
+    ).await;
+    match res {
+        Ok(_) => HttpResponse::Created().json(IdResponse { id }),
+        Err(e) => { warn!("create_template error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `GET /api/v1/templates`
+#[utoipa::path(get, path = "/api/v1/templates", tag = "templates", responses((status = 200, body = [Template]), (status = 401)))]
+pub async fn list_templates(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let rows = data.db.query(
+        "SELECT t.id::text, t.name, t.description, t.records, (SELECT count(*) FROM zones z WHERE z.template_id = t.id)
+         FROM zone_templates t WHERE $1 OR t.org_id::text = ANY($2) ORDER BY t.name",
+        &[&caller.all, &caller.orgs],
+    ).await.unwrap_or_default();
+    let out: Vec<Template> = rows.into_iter().map(|r| Template {
+        id: r.get(0),
+        name: r.get(1),
+        description: r.get(2),
+        records: serde_json::from_value(r.get(3)).unwrap_or_default(),
+        zones: r.get(4),
+    }).collect();
+    HttpResponse::Ok().json(out)
+}
+
+/// `GET /api/v1/templates/{id}`
+#[utoipa::path(get, path = "/api/v1/templates/{id}", tag = "templates", params(("id" = String, Path, description = "Template id")), responses((status = 200, body = Template), (status = 401), (status = 404)))]
+pub async fn get_template(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let id = path.into_inner();
+    let row = data.db.query_opt(
+        "SELECT t.id::text, t.name, t.description, t.records, (SELECT count(*) FROM zones z WHERE z.template_id = t.id)
+         FROM zone_templates t WHERE t.id::text = $1 AND ($2 OR t.org_id::text = ANY($3))",
+        &[&id, &caller.all, &caller.orgs],
+    ).await;
+    match row {
+        Ok(Some(r)) => HttpResponse::Ok().json(Template {
+            id: r.get(0),
+            name: r.get(1),
+            description: r.get(2),
+            records: serde_json::from_value(r.get(3)).unwrap_or_default(),
+            zones: r.get(4),
+        }),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("get_template error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `PUT /api/v1/templates/{id}`: replace a template.
+///
+/// Zones created from it are not changed until the update is propagated.
+#[utoipa::path(
+    put, path = "/api/v1/templates/{id}", tag = "templates", params(("id" = String, Path, description = "Template id")), request_body = TemplateReq,
+    responses((status = 200), (status = 400), (status = 401), (status = 404)),
+)]
+pub async fn update_template(path: web::Path<String>, body: web::Json<TemplateReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    if let Err(e) = validate_template(&body) {
+        return HttpResponse::BadRequest().body(e);
+    }
+    let id = path.into_inner();
+    let records = serde_json::to_value(&body.records).unwrap_or_default();
+    let res = data.db.execute(
+        "UPDATE zone_templates SET name = $1, description = $2, records = $3, updated_at = now() WHERE id::text = $4 AND ($5 OR org_id::text = ANY($6))",
+        &[&body.name, &body.description, &records, &id, &caller.all, &caller.orgs],
+    ).await;
+    match res {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("update_template error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `DELETE /api/v1/templates/{id}`: zones created from the template keep their records.
+#[utoipa::path(delete, path = "/api/v1/templates/{id}", tag = "templates", params(("id" = String, Path, description = "Template id")), responses((status = 200), (status = 401), (status = 404)))]
+pub async fn delete_template(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let id = path.into_inner();
+    let res = data.db.execute(
+        "DELETE FROM zone_templates WHERE id::text = $1 AND ($2 OR org_id::text = ANY($3))",
+        &[&id, &caller.all, &caller.orgs],
+    ).await;
+    match res {
+        Ok(0) => HttpResponse::NotFound().finish(),
+        Ok(_) => HttpResponse::Ok().finish(),
+        Err(e) => { warn!("delete_template error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// The records and organization of a template the caller may use.
+async fn template_records(db: &PgClient, id: &str, caller: &Caller) -> Result<Option<(Vec<ZoneRecord>, Option<String>)>, tokio_postgres::Error> {
+    let row = db.query_opt(
+        "SELECT records, org_id::text FROM zone_templates WHERE id::text = $1 AND ($2 OR org_id::text = ANY($3))",
+        &[&id, &caller.all, &caller.orgs],
+    ).await?;
+    Ok(row.map(|r| (serde_json::from_value(r.get(0)).unwrap_or_default(), r.get(1))))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ApplyReq {
+    domain: String,
+    #[serde(default)]
+    params: HashMap<String, String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BulkApplyReq {
+    zones: Vec<ApplyReq>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BulkApplyResponse {
+    /// Ids of the created zones, in the order of the request
+    ids: Vec<String>,
+}
+
+/// `POST /api/v1/templates/{id}/apply`: create a zone from a template.
+#[utoipa::path(
+    post, path = "/api/v1/templates/{id}/apply", tag = "templates", params(("id" = String, Path, description = "Template id")), request_body = ApplyReq,
+    responses((status = 201, body = IdResponse), (status = 400), (status = 401), (status = 403, body = orgs::QuotaExceeded), (status = 404)),
+)]
+pub async fn apply_template(path: web::Path<String>, body: web::Json<ApplyReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let body = BulkApplyReq { zones: vec![body.into_inner()] };
+    match create_zones(&path, &body, &data, &req).await {
+        Ok(mut ids) => HttpResponse::Created().json(IdResponse { id: ids.pop().unwrap_or_default() }),
+        Err(resp) => resp,
+    }
+}
+
+/// `POST /api/v1/templates/{id}/bulk`: create many zones from a template, all or none.
+#[utoipa::path(
+    post, path = "/api/v1/templates/{id}/bulk", tag = "templates", params(("id" = String, Path, description = "Template id")), request_body = BulkApplyReq,
+    responses((status = 201, body = BulkApplyResponse), (status = 400), (status = 401), (status = 403, body = orgs::QuotaExceeded), (status = 404)),
+)]
+pub async fn bulk_apply_template(path: web::Path<String>, body: web::Json<BulkApplyReq>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if body.zones.is_empty() || body.zones.len() > MAX_BULK_ZONES {
+        return HttpResponse::BadRequest().body(format!("between 1 and {} zones can be created at once", MAX_BULK_ZONES));
+    }
+    match create_zones(&path, &body, &data, &req).await {
+        Ok(ids) => HttpResponse::Created().json(BulkApplyResponse { ids }),
+        Err(resp) => resp,
+    }
+}
+
+async fn create_zones(template_id: &str, body: &BulkApplyReq, data: &AppState, req: &HttpRequest) -> Result<Vec<String>, HttpResponse> {
+    let caller = orgs::caller(data, req).await?;
+    let (template, org_id) = match template_records(&data.db, template_id, &caller).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return Err(HttpResponse::NotFound().finish()),
+        Err(e) => { warn!("apply_template error: {}", e); return Err(HttpResponse::InternalServerError().finish()); }
+    };
+
+    let mut seen = HashSet::new();
+    let mut zones = Vec::with_capacity(body.zones.len());
+    let mut records = Vec::new();
+    for z in &body.zones {
+        let domain = z.domain.trim_end_matches('.').to_ascii_lowercase();
+        if domain.is_empty() || !seen.insert(domain.clone()) {
+            return Err(HttpResponse::BadRequest().body(format!("empty or duplicate domain {:?}", z.domain)));
+        }
+        let rendered = render_records(&template, &domain, &z.params)
+            .map_err(|e| HttpResponse::BadRequest().body(format!("{}: {}", domain, e)))?;
+        let id = Uuid::new_v4().to_string();
+        records.extend(rendered.into_iter().map(|r| template_record_json(&id, &r)));
+        zones.push(serde_json::json!({"id": id, "domain": domain, "params": z.params}));
+    }
+
+    // zones are created in the template's organization, in one statement, so that either every
+    // zone is created or none is
+    let (adding_zones, adding_records) = (zones.len() as i64, records.len() as i64);
+    let res = data.db.query_one(
+        &format!(
+            "WITH allowed AS (SELECT {} AND {} AS ok),
+             new_zones AS (
+                 INSERT INTO zones (id, domain, owner, template_id, template_params, org_id)
+                 SELECT z.id, z.domain, $3::text::uuid, $4::text::uuid, z.params, $5::text::uuid FROM jsonb_to_recordset($1) AS z(id uuid, domain text, params jsonb)
+                 WHERE (SELECT ok FROM allowed)
+             ),
+             new_records AS (
+                 INSERT INTO records (id, zone_id, name, type, value, ttl, weight, from_template)
+                 SELECT r.id, r.zone_id, r.name, r.type, r.value, r.ttl, r.weight, true
+                 FROM jsonb_to_recordset($2) AS r(id uuid, zone_id uuid, name text, type text, value text, ttl int, weight int)
+                 WHERE (SELECT ok FROM allowed)
+             )
+             SELECT ok FROM allowed",
+            Quota::Zones.allows("$5::text::uuid", "$6"),
+            Quota::Records.allows("$5::text::uuid", "$7"),
+        ),
+        &[&serde_json::Value::from(zones.clone()), &serde_json::Value::from(records), &caller.sub, &template_id, &org_id, &adding_zones, &adding_records],
+    ).await;
+    match res {
+        Ok(row) if row.get::<_, bool>(0) => {}
+        Ok(_) => {
+            let wanted = [(org_id.as_deref(), Quota::Zones, adding_zones), (org_id.as_deref(), Quota::Records, adding_records)];
+            return Err(orgs::quota_refused(&data.db, &wanted).await);
+        }
+        Err(e) => { warn!("apply_template error: {}", e); return Err(HttpResponse::InternalServerError().finish()); }
+    }
+
+    let mut ids = Vec::with_capacity(zones.len());
+    for z in zones {
+        let zone_id = z["id"].as_str().unwrap_or_default().to_string();
+        let domain = z["domain"].as_str().unwrap_or_default().to_string();
+        data.events.publish(Event::ZoneCreated { zone_id: zone_id.clone(), domain }, org_id.clone()).await;
+        ids.push(zone_id);
+    }
+    Ok(ids)
+}
+
+fn template_record_json(zone_id: &str, r: &ZoneRecord) -> serde_json::Value {
+    serde_json::json!({
+        "id": Uuid::new_v4().to_string(),
+        "zone_id": zone_id,
+        "name": r.name,
+        "type": r.record_type,
+        "value": r.value,
+        "ttl": r.ttl,
+        "weight": r.weight,
+    })
+}
+
+/// Changes a template update would make to one zone.
+#[derive(Serialize, ToSchema)]
+pub struct ZoneDiff {
+    zone_id: String,
+    domain: String,
+    #[serde(skip)]
+    org_id: Option<String>,
+    add: Vec<ZoneRecord>,
+    #[serde(serialize_with = "serialize_removed")]
+    #[schema(value_type = Vec<RemovedRecord>)]
+    remove: Vec<(String, ZoneRecord)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A record a template update would remove.
+#[derive(Serialize, ToSchema)]
+pub struct RemovedRecord<'a> {
+    id: &'a str,
+    #[serde(flatten)]
+    record: &'a ZoneRecord,
+}
+
+fn serialize_removed<S: serde::Serializer>(remove: &[(String, ZoneRecord)], s: S) -> Result<S::Ok, S::Error> {
+    s.collect_seq(remove.iter().map(|(id, record)| RemovedRecord { id, record }))
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TemplatePreview {
+    /// Zones that would change, or that the template cannot be rendered for
+    zones: Vec<ZoneDiff>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PropagateResponse {
+    added: usize,
+    removed: usize,
+}
+
+/// Compare every zone created from a template with what the template renders to now.
+async fn template_diffs(db: &PgClient, template_id: &str, template: &[ZoneRecord]) -> Result<Vec<ZoneDiff>, tokio_postgres::Error> {
+    let zones = db.query(
+        "SELECT id::text, domain, template_params, org_id::text FROM zones WHERE template_id::text = $1 ORDER BY domain",
+        &[&template_id],
+    ).await?;
+    let mut diffs = Vec::with_capacity(zones.len());
+    for z in zones {
+        let zone_id: String = z.get(0);
+        let domain: String = z.get(1);
+        let params: HashMap<String, String> = z.get::<usize, Option<serde_json::Value>>(2)
+            .and_then(|p| serde_json::from_value(p).ok())
+            .unwrap_or_default();
+        let current: Vec<(String, ZoneRecord)> = db.query(
+            "SELECT id::text, name, type, value, ttl, weight FROM records WHERE zone_id::text = $1 AND from_template",
+            &[&zone_id],
+        ).await?.into_iter().map(|r| (r.get(0), ZoneRecord {
+            name: r.get(1),
+            record_type: r.get(2),
+            value: r.get(3),
+            ttl: r.get::<usize, i32>(4) as u32,
+            weight: r.get::<usize, i32>(5) as u32,
+        })).collect();
+
+        let (add, remove, error) = match render_records(template, &domain, &params) {
+            Ok(wanted) => {
+                let (add, remove) = diff(&current, &wanted);
+                (add, remove, None)
+            }
+            Err(e) => (Vec::new(), Vec::new(), Some(e)),
+        };
+        diffs.push(ZoneDiff { zone_id, domain, org_id: z.get(3), add, remove, error });
+    }
+    Ok(diffs)
+}
+
+/// `GET /api/v1/templates/{id}/preview`: the changes propagating the template would make.
+#[utoipa::path(get, path = "/api/v1/templates/{id}/preview", tag = "templates", params(("id" = String, Path, description = "Template id")), responses((status = 200, body = TemplatePreview), (status = 401), (status = 404)))]
+pub async fn preview_template(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let id = path.into_inner();
+    let template = match template_records(&data.db, &id, &caller).await {
+        Ok(Some((t, _))) => t,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("preview_template error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    match template_diffs(&data.db, &id, &template).await {
+        Ok(diffs) => {
+            let changed: Vec<_> = diffs.into_iter().filter(|d| d.error.is_some() || !d.add.is_empty() || !d.remove.is_empty()).collect();
+            HttpResponse::Ok().json(TemplatePreview { zones: changed })
+        }
+        Err(e) => { warn!("preview_template error: {}", e); HttpResponse::InternalServerError().finish() }
+    }
+}
+
+/// `POST /api/v1/templates/{id}/propagate`: apply the previewed changes to every zone.
+///
+/// Nothing is changed if the template cannot be rendered for any of the zones.
+#[utoipa::path(
+    post, path = "/api/v1/templates/{id}/propagate", tag = "templates", params(("id" = String, Path, description = "Template id")),
+    responses(
+        (status = 200, body = PropagateResponse),
+        (status = 400, body = TemplatePreview, description = "The zones the template cannot be rendered for"),
+        (status = 401), (status = 403, body = orgs::QuotaExceeded), (status = 404),
+    ),
+)]
+pub async fn propagate_template(path: web::Path<String>, data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    let caller = match orgs::caller(&data, &req).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let id = path.into_inner();
+    let template = match template_records(&data.db, &id, &caller).await {
+        Ok(Some((t, _))) => t,
+        Ok(None) => return HttpResponse::NotFound().finish(),
+        Err(e) => { warn!("propagate_template error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    let diffs = match template_diffs(&data.db, &id, &template).await {
+        Ok(d) => d,
+        Err(e) => { warn!("propagate_template error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    };
+    if diffs.iter().any(|d| d.error.is_some()) {
+        let failed = diffs.into_iter().filter(|d| d.error.is_some()).collect();
+        return HttpResponse::BadRequest().json(TemplatePreview { zones: failed });
+    }
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    let mut changes = Vec::new();
+    for d in &diffs {
+        for r in &d.add {
+            let rec = template_record_json(&d.zone_id, r);
+            changes.push((d.zone_id.clone(), rec["id"].as_str().unwrap_or_default().to_string(), RecordChange::Created, d.org_id.clone()));
+            added.push(rec);
+        }
+        for (record_id, _) in &d.remove {
+            changes.push((d.zone_id.clone(), record_id.clone(), RecordChange::Deleted, d.org_id.clone()));
+            removed.push(record_id.clone());
+        }
+    }
+    if changes.is_empty() {
+        return HttpResponse::Ok().json(PropagateResponse { added: 0, removed: 0 });
+    }
+    // organizations are locked in order of their ids, so that concurrent propagations cannot deadlock
+    let mut growth: BTreeMap<Option<&str>, i64> = BTreeMap::new();
+    for d in &diffs {
+        *growth.entry(d.org_id.as_deref()).or_default() += d.add.len() as i64 - d.remove.len() as i64;
+    }
+    let growth: Vec<_> = growth.into_iter().filter(|&(org_id, adding)| org_id.is_some() && adding > 0).collect();
+    let growth_json: Vec<_> = growth.iter().map(|(org_id, adding)| serde_json::json!({"org_id": org_id, "adding": adding})).collect();
+
+    let res = data.db.query_one(
+        &format!(
+            "WITH allowed AS (SELECT coalesce(bool_and({}), true) AS ok FROM jsonb_to_recordset($3) AS g(org_id uuid, adding bigint)),
+             removed AS (DELETE FROM records WHERE id::text = ANY($1) AND from_template AND (SELECT ok FROM allowed)),
+             new_records AS (
+                 INSERT INTO records (id, zone_id, name, type, value, ttl, weight, from_template)
+                 SELECT r.id, r.zone_id, r.name, r.type, r.value, r.ttl, r.weight, true
+                 FROM jsonb_to_recordset($2) AS r(id uuid, zone_id uuid, name text, type text, value text, ttl int, weight int)
+                 WHERE (SELECT ok FROM allowed)
+             )
+             SELECT ok FROM allowed",
+            Quota::Records.allows("g.org_id", "g.adding"),
+        ),
+        &[&removed, &serde_json::Value::from(added.clone()), &serde_json::Value::from(growth_json)],
+    ).await;
+    match res {
+        Ok(row) if row.get::<_, bool>(0) => {}
+        Ok(_) => {
+            let wanted: Vec<_> = growth.iter().map(|&(org_id, adding)| (org_id, Quota::Records, adding)).collect();
+            return orgs::quota_refused(&data.db, &wanted).await;
+        }
+        Err(e) => { warn!("propagate_template error: {}", e); return HttpResponse::InternalServerError().finish(); }
+    }
+    for (zone_id, record_id, change, org_id) in changes {
+        data.events.publish(Event::RecordsChanged { zone_id, record_id, change }, org_id).await;
+    }
+    let touched = diffs.iter().flat_map(|d| d.add.iter().chain(d.remove.iter().map(|(_, r)| r)));
+    crate::ptr::sync_records(&data, touched.map(|r| (r.record_type.as_str(), r.value.as_str()))).await;
+    HttpResponse::Ok().json(PropagateResponse { added: added.len(), removed: removed.len() })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::default_weight;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn record(name: &str, record_type: &str, value: &str) -> ZoneRecord {
+        ZoneRecord { name: name.to_string(), record_type: record_type.to_string(), value: value.to_string(), ttl: 3600, weight: default_weight() }
+    }
+
+    #[test]
+    fn test_render() {
+        let v = vars(&[("domain", "example.com"), ("mx", "mail.example.net.")]);
+        assert_eq!(render("v=DMARC1; p=reject; rua=mailto:dmarc@{{domain}}", &v).unwrap(), "v=DMARC1; p=reject; rua=mailto:dmarc@example.com");
+        assert_eq!(render("10 {{ mx }}", &v).unwrap(), "10 mail.example.net.");
+        assert_eq!(render("no variables", &v).unwrap(), "no variables");
+        assert!(render("{{missing}}", &v).is_err());
+        assert!(render("{{domain", &v).is_err());
+    }
+
+    #[test]
+    fn test_render_records() {
+        let template = vec![record("@", "MX", "10 mx.{{domain}}."), record("_dmarc", "TXT", "\"v=DMARC1; p={{policy}}\"")];
+        let rendered = render_records(&template, "example.com.", &vars(&[("policy", "none")])).unwrap();
+        assert_eq!(rendered[0].value, "10 mx.example.com.");
+        assert_eq!(rendered[1].value, "\"v=DMARC1; p=none\"");
+        assert!(render_records(&template, "example.com", &HashMap::new()).is_err());
+    }
+
+    #[test]
+    fn test_diff() {
+        let current = vec![
+            ("1".to_string(), record("@", "NS", "ns1.example.net.")),
+            ("2".to_string(), record("@", "NS", "ns2.example.net.")),
+            ("3".to_string(), record("@", "CAA", "0 issue \"ca.example\"")),
+        ];
+        let wanted = vec![
+            record("@", "NS", "ns1.example.net."),
+            record("@", "NS", "ns3.example.net."),
+            record("@", "caa", "0 issue \"ca.example\""),
+        ];
+        let (add, remove) = diff(&current, &wanted);
+        assert_eq!(add.len(), 1);
+        assert_eq!(add[0].value, "ns3.example.net.");
+        assert_eq!(remove.len(), 1);
+        assert_eq!(remove[0].0, "2");
+
+        let (add, remove) = diff(&current, &[]);
+        assert!(add.is_empty());
+        assert_eq!(remove.len(), 3);
+    }
+
+    #[test]
+    fn test_validate_template() {
+        let ok = TemplateReq { name: "customer".to_string(), description: String::new(), records: vec![record("@", "MX", "10 {{ mx }}")] };
+        assert!(validate_template(&ok).is_ok());
+        let bad = TemplateReq { name: "customer".to_string(), description: String::new(), records: vec![record("@", "MX", "10 {{mx")] };
+        assert!(validate_template(&bad).is_err());
+    }
+}
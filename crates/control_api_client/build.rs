@@ -0,0 +1,328 @@
+//! Generates the types and methods of the client from the OpenAPI document of the control API.
+//!
+//! Only the subset of OpenAPI the control API spec uses is supported: object schemas (merged when
+//! combined with `allOf`), string enums, `oneOf` of objects sharing a single valued `type`
+//! property (internally tagged enums), and operations with path, header and query parameters, a
+//! JSON body and a JSON, text or event stream response.
+
+use std::collections::BTreeSet;
+use std::env;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value};
+
+const SPEC: &str = "../control_api/openapi.json";
+
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "box", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "false", "fn",
+    "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "static", "struct",
+    "trait", "true", "type", "unsafe", "use", "where", "while", "yield",
+];
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SPEC);
+    let spec: Value = serde_json::from_str(&fs::read_to_string(SPEC).expect("reading the spec")).expect("parsing the spec");
+    let schemas = spec["components"]["schemas"].as_object().expect("components.schemas");
+
+    let mut gen = Generator { schemas, out: String::new() };
+    gen.types();
+    gen.operations(spec["paths"].as_object().expect("paths"));
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("client.rs");
+    fs::write(out, gen.out).expect("writing the client");
+}
+
+struct Generator<'a> {
+    schemas: &'a Map<String, Value>,
+    out: String,
+}
+
+/// A field of a generated struct or enum variant.
+struct Field {
+    name: String,
+    ty: String,
+    optional: bool,
+    description: Option<String>,
+}
+
+impl<'a> Generator<'a> {
+    fn types(&mut self) {
+        let defaults = self.defaultable();
+        for (name, schema) in self.schemas {
+            doc(&mut self.out, "", schema["description"].as_str());
+            if let Some(variants) = schema["oneOf"].as_array() {
+                self.tagged_enum(name, variants);
+            } else if let Some(values) = schema["enum"].as_array() {
+                self.string_enum(name, values);
+            } else {
+                let fields = self.fields(schema);
+                let derive = if defaults.contains(name.as_str()) { "Clone, Debug, Default, PartialEq, Serialize, Deserialize" } else { "Clone, Debug, PartialEq, Serialize, Deserialize" };
+                writeln!(self.out, "#[derive({})]\npub struct {} {{", derive, name).unwrap();
+                for field in &fields {
+                    self.field(field, "    ", "pub ");
+                }
+                writeln!(self.out, "}}\n").unwrap();
+            }
+        }
+    }
+
+    /// Names of the schemas whose type can derive `Default`, all but the enums and the structs
+    /// requiring one.
+    fn defaultable(&self) -> BTreeSet<&'a str> {
+        let schemas: &'a Map<String, Value> = self.schemas;
+        let mut defaults: BTreeSet<&str> = schemas.iter().filter(|(_, s)| s.get("oneOf").is_none() && s.get("enum").is_none()).map(|(n, _)| n.as_str()).collect();
+        loop {
+            let before = defaults.len();
+            let fields: Vec<(&str, Vec<Field>)> = defaults.iter().map(|n| (*n, self.fields(&schemas[*n]))).collect();
+            for (name, fields) in fields {
+                if fields.iter().any(|f| !f.optional && self.schemas.contains_key(&f.ty) && !defaults.contains(f.ty.as_str())) {
+                    defaults.remove(name);
+                }
+            }
+            if defaults.len() == before {
+                return defaults;
+            }
+        }
+    }
+
+    fn tagged_enum(&mut self, name: &str, variants: &[Value]) {
+        let tag = variants[0]["properties"].as_object().expect("oneOf of objects").iter()
+            .find(|(_, p)| p["enum"].as_array().is_some_and(|e| e.len() == 1))
+            .map(|(n, _)| n.clone())
+            .unwrap_or_else(|| panic!("{}: oneOf without a tag property", name));
+        writeln!(self.out, "#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]\n#[serde(tag = \"{}\")]\npub enum {} {{", tag, name).unwrap();
+        for variant in variants {
+            let value = variant["properties"][&tag]["enum"][0].as_str().unwrap_or_else(|| panic!("{}: variant without a {}", name, tag));
+            doc(&mut self.out, "    ", variant["description"].as_str());
+            writeln!(self.out, "    #[serde(rename = \"{}\")]\n    {} {{", value, pascal(value)).unwrap();
+            for field in self.fields(variant).iter().filter(|f| f.name != tag) {
+                self.field(field, "        ", "");
+            }
+            writeln!(self.out, "    }},").unwrap();
+        }
+        writeln!(self.out, "}}\n").unwrap();
+    }
+
+    fn string_enum(&mut self, name: &str, values: &[Value]) {
+        writeln!(self.out, "#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]\npub enum {} {{", name).unwrap();
+        for value in values {
+            let value = value.as_str().unwrap_or_else(|| panic!("{}: enum of non strings", name));
+            writeln!(self.out, "    #[serde(rename = \"{}\")]\n    {},", value, pascal(value)).unwrap();
+        }
+        writeln!(self.out, "}}\n").unwrap();
+    }
+
+    fn field(&mut self, field: &Field, indent: &str, vis: &str) {
+        doc(&mut self.out, indent, field.description.as_deref());
+        if field.optional {
+            writeln!(self.out, "{}#[serde(default, skip_serializing_if = \"Option::is_none\")]", indent).unwrap();
+            writeln!(self.out, "{}{}{}: Option<{}>,", indent, vis, ident(&field.name), field.ty).unwrap();
+        } else {
+            writeln!(self.out, "{}{}{}: {},", indent, vis, ident(&field.name), field.ty).unwrap();
+        }
+    }
+
+    /// Fields of an object schema, with the ones of the schemas it is combined with by `allOf`.
+    fn fields(&self, schema: &Value) -> Vec<Field> {
+        if let Some(parts) = schema["allOf"].as_array() {
+            return parts.iter().flat_map(|part| self.fields(self.resolve(part))).collect();
+        }
+        let required: BTreeSet<&str> = schema["required"].as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+        let Some(properties) = schema["properties"].as_object() else { return Vec::new() };
+        properties.iter().map(|(name, property)| {
+            let (ty, nullable) = rust_type(property);
+            Field { name: name.clone(), ty, optional: nullable || !required.contains(name.as_str()), description: property["description"].as_str().map(str::to_string) }
+        }).collect()
+    }
+
+    fn resolve<'s>(&'s self, schema: &'s Value) -> &'s Value {
+        match schema["$ref"].as_str() {
+            Some(r) => &self.schemas[ref_name(r)],
+            None => schema,
+        }
+    }
+
+    fn operations(&mut self, paths: &Map<String, Value>) {
+        let mut methods = String::new();
+        for (path, item) in paths {
+            for (method, op) in item.as_object().expect("path item") {
+                self.operation(&mut methods, path, method, op);
+            }
+        }
+        writeln!(self.out, "impl Client {{\n{}\n}}", methods.trim_end()).unwrap();
+    }
+
+    fn operation(&mut self, out: &mut String, path: &str, method: &str, op: &Value) {
+        let id = op["operationId"].as_str().expect("operationId");
+        let parameters: Vec<&Value> = op["parameters"].as_array().into_iter().flatten().collect();
+        let query: Vec<&Value> = parameters.iter().copied().filter(|p| p["in"] == "query").collect();
+        let headers: Vec<&Value> = parameters.iter().copied().filter(|p| p["in"] == "header").collect();
+        let security = op.get("security").and_then(Value::as_array);
+        let bearer = security.is_none_or(|s| s.iter().any(|r| r.get("bearer").is_some()));
+        let basic = security.is_some_and(|s| s.iter().any(|r| r.get("basic").is_some()));
+
+        let mut args = Vec::new();
+        let segments: Vec<String> = path.trim_start_matches('/').split('/').map(|segment| {
+            match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => {
+                    args.push(format!("{}: &str", ident(name)));
+                    ident(name)
+                }
+                None => format!("{:?}", segment),
+            }
+        }).collect();
+        for header in &headers {
+            args.push(format!("{}: &str", header_ident(&header["name"])));
+        }
+        if basic {
+            args.push("username: &str, password: &str".to_string());
+        }
+        if !query.is_empty() {
+            let name = format!("{}Query", pascal(id));
+            self.query_struct(&name, id, &query);
+            args.push(format!("query: &{}", name));
+        }
+        let body = op["requestBody"]["content"]["application/json"]["schema"].as_object().map(|s| rust_type(&Value::Object(s.clone())).0);
+        if let Some(body) = &body {
+            args.push(format!("body: &{}", body));
+        }
+
+        let (status, response) = op["responses"].as_object().expect("responses").iter()
+            .find(|(status, _)| status.starts_with('2'))
+            .unwrap_or_else(|| panic!("{}: no success response", id));
+        let content = response["content"].as_object();
+        let paged = response["headers"].get("X-Next-Cursor").is_some();
+        let (ret, tail) = match content.and_then(|c| c.iter().next()) {
+            None => ("()".to_string(), "        self.send(request).await?;\n        Ok(())".to_string()),
+            Some((mime, _)) if mime == "text/plain" => ("String".to_string(), "        Ok(self.send(request).await?.text().await?)".to_string()),
+            Some((mime, _)) if mime == "text/event-stream" => ("reqwest::Response".to_string(), "        self.send(request).await".to_string()),
+            Some((_, media)) if paged => {
+                let items = rust_type(&media["schema"]["items"]).0;
+                (format!("Page<{}>", items), "        Page::from_response(self.send(request).await?).await".to_string())
+            }
+            Some((_, media)) => (rust_type(&media["schema"]).0, "        Ok(self.send(request).await?.json().await?)".to_string()),
+        };
+
+        if let Some(summary) = op["summary"].as_str() {
+            doc(out, "    ", Some(summary));
+        }
+        if let Some(description) = op["description"].as_str() {
+            if op["summary"].is_string() {
+                writeln!(out, "    ///").unwrap();
+            }
+            doc(out, "    ", Some(description));
+        }
+        let route = format!("`{} {}`", method.to_uppercase(), path);
+        if !op["summary"].as_str().is_some_and(|s| s.contains(&route)) {
+            if op["summary"].is_string() || op["description"].is_string() {
+                writeln!(out, "    ///").unwrap();
+            }
+            writeln!(out, "    /// {}, {} on success.", route, status).unwrap();
+        }
+        writeln!(out, "    pub async fn {}(&self{}) -> Result<{}, Error> {{", id, args.iter().map(|a| format!(", {}", a)).collect::<String>(), ret).unwrap();
+        writeln!(out, "        let url = self.url(&[{}]);", segments.join(", ")).unwrap();
+        write!(out, "        let request = self.request(reqwest::Method::{}, url, {})", method.to_uppercase(), bearer).unwrap();
+        for header in &headers {
+            write!(out, "\n            .header({:?}, {})", header["name"].as_str().unwrap(), header_ident(&header["name"])).unwrap();
+        }
+        if basic {
+            write!(out, "\n            .basic_auth(username, Some(password))").unwrap();
+        }
+        if !query.is_empty() {
+            write!(out, "\n            .query(query)").unwrap();
+        }
+        if body.is_some() {
+            write!(out, "\n            .json(body)").unwrap();
+        }
+        writeln!(out, ";\n{}\n    }}\n", tail).unwrap();
+    }
+
+    fn query_struct(&mut self, name: &str, id: &str, parameters: &[&Value]) {
+        writeln!(self.out, "/// Query parameters of [`Client::{}`].\n#[derive(Clone, Debug, Default, PartialEq, Serialize)]\npub struct {} {{", id, name).unwrap();
+        for parameter in parameters {
+            let field = Field {
+                name: parameter["name"].as_str().expect("parameter name").to_string(),
+                ty: rust_type(&parameter["schema"]).0,
+                optional: true,
+                description: parameter["description"].as_str().map(str::to_string),
+            };
+            self.field(&field, "    ", "pub ");
+        }
+        writeln!(self.out, "}}\n").unwrap();
+    }
+}
+
+/// The Rust type of a schema, and whether it is nullable.
+fn rust_type(schema: &Value) -> (String, bool) {
+    if let Some(r) = schema["$ref"].as_str() {
+        return (ref_name(r).to_string(), false);
+    }
+    if let Some(variants) = schema["oneOf"].as_array() {
+        // `Option` of a named schema
+        let nullable = variants.iter().any(|v| v["type"] == "null");
+        if let Some(named) = variants.iter().find(|v| v["type"] != "null") {
+            return (rust_type(named).0, nullable);
+        }
+    }
+    let (ty, nullable) = match &schema["type"] {
+        Value::String(ty) => (ty.as_str(), false),
+        Value::Array(types) => (
+            types.iter().filter_map(Value::as_str).find(|t| *t != "null").unwrap_or("null"),
+            types.iter().any(|t| t == "null"),
+        ),
+        _ => ("", false),
+    };
+    let unsigned = schema["minimum"].as_f64().is_some_and(|m| m >= 0.0);
+    let ty = match (ty, schema["format"].as_str()) {
+        ("string", Some("date-time")) => "chrono::DateTime<chrono::Utc>".to_string(),
+        ("string", _) => "String".to_string(),
+        ("boolean", _) => "bool".to_string(),
+        ("integer", Some("int32")) if unsigned => "u32".to_string(),
+        ("integer", Some("int32")) => "i32".to_string(),
+        ("integer", Some("int64")) => "i64".to_string(),
+        ("integer", _) if unsigned => "u64".to_string(),
+        ("integer", _) => "i64".to_string(),
+        ("number", _) => "f64".to_string(),
+        ("array", _) => format!("Vec<{}>", rust_type(&schema["items"]).0),
+        ("object", _) if schema["additionalProperties"].is_object() => {
+            format!("std::collections::HashMap<String, {}>", rust_type(&schema["additionalProperties"]).0)
+        }
+        _ => "serde_json::Value".to_string(),
+    };
+    (ty, nullable)
+}
+
+fn ref_name(r: &str) -> &str {
+    r.rsplit('/').next().unwrap()
+}
+
+fn ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn header_ident(name: &Value) -> String {
+    name.as_str().expect("header name").to_lowercase().replace('-', "_")
+}
+
+fn pascal(name: &str) -> String {
+    name.split(['_', '-']).map(|word| {
+        let mut chars = word.chars();
+        chars.next().map(|c| c.to_uppercase().chain(chars).collect::<String>()).unwrap_or_default()
+    }).collect()
+}
+
+fn doc(out: &mut String, indent: &str, text: Option<&str>) {
+    for line in text.into_iter().flat_map(str::lines) {
+        if line.is_empty() {
+            writeln!(out, "{}///", indent).unwrap();
+        } else {
+            writeln!(out, "{}/// {}", indent, line).unwrap();
+        }
+    }
+}
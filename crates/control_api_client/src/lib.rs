@@ -0,0 +1,185 @@
+//! Client of the control API.
+//!
+//! The request and response types and one method per operation are generated at build time from
+//! `crates/control_api/openapi.json`, the spec the control API serves at `/api/v1/openapi.json`;
+//! this file only holds what every operation shares.
+//!
+//! ```no_run
+//! # async fn run() -> Result<(), control_api_client::Error> {
+//! use control_api_client::{Client, LoginRequest, ListZonesQuery};
+//!
+//! let client = Client::new("http://localhost:8080")?;
+//! let login = client.login(&LoginRequest { username: "admin".into(), password: "secret".into() }).await?;
+//! let client = client.with_token(login.token);
+//! let page = client.list_zones(&ListZonesQuery { limit: Some(50), ..Default::default() }).await?;
+//! for zone in page.items {
+//!     println!("{}", zone.domain);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+include!(concat!(env!("OUT_DIR"), "/client.rs"));
+
+/// Error of a call to the API.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The request could not be sent or its response read.
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The API answered with an error status.
+    #[error("{status}: {body}")]
+    Status { status: reqwest::StatusCode, body: String },
+    /// The base URL cannot have a path appended.
+    #[error("invalid base URL: {0}")]
+    InvalidUrl(String),
+}
+
+impl Error {
+    /// The status the API answered with, if it did.
+    pub fn status(&self) -> Option<reqwest::StatusCode> {
+        match self {
+            Self::Status { status, .. } => Some(*status),
+            Self::Http(e) => e.status(),
+            Self::InvalidUrl(_) => None,
+        }
+    }
+}
+
+/// A page of a paginated list.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Cursor of the next page, `None` on the last page.
+    pub next_cursor: Option<String>,
+    /// Number of items matching the query over all pages.
+    pub total: Option<u64>,
+}
+
+impl<T: DeserializeOwned> Page<T> {
+    async fn from_response(response: reqwest::Response) -> Result<Self, Error> {
+        let header = |name| response.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        let next_cursor = header("X-Next-Cursor");
+        let total = header("X-Total-Count").and_then(|v| v.parse().ok());
+        Ok(Self { items: response.json().await?, next_cursor, total })
+    }
+}
+
+/// Client of the control API.
+#[derive(Clone, Debug)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: reqwest::Url,
+    token: Option<String>,
+    org_id: Option<String>,
+}
+
+impl Client {
+    /// A client of the API at `base_url`, e.g. `http://localhost:8080`.
+    pub fn new(base_url: &str) -> Result<Self, Error> {
+        let base_url = reqwest::Url::parse(base_url).map_err(|e| Error::InvalidUrl(format!("{}: {}", base_url, e)))?;
+        if base_url.cannot_be_a_base() {
+            return Err(Error::InvalidUrl(base_url.to_string()));
+        }
+        Ok(Self { http: reqwest::Client::new(), base_url, token: None, org_id: None })
+    }
+
+    /// Authenticate the requests with `token`, the JWT of a user or the token of an agent.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Scope the requests to an organization, sent as `X-Org-Id`.
+    pub fn with_org(mut self, org_id: impl Into<String>) -> Self {
+        self.org_id = Some(org_id.into());
+        self
+    }
+
+    /// URL of the path made of `segments`, each percent-encoded.
+    fn url(&self, segments: &[&str]) -> reqwest::Url {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut().expect("checked by new").pop_if_empty().extend(segments);
+        url
+    }
+
+    fn request(&self, method: reqwest::Method, url: reqwest::Url, bearer: bool) -> reqwest::RequestBuilder {
+        let mut request = self.http.request(method, url);
+        if let (true, Some(token)) = (bearer, &self.token) {
+            request = request.bearer_auth(token);
+        }
+        if let Some(org_id) = &self.org_id {
+            request = request.header("X-Org-Id", org_id);
+        }
+        request
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+        let response = request.send().await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(Error::Status { status, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let client = Client::new("http://localhost:8080/control/").unwrap();
+        let url = client.url(&["api", "v1", "zones", "a b/c", "records"]);
+        assert_eq!(url.as_str(), "http://localhost:8080/control/api/v1/zones/a%20b%2Fc/records");
+        assert!(matches!(Client::new("mailto:admin@example.com"), Err(Error::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn test_tagged_enum() {
+        let probe: Probe = serde_json::from_str(r#"{"type": "http", "path": "/health"}"#).unwrap();
+        assert_eq!(probe, Probe::Http { port: None, path: Some("/health".to_string()), expected_status: None, host: None });
+        assert_eq!(serde_json::to_value(Probe::Tcp { port: 443 }).unwrap(), serde_json::json!({"type": "tcp", "port": 443}));
+    }
+
+    #[test]
+    fn test_all_of() {
+        let req = CreateOrgReq { name: "acme".to_string(), max_zones: Some(10), ..Default::default() };
+        assert_eq!(serde_json::to_value(req).unwrap(), serde_json::json!({"name": "acme", "max_zones": 10}));
+    }
+
+    /// Paginated lists return the cursor and count headers along with the items.
+    #[tokio::test]
+    async fn test_page() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 4096];
+            let n = socket.read(&mut buf).await.unwrap();
+            let body = r#"[{"id":"z1","domain":"example.com","records":[]}]"#;
+            let response = format!("HTTP/1.1 200 OK\r\ncontent-type: application/json\r\nx-next-cursor: abc\r\nx-total-count: 3\r\ncontent-length: {}\r\n\r\n{}", body.len(), body);
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf[..n]).into_owned()
+        });
+
+        let client = Client::new(&format!("http://{}", addr)).unwrap().with_token("t0k").with_org("o1");
+        let page = client.list_zones(&ListZonesQuery { limit: Some(1), ..Default::default() }).await.unwrap();
+        assert_eq!(page.items.len(), 1);
+        assert_eq!(page.items[0].domain, "example.com");
+        assert_eq!(page.next_cursor.as_deref(), Some("abc"));
+        assert_eq!(page.total, Some(3));
+
+        let request = server.await.unwrap().to_lowercase();
+        assert!(request.starts_with("get /api/v1/zones?limit=1 "), "{}", request);
+        assert!(request.contains("authorization: bearer t0k"));
+        assert!(request.contains("x-org-id: o1"));
+    }
+}
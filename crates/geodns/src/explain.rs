@@ -0,0 +1,155 @@
+//! Explanations of how the rules of an engine decided for a client
+
+use std::net::IpAddr;
+
+use crate::{GeoDecision, GeoRule, GeoRuleEngine, Location, MatchType, MatchValue};
+
+/// How a rule fared for a client
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RuleOutcome {
+    Matched,
+    NotMatched,
+    /// Another rule matched before the rule was evaluated
+    NotEvaluated,
+}
+
+/// A rule considered for a client, and why it matched or not
+#[derive(Clone, Debug)]
+pub struct RuleCheck {
+    pub rule_id: String,
+    pub priority: u32,
+    pub match_type: MatchType,
+    pub match_value: String,
+    pub outcome: RuleOutcome,
+    pub reason: String,
+}
+
+/// How the rules of an engine decided for a client
+#[derive(Clone, Debug)]
+pub struct GeoExplanation {
+    /// Where the client was located, None without a database or if it is in none
+    pub location: Option<Location>,
+    /// Every rule, in the order they are evaluated
+    pub rules: Vec<RuleCheck>,
+    pub decision: GeoDecision,
+}
+
+impl GeoRuleEngine {
+    /// Evaluate the rules for a client IP, explaining why each rule matched or not
+    pub fn explain(&self, client_ip: IpAddr) -> GeoExplanation {
+        let location = self.db.as_ref().and_then(|db| db.locate(client_ip));
+        let mut matched = None::<&GeoRule>;
+        let mut rules = Vec::with_capacity(self.rules.len());
+
+        for (priority, level) in &self.levels {
+            let (view, _) = level.views.lookup(client_ip);
+            let view = view.map(|idx| &self.rules[idx]);
+            let networks = self.rules.iter().filter(|rule| {
+                rule.priority == *priority && matches!(rule.value, MatchValue::Network(_))
+            });
+            for rule in networks {
+                let (outcome, reason) = match (&rule.value, matched, view) {
+                    (_, Some(first), _) => not_evaluated(first),
+                    (_, None, Some(view)) if std::ptr::eq(view, rule) => {
+                        let reason = format!("{client_ip} is in {}", rule.match_value);
+                        (RuleOutcome::Matched, reason)
+                    }
+                    (MatchValue::Network(network), None, Some(view))
+                        if network.contains(&client_ip) =>
+                    {
+                        let reason = match &view.value {
+                            MatchValue::Network(longest) if longest == network => {
+                                format!("rule {} for the same network takes precedence", view.id)
+                            }
+                            _ => format!(
+                                "the longer prefix {} of rule {} matched",
+                                view.match_value, view.id
+                            ),
+                        };
+                        (RuleOutcome::NotMatched, reason)
+                    }
+                    _ => {
+                        let reason = format!("{client_ip} is not in {}", rule.match_value);
+                        (RuleOutcome::NotMatched, reason)
+                    }
+                };
+                rules.push(check(rule, outcome, reason));
+            }
+            matched = matched.or(view);
+
+            for rule in level.location.iter().map(|idx| &self.rules[*idx]) {
+                let (outcome, reason) = match (matched, &location) {
+                    (Some(first), _) => not_evaluated(first),
+                    (None, None) if self.db.is_none() => {
+                        (RuleOutcome::NotMatched, "there is no GeoIP database".into())
+                    }
+                    (None, None) => (
+                        RuleOutcome::NotMatched,
+                        format!("{client_ip} is not in the GeoIP database"),
+                    ),
+                    (None, Some(location)) => {
+                        let found = describe(rule.match_type, location);
+                        match rule.matches(location) {
+                            true => {
+                                matched = Some(rule);
+                                (
+                                    RuleOutcome::Matched,
+                                    format!("{} is {found}", rule.match_type),
+                                )
+                            }
+                            false => (
+                                RuleOutcome::NotMatched,
+                                format!("{} is {found}, not {}", rule.match_type, rule.match_value),
+                            ),
+                        }
+                    }
+                };
+                rules.push(check(rule, outcome, reason));
+            }
+        }
+
+        GeoExplanation {
+            location,
+            rules,
+            decision: self.evaluate(client_ip),
+        }
+    }
+}
+
+fn check(rule: &GeoRule, outcome: RuleOutcome, reason: String) -> RuleCheck {
+    RuleCheck {
+        rule_id: rule.id.clone(),
+        priority: rule.priority,
+        match_type: rule.match_type,
+        match_value: rule.match_value.clone(),
+        outcome,
+        reason,
+    }
+}
+
+fn not_evaluated(first: &GeoRule) -> (RuleOutcome, String) {
+    let reason = format!("rule {} matched first", first.id);
+    (RuleOutcome::NotEvaluated, reason)
+}
+
+/// What a location has for a match type, e.g. "US" for the country
+fn describe(match_type: MatchType, location: &Location) -> String {
+    let unknown = || "unknown".to_string();
+    match match_type {
+        MatchType::Country => location.country.clone().unwrap_or_else(unknown),
+        MatchType::Continent => location.continent.clone().unwrap_or_else(unknown),
+        MatchType::Subdivision if location.subdivisions.is_empty() => unknown(),
+        MatchType::Subdivision => location.subdivisions.join(", "),
+        MatchType::City => match (&location.city, location.city_id) {
+            (Some(city), Some(id)) => format!("{city} ({id})"),
+            (Some(city), None) => city.clone(),
+            (None, Some(id)) => id.to_string(),
+            (None, None) => unknown(),
+        },
+        MatchType::Asn => location
+            .asn
+            .map(|asn| format!("AS{asn}"))
+            .unwrap_or_else(unknown),
+        MatchType::Cidr => unknown(),
+    }
+}
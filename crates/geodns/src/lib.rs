@@ -1,101 +1,578 @@
-use maxminddb::Reader;
-use std::net::IpAddr;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use maxminddb::{Reader, geoip2};
+use prefix_trie::{Prefix, PrefixMap};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub struct GeoDB {
-    reader: std::sync::Arc<Reader<Vec<u8>>>,
-}
+mod explain;
+mod reload;
+mod target;
+#[cfg(feature = "testing")]
+pub mod testing;
 
-impl Clone for GeoDB {
-    fn clone(&self) -> Self {
-        Self {
-            reader: self.reader.clone(),
-        }
-    }
+pub use explain::{GeoExplanation, RuleCheck, RuleOutcome};
+pub use reload::{GeoDbError, ReloadableGeoDB};
+pub use target::{GeoAction, GeoDecision, GeoTarget, WeightedTarget, pick_weighted};
+
+/// Addresses looked up when opening a database, to check that its records can be decoded
+const PROBES: [IpAddr; 4] = [
+    IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+    IpAddr::V4(Ipv4Addr::new(81, 2, 69, 142)),
+    IpAddr::V6(Ipv6Addr::new(0x2001, 0x4860, 0x4860, 0, 0, 0, 0, 0x8888)),
+    IpAddr::V6(Ipv6Addr::new(0x2a02, 0xcf40, 0, 0, 0, 0, 0, 0)),
+];
+
+/// One or more MaxMind databases, looked up together
+///
+/// City or Country databases provide the geographic location of an address, and ASN databases
+/// the autonomous system it is announced from.
+#[derive(Clone)]
+pub struct GeoDB {
+    readers: Vec<Arc<Reader<Vec<u8>>>>,
 }
 
 impl GeoDB {
+    /// Open a City, Country or ASN database, checking that its records can be decoded
     pub fn open_from_bytes(bytes: Vec<u8>) -> Result<Self, maxminddb::MaxMindDBError> {
         let reader = Reader::from_source(bytes)?;
+        validate(&reader)?;
         Ok(Self {
-            reader: std::sync::Arc::new(reader),
+            readers: vec![Arc::new(reader)],
         })
     }
 
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, maxminddb::MaxMindDBError> {
+        Self::open_from_bytes(std::fs::read(path)?)
+    }
+
+    /// Combine this database with `other`, e.g. a City database with an ASN database
+    pub fn merge(mut self, other: GeoDB) -> Self {
+        self.readers.extend(other.readers);
+        self
+    }
+
+    /// Type and build time of each database, in the order they were merged
+    pub fn info(&self) -> Vec<GeoDbInfo> {
+        self.readers
+            .iter()
+            .map(|reader| GeoDbInfo {
+                database_type: reader.metadata.database_type.clone(),
+                build_epoch: reader.metadata.build_epoch,
+            })
+            .collect()
+    }
+
     pub fn country(&self, ip: IpAddr) -> Option<String> {
-        if let Ok(country) = self.reader.lookup::<maxminddb::geoip2::Country>(ip) {
-            if let Some(country) = country.country {
-                return country.iso_code.map(|s| s.to_string());
+        self.locate(ip)?.country
+    }
+
+    /// Look up `ip`, returning None if it is in none of the databases
+    pub fn locate(&self, ip: IpAddr) -> Option<Location> {
+        let mut location = None::<Location>;
+        for reader in &self.readers {
+            let is_asn = reader.metadata.database_type.contains("ASN");
+            let found = match is_asn {
+                true => reader
+                    .lookup_prefix::<geoip2::Asn>(ip)
+                    .ok()
+                    .map(|(record, prefix_len)| {
+                        let mut found = Location::new(prefix_len as u8);
+                        found.asn = record.autonomous_system_number;
+                        found
+                    }),
+                false => reader
+                    .lookup_prefix::<geoip2::City>(ip)
+                    .ok()
+                    .map(|(record, prefix_len)| Location::from_city(record, prefix_len as u8)),
+            };
+            let Some(found) = found else { continue };
+
+            location = Some(match location {
+                None => found,
+                Some(location) => location.merge(found),
+            });
+        }
+        location
+    }
+}
+
+/// Check that `reader` is a database we can use: a City, Country or ASN database whose records
+/// decode
+fn validate(reader: &Reader<Vec<u8>>) -> Result<(), maxminddb::MaxMindDBError> {
+    let database_type = &reader.metadata.database_type;
+    let is_asn = database_type.contains("ASN");
+    if !is_asn
+        && !["City", "Country", "Enterprise"]
+            .iter()
+            .any(|t| database_type.contains(t))
+    {
+        return Err(maxminddb::MaxMindDBError::InvalidDatabaseError(format!(
+            "unsupported database type {database_type}"
+        )));
+    }
+
+    let probes = PROBES
+        .iter()
+        .filter(|ip| ip.is_ipv4() || reader.metadata.ip_version == 6);
+    for &ip in probes {
+        let found = match is_asn {
+            true => reader.lookup_prefix::<geoip2::Asn>(ip).map(|_| ()),
+            false => reader.lookup_prefix::<geoip2::City>(ip).map(|_| ()),
+        };
+        match found {
+            Ok(()) | Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Version of a loaded database
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GeoDbInfo {
+    /// e.g. "GeoLite2-City"
+    pub database_type: String,
+    /// When the database was built, in seconds since the Unix epoch
+    pub build_epoch: u64,
+}
+
+/// Where an address was found in the GeoIP database
+#[derive(Clone, Debug, PartialEq)]
+pub struct Location {
+    /// ISO country code, if the database has one for the address
+    pub country: Option<String>,
+    /// Continent code, e.g. "EU"
+    pub continent: Option<String>,
+    /// ISO 3166-2 codes of the subdivisions, most general first, e.g. "US-TX"
+    pub subdivisions: Vec<String>,
+    /// English name of the city
+    pub city: Option<String>,
+    /// GeoNames id of the city
+    pub city_id: Option<u32>,
+    /// Autonomous system number, from an ASN database
+    pub asn: Option<u32>,
+    /// Approximate coordinates, from a City database
+    pub coordinates: Option<Coordinates>,
+    /// Prefix length of the database network containing the address; every address in that
+    /// network has the same location
+    pub prefix_len: u8,
+}
+
+impl Location {
+    fn new(prefix_len: u8) -> Self {
+        Self {
+            country: None,
+            continent: None,
+            subdivisions: Vec::new(),
+            city: None,
+            city_id: None,
+            asn: None,
+            coordinates: None,
+            prefix_len,
+        }
+    }
+
+    fn from_city(record: geoip2::City<'_>, prefix_len: u8) -> Self {
+        let country = record
+            .country
+            .and_then(|country| country.iso_code)
+            .map(|s| s.to_string());
+        let subdivisions = match &country {
+            Some(country) => record
+                .subdivisions
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|subdivision| subdivision.iso_code)
+                .map(|code| format!("{country}-{code}"))
+                .collect(),
+            None => Vec::new(),
+        };
+        let city = record.city.as_ref();
+
+        Self {
+            country,
+            continent: record
+                .continent
+                .and_then(|continent| continent.code)
+                .map(|s| s.to_string()),
+            subdivisions,
+            city: city
+                .and_then(|city| city.names.as_ref()?.get("en").copied())
+                .map(|s| s.to_string()),
+            city_id: city.and_then(|city| city.geoname_id),
+            asn: None,
+            coordinates: record.location.and_then(|location| {
+                Some(Coordinates {
+                    latitude: location.latitude?,
+                    longitude: location.longitude?,
+                })
+            }),
+            prefix_len,
+        }
+    }
+
+    /// Combine the location from one database with the one from another
+    ///
+    /// The combination only holds for the more specific of the two networks.
+    fn merge(self, other: Self) -> Self {
+        Self {
+            country: self.country.or(other.country),
+            continent: self.continent.or(other.continent),
+            subdivisions: match self.subdivisions.is_empty() {
+                true => other.subdivisions,
+                false => self.subdivisions,
+            },
+            city: self.city.or(other.city),
+            city_id: self.city_id.or(other.city_id),
+            asn: self.asn.or(other.asn),
+            coordinates: self.coordinates.or(other.coordinates),
+            prefix_len: self.prefix_len.max(other.prefix_len),
+        }
+    }
+}
+
+/// A point on the earth, in degrees
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    /// Mean radius of the earth in kilometers
+    const EARTH_RADIUS_KM: f64 = 6_371.0;
+
+    /// Great-circle distance to `other` in kilometers, by the haversine formula
+    pub fn distance_km(&self, other: &Coordinates) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let dlat = lat2 - lat1;
+        let dlon = (other.longitude - self.longitude).to_radians();
+        let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlon / 2.0).sin().powi(2);
+        2.0 * Self::EARTH_RADIUS_KM * a.sqrt().min(1.0).asin()
+    }
+}
+
+/// What a [`GeoRule`] matches the client against
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchType {
+    /// ISO 3166-1 country code, e.g. "US"
+    Country,
+    /// Continent code, e.g. "EU"
+    Continent,
+    /// ISO 3166-2 subdivision code, e.g. "US-TX"
+    Subdivision,
+    /// English city name or GeoNames id, e.g. "Berlin" or "2950159"
+    City,
+    /// Autonomous system number, e.g. "AS64500" or "64500"
+    Asn,
+    /// Network of the client address, e.g. "192.0.2.0/24"; needs no database
+    Cidr,
+}
+
+impl MatchType {
+    pub const ALL: [Self; 6] = [
+        Self::Country,
+        Self::Continent,
+        Self::Subdivision,
+        Self::City,
+        Self::Asn,
+        Self::Cidr,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Country => "country",
+            Self::Continent => "continent",
+            Self::Subdivision => "subdivision",
+            Self::City => "city",
+            Self::Asn => "asn",
+            Self::Cidr => "cidr",
+        }
+    }
+}
+
+impl fmt::Display for MatchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for MatchType {
+    type Err = GeoRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| GeoRuleError::UnknownMatchType(s.to_string()))
+    }
+}
+
+/// An invalid [`GeoRule`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeoRuleError {
+    /// The match type is not one of [`MatchType::ALL`]
+    UnknownMatchType(String),
+    /// The match value is not valid for the match type
+    InvalidMatchValue(MatchType, String),
+    /// The target is not an address or hostname of its record type
+    InvalidTarget(String),
+    /// An answer without targets
+    NoTargets,
+}
+
+impl fmt::Display for GeoRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownMatchType(match_type) => {
+                let known = MatchType::ALL.map(|t| t.as_str()).join(", ");
+                write!(
+                    f,
+                    "unknown match type {match_type:?}, expected one of {known}"
+                )
+            }
+            Self::InvalidMatchValue(match_type, value) => {
+                write!(f, "invalid {match_type} match value {value:?}")
             }
+            Self::InvalidTarget(target) => write!(f, "invalid target {target:?}"),
+            Self::NoTargets => f.write_str("an answer needs at least one target"),
         }
-        None
     }
 }
 
+impl std::error::Error for GeoRuleError {}
+
 /// GeoDNS rule for matching and routing based on geographic criteria.
 #[derive(Clone, Debug)]
 pub struct GeoRule {
     pub id: String,
-    pub match_type: String,  // "country", "region", "continent"
-    pub match_value: String, // e.g., "US", "EU"
-    pub target: String,      // IP address or hostname to return
+    /// Rules with a lower priority are evaluated first
+    pub priority: u32,
+    pub match_type: MatchType,
+    pub match_value: String, // e.g., "US", "EU", "AS64500"
+    pub action: GeoAction,
+    value: MatchValue,
+}
+
+/// The parsed match value of a rule
+#[derive(Clone, Debug)]
+enum MatchValue {
+    Code(String),
+    CityName(String),
+    CityId(u32),
+    Asn(u32),
+    Network(IpNet),
+}
+
+impl GeoRule {
+    /// Create a rule with priority 0, validating the match value for the match type
+    pub fn new(
+        id: impl Into<String>,
+        match_type: MatchType,
+        match_value: impl Into<String>,
+        action: GeoAction,
+    ) -> Result<Self, GeoRuleError> {
+        let match_value = match_value.into();
+        let value = MatchValue::parse(match_type, match_value.trim())
+            .ok_or_else(|| GeoRuleError::InvalidMatchValue(match_type, match_value.clone()))?;
+        if let GeoAction::Answer(targets) = &action {
+            if targets.is_empty() {
+                return Err(GeoRuleError::NoTargets);
+            }
+        }
+        Ok(Self {
+            id: id.into(),
+            priority: 0,
+            match_type,
+            match_value,
+            action,
+            value,
+        })
+    }
+
+    /// Set the priority of the rule
+    pub fn with_priority(mut self, priority: u32) -> Self {
+        self.priority = priority;
+        self
+    }
+
+    fn matches(&self, location: &Location) -> bool {
+        let code = |field: &Option<String>, code: &str| {
+            field
+                .as_deref()
+                .is_some_and(|f| f.eq_ignore_ascii_case(code))
+        };
+        match (&self.value, self.match_type) {
+            (MatchValue::Code(c), MatchType::Country) => code(&location.country, c),
+            (MatchValue::Code(c), MatchType::Continent) => code(&location.continent, c),
+            (MatchValue::Code(c), _) => location
+                .subdivisions
+                .iter()
+                .any(|s| s.eq_ignore_ascii_case(c)),
+            (MatchValue::CityName(name), _) => code(&location.city, name),
+            (MatchValue::CityId(id), _) => location.city_id == Some(*id),
+            (MatchValue::Asn(asn), _) => location.asn == Some(*asn),
+            (MatchValue::Network(_), _) => false,
+        }
+    }
+}
+
+impl MatchValue {
+    fn parse(match_type: MatchType, value: &str) -> Option<Self> {
+        let is_code = |s: &str, len: std::ops::RangeInclusive<usize>| {
+            len.contains(&s.len()) && s.bytes().all(|b| b.is_ascii_alphanumeric())
+        };
+        match match_type {
+            MatchType::Country => (value.len() == 2
+                && value.bytes().all(|b| b.is_ascii_alphabetic()))
+            .then(|| Self::Code(value.to_ascii_uppercase())),
+            MatchType::Continent => CONTINENTS
+                .iter()
+                .any(|c| c.eq_ignore_ascii_case(value))
+                .then(|| Self::Code(value.to_ascii_uppercase())),
+            MatchType::Subdivision => {
+                let (country, code) = value.split_once('-')?;
+                (is_code(country, 2..=2) && is_code(code, 1..=3))
+                    .then(|| Self::Code(value.to_ascii_uppercase()))
+            }
+            MatchType::City if value.is_empty() => None,
+            MatchType::City => Some(match value.parse() {
+                Ok(id) => Self::CityId(id),
+                Err(_) => Self::CityName(value.to_string()),
+            }),
+            MatchType::Asn => {
+                let number = match value.get(..2) {
+                    Some(prefix) if prefix.eq_ignore_ascii_case("AS") => &value[2..],
+                    _ => value,
+                };
+                number.parse().ok().map(Self::Asn)
+            }
+            MatchType::Cidr => value
+                .parse::<IpNet>()
+                .or_else(|_| value.parse::<IpAddr>().map(IpNet::from))
+                .ok()
+                .map(|network| Self::Network(network.trunc())),
+        }
+    }
 }
 
-/// GeoRule engine: evaluates rules and returns best target based on client IP
+const CONTINENTS: [&str; 7] = ["AF", "AN", "AS", "EU", "NA", "OC", "SA"];
+
+/// GeoRule engine: evaluates rules and decides what to answer based on client IP
+///
+/// Rules are evaluated by ascending priority, and in the order they were added within a
+/// priority. Within a priority, CIDR rules form network views which take precedence over the
+/// location rules: the rule with the longest prefix containing the client wins. Network views
+/// need no database. If no rule matches, the default applies.
 pub struct GeoRuleEngine {
     rules: Vec<GeoRule>,
+    levels: BTreeMap<u32, Level>,
+    default: Option<GeoAction>,
     db: Option<GeoDB>,
 }
 
+/// The rules of one priority, as indexes into the rules of the engine
+#[derive(Default)]
+struct Level {
+    views: NetworkViews,
+    location: Vec<usize>,
+}
+
 impl GeoRuleEngine {
     /// Create a new GeoRule engine, optionally with a GeoDB
     pub fn new(db: Option<GeoDB>) -> Self {
         Self {
             rules: Vec::new(),
+            levels: BTreeMap::new(),
+            default: None,
             db,
         }
     }
 
     /// Add a rule to the engine
+    ///
+    /// Of several CIDR rules for the same network and priority, the first one added is used.
     pub fn add_rule(&mut self, rule: GeoRule) {
+        let idx = self.rules.len();
+        let level = self.levels.entry(rule.priority).or_default();
+        match rule.value {
+            MatchValue::Network(network) => level.views.insert(network, idx),
+            _ => level.location.push(idx),
+        }
         self.rules.push(rule);
     }
 
     /// Set all rules at once
     pub fn set_rules(&mut self, rules: Vec<GeoRule>) {
-        self.rules = rules;
-    }
-
-    /// Evaluate rules for a client IP and return the target address
-    /// Returns Some(target) if a rule matches, None if no match (use default)
-    pub fn evaluate(&self, client_ip: IpAddr) -> Option<String> {
-        if self.db.is_none() {
-            return None; // No GeoIP database available
-        }
-        
-        let db = self.db.as_ref().unwrap();
-        
-        // Get the client's country
-        let country = db.country(client_ip)?;
-        
-        // Find first matching rule
-        for rule in &self.rules {
-            match rule.match_type.as_str() {
-                "country" => {
-                    if rule.match_value.eq_ignore_ascii_case(&country) {
-                        return Some(rule.target.clone());
-                    }
-                }
-                "region" | "continent" => {
-                    // For now, treat as simple string match (can be extended)
-                    if rule.match_value.eq_ignore_ascii_case(&country) {
-                        return Some(rule.target.clone());
-                    }
-                }
-                _ => {}
+        self.rules.clear();
+        self.levels.clear();
+        for rule in rules {
+            self.add_rule(rule);
+        }
+    }
+
+    /// Set what to answer when no rule matches
+    pub fn set_default(&mut self, default: Option<GeoAction>) {
+        self.default = default;
+    }
+
+    /// Evaluate rules for a client IP and decide what to answer
+    pub fn evaluate(&self, client_ip: IpAddr) -> GeoDecision {
+        let mut location = None::<Option<Location>>;
+        // the decision holds for the most specific network any rule so far depended on
+        let mut scope = Some(0);
+
+        for level in self.levels.values() {
+            let (view, view_scope) = level.views.lookup(client_ip);
+            scope = scope.map(|s: u8| s.max(view_scope));
+            if let Some(idx) = view {
+                return self.decision(idx, scope);
             }
+            if level.location.is_empty() {
+                continue;
+            }
+
+            // Get the client's location
+            let location = location
+                .get_or_insert_with(|| self.db.as_ref().and_then(|db| db.locate(client_ip)));
+            let Some(location) = location else {
+                scope = None;
+                continue;
+            };
+            scope = scope.map(|s| s.max(location.prefix_len));
+
+            // Find first matching rule
+            let matched = level
+                .location
+                .iter()
+                .find(|idx| self.rules[**idx].matches(location));
+            if let Some(idx) = matched {
+                return self.decision(*idx, scope);
+            }
+        }
+
+        GeoDecision {
+            rule_id: None,
+            action: self.default.clone(),
+            fallback: true,
+            scope,
+        }
+    }
+
+    fn decision(&self, idx: usize, scope: Option<u8>) -> GeoDecision {
+        let rule = &self.rules[idx];
+        GeoDecision {
+            rule_id: Some(rule.id.clone()),
+            action: Some(rule.action.clone()),
+            fallback: false,
+            scope,
         }
-        
-        None // No matching rule found
     }
 
     /// Get all rules
@@ -103,3 +580,229 @@ impl GeoRuleEngine {
         &self.rules
     }
 }
+
+/// The CIDR rules of an engine, by network, as indexes into its rules
+#[derive(Default)]
+struct NetworkViews {
+    ipv4: PrefixMap<Ipv4Net, usize>,
+    ipv6: PrefixMap<Ipv6Net, usize>,
+}
+
+impl NetworkViews {
+    fn insert(&mut self, network: IpNet, idx: usize) {
+        match network {
+            IpNet::V4(v4) => {
+                self.ipv4.entry(v4).or_insert(idx);
+            }
+            IpNet::V6(v6) => {
+                self.ipv6.entry(v6).or_insert(idx);
+            }
+        }
+    }
+
+    /// The rule with the longest network containing `client_ip`, and the prefix length of the
+    /// client network over which that holds
+    fn lookup(&self, client_ip: IpAddr) -> (Option<usize>, u8) {
+        match client_ip {
+            IpAddr::V4(v4) => Self::lookup_in(&self.ipv4, Ipv4Net::from(v4)),
+            IpAddr::V6(v6) => Self::lookup_in(&self.ipv6, Ipv6Net::from(v6)),
+        }
+    }
+
+    fn lookup_in<P: Prefix>(views: &PrefixMap<P, usize>, client: P) -> (Option<usize>, u8) {
+        let matched = views.get_lpm(&client);
+
+        // the result holds within the matched network, except near more specific networks in it:
+        // it holds for the client networks without any of them, and those networks only get
+        // longer, so the shortest one is bisected along the path of the client in the trie
+        let (mut shortest, mut longest) = (
+            matched.map_or(0, |(network, _)| network.prefix_len()),
+            client.prefix_len(),
+        );
+        while shortest < longest {
+            let len = (shortest + longest) / 2;
+            if has_more_specific(views, &client, len) {
+                shortest = len + 1;
+            } else {
+                longest = len;
+            }
+        }
+        (matched.map(|(_, idx)| *idx), shortest)
+    }
+}
+
+/// Whether a network of `views` is inside the client network of prefix length `len`, and more
+/// specific than it
+fn has_more_specific<P: Prefix>(views: &PrefixMap<P, usize>, client: &P, len: u8) -> bool {
+    let network = P::from_repr_len(client.repr(), len);
+    let network = P::from_repr_len(network.mask(), len);
+    // children come after the network itself, so at most two of them are looked at
+    views
+        .children(&network)
+        .any(|(child, _)| child.prefix_len() > len)
+}
+
+/// A target of a [`NearestSelector`] and where it is
+#[derive(Clone, Debug, PartialEq)]
+pub struct NearestTarget {
+    pub target: GeoTarget,
+    pub coordinates: Coordinates,
+    pub healthy: bool,
+}
+
+impl NearestTarget {
+    /// A healthy target at `coordinates`
+    pub fn new(target: GeoTarget, coordinates: Coordinates) -> Self {
+        Self {
+            target,
+            coordinates,
+            healthy: true,
+        }
+    }
+}
+
+/// How a [`NearestSelector`] chooses between targets at the same distance
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TieBreak {
+    /// The tied target listed first, which is not necessarily the nearest of them: targets within
+    /// the tie distance of the nearest one count as equally near, and the order in which they
+    /// were added tells which one is preferred
+    #[default]
+    First,
+    /// A target picked by a hash of the client's network, spreading clients over the tied targets
+    /// while each network keeps getting the same one
+    ClientHash,
+}
+
+/// 64-bit FNV-1a; stable across builds and platforms, unlike the std hashers
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Nearest selector: picks the healthy target closest to the client location
+///
+/// The client is located in a City database; if it cannot be located, has no coordinates or no
+/// target is healthy, the fallback target is returned.
+pub struct NearestSelector {
+    targets: Vec<NearestTarget>,
+    tie_break: TieBreak,
+    tie_distance_km: f64,
+    fallback: Option<GeoTarget>,
+    db: Option<GeoDB>,
+}
+
+impl NearestSelector {
+    /// Create a new selector without targets, optionally with a GeoDB
+    pub fn new(db: Option<GeoDB>) -> Self {
+        Self {
+            targets: Vec::new(),
+            tie_break: TieBreak::default(),
+            tie_distance_km: 0.0,
+            fallback: None,
+            db,
+        }
+    }
+
+    /// Add a target to the selector
+    pub fn add_target(&mut self, target: NearestTarget) {
+        self.targets.push(target);
+    }
+
+    /// Set how targets at the same distance are chosen between, and how many kilometers apart
+    /// the distances of targets may be to still count as the same
+    pub fn set_tie_break(&mut self, tie_break: TieBreak, tie_distance_km: f64) {
+        self.tie_break = tie_break;
+        self.tie_distance_km = tie_distance_km.max(0.0);
+    }
+
+    /// Set the target returned when no location is known or no target is healthy
+    pub fn set_fallback(&mut self, fallback: Option<GeoTarget>) {
+        self.fallback = fallback;
+    }
+
+    /// Mark all targets equal to `target` healthy or unhealthy; unhealthy targets are never
+    /// selected
+    ///
+    /// The server only sets the health of targets from its configuration when it starts, it does
+    /// not check or update it at runtime.
+    pub fn set_healthy(&mut self, target: &GeoTarget, healthy: bool) {
+        for t in self.targets.iter_mut().filter(|t| t.target == *target) {
+            t.healthy = healthy;
+        }
+    }
+
+    /// Select the target for a client IP, answering with the fallback if there is none
+    pub fn select(&self, client_ip: IpAddr) -> GeoDecision {
+        let Some(location) = self.db.as_ref().and_then(|db| db.locate(client_ip)) else {
+            return self.decision(None, None);
+        };
+        let scope = Some(location.prefix_len);
+        let Some(client) = location.coordinates else {
+            return self.decision(None, scope);
+        };
+
+        let distances = self
+            .targets
+            .iter()
+            .filter(|t| t.healthy)
+            .map(|t| (t, client.distance_km(&t.coordinates)))
+            .collect::<Vec<_>>();
+        let Some(nearest) = distances.iter().map(|(_, d)| *d).min_by(f64::total_cmp) else {
+            return self.decision(None, scope);
+        };
+
+        let tied = distances
+            .iter()
+            .filter(|(_, d)| *d <= nearest + self.tie_distance_km)
+            .map(|(t, _)| *t)
+            .collect::<Vec<_>>();
+        let idx = match self.tie_break {
+            TieBreak::First => 0,
+            TieBreak::ClientHash => {
+                // the hash of the database network keeps the choice the same within the scope, and
+                // across builds, so that every server sends a network to the same target
+                let network = IpNet::new(client_ip, location.prefix_len)
+                    .map(|network| network.trunc())
+                    .unwrap_or_else(|_| IpNet::from(client_ip));
+                let mut hash = Fnv1a::default();
+                match network.addr() {
+                    IpAddr::V4(ip) => hash.write(&ip.octets()),
+                    IpAddr::V6(ip) => hash.write(&ip.octets()),
+                }
+                hash.write(&[network.prefix_len()]);
+                (hash.0 % tied.len() as u64) as usize
+            }
+        };
+        self.decision(Some(&tied[idx].target), scope)
+    }
+
+    /// A decision for the nearest target, or the fallback if there is none
+    fn decision(&self, nearest: Option<&GeoTarget>, scope: Option<u8>) -> GeoDecision {
+        let target = nearest.or(self.fallback.as_ref());
+        GeoDecision {
+            rule_id: None,
+            action: target.map(|t| GeoAction::Answer(vec![WeightedTarget::new(t.clone())])),
+            fallback: nearest.is_none(),
+            scope,
+        }
+    }
+
+    /// Get all targets
+    pub fn targets(&self) -> &[NearestTarget] {
+        &self.targets
+    }
+}
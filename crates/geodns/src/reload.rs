@@ -0,0 +1,151 @@
+//! GeoIP databases replaced while in use, when their files are updated
+
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use arc_swap::ArcSwap;
+
+use crate::{GeoDB, GeoDbInfo};
+
+/// Modification time and size of a file, None if it cannot be read
+type FileVersion = Option<(SystemTime, u64)>;
+
+fn version(path: &Path) -> FileVersion {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+struct Loaded {
+    db: Option<GeoDB>,
+    info: Vec<GeoDbInfo>,
+    loaded_at: Option<SystemTime>,
+}
+
+/// GeoIP databases read from files, which can be reloaded while lookups are made
+///
+/// Reloading reads and validates every file before switching to the new databases atomically;
+/// lookups in progress finish with the databases they started with. If a file cannot be read, is
+/// not a valid database, or is of another type than the database it replaces, the current
+/// databases are kept.
+#[derive(Clone)]
+pub struct ReloadableGeoDB {
+    paths: Arc<[PathBuf]>,
+    current: Arc<ArcSwap<Loaded>>,
+    /// Versions of the files when they were last loaded or refused
+    seen: Arc<Mutex<Vec<FileVersion>>>,
+}
+
+impl ReloadableGeoDB {
+    /// Databases read from `paths`, merged in that order, e.g. a City then an ASN database
+    ///
+    /// Nothing is loaded until the first reload.
+    pub fn new(paths: Vec<PathBuf>) -> Self {
+        Self {
+            paths: paths.into(),
+            current: Arc::new(ArcSwap::from_pointee(Loaded {
+                db: None,
+                info: Vec::new(),
+                loaded_at: None,
+            })),
+            seen: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Files the databases are read from
+    pub fn paths(&self) -> &[PathBuf] {
+        &self.paths
+    }
+
+    /// The databases currently loaded, None before the first successful reload or if there are
+    /// no files
+    pub fn get(&self) -> Option<GeoDB> {
+        self.current.load().db.clone()
+    }
+
+    /// Type and build time of the loaded database of each file
+    pub fn info(&self) -> Vec<GeoDbInfo> {
+        self.current.load().info.clone()
+    }
+
+    /// When the current databases were loaded
+    pub fn loaded_at(&self) -> Option<SystemTime> {
+        self.current.load().loaded_at
+    }
+
+    /// Read the files again and switch to the databases they hold if they are all valid
+    pub fn reload(&self) -> Result<Vec<GeoDbInfo>, GeoDbError> {
+        *self.seen.lock().expect("poisoned") = self.paths.iter().map(|p| version(p)).collect();
+
+        let mut db = None::<GeoDB>;
+        for path in self.paths.iter() {
+            let bytes = std::fs::read(path).map_err(|e| GeoDbError::Io(path.clone(), e))?;
+            let opened =
+                GeoDB::open_from_bytes(bytes).map_err(|e| GeoDbError::Invalid(path.clone(), e))?;
+            db = Some(match db {
+                None => opened,
+                Some(db) => db.merge(opened),
+            });
+        }
+
+        let info = db.as_ref().map(GeoDB::info).unwrap_or_default();
+        let current = self.current.load();
+        let replaced = self.paths.iter().zip(&current.info).zip(&info);
+        for ((path, old), new) in replaced {
+            if old.database_type != new.database_type {
+                return Err(GeoDbError::TypeChanged {
+                    path: path.clone(),
+                    from: old.database_type.clone(),
+                    to: new.database_type.clone(),
+                });
+            }
+        }
+
+        self.current.store(Arc::new(Loaded {
+            db,
+            info: info.clone(),
+            loaded_at: Some(SystemTime::now()),
+        }));
+        Ok(info)
+    }
+
+    /// Reload if a file was modified since it was last loaded or refused, None if none was
+    pub fn reload_if_changed(&self) -> Option<Result<Vec<GeoDbInfo>, GeoDbError>> {
+        let versions = self.paths.iter().map(|p| version(p)).collect::<Vec<_>>();
+        if *self.seen.lock().expect("poisoned") == versions {
+            return None;
+        }
+        Some(self.reload())
+    }
+}
+
+/// Why databases were not reloaded
+#[derive(Debug)]
+pub enum GeoDbError {
+    Io(PathBuf, io::Error),
+    Invalid(PathBuf, maxminddb::MaxMindDBError),
+    /// The file holds another type of database than the one it replaces
+    TypeChanged {
+        path: PathBuf,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for GeoDbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(path, e) => write!(f, "reading {}: {e}", path.display()),
+            Self::Invalid(path, e) => write!(f, "invalid database {}: {e}", path.display()),
+            Self::TypeChanged { path, from, to } => write!(
+                f,
+                "{} holds a {to} database instead of a {from} database",
+                path.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for GeoDbError {}
@@ -0,0 +1,167 @@
+//! What geo rules answer with
+
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use crate::GeoRuleError;
+
+/// A target answered by a rule, typed by the record it is answered with
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum GeoTarget {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    /// A hostname, relative to the zone unless it ends with a dot
+    Cname(String),
+}
+
+impl GeoTarget {
+    /// Parse a target of the given record type, `A`, `AAAA` or `CNAME`, or infer the type from
+    /// the value if there is none
+    pub fn new(record_type: Option<&str>, value: &str) -> Result<Self, GeoRuleError> {
+        let invalid = || GeoRuleError::InvalidTarget(value.to_string());
+        let value = value.trim();
+        let Some(record_type) = record_type else {
+            return value.parse();
+        };
+
+        match record_type.to_ascii_uppercase().as_str() {
+            "A" => value.parse().map(Self::A).map_err(|_| invalid()),
+            "AAAA" => value.parse().map(Self::Aaaa).map_err(|_| invalid()),
+            "CNAME" if value.parse::<IpAddr>().is_err() && is_hostname(value) => {
+                Ok(Self::Cname(value.to_string()))
+            }
+            "CNAME" => Err(invalid()),
+            _ => Err(GeoRuleError::InvalidTarget(format!(
+                "{record_type} {value}"
+            ))),
+        }
+    }
+
+    /// The type of the record the target is answered with
+    pub fn record_type(&self) -> &'static str {
+        match self {
+            Self::A(_) => "A",
+            Self::Aaaa(_) => "AAAA",
+            Self::Cname(_) => "CNAME",
+        }
+    }
+}
+
+impl FromStr for GeoTarget {
+    type Err = GeoRuleError;
+
+    /// An IPv4 address is an A target, an IPv6 address an AAAA target and a hostname a CNAME
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => Ok(Self::A(ip)),
+            Ok(IpAddr::V6(ip)) => Ok(Self::Aaaa(ip)),
+            Err(_) if is_hostname(s) => Ok(Self::Cname(s.to_string())),
+            Err(_) => Err(GeoRuleError::InvalidTarget(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for GeoTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::A(ip) => write!(f, "{ip}"),
+            Self::Aaaa(ip) => write!(f, "{ip}"),
+            Self::Cname(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Whether `s` is a plausible hostname: dot separated labels of letters, digits, `-` and `_`
+fn is_hostname(s: &str) -> bool {
+    let name = s.strip_suffix('.').unwrap_or(s);
+    !name.is_empty()
+        && name.len() <= 253
+        && name.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+        })
+}
+
+/// A target and its relative weight
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WeightedTarget {
+    pub target: GeoTarget,
+    pub weight: u32,
+}
+
+impl WeightedTarget {
+    /// A target with a weight of 1
+    pub fn new(target: GeoTarget) -> Self {
+        Self { target, weight: 1 }
+    }
+}
+
+/// What a rule or the default answers
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GeoAction {
+    /// One of the targets, chosen by weight
+    Answer(Vec<WeightedTarget>),
+    /// NXDOMAIN, the name does not exist for the client
+    NxDomain,
+}
+
+impl GeoAction {
+    /// Answer with one of `targets`, which must not be empty
+    pub fn answer(targets: Vec<WeightedTarget>) -> Result<Self, GeoRuleError> {
+        match targets.is_empty() {
+            true => Err(GeoRuleError::NoTargets),
+            false => Ok(Self::Answer(targets)),
+        }
+    }
+
+    /// The targets of an answer, none for NXDOMAIN
+    pub fn targets(&self) -> &[WeightedTarget] {
+        match self {
+            Self::Answer(targets) => targets,
+            Self::NxDomain => &[],
+        }
+    }
+}
+
+/// The outcome of evaluating the rules for a client
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoDecision {
+    /// Id of the rule that matched, None if the default applies or there is none
+    pub rule_id: Option<String>,
+    /// What to answer, None if no rule matched and there is no default
+    pub action: Option<GeoAction>,
+    /// Whether no rule matched, or no target could be chosen by distance, so that the default or
+    /// fallback applies
+    pub fallback: bool,
+    /// Prefix length of the client network the decision holds for, or None if it depends on a
+    /// location that is not known
+    pub scope: Option<u8>,
+}
+
+/// Choose one of `targets` with a probability proportional to its weight, using `seed` as the
+/// random number so that the same seed always chooses the same target
+///
+/// Targets with a weight of zero are never chosen, unless every target has a weight of zero.
+pub fn pick_weighted<'t>(
+    targets: impl IntoIterator<Item = &'t WeightedTarget>,
+    seed: u64,
+) -> Option<&'t GeoTarget> {
+    let targets = targets.into_iter().collect::<Vec<_>>();
+    let total = targets.iter().map(|t| u64::from(t.weight)).sum::<u64>();
+    if total == 0 {
+        let idx = (seed % targets.len().max(1) as u64) as usize;
+        return targets.get(idx).map(|t| &t.target);
+    }
+
+    let mut point = seed % total;
+    for t in targets {
+        match point.checked_sub(u64::from(t.weight)) {
+            Some(rest) => point = rest,
+            None => return Some(&t.target),
+        }
+    }
+    None
+}
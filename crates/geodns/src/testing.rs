@@ -0,0 +1,238 @@
+//! A minimal MaxMind DB writer for building test databases in memory.
+
+use std::net::IpAddr;
+
+/// A value in the data section of a MaxMind DB
+#[derive(Clone, Debug)]
+pub enum Value {
+    String(String),
+    Double(f64),
+    Uint16(u16),
+    Uint32(u32),
+    Uint64(u64),
+    Bool(bool),
+    Array(Vec<Value>),
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Build a map value from `(key, value)` pairs
+    pub fn map<'a>(entries: impl IntoIterator<Item = (&'a str, Value)>) -> Self {
+        Self::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    /// Build a string value
+    pub fn str(s: &str) -> Self {
+        Self::String(s.to_string())
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::String(s) => {
+                control(out, 2, s.len());
+                out.extend_from_slice(s.as_bytes());
+            }
+            Self::Double(d) => {
+                control(out, 3, 8);
+                out.extend_from_slice(&d.to_be_bytes());
+            }
+            Self::Uint16(n) => uint(out, 5, u64::from(*n)),
+            Self::Uint32(n) => uint(out, 6, u64::from(*n)),
+            Self::Uint64(n) => uint(out, 9, *n),
+            Self::Bool(b) => control(out, 14, usize::from(*b)),
+            Self::Array(items) => {
+                control(out, 11, items.len());
+                for item in items {
+                    item.encode(out);
+                }
+            }
+            Self::Map(entries) => {
+                control(out, 7, entries.len());
+                for (key, value) in entries {
+                    Self::String(key.clone()).encode(out);
+                    value.encode(out);
+                }
+            }
+        }
+    }
+}
+
+fn uint(out: &mut Vec<u8>, type_num: u8, n: u64) {
+    let bytes = n.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    control(out, type_num, bytes.len() - skip);
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn control(out: &mut Vec<u8>, type_num: u8, size: usize) {
+    let (size_bits, extra) = match size {
+        0..=28 => (size as u8, vec![]),
+        29..=284 => (29, vec![(size - 29) as u8]),
+        285..=65_820 => (30, (size - 285).to_be_bytes()[6..].to_vec()),
+        _ => (31, (size - 65_821).to_be_bytes()[5..].to_vec()),
+    };
+    match type_num {
+        1..=7 => out.push(type_num << 5 | size_bits),
+        _ => out.extend_from_slice(&[size_bits, type_num - 7]),
+    }
+    out.extend_from_slice(&extra);
+}
+
+#[derive(Clone, Copy)]
+enum Slot {
+    Empty,
+    Node(usize),
+    Data(usize),
+}
+
+/// Builds an IPv6 MaxMind DB with 32-bit records; IPv4 networks are stored under `::/96`.
+///
+/// Networks must be inserted from least to most specific.
+pub struct MmdbWriter {
+    database_type: String,
+    build_epoch: u64,
+    nodes: Vec<[Slot; 2]>,
+    data: Vec<u8>,
+}
+
+impl MmdbWriter {
+    pub fn new(database_type: &str, build_epoch: u64) -> Self {
+        Self {
+            database_type: database_type.to_string(),
+            build_epoch,
+            nodes: vec![[Slot::Empty; 2]],
+            data: Vec::new(),
+        }
+    }
+
+    /// Map `network/prefix_len` to `value`
+    pub fn insert(&mut self, network: IpAddr, prefix_len: u8, value: &Value) -> &mut Self {
+        let (bits, prefix_len) = match network {
+            IpAddr::V4(ip) => (u128::from(ip.to_ipv6_compatible()), prefix_len + 96),
+            IpAddr::V6(ip) => (u128::from(ip), prefix_len),
+        };
+        assert!(prefix_len > 0 && prefix_len <= 128, "invalid prefix length");
+
+        let offset = self.data.len();
+        value.encode(&mut self.data);
+
+        let mut node = 0;
+        for i in 0..prefix_len {
+            let bit = (bits >> (127 - i) & 1) as usize;
+            if i + 1 == prefix_len {
+                self.nodes[node][bit] = Slot::Data(offset);
+                break;
+            }
+            node = match self.nodes[node][bit] {
+                Slot::Node(next) => next,
+                // push a less specific network down so that it still covers the sibling
+                slot => {
+                    let fill = match slot {
+                        Slot::Data(_) => slot,
+                        _ => Slot::Empty,
+                    };
+                    self.nodes.push([fill; 2]);
+                    let next = self.nodes.len() - 1;
+                    self.nodes[node][bit] = Slot::Node(next);
+                    next
+                }
+            };
+        }
+        self
+    }
+
+    /// Serialize the database
+    pub fn build(&self) -> Vec<u8> {
+        let node_count = self.nodes.len();
+        let mut out = Vec::new();
+        for node in &self.nodes {
+            for slot in node {
+                let record = match *slot {
+                    Slot::Empty => node_count,
+                    Slot::Node(next) => next,
+                    Slot::Data(offset) => node_count + 16 + offset,
+                };
+                out.extend_from_slice(&(record as u32).to_be_bytes());
+            }
+        }
+        out.extend_from_slice(&[0; 16]);
+        out.extend_from_slice(&self.data);
+
+        out.extend_from_slice(b"\xab\xcd\xefMaxMind.com");
+        Value::map([
+            ("binary_format_major_version", Value::Uint16(2)),
+            ("binary_format_minor_version", Value::Uint16(0)),
+            ("build_epoch", Value::Uint64(self.build_epoch)),
+            ("database_type", Value::String(self.database_type.clone())),
+            ("description", Value::map([("en", Value::str("test"))])),
+            ("ip_version", Value::Uint16(6)),
+            ("languages", Value::Array(vec![Value::str("en")])),
+            ("node_count", Value::Uint32(node_count as u32)),
+            ("record_size", Value::Uint16(32)),
+        ])
+        .encode(&mut out);
+        out
+    }
+}
+
+/// A GeoIP2 Country record for an ISO country code and continent code
+pub fn country(iso_code: &str, continent: &str) -> Value {
+    Value::map([
+        ("continent", Value::map([("code", Value::str(continent))])),
+        ("country", Value::map([("iso_code", Value::str(iso_code))])),
+    ])
+}
+
+/// A GeoIP2 City record with a subdivision ISO code (without the country prefix), an English
+/// city name and a GeoNames id
+pub fn city(
+    iso_code: &str,
+    continent: &str,
+    subdivision: &str,
+    name: &str,
+    geoname_id: u32,
+) -> Value {
+    Value::map([
+        (
+            "city",
+            Value::map([
+                ("geoname_id", Value::Uint32(geoname_id)),
+                ("names", Value::map([("en", Value::str(name))])),
+            ]),
+        ),
+        ("continent", Value::map([("code", Value::str(continent))])),
+        ("country", Value::map([("iso_code", Value::str(iso_code))])),
+        (
+            "subdivisions",
+            Value::Array(vec![Value::map([("iso_code", Value::str(subdivision))])]),
+        ),
+    ])
+}
+
+/// A GeoLite2 ASN record
+pub fn asn(number: u32, organization: &str) -> Value {
+    Value::map([
+        ("autonomous_system_number", Value::Uint32(number)),
+        ("autonomous_system_organization", Value::str(organization)),
+    ])
+}
+
+/// Add the coordinates of a GeoIP2 City `location` to a record
+pub fn located(record: Value, latitude: f64, longitude: f64) -> Value {
+    let Value::Map(mut entries) = record else {
+        panic!("records are maps");
+    };
+    entries.push((
+        "location".to_string(),
+        Value::map([
+            ("latitude", Value::Double(latitude)),
+            ("longitude", Value::Double(longitude)),
+        ]),
+    ));
+    Value::Map(entries)
+}
@@ -560,15 +560,12 @@ pub fn delete_all(
 ///
 /// # Arguments
 /// * `zone_origin` - the zone name to update, i.e. SOA name
-/// * `last_soa` - the last SOA known, if any. If provided, name must match `zone_origin`
+/// * `last_soa` - the last SOA known for `zone_origin`, if any
 #[cfg(any(feature = "std", feature = "no-std-rand"))]
 pub fn zone_transfer(zone_origin: Name, last_soa: Option<SOA>) -> Message {
-    if let Some(soa) = &last_soa {
-        assert_eq!(&zone_origin, soa.mname());
-    }
-
     let mut zone: Query = Query::new();
-    zone.set_name(zone_origin).set_query_class(DNSClass::IN);
+    zone.set_name(zone_origin.clone())
+        .set_query_class(DNSClass::IN);
     if last_soa.is_some() {
         zone.set_query_type(RecordType::IXFR);
     } else {
@@ -582,7 +579,7 @@ pub fn zone_transfer(zone_origin: Name, last_soa: Option<SOA>) -> Message {
 
     if let Some(soa) = last_soa {
         // for IXFR, old SOA is put as authority to indicate last known version
-        let record = Record::from_rdata(soa.mname().clone(), 0, RData::SOA(soa));
+        let record = Record::from_rdata(zone_origin, 0, RData::SOA(soa));
         message.add_authority(record);
     }
 
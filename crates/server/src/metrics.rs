@@ -747,6 +747,104 @@ pub const ZONE_RECORDS_MODIFIED_TOTAL: &str = "hickory_zone_records_modified_tot
 /// Number of requests by transport protocol.
 pub const REQUEST_PROTOCOLS_TOTAL: &str = "hickory_request_protocols_total";
 
+/// Metrics related to the optional geo zone handler
+#[cfg(feature = "geo")]
+pub mod geo {
+    use std::collections::HashMap;
+
+    use geodns::{GeoDecision, GeoRule, GeoTarget};
+    use metrics::{Counter, Unit, counter, describe_counter};
+
+    /// Hit counters of the rules, nearest targets and fallbacks of a name answered by location
+    pub(crate) struct GeoNameMetrics {
+        zone: String,
+        name: String,
+        rules: HashMap<String, Counter>,
+        targets: HashMap<GeoTarget, Counter>,
+        fallback: Counter,
+        none: Counter,
+    }
+
+    impl GeoNameMetrics {
+        /// Counters for `name` in `zone`, whose fallback is labeled `fallback`: `default` for
+        /// rules and `fallback` for nearest targets
+        pub(crate) fn new(zone: &str, name: &str, fallback: &'static str) -> Self {
+            describe_counter!(
+                RULE_HITS_TOTAL,
+                Unit::Count,
+                "The total number of geo lookups decided by a rule"
+            );
+            describe_counter!(
+                NEAREST_HITS_TOTAL,
+                Unit::Count,
+                "The total number of geo lookups answered with the target nearest to the client"
+            );
+            describe_counter!(
+                FALLBACK_HITS_TOTAL,
+                Unit::Count,
+                "The total number of geo lookups no rule or nearest target was found for, by the fallback used"
+            );
+
+            let fallback_key = "fallback";
+            Self {
+                zone: zone.to_string(),
+                name: name.to_string(),
+                rules: HashMap::new(),
+                targets: HashMap::new(),
+                fallback: counter!(FALLBACK_HITS_TOTAL, "zone" => zone.to_string(), "name" => name.to_string(), fallback_key => fallback),
+                none: counter!(FALLBACK_HITS_TOTAL, "zone" => zone.to_string(), "name" => name.to_string(), fallback_key => "none"),
+            }
+        }
+
+        pub(crate) fn add_rule(&mut self, rule: &GeoRule) {
+            let counter = counter!(
+                RULE_HITS_TOTAL,
+                "zone" => self.zone.clone(),
+                "name" => self.name.clone(),
+                "rule" => rule.id.clone(),
+                "match" => format!("{} {}", rule.match_type, rule.match_value),
+            );
+            self.rules.insert(rule.id.clone(), counter);
+        }
+
+        pub(crate) fn add_target(&mut self, target: &GeoTarget) {
+            let counter = counter!(
+                NEAREST_HITS_TOTAL,
+                "zone" => self.zone.clone(),
+                "name" => self.name.clone(),
+                "target" => target.to_string(),
+            );
+            self.targets.insert(target.clone(), counter);
+        }
+
+        /// Count what decided a lookup
+        pub(crate) fn record(&self, decision: &GeoDecision) {
+            let counter = match (&decision.rule_id, &decision.action) {
+                (Some(rule_id), _) => self.rules.get(rule_id),
+                (None, Some(_)) if decision.fallback => Some(&self.fallback),
+                (None, Some(action)) => action
+                    .targets()
+                    .first()
+                    .and_then(|nearest| self.targets.get(&nearest.target)),
+                (None, None) => Some(&self.none),
+            };
+            if let Some(counter) = counter {
+                counter.increment(1);
+            }
+        }
+    }
+
+    /// The total number of geo lookups decided by a rule
+    pub const RULE_HITS_TOTAL: &str = "hickory_geo_rule_hits_total";
+
+    /// The total number of geo lookups answered with the target nearest to the client
+    pub const NEAREST_HITS_TOTAL: &str = "hickory_geo_nearest_hits_total";
+
+    /// The total number of geo lookups no rule or nearest target was found for, by the fallback
+    /// used: `default` or `fallback` if one is configured, `none` otherwise
+    pub const FALLBACK_HITS_TOTAL: &str = "hickory_geo_fallback_hits_total";
+}
+
 /// Metrics related to the optional blocklist feature
 #[cfg(feature = "blocklist")]
 pub mod blocklist {
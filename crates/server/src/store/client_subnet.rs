@@ -0,0 +1,71 @@
+// Copyright 2015-2025 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! The EDNS Client Subnet of queries, for stores whose answers depend on the client
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::{
+    proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption},
+    server::Request,
+};
+
+/// Whether the EDNS Client Subnet option of a query is used instead of its source address
+#[derive(Clone, Default, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientSubnetPolicy {
+    /// Use the client subnet sent by any resolver
+    #[default]
+    Trust,
+    /// Always use the source address of the query
+    Ignore,
+    /// Use the client subnet only when sent by a resolver in one of these networks
+    TrustFrom(Vec<IpNet>),
+}
+
+impl ClientSubnetPolicy {
+    /// The client subnet of the request, if there is one and the policy allows using it
+    ///
+    /// The scope prefix of the returned subnet is 0, for the caller to set to the prefix length
+    /// its answer holds for.
+    pub(crate) fn client_subnet(&self, request: &Request) -> Option<ClientSubnet> {
+        let Some(EdnsOption::Subnet(subnet)) = request.edns()?.option(EdnsCode::Subnet) else {
+            return None;
+        };
+
+        let src = request.src().ip();
+        let trusted = match self {
+            Self::Trust => true,
+            Self::Ignore => false,
+            Self::TrustFrom(networks) => networks.iter().any(|network| network.contains(&src)),
+        };
+        if !trusted {
+            debug!("ignoring client subnet from {src}");
+            return None;
+        }
+
+        // only the source prefix is significant, RFC 7871 section 6
+        let network = IpNet::new(subnet.addr(), subnet.source_prefix()).ok()?;
+        Some(ClientSubnet::new(
+            network.network(),
+            subnet.source_prefix(),
+            0,
+        ))
+    }
+}
+
+/// The prefix length of a single address
+pub(crate) fn full_prefix(ip: IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
@@ -0,0 +1,1440 @@
+// Copyright 2015-2025 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Geographic answer selection based on the location of the client
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, hash_map::Entry},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use geodns::{
+    Coordinates, GeoAction, GeoDB, GeoDecision, GeoRule, GeoRuleEngine, GeoTarget, MatchType,
+    NearestSelector, NearestTarget, TieBreak, WeightedTarget, pick_weighted,
+};
+use ipnet::IpNet;
+use serde::Deserialize;
+use tracing::{debug, info};
+
+#[cfg(feature = "metrics")]
+use crate::metrics::geo::GeoNameMetrics;
+#[cfg(feature = "__dnssec")]
+use crate::{dnssec::NxProofKind, zone_handler::Nsec3QueryInfo};
+use crate::{
+    proto::{
+        op::ResponseCode,
+        rr::{
+            LowerName, Name, RData, Record, RecordSet, RecordType, TSigResponseContext,
+            rdata::{A, AAAA, CNAME},
+        },
+    },
+    server::{Request, RequestInfo},
+    store::{ClientSubnetPolicy, client_subnet::full_prefix, fnv::Fnv1a},
+    zone_handler::{
+        AuthLookup, AxfrPolicy, LookupControlFlow, LookupError, LookupOptions, LookupRecords,
+        ZoneHandler, ZoneTransfer, ZoneType,
+    },
+};
+
+/// A zone handler wrapper which answers queries for configured names based on the location of
+/// the client.
+///
+/// The client's source address is looked up in a MaxMind database. Each configured name either
+/// has rules, which are evaluated in order and match the country, continent, subdivision or city
+/// of the client, its autonomous system if an ASN database is configured, or its network; or it
+/// has targets with coordinates, of which the one nearest to the client is chosen.
+///
+/// Rules are evaluated by priority and answer with one of their targets, chosen by weight, or
+/// with NXDOMAIN. The chosen target is returned as an A, AAAA or CNAME record. If no rule matches
+/// and there is no default answer, no target is chosen, or no target answers the query type, the
+/// lookup is passed to the wrapped zone handler. Zone transfers and updates are always passed
+/// through unchanged.
+///
+/// When a query carries an EDNS Client Subnet option (RFC 7871) and the [`ClientSubnetPolicy`]
+/// allows it, the client subnet is located instead of the source address, and the response
+/// echoes the option with the scope of the database network the answer was chosen for.
+///
+/// Synthesized answers are not signed, even in a DNSSEC signed zone.
+pub struct GeoZoneHandler {
+    inner: Arc<dyn ZoneHandler>,
+    ttl: u32,
+    client_subnet: ClientSubnetPolicy,
+    names: HashMap<LowerName, GeoName>,
+}
+
+/// How the target of one owner name is chosen, and the records the targets are answered with
+struct GeoName {
+    selector: Selector,
+    targets: HashMap<GeoTarget, RData>,
+    #[cfg(feature = "metrics")]
+    metrics: GeoNameMetrics,
+}
+
+enum Selector {
+    Rules(GeoRuleEngine),
+    Nearest(NearestSelector),
+}
+
+impl Selector {
+    fn decide(&self, client: IpAddr) -> GeoDecision {
+        match self {
+            Self::Rules(engine) => engine.evaluate(client),
+            Self::Nearest(selector) => selector.select(client),
+        }
+    }
+}
+
+impl GeoZoneHandler {
+    /// Wrap `inner` with the rules from the given configuration, opening the configured databases
+    ///
+    /// A relative `db_path`, `asn_db_path` or `rules_file` is resolved against `root_dir`. The
+    /// rules of the rules file are read before the databases are opened.
+    pub fn try_from_config(
+        inner: Arc<dyn ZoneHandler>,
+        config: &GeoConfig,
+        root_dir: Option<&Path>,
+    ) -> Result<Self, String> {
+        let resolve = |path: &Path| match root_dir {
+            Some(root) => root.join(path),
+            None => path.to_owned(),
+        };
+
+        let mut config = Cow::Borrowed(config);
+        if let Some(path) = &config.rules_file {
+            let path = resolve(path);
+            info!("loading geo rules from {}", path.display());
+            let file = GeoRulesFile::read(&path)?;
+            let config = config.to_mut();
+            config.rules.extend(file.rules);
+            config.nearest.extend(file.nearest);
+        }
+
+        let open = |path: &Path| {
+            let path = resolve(path);
+            info!("loading GeoIP database from {}", path.display());
+            GeoDB::open(&path)
+                .map_err(|e| format!("failed to load GeoIP database {}: {e}", path.display()))
+        };
+
+        let mut db = None::<GeoDB>;
+        for path in [&config.db_path, &config.asn_db_path].into_iter().flatten() {
+            let opened = open(path)?;
+            db = Some(match db {
+                Some(db) => db.merge(opened),
+                None => opened,
+            });
+        }
+
+        Self::with_database(inner, db, &config)
+    }
+
+    /// Wrap `inner` with the rules from the given configuration, using an already opened database
+    ///
+    /// Without a database only `cidr` rules can be used. Relative names and hostname targets in
+    /// the configuration are relative to the origin of `inner`. The `rules_file` is not read.
+    pub fn with_database(
+        inner: Arc<dyn ZoneHandler>,
+        db: Option<GeoDB>,
+        config: &GeoConfig,
+    ) -> Result<Self, String> {
+        let origin = Name::from(inner.origin());
+        let default = config
+            .default
+            .as_ref()
+            .map(|default| geo_action(&default.target, &default.targets, default.action))
+            .transpose()
+            .map_err(|e| format!("invalid geo default: {e}"))?;
+
+        let mut names = HashMap::<_, GeoName>::new();
+        for (idx, rule) in config.rules.iter().enumerate() {
+            let name = absolute(&rule.name, &origin)?;
+            if db.is_none() && rule.match_type != MatchType::Cidr {
+                return Err(format!(
+                    "geo rule on {name} matching {} needs a GeoIP database",
+                    rule.match_type
+                ));
+            }
+            let invalid = |e| {
+                format!(
+                    "invalid geo rule on {name} matching {} {:?}: {e}",
+                    rule.match_type, rule.match_value
+                )
+            };
+            let action = geo_action(&rule.target, &rule.targets, rule.action).map_err(invalid)?;
+            let geo_rule =
+                GeoRule::new(idx.to_string(), rule.match_type, &rule.match_value, action)
+                    .map_err(|e| invalid(e.to_string()))?
+                    .with_priority(rule.priority);
+
+            let geo = match names.entry(LowerName::new(&name)) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => {
+                    let mut engine = GeoRuleEngine::new(db.clone());
+                    let mut targets = HashMap::new();
+                    if let Some(default) = &default {
+                        add_targets(&mut targets, default.targets(), &name, &origin)?;
+                        engine.set_default(Some(default.clone()));
+                    }
+                    entry.insert(GeoName {
+                        selector: Selector::Rules(engine),
+                        targets,
+                        #[cfg(feature = "metrics")]
+                        metrics: GeoNameMetrics::new(
+                            &origin.to_string(),
+                            &name.to_string(),
+                            "default",
+                        ),
+                    })
+                }
+            };
+            add_targets(&mut geo.targets, geo_rule.action.targets(), &name, &origin)?;
+            #[cfg(feature = "metrics")]
+            geo.metrics.add_rule(&geo_rule);
+            let Selector::Rules(engine) = &mut geo.selector else {
+                unreachable!("rules are added before nearest targets");
+            };
+            engine.add_rule(geo_rule);
+        }
+
+        for nearest in &config.nearest {
+            let name = absolute(&nearest.name, &origin)?;
+            if names.contains_key(&LowerName::new(&name)) {
+                return Err(format!(
+                    "geo name {name} has both rules and nearest targets"
+                ));
+            }
+
+            if db.is_none() {
+                return Err(format!("nearest targets of {name} need a GeoIP database"));
+            }
+
+            let mut selector = NearestSelector::new(db.clone());
+            let mut targets = HashMap::new();
+            #[cfg(feature = "metrics")]
+            let mut metrics =
+                GeoNameMetrics::new(&origin.to_string(), &name.to_string(), "fallback");
+            selector.set_tie_break(nearest.tie_break, nearest.tie_distance_km);
+            for target in &nearest.targets {
+                if !(-90.0..=90.0).contains(&target.latitude)
+                    || !(-180.0..=180.0).contains(&target.longitude)
+                {
+                    return Err(format!(
+                        "invalid coordinates for target {} of {name}",
+                        target.target
+                    ));
+                }
+                let coordinates = Coordinates {
+                    latitude: target.latitude,
+                    longitude: target.longitude,
+                };
+                let healthy = target.healthy;
+                let target = parse_target(&target.target, &name)?;
+                add_target(&mut targets, &target, &name, &origin)?;
+                #[cfg(feature = "metrics")]
+                metrics.add_target(&target);
+                selector.add_target(NearestTarget {
+                    healthy,
+                    ..NearestTarget::new(target, coordinates)
+                });
+            }
+            if let Some(fallback) = &nearest.fallback {
+                let fallback = parse_target(fallback, &name)?;
+                add_target(&mut targets, &fallback, &name, &origin)?;
+                selector.set_fallback(Some(fallback));
+            }
+
+            names.insert(
+                LowerName::new(&name),
+                GeoName {
+                    selector: Selector::Nearest(selector),
+                    targets,
+                    #[cfg(feature = "metrics")]
+                    metrics,
+                },
+            );
+        }
+
+        Ok(Self {
+            inner,
+            ttl: config.ttl,
+            client_subnet: config.client_subnet.clone(),
+            names,
+        })
+    }
+
+    /// The answer for `client`, or `None` to use the wrapped zone handler, and the prefix length
+    /// of the client's network that the decision holds for
+    ///
+    /// `source_prefix` is the number of significant bits in `client`.
+    fn geo_lookup(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        client: IpAddr,
+        source_prefix: u8,
+        lookup_options: LookupOptions,
+    ) -> (Option<Result<AuthLookup, LookupError>>, u8) {
+        let Some(geo) = self.names.get(name) else {
+            // the wrapped zone answers the same for every client
+            return (None, 0);
+        };
+        let decision = geo.selector.decide(client);
+        #[cfg(feature = "metrics")]
+        if !matches!(rtype, RecordType::AXFR | RecordType::IXFR) {
+            geo.metrics.record(&decision);
+        }
+        let scope = decision.scope.unwrap_or(source_prefix);
+        let targets = match (decision.action, rtype) {
+            (None, _) | (_, RecordType::AXFR | RecordType::IXFR) => return (None, scope),
+            (Some(GeoAction::NxDomain), _) => {
+                debug!("geo NXDOMAIN for {name} {rtype} from {client}/{source_prefix}");
+                let nx_domain = LookupError::from(ResponseCode::NXDomain);
+                return (Some(Err(nx_domain)), scope);
+            }
+            (Some(GeoAction::Answer(targets)), _) => targets,
+        };
+
+        // targets of the queried type, or else hostnames to alias the name to
+        let of_type = |record_type: RecordType| {
+            targets
+                .iter()
+                .filter(move |t| geo.targets[&t.target].record_type() == record_type)
+                .collect::<Vec<_>>()
+        };
+        let candidates = match rtype {
+            RecordType::ANY => targets.iter().collect(),
+            _ => match of_type(rtype) {
+                exact if exact.is_empty() => of_type(RecordType::CNAME),
+                exact => exact,
+            },
+        };
+
+        // the same choice for every client the decision holds for, so that it can be cached, and
+        // on every server, so that the answer does not depend on which one a client asks
+        let mut hash = Fnv1a::default();
+        hash.write_ip(IpNet::new(client, scope).map_or(client, |network| network.network()));
+        hash.write(&[scope]);
+        hash.write(name.to_ascii().as_bytes());
+        let Some(target) = pick_weighted(candidates, hash.finish()) else {
+            return (None, scope);
+        };
+        let rdata = &geo.targets[target];
+        let record_type = rdata.record_type();
+
+        debug!("geo answer for {name} {rtype} from {client}/{source_prefix}: {target}");
+        let name = Name::from(name);
+        let mut rrset = RecordSet::new(name.clone(), record_type, 0);
+        rrset.insert(Record::from_rdata(name, self.ttl, rdata.clone()), 0);
+        let lookup = AuthLookup::answers(LookupRecords::new(lookup_options, Arc::new(rrset)), None);
+        (Some(Ok(lookup)), scope)
+    }
+}
+
+#[async_trait::async_trait]
+impl ZoneHandler for GeoZoneHandler {
+    fn zone_type(&self) -> ZoneType {
+        self.inner.zone_type()
+    }
+
+    fn axfr_policy(&self) -> AxfrPolicy {
+        self.inner.axfr_policy()
+    }
+
+    fn can_validate_dnssec(&self) -> bool {
+        self.inner.can_validate_dnssec()
+    }
+
+    async fn update(
+        &self,
+        update: &Request,
+        now: u64,
+    ) -> (Result<bool, ResponseCode>, Option<TSigResponseContext>) {
+        self.inner.update(update, now).await
+    }
+
+    async fn notify(&self, notify: &Request) -> Result<(), ResponseCode> {
+        self.inner.notify(notify).await
+    }
+
+    fn origin(&self) -> &LowerName {
+        self.inner.origin()
+    }
+
+    /// Answers from the geo rules for the client, or looks up the records in the wrapped zone
+    /// handler.
+    ///
+    /// Without `request_info` there is no client to locate, and the wrapped zone handler answers.
+    async fn lookup(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        if let Some(info) = request_info {
+            let client = info.src.ip();
+            let (lookup, _) =
+                self.geo_lookup(name, rtype, client, full_prefix(client), lookup_options);
+            if let Some(lookup) = lookup {
+                return LookupControlFlow::Continue(lookup);
+            }
+        }
+
+        self.inner
+            .lookup(name, rtype, request_info, lookup_options)
+            .await
+    }
+
+    async fn consult(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+        last_result: LookupControlFlow<AuthLookup>,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        self.inner
+            .consult(name, rtype, request_info, lookup_options, last_result)
+            .await
+    }
+
+    /// Answers from the geo rules for the client subnet or the source address of the request,
+    /// or searches the wrapped zone handler.
+    ///
+    /// If the client subnet was used, the answer carries it with the scope the answer holds for.
+    async fn search(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        let Ok(info) = request.request_info() else {
+            return self.inner.search(request, lookup_options).await;
+        };
+
+        let subnet = self.client_subnet.client_subnet(request);
+        let (client, source_prefix) = match subnet {
+            Some(subnet) => (subnet.addr(), subnet.source_prefix()),
+            None => (info.src.ip(), full_prefix(info.src.ip())),
+        };
+        let (lookup, scope) = self.geo_lookup(
+            info.query.name(),
+            info.query.query_type(),
+            client,
+            source_prefix,
+            lookup_options,
+        );
+        let (lookup, context) = match lookup {
+            Some(lookup) => (LookupControlFlow::Continue(lookup), None),
+            None => self.inner.search(request, lookup_options).await,
+        };
+
+        match subnet {
+            Some(mut subnet) => {
+                subnet.set_scope_prefix(scope);
+                (
+                    lookup.map(|lookup| lookup.with_client_subnet(subnet)),
+                    context,
+                )
+            }
+            None => (lookup, context),
+        }
+    }
+
+    async fn nsec_records(
+        &self,
+        name: &LowerName,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner.nsec_records(name, lookup_options).await
+    }
+
+    #[cfg(feature = "__dnssec")]
+    async fn nsec3_records(
+        &self,
+        info: Nsec3QueryInfo<'_>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner.nsec3_records(info, lookup_options).await
+    }
+
+    async fn zone_transfer(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+        now: u64,
+    ) -> Option<(
+        Result<ZoneTransfer, LookupError>,
+        Option<TSigResponseContext>,
+    )> {
+        self.inner.zone_transfer(request, lookup_options, now).await
+    }
+
+    #[cfg(feature = "__dnssec")]
+    fn nx_proof_kind(&self) -> Option<&NxProofKind> {
+        self.inner.nx_proof_kind()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn metrics_label(&self) -> &'static str {
+        self.inner.metrics_label()
+    }
+}
+
+/// `name` made absolute, relative to `origin`
+fn absolute(name: &Name, origin: &Name) -> Result<Name, String> {
+    match name.is_fqdn() {
+        true => Ok(name.clone()),
+        false => name
+            .clone()
+            .append_domain(origin)
+            .map_err(|e| format!("invalid geo name {name}: {e}")),
+    }
+}
+
+/// What a rule or the default with these settings answers
+fn geo_action(
+    target: &Option<String>,
+    targets: &[GeoTargetConfig],
+    action: GeoActionConfig,
+) -> Result<GeoAction, String> {
+    if action == GeoActionConfig::NxDomain {
+        return match target.is_none() && targets.is_empty() {
+            true => Ok(GeoAction::NxDomain),
+            false => Err("an nxdomain action takes no targets".to_string()),
+        };
+    }
+
+    let mut weighted = Vec::with_capacity(targets.len() + 1);
+    if let Some(target) = target {
+        let target = GeoTarget::from_str(target).map_err(|e| e.to_string())?;
+        weighted.push(WeightedTarget::new(target));
+    }
+    for target in targets {
+        let record_type = target.record_type.map(|t| t.to_string());
+        weighted.push(WeightedTarget {
+            target: GeoTarget::new(record_type.as_deref(), &target.value)
+                .map_err(|e| e.to_string())?,
+            weight: target.weight,
+        });
+    }
+    GeoAction::answer(weighted).map_err(|e| e.to_string())
+}
+
+/// A target of `name`, inferring its type from the value
+fn parse_target(target: &str, name: &Name) -> Result<GeoTarget, String> {
+    GeoTarget::from_str(target).map_err(|e| format!("invalid geo target for {name}: {e}"))
+}
+
+fn add_targets(
+    targets: &mut HashMap<GeoTarget, RData>,
+    weighted: &[WeightedTarget],
+    name: &Name,
+    origin: &Name,
+) -> Result<(), String> {
+    weighted
+        .iter()
+        .try_for_each(|t| add_target(targets, &t.target, name, origin))
+}
+
+/// Add the record `target` is answered with for `name`: A or AAAA for addresses, CNAME for
+/// hostnames
+fn add_target(
+    targets: &mut HashMap<GeoTarget, RData>,
+    target: &GeoTarget,
+    name: &Name,
+    origin: &Name,
+) -> Result<(), String> {
+    let rdata = match target {
+        GeoTarget::A(ip) => RData::A(A(*ip)),
+        GeoTarget::Aaaa(ip) => RData::AAAA(AAAA(*ip)),
+        GeoTarget::Cname(_) if name == origin => {
+            return Err(format!(
+                "geo target on the zone apex {name} cannot be a hostname"
+            ));
+        }
+        GeoTarget::Cname(host) => {
+            let host = Name::from_str(host)
+                .and_then(|host| match host.is_fqdn() {
+                    true => Ok(host),
+                    false => host.append_domain(origin),
+                })
+                .map_err(|e| format!("invalid geo target {host} for {name}: {e}"))?;
+            RData::CNAME(CNAME(host))
+        }
+    };
+    targets.insert(target.clone(), rdata);
+    Ok(())
+}
+
+/// Configuration for geographic answer selection
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoConfig {
+    /// Path to a MaxMind GeoIP2 or GeoLite2 database, relative to the zone directory; not needed
+    /// when only `cidr` rules are used
+    #[serde(default)]
+    pub db_path: Option<PathBuf>,
+    /// Path to a MaxMind ASN database for `asn` rules, relative to the zone directory
+    #[serde(default)]
+    pub asn_db_path: Option<PathBuf>,
+    /// TTL of the synthesized answers
+    #[serde(default = "default_ttl")]
+    pub ttl: u32,
+    /// Whether the EDNS Client Subnet of queries is used to locate clients
+    #[serde(default)]
+    pub client_subnet: ClientSubnetPolicy,
+    /// Answer for names with rules when none of them matches, instead of the wrapped zone
+    #[serde(default)]
+    pub default: Option<GeoDefaultConfig>,
+    /// The rules, evaluated in the order they are listed for each name
+    #[serde(default)]
+    pub rules: Vec<GeoRuleConfig>,
+    /// Names answered with the target nearest to the client
+    #[serde(default)]
+    pub nearest: Vec<NearestConfig>,
+    /// Path to a TOML file with more `[[rules]]` and `[[nearest]]` names, relative to the zone
+    /// directory; its rules are evaluated after the ones listed here
+    #[serde(default)]
+    pub rules_file: Option<PathBuf>,
+}
+
+/// Rules and names answered with the nearest target, kept in a file apart from the zone
+/// configuration
+#[derive(Clone, Default, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoRulesFile {
+    /// Rules, as in [`GeoConfig::rules`]
+    #[serde(default)]
+    pub rules: Vec<GeoRuleConfig>,
+    /// Names answered with the target nearest to the client, as in [`GeoConfig::nearest`]
+    #[serde(default)]
+    pub nearest: Vec<NearestConfig>,
+}
+
+impl GeoRulesFile {
+    /// Read the rules from a TOML file
+    #[cfg(feature = "toml")]
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read geo rules file {}: {e}", path.display()))?;
+        toml::from_str(&toml).map_err(|e| format!("invalid geo rules file {}: {e}", path.display()))
+    }
+
+    /// Reading a rules file needs the `toml` feature
+    #[cfg(not(feature = "toml"))]
+    pub fn read(path: &Path) -> Result<Self, String> {
+        Err(format!(
+            "cannot read geo rules file {} without the toml feature",
+            path.display()
+        ))
+    }
+}
+
+/// A single geo rule
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoRuleConfig {
+    /// Owner name the rule answers for, relative to the zone origin unless fully qualified
+    pub name: Name,
+    /// Rules with a lower priority are evaluated first; rules of the same priority in the order
+    /// they are listed
+    #[serde(default)]
+    pub priority: u32,
+    /// What is matched: `country`, `continent`, `subdivision`, `city`, `asn` or `cidr`
+    pub match_type: MatchType,
+    /// The value to match, e.g. `US`, `EU`, `US-TX`, `Berlin`, `AS64500` or `192.0.2.0/24`
+    pub match_value: String,
+    /// A single target with a weight of 1: an IPv4 or IPv6 address, or a hostname which is
+    /// answered as a CNAME
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Targets chosen between by weight
+    #[serde(default)]
+    pub targets: Vec<GeoTargetConfig>,
+    /// Whether the rule answers with a target or NXDOMAIN
+    #[serde(default)]
+    pub action: GeoActionConfig,
+}
+
+/// The answer for names whose rules all fail to match
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoDefaultConfig {
+    /// A single target with a weight of 1
+    #[serde(default)]
+    pub target: Option<String>,
+    /// Targets chosen between by weight
+    #[serde(default)]
+    pub targets: Vec<GeoTargetConfig>,
+    /// Whether to answer with a target or NXDOMAIN
+    #[serde(default)]
+    pub action: GeoActionConfig,
+}
+
+/// A weighted target
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct GeoTargetConfig {
+    /// An IPv4 or IPv6 address, or a hostname
+    pub value: String,
+    /// `A`, `AAAA` or `CNAME`, inferred from the value if not set
+    pub record_type: Option<RecordType>,
+    /// Relative weight of the target
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+}
+
+/// What a rule answers
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum GeoActionConfig {
+    /// One of the targets
+    #[default]
+    Answer,
+    /// NXDOMAIN
+    NxDomain,
+}
+
+/// A name answered with the target nearest to the client, by great-circle distance from the
+/// location of the client in a City database
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NearestConfig {
+    /// Owner name, relative to the zone origin unless fully qualified
+    pub name: Name,
+    /// The targets to choose from
+    pub targets: Vec<NearestTargetConfig>,
+    /// How targets at the same distance are chosen between, `first` or `client_hash`
+    #[serde(default)]
+    pub tie_break: TieBreak,
+    /// How many kilometers further than the nearest target a target may be to count as a tie
+    #[serde(default)]
+    pub tie_distance_km: f64,
+    /// Answered when the client has no known location or no target is healthy; otherwise the
+    /// wrapped zone answers
+    pub fallback: Option<String>,
+}
+
+/// A target of a [`NearestConfig`] and where it is
+#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NearestTargetConfig {
+    /// An IPv4 or IPv6 address, or a hostname which is answered as a CNAME
+    pub target: String,
+    /// Latitude in degrees
+    pub latitude: f64,
+    /// Longitude in degrees
+    pub longitude: f64,
+    /// Whether the target may be chosen
+    ///
+    /// This is only read when the server starts, there is no live health checking: taking a
+    /// target out of rotation takes a change of the configuration and a restart of the server.
+    #[serde(default = "default_healthy")]
+    pub healthy: bool,
+}
+
+fn default_ttl() -> u32 {
+    60
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+fn default_healthy() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use geodns::testing::{MmdbWriter, asn, city, country, located};
+    #[cfg(feature = "metrics")]
+    use metrics::{Label, with_local_recorder};
+    #[cfg(feature = "metrics")]
+    use metrics_util::debugging::DebuggingRecorder;
+    #[cfg(feature = "metrics")]
+    use test_support::assert_counter_eq;
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::{
+        net::{runtime::TokioRuntimeProvider, xfer::Protocol},
+        proto::{
+            op::{Edns, Header, LowerQuery, Message, MessageType, OpCode, Query},
+            rr::rdata::{
+                SOA,
+                opt::{ClientSubnet, EdnsOption},
+            },
+        },
+        store::in_memory::InMemoryZoneHandler,
+    };
+
+    fn origin() -> Name {
+        Name::from_str("example.com.").unwrap()
+    }
+
+    fn inner() -> Arc<dyn ZoneHandler> {
+        let mut handler = InMemoryZoneHandler::<TokioRuntimeProvider>::empty(
+            origin(),
+            ZoneType::Primary,
+            AxfrPolicy::Deny,
+            #[cfg(feature = "__dnssec")]
+            None,
+        );
+        let soa = SOA::new(
+            Name::from_str("ns.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            1,
+            3600,
+            600,
+            86_400,
+            300,
+        );
+        handler.upsert_mut(Record::from_rdata(origin(), 3600, RData::SOA(soa)), 1);
+        let apex = RData::A(A::new(192, 0, 2, 100));
+        handler.upsert_mut(Record::from_rdata(origin(), 300, apex), 1);
+
+        let www = Name::from_str("www.example.com.").unwrap();
+        let a = RData::A(A::new(192, 0, 2, 1));
+        handler.upsert_mut(Record::from_rdata(www.clone(), 300, a), 1);
+        let aaaa = RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        handler.upsert_mut(Record::from_rdata(www, 300, aaaa), 1);
+        Arc::new(handler)
+    }
+
+    fn db() -> GeoDB {
+        let mut writer = MmdbWriter::new("GeoLite2-Country", 1_700_000_000);
+        writer
+            .insert(IpAddr::from([198, 51, 100, 0]), 24, &country("US", "NA"))
+            .insert(IpAddr::from([203, 0, 113, 0]), 24, &country("DE", "EU"));
+        GeoDB::open_from_bytes(writer.build()).unwrap()
+    }
+
+    fn config(rules: &[(&str, &str, &str)]) -> GeoConfig {
+        GeoConfig {
+            db_path: None,
+            asn_db_path: None,
+            ttl: 30,
+            client_subnet: ClientSubnetPolicy::Trust,
+            default: None,
+            nearest: Vec::new(),
+            rules_file: None,
+            rules: rules
+                .iter()
+                .map(|(name, country, target)| rule(name, MatchType::Country, country, target))
+                .collect(),
+        }
+    }
+
+    fn rule(name: &str, match_type: MatchType, match_value: &str, target: &str) -> GeoRuleConfig {
+        GeoRuleConfig {
+            name: Name::from_str(name).unwrap(),
+            priority: 0,
+            match_type,
+            match_value: match_value.to_string(),
+            target: Some(target.to_string()),
+            targets: Vec::new(),
+            action: GeoActionConfig::Answer,
+        }
+    }
+
+    async fn lookup_from(handler: &GeoZoneHandler, rtype: RecordType, src: IpAddr) -> Vec<Record> {
+        let name = LowerName::from_str("www.example.com.").unwrap();
+        let header = Header::new(0, MessageType::Query, OpCode::Query);
+        let query = LowerQuery::query(Query::query(Name::from(&name), rtype));
+        let info = RequestInfo::new(SocketAddr::new(src, 53000), Protocol::Udp, &header, &query);
+        handler
+            .lookup(&name, rtype, Some(&info), LookupOptions::default())
+            .await
+            .unwrap()
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_answer_by_country() {
+        subscribe();
+        let config = config(&[("www", "US", "192.0.2.10"), ("www", "DE", "2001:db8::de")]);
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+
+        let us = IpAddr::from([198, 51, 100, 7]);
+        let records = lookup_from(&handler, RecordType::A, us).await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].data(), &RData::A(A::new(192, 0, 2, 10)));
+        assert_eq!(records[0].ttl(), 30);
+
+        // the target only answers A, so AAAA comes from the wrapped zone
+        let records = lookup_from(&handler, RecordType::AAAA, us).await;
+        assert_eq!(
+            records[0].data(),
+            &RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        );
+
+        let de = IpAddr::from([203, 0, 113, 9]);
+        let records = lookup_from(&handler, RecordType::AAAA, de).await;
+        assert_eq!(
+            records[0].data(),
+            &RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0xde))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_zone() {
+        subscribe();
+        let config = config(&[("www", "US", "192.0.2.10")]);
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+
+        // neither located nor matched
+        for client in [
+            IpAddr::from(Ipv4Addr::LOCALHOST),
+            IpAddr::from([203, 0, 113, 9]),
+        ] {
+            let records = lookup_from(&handler, RecordType::A, client).await;
+            assert_eq!(records.len(), 1);
+            assert_eq!(records[0].data(), &RData::A(A::new(192, 0, 2, 1)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hostname_target() {
+        subscribe();
+        let config = config(&[("www", "DE", "eu.cdn")]);
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+
+        let de = IpAddr::from([203, 0, 113, 9]);
+        for rtype in [RecordType::A, RecordType::AAAA] {
+            let records = lookup_from(&handler, rtype, de).await;
+            assert_eq!(records.len(), 1);
+            assert_eq!(
+                records[0].data(),
+                &RData::CNAME(CNAME(Name::from_str("eu.cdn.example.com.").unwrap()))
+            );
+        }
+    }
+
+    async fn search_from(
+        handler: &GeoZoneHandler,
+        name: &str,
+        src: IpAddr,
+        subnet: &str,
+    ) -> (Vec<RData>, Option<ClientSubnet>) {
+        let mut message = Message::query();
+        message.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        let mut edns = Edns::new();
+        let subnet = ClientSubnet::from_str(subnet).unwrap();
+        edns.options_mut().insert(EdnsOption::Subnet(subnet));
+        message.set_edns(edns);
+
+        let src = SocketAddr::new(src, 53000);
+        let request = Request::from_bytes(message.to_vec().unwrap(), src, Protocol::Udp).unwrap();
+        let lookup = handler
+            .search(&request, LookupOptions::default())
+            .await
+            .0
+            .unwrap();
+        let answers = lookup.iter().map(|record| record.data().clone()).collect();
+        (answers, lookup.client_subnet())
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet() {
+        subscribe();
+        let config = config(&[("www", "DE", "192.0.2.20")]);
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+        let resolver = IpAddr::from(Ipv4Addr::LOCALHOST);
+
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "203.0.113.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 20))]);
+        let subnet = subnet.unwrap();
+        assert_eq!(subnet.addr(), IpAddr::from([203, 0, 113, 0]));
+        assert_eq!((subnet.source_prefix(), subnet.scope_prefix()), (24, 24));
+
+        // more specific than the database network, the answer holds for the whole network
+        let (_, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "203.0.113.128/28").await;
+        assert_eq!(subnet.unwrap().scope_prefix(), 24);
+
+        // no rule matched, but the fallback still depends on where the client is
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "198.51.100.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 1))]);
+        assert_eq!(subnet.unwrap().scope_prefix(), 24);
+
+        // not located, so only the sent prefix is known to get this answer
+        let (_, subnet) = search_from(&handler, "www.example.com.", resolver, "192.0.2.0/24").await;
+        assert_eq!(subnet.unwrap().scope_prefix(), 24);
+
+        // names without rules answer the same everywhere
+        let (_, subnet) = search_from(&handler, "example.com.", resolver, "203.0.113.0/24").await;
+        assert_eq!(subnet.unwrap().scope_prefix(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet_policy() {
+        subscribe();
+        let mut config = config(&[("www", "DE", "192.0.2.20")]);
+        let german = IpAddr::from([203, 0, 113, 9]);
+
+        config.client_subnet = ClientSubnetPolicy::Ignore;
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", german, "198.51.100.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 20))]);
+        assert!(subnet.is_none());
+
+        config.client_subnet =
+            ClientSubnetPolicy::TrustFrom(vec![IpNet::from_str("10.0.0.0/8").unwrap()]);
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", german, "198.51.100.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 20))]);
+        assert!(subnet.is_none());
+
+        let resolver = IpAddr::from([10, 0, 0, 53]);
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "203.0.113.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 20))]);
+        assert_eq!(subnet.unwrap().scope_prefix(), 24);
+    }
+
+    #[tokio::test]
+    async fn test_match_types() {
+        subscribe();
+        let mut writer = MmdbWriter::new("GeoLite2-City", 1_700_000_000);
+        writer
+            .insert(
+                IpAddr::from([198, 51, 100, 0]),
+                24,
+                &city("US", "NA", "TX", "Austin", 4_671_654),
+            )
+            .insert(
+                IpAddr::from([203, 0, 113, 0]),
+                24,
+                &city("DE", "EU", "BE", "Berlin", 2_950_159),
+            );
+        let cities = GeoDB::open_from_bytes(writer.build()).unwrap();
+        let mut writer = MmdbWriter::new("GeoLite2-ASN", 1_700_000_000);
+        writer.insert(IpAddr::from([198, 51, 100, 0]), 25, &asn(64_500, "Example"));
+        let db = cities.merge(GeoDB::open_from_bytes(writer.build()).unwrap());
+
+        let handler_for = |match_type, match_value: &str| {
+            let mut config = config(&[]);
+            config
+                .rules
+                .push(rule("www", match_type, match_value, "192.0.2.10"));
+            GeoZoneHandler::with_database(inner(), Some(db.clone()), &config).unwrap()
+        };
+        let texas = IpAddr::from([198, 51, 100, 7]);
+        let berlin = IpAddr::from([203, 0, 113, 9]);
+        let cases = [
+            (MatchType::Continent, "eu", berlin, texas),
+            (MatchType::Subdivision, "US-TX", texas, berlin),
+            (MatchType::City, "berlin", berlin, texas),
+            (MatchType::City, "4671654", texas, berlin),
+            (
+                MatchType::Asn,
+                "AS64500",
+                texas,
+                IpAddr::from([198, 51, 100, 200]),
+            ),
+            (
+                MatchType::Cidr,
+                "10.0.0.0/8",
+                IpAddr::from([10, 1, 2, 3]),
+                texas,
+            ),
+        ];
+        for (match_type, value, matching, other) in cases {
+            let handler = handler_for(match_type, value);
+            let records = lookup_from(&handler, RecordType::A, matching).await;
+            assert_eq!(
+                records[0].data(),
+                &RData::A(A::new(192, 0, 2, 10)),
+                "{match_type} {value}"
+            );
+            let records = lookup_from(&handler, RecordType::A, other).await;
+            assert_eq!(
+                records[0].data(),
+                &RData::A(A::new(192, 0, 2, 1)),
+                "{match_type} {value}"
+            );
+        }
+
+        // the AS network is more specific than the city network
+        let handler = handler_for(MatchType::Asn, "64500");
+        let (_, subnet) = search_from(&handler, "www.example.com.", texas, "198.51.100.0/24").await;
+        assert_eq!(subnet.unwrap().scope_prefix(), 25);
+
+        // a network rule needs no database
+        let handler = handler_for(MatchType::Cidr, "10.0.0.0/8");
+        let (_, subnet) = search_from(&handler, "www.example.com.", texas, "10.1.0.0/16").await;
+        assert_eq!(subnet.unwrap().scope_prefix(), 8);
+    }
+
+    #[tokio::test]
+    async fn test_nearest() {
+        subscribe();
+        let dallas = located(city("US", "NA", "TX", "Dallas", 4_684_888), 32.78, -96.8);
+        let berlin = located(city("DE", "EU", "BE", "Berlin", 2_950_159), 52.52, 13.4);
+        let mut writer = MmdbWriter::new("GeoLite2-City", 1_700_000_000);
+        writer
+            .insert(IpAddr::from([198, 51, 100, 0]), 24, &dallas)
+            .insert(IpAddr::from([203, 0, 113, 0]), 24, &berlin);
+        for third in 0..16 {
+            writer.insert(IpAddr::from([100, 64, third, 0]), 24, &dallas);
+        }
+        let db = GeoDB::open_from_bytes(writer.build()).unwrap();
+
+        let target = |target: &str, latitude, longitude| NearestTargetConfig {
+            target: target.to_string(),
+            latitude,
+            longitude,
+            healthy: true,
+        };
+        let mut config = config(&[]);
+        config.nearest.push(NearestConfig {
+            name: Name::from_str("www").unwrap(),
+            targets: vec![
+                target("192.0.2.31", 38.95, -77.45), // Ashburn
+                target("192.0.2.32", 32.9, -97.04),  // Dallas
+                target("192.0.2.33", 50.11, 8.68),   // Frankfurt
+            ],
+            tie_break: TieBreak::First,
+            tie_distance_km: 0.0,
+            fallback: Some("192.0.2.30".to_string()),
+        });
+        let handler = GeoZoneHandler::with_database(inner(), Some(db.clone()), &config).unwrap();
+        let answer = |records: Vec<Record>| records[0].data().clone();
+
+        let texas = IpAddr::from([198, 51, 100, 7]);
+        let records = lookup_from(&handler, RecordType::A, texas).await;
+        assert_eq!(answer(records), RData::A(A::new(192, 0, 2, 32)));
+        let records = lookup_from(&handler, RecordType::A, IpAddr::from([203, 0, 113, 9])).await;
+        assert_eq!(answer(records), RData::A(A::new(192, 0, 2, 33)));
+
+        // not located
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", texas, "192.0.2.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 30))]);
+        assert_eq!(subnet.unwrap().scope_prefix(), 24);
+
+        // unhealthy targets are skipped, and the fallback answers when none is healthy
+        config.nearest[0].targets[1].healthy = false;
+        let handler = GeoZoneHandler::with_database(inner(), Some(db.clone()), &config).unwrap();
+        let records = lookup_from(&handler, RecordType::A, texas).await;
+        assert_eq!(answer(records), RData::A(A::new(192, 0, 2, 31)));
+        for target in &mut config.nearest[0].targets {
+            target.healthy = false;
+        }
+        let handler = GeoZoneHandler::with_database(inner(), Some(db.clone()), &config).unwrap();
+        let records = lookup_from(&handler, RecordType::A, texas).await;
+        assert_eq!(answer(records), RData::A(A::new(192, 0, 2, 30)));
+        for target in &mut config.nearest[0].targets {
+            target.healthy = true;
+        }
+
+        // within the tie distance, the first listed target wins, even if it is not the nearest
+        config.nearest[0].tie_distance_km = 2_000.0;
+        let handler = GeoZoneHandler::with_database(inner(), Some(db.clone()), &config).unwrap();
+        let records = lookup_from(&handler, RecordType::A, texas).await;
+        assert_eq!(answer(records), RData::A(A::new(192, 0, 2, 31)));
+
+        // or clients are spread over them by network
+        config.nearest[0].tie_break = TieBreak::ClientHash;
+        let handler = GeoZoneHandler::with_database(inner(), Some(db), &config).unwrap();
+        let mut seen = Vec::new();
+        for third in 0..16 {
+            let client = IpAddr::from([100, 64, third, 1]);
+            let first = answer(lookup_from(&handler, RecordType::A, client).await);
+            let again = IpAddr::from([100, 64, third, 200]);
+            assert_eq!(
+                answer(lookup_from(&handler, RecordType::A, again).await),
+                first
+            );
+            if !seen.contains(&first) {
+                seen.push(first);
+            }
+        }
+        assert_eq!(seen.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_network_views() {
+        subscribe();
+        let mut config = config(&[]);
+        for (network, target) in [
+            ("10.0.0.0/8", "192.0.2.40"),
+            ("10.1.0.0/16", "192.0.2.41"),
+            ("2001:db8::/32", "2001:db8::42"),
+        ] {
+            config
+                .rules
+                .push(rule("www", MatchType::Cidr, network, target));
+        }
+        // no database for network views only
+        let handler = GeoZoneHandler::with_database(inner(), None, &config).unwrap();
+        let resolver = IpAddr::from(Ipv4Addr::LOCALHOST);
+
+        // the longest prefix wins, though the broader network is listed first
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "10.1.2.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 41))]);
+        assert_eq!(subnet.unwrap().scope_prefix(), 16);
+
+        // outside the more specific network, the answer holds for 10.2.0.0/15, clear of it
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "10.2.0.0/16").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 40))]);
+        assert_eq!(subnet.unwrap().scope_prefix(), 15);
+
+        let (answers, subnet) =
+            search_from(&handler, "www.example.com.", resolver, "192.0.2.0/24").await;
+        assert_eq!(answers, vec![RData::A(A::new(192, 0, 2, 1))]);
+        assert_eq!(subnet.unwrap().scope_prefix(), 1);
+
+        let v6 = IpAddr::from_str("2001:db8::1").unwrap();
+        let records = lookup_from(&handler, RecordType::AAAA, v6).await;
+        assert_eq!(
+            records[0].data(),
+            &RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x42))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_priority_and_actions() {
+        subscribe();
+        let target = |value: &str, record_type, weight| GeoTargetConfig {
+            value: value.to_string(),
+            record_type,
+            weight,
+        };
+        let mut config = config(&[("www", "US", "192.0.2.10")]);
+        config.rules[0].priority = 10;
+
+        let mut america = rule("www", MatchType::Continent, "NA", "192.0.2.50");
+        america.priority = 5;
+        america.targets = vec![
+            target("192.0.2.51", None, 0),
+            target("2001:db8::51", Some(RecordType::AAAA), 1),
+        ];
+        config.rules.push(america);
+
+        let mut blocked = rule("www", MatchType::Cidr, "198.51.100.0/25", "unused");
+        blocked.priority = 1;
+        blocked.target = None;
+        blocked.action = GeoActionConfig::NxDomain;
+        config.rules.push(blocked);
+
+        config.default = Some(GeoDefaultConfig {
+            target: None,
+            targets: vec![target("default.cdn", Some(RecordType::CNAME), 1)],
+            action: GeoActionConfig::Answer,
+        });
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+
+        let blocked = IpAddr::from([198, 51, 100, 7]);
+        let name = LowerName::from_str("www.example.com.").unwrap();
+        let header = Header::new(0, MessageType::Query, OpCode::Query);
+        let query = LowerQuery::query(Query::query(Name::from(&name), RecordType::A));
+        let info = RequestInfo::new(
+            SocketAddr::new(blocked, 53000),
+            Protocol::Udp,
+            &header,
+            &query,
+        );
+        let error = handler
+            .lookup(&name, RecordType::A, Some(&info), LookupOptions::default())
+            .await
+            .expect_err("blocked");
+        assert!(error.is_nx_domain());
+
+        // the continent rule comes first by priority; a zero weight is never chosen
+        let american = IpAddr::from([198, 51, 100, 200]);
+        let records = lookup_from(&handler, RecordType::A, american).await;
+        assert_eq!(records[0].data(), &RData::A(A::new(192, 0, 2, 50)));
+        let records = lookup_from(&handler, RecordType::AAAA, american).await;
+        assert_eq!(
+            records[0].data(),
+            &RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x51))
+        );
+
+        // no rule matches
+        let records = lookup_from(&handler, RecordType::A, IpAddr::from([203, 0, 113, 9])).await;
+        assert_eq!(
+            records[0].data(),
+            &RData::CNAME(CNAME(Name::from_str("default.cdn.example.com.").unwrap()))
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_hit_metrics() {
+        use crate::metrics::geo::{FALLBACK_HITS_TOTAL, NEAREST_HITS_TOTAL, RULE_HITS_TOTAL};
+
+        subscribe();
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        with_local_recorder(&recorder, || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let mut config = config(&[("www", "US", "192.0.2.10")]);
+                config
+                    .rules
+                    .push(rule("www", MatchType::Cidr, "10.0.0.0/8", "192.0.2.40"));
+                let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+                let clients: [[u8; 4]; 4] = [
+                    [198, 51, 100, 1],
+                    [198, 51, 100, 2],
+                    [10, 0, 0, 1],
+                    [203, 0, 113, 9],
+                ];
+                for client in clients {
+                    lookup_from(&handler, RecordType::A, IpAddr::from(client)).await;
+                }
+            });
+        });
+
+        #[allow(clippy::mutable_key_type)]
+        let map = snapshotter.snapshot().into_hashmap();
+        let labels = |extra: &[(&'static str, &str)]| {
+            [("zone", "example.com."), ("name", "www.example.com.")]
+                .iter()
+                .chain(extra)
+                .map(|(key, value)| Label::new(*key, value.to_string()))
+                .collect::<Vec<_>>()
+        };
+        let us = labels(&[("rule", "0"), ("match", "country US")]);
+        assert_counter_eq(&map, RULE_HITS_TOTAL, us, 2);
+        let network = labels(&[("rule", "1"), ("match", "cidr 10.0.0.0/8")]);
+        assert_counter_eq(&map, RULE_HITS_TOTAL, network, 1);
+        let none = labels(&[("fallback", "none")]);
+        assert_counter_eq(&map, FALLBACK_HITS_TOTAL, none, 1);
+        let default = labels(&[("fallback", "default")]);
+        assert_counter_eq(&map, FALLBACK_HITS_TOTAL, default, 0);
+        assert!(map.keys().all(|key| key.key().name() != NEAREST_HITS_TOTAL));
+    }
+
+    #[cfg(feature = "toml")]
+    #[tokio::test]
+    async fn test_rules_file() {
+        subscribe();
+        let dir = Path::new("../../tests/test-data/test_configs");
+        let file = GeoRulesFile::read(&dir.join("default/example.com.geo.toml")).unwrap();
+        assert_eq!(file.rules.len(), 2);
+        assert_eq!(file.nearest.len(), 1);
+
+        // the rules of the file are evaluated after the ones of the configuration
+        let mut config = config(&[("www", "US", "192.0.2.10")]);
+        config.rules.extend(file.rules);
+        config.nearest.extend(file.nearest);
+        let handler = GeoZoneHandler::with_database(inner(), Some(db()), &config).unwrap();
+        let records = lookup_from(&handler, RecordType::A, IpAddr::from([198, 51, 100, 1])).await;
+        assert_eq!(records[0].data(), &RData::A(A::new(192, 0, 2, 10)));
+        let records = lookup_from(&handler, RecordType::A, IpAddr::from([203, 0, 113, 1])).await;
+        assert_eq!(records[0].data(), &RData::A(A::new(192, 0, 2, 50)));
+
+        // only cidr rules can be used without a database
+        let mut without_db = config.clone();
+        without_db.rules.clear();
+        without_db.nearest.clear();
+        without_db.rules_file = Some(PathBuf::from("default/example.com.geo.toml"));
+        let err = GeoZoneHandler::try_from_config(inner(), &without_db, Some(dir))
+            .err()
+            .unwrap();
+        assert!(err.contains("needs a GeoIP database"), "{err}");
+
+        let path = std::env::temp_dir().join(format!("geo-rules-{}.toml", std::process::id()));
+        std::fs::write(&path, "[[rules]]\nname = \"www\"\nmatch = \"country\"\n").unwrap();
+        without_db.rules_file = Some(path.clone());
+        let err = GeoZoneHandler::try_from_config(inner(), &without_db, None)
+            .err()
+            .unwrap();
+        assert!(
+            err.contains("invalid geo rules file") && err.contains("match"),
+            "{err}"
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        without_db.rules_file = Some(PathBuf::from("missing.toml"));
+        let err = GeoZoneHandler::try_from_config(inner(), &without_db, Some(dir))
+            .err()
+            .unwrap();
+        assert!(err.contains("failed to read geo rules file"), "{err}");
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        subscribe();
+        let bad_target = config(&[("www", "US", "a..b")]);
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &bad_target).is_err());
+
+        let mut bad_type = config(&[("www", "US", "192.0.2.10")]);
+        bad_type.rules[0].target = None;
+        bad_type.rules[0].targets = vec![GeoTargetConfig {
+            value: "192.0.2.10".to_string(),
+            record_type: Some(RecordType::AAAA),
+            weight: 1,
+        }];
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &bad_type).is_err());
+
+        let mut no_targets = config(&[("www", "US", "192.0.2.10")]);
+        no_targets.rules[0].target = None;
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &no_targets).is_err());
+
+        let mut nxdomain_target = config(&[("www", "US", "192.0.2.10")]);
+        nxdomain_target.rules[0].action = GeoActionConfig::NxDomain;
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &nxdomain_target).is_err());
+
+        let bad_country = config(&[("www", "USA", "192.0.2.10")]);
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &bad_country).is_err());
+
+        let apex_cname = config(&[("example.com.", "US", "cdn.example.net.")]);
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &apex_cname).is_err());
+
+        let mut bad_coordinates = config(&[]);
+        bad_coordinates.nearest.push(NearestConfig {
+            name: Name::from_str("www").unwrap(),
+            targets: vec![NearestTargetConfig {
+                target: "192.0.2.10".to_string(),
+                latitude: 91.0,
+                longitude: 0.0,
+                healthy: true,
+            }],
+            tie_break: TieBreak::First,
+            tie_distance_km: 0.0,
+            fallback: None,
+        });
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &bad_coordinates).is_err());
+
+        let mut both = config(&[("www", "US", "192.0.2.10")]);
+        both.nearest = bad_coordinates.nearest;
+        both.nearest[0].targets[0].latitude = 0.0;
+        assert!(GeoZoneHandler::with_database(inner(), Some(db()), &both).is_err());
+
+        let no_db = config(&[("www", "US", "192.0.2.10")]);
+        assert!(GeoZoneHandler::with_database(inner(), None, &no_db).is_err());
+
+        let mut missing_db = config(&[]);
+        missing_db.db_path = Some(PathBuf::from("GeoLite2-Country.mmdb"));
+        assert!(
+            GeoZoneHandler::try_from_config(inner(), &missing_db, Some(Path::new("/nonexistent")))
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,303 @@
+// Copyright 2015-2025 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Bounded history of the changes to a zone, used to answer IXFR requests
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Arc,
+};
+
+use tracing::debug;
+
+use crate::proto::rr::{LowerName, RData, Record, RecordSet, RecordType, RrKey};
+
+/// The number of changes kept for incremental zone transfers
+///
+/// Clients with an older version of the zone receive a full zone transfer instead.
+pub const MAX_CHANGES: usize = 100;
+
+/// The differences between consecutive versions of a zone, oldest first
+#[derive(Default)]
+pub(super) struct History {
+    changes: VecDeque<Change>,
+}
+
+/// The difference between two versions of a zone, as sent in an IXFR response (RFC 1995)
+struct Change {
+    /// The SOA record of the old version
+    from: Record,
+    deleted: Vec<Record>,
+    /// The SOA record of the new version
+    to: Record,
+    added: Vec<Record>,
+}
+
+impl History {
+    /// Records the change from `before` to `after`
+    ///
+    /// `before` holds the RRsets of the old version that the change may have touched, `None` for
+    /// those that did not exist, and always the SOA RRset of the zone. Every other RRset is the
+    /// same in both versions, so the cost of a change does not depend on the size of the zone.
+    ///
+    /// The history is restarted if `before` is not the version that the last change led to, or if
+    /// the zone changed without a new serial: the older changes no longer lead to the current
+    /// version of the zone.
+    pub(super) fn record(
+        &mut self,
+        origin: &LowerName,
+        before: &BTreeMap<RrKey, Option<Arc<RecordSet>>>,
+        after: &BTreeMap<RrKey, Arc<RecordSet>>,
+    ) {
+        let soa_key = RrKey::new(origin.clone(), RecordType::SOA);
+        let from = soa(before.get(&soa_key).and_then(Option::as_deref));
+        let to = soa(after.get(&soa_key).map(Arc::as_ref));
+        let (Some(from), Some(to)) = (from, to) else {
+            self.changes.clear();
+            return;
+        };
+
+        if self
+            .changes
+            .back()
+            .is_some_and(|last| serial(&last.to) != serial(from))
+        {
+            debug!(%origin, "zone changed outside of the history, restarting it");
+            self.changes.clear();
+        }
+
+        let mut deleted = Vec::new();
+        let mut added = Vec::new();
+        for (key, old) in before {
+            match (old, after.get(key)) {
+                (Some(old), Some(new)) if Arc::ptr_eq(old, new) => {}
+                (old, new) => {
+                    let old = old.as_deref().map(records).unwrap_or_default();
+                    let new = new.map(|new| records(new)).unwrap_or_default();
+                    deleted.extend(old.iter().filter(|r| !new.contains(r)).cloned());
+                    added.extend(new.iter().filter(|r| !old.contains(r)).cloned());
+                }
+            }
+        }
+
+        if serial(from) == serial(to) {
+            if !deleted.is_empty() || !added.is_empty() {
+                debug!(%origin, "zone changed without a new serial, restarting the history");
+                self.changes.clear();
+            }
+            return;
+        }
+
+        self.changes.push_back(Change {
+            from: from.clone(),
+            deleted,
+            to: to.clone(),
+            added,
+        });
+        if self.changes.len() > MAX_CHANGES {
+            self.changes.pop_front();
+        }
+    }
+
+    /// The changes from the version with `serial_from` to the version with `current`
+    ///
+    /// These are the sequences of the old SOA record and the deleted records, then the new SOA
+    /// record and the added records, of every change in between. Returns `None` if the history
+    /// does not cover that range.
+    pub(super) fn since(&self, serial_from: u32, current: u32) -> Option<Vec<Record>> {
+        if self.changes.back().map(|last| serial(&last.to)) != Some(current) {
+            return None;
+        }
+
+        let start = self
+            .changes
+            .iter()
+            .position(|change| serial(&change.from) == serial_from)?;
+
+        let mut records = Vec::new();
+        for change in self.changes.iter().skip(start) {
+            records.push(change.from.clone());
+            records.extend(change.deleted.iter().cloned());
+            records.push(change.to.clone());
+            records.extend(change.added.iter().cloned());
+        }
+        Some(records)
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.changes.clear();
+    }
+}
+
+fn soa(rrset: Option<&RecordSet>) -> Option<&Record> {
+    rrset?.records_without_rrsigs().next()
+}
+
+fn serial(soa: &Record) -> u32 {
+    match soa.data() {
+        RData::SOA(soa) => soa.serial(),
+        _ => 0,
+    }
+}
+
+/// The records of an RRset, including RRSIGs, except for the SOA record itself
+fn records(rrset: &RecordSet) -> Vec<Record> {
+    #[cfg(feature = "__dnssec")]
+    let records = rrset.records_with_rrsigs();
+    #[cfg(not(feature = "__dnssec"))]
+    let records = rrset.records_without_rrsigs();
+
+    records
+        .filter(|record| record.record_type() != RecordType::SOA)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::Ipv4Addr, str::FromStr};
+
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::proto::rr::{
+        Name,
+        rdata::{A, SOA},
+    };
+
+    type Zone = BTreeMap<RrKey, Arc<RecordSet>>;
+
+    fn origin() -> Name {
+        Name::from_str("example.com.").unwrap()
+    }
+
+    fn soa_key() -> RrKey {
+        RrKey::new(origin().into(), RecordType::SOA)
+    }
+
+    fn www_key() -> RrKey {
+        RrKey::new(
+            Name::from_str("www.example.com.").unwrap().into(),
+            RecordType::A,
+        )
+    }
+
+    fn soa(serial: u32) -> Record {
+        let soa = SOA::new(
+            Name::from_str("ns.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            serial,
+            3600,
+            600,
+            86_400,
+            300,
+        );
+        Record::from_rdata(origin(), 3600, RData::SOA(soa))
+    }
+
+    fn www(last: u8) -> Record {
+        Record::from_rdata(
+            Name::from_str("www.example.com.").unwrap(),
+            300,
+            RData::A(A(Ipv4Addr::new(192, 0, 2, last))),
+        )
+    }
+
+    fn zone(serial: u32) -> Zone {
+        Zone::from([
+            (soa_key(), Arc::new(RecordSet::from(soa(serial)))),
+            (www_key(), Arc::new(RecordSet::from(www(0)))),
+        ])
+    }
+
+    /// Moves www to the address ending in `last` at `serial`, and records the change
+    fn update(history: &mut History, zone: &mut Zone, serial: u32, last: u8) {
+        let before = [soa_key(), www_key()]
+            .into_iter()
+            .map(|key| {
+                let rrset = zone.get(&key).cloned();
+                (key, rrset)
+            })
+            .collect();
+        zone.insert(soa_key(), Arc::new(RecordSet::from(soa(serial))));
+        zone.insert(www_key(), Arc::new(RecordSet::from(www(last))));
+        history.record(&origin().into(), &before, zone);
+    }
+
+    #[test]
+    fn test_since() {
+        subscribe();
+        let mut history = History::default();
+        let mut zone = zone(1);
+        update(&mut history, &mut zone, 2, 2);
+        update(&mut history, &mut zone, 3, 3);
+
+        assert_eq!(
+            history.since(2, 3),
+            Some(vec![soa(2), www(2), soa(3), www(3)])
+        );
+        assert_eq!(history.since(1, 3).map(|records| records.len()), Some(8));
+
+        // serials outside of the history, or a current serial that is not the last one
+        assert_eq!(history.since(0, 3), None);
+        assert_eq!(history.since(3, 3), None);
+        assert_eq!(history.since(1, 2), None);
+    }
+
+    #[test]
+    fn test_restart_on_serial_gap() {
+        subscribe();
+        let mut history = History::default();
+        let mut zone = zone(1);
+        update(&mut history, &mut zone, 2, 2);
+
+        // the zone went from 2 to 5 without the history
+        zone.insert(soa_key(), Arc::new(RecordSet::from(soa(5))));
+        update(&mut history, &mut zone, 6, 6);
+
+        assert_eq!(history.since(1, 6), None);
+        assert_eq!(
+            history.since(5, 6),
+            Some(vec![soa(5), www(2), soa(6), www(6)])
+        );
+    }
+
+    #[test]
+    fn test_restart_on_change_without_serial() {
+        subscribe();
+        let mut history = History::default();
+        let mut zone = zone(1);
+        update(&mut history, &mut zone, 2, 2);
+        assert!(history.since(1, 2).is_some());
+
+        update(&mut history, &mut zone, 2, 7);
+        assert_eq!(history.since(1, 2), None);
+
+        // the same serial without any change keeps the history
+        update(&mut history, &mut zone, 3, 3);
+        update(&mut history, &mut zone, 3, 3);
+        assert!(history.since(2, 3).is_some());
+    }
+
+    #[test]
+    fn test_evict_oldest_change() {
+        subscribe();
+        let mut history = History::default();
+        let mut zone = zone(1);
+        let last = MAX_CHANGES as u32 + 2;
+        for serial in 2..=last {
+            update(&mut history, &mut zone, serial, serial as u8);
+        }
+
+        assert_eq!(history.changes.len(), MAX_CHANGES);
+        assert_eq!(history.since(1, last), None);
+        assert_eq!(
+            history.since(2, last).map(|records| records.len()),
+            Some(4 * MAX_CHANGES)
+        );
+    }
+}
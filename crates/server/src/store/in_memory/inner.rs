@@ -28,7 +28,7 @@ use crate::{
     zone_handler::{LookupError, Nsec3QueryInfo},
 };
 
-use super::maybe_next_name;
+use super::{history::History, maybe_next_name};
 use crate::{
     proto::rr::{
         DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey, rdata::SOA,
@@ -46,6 +46,7 @@ pub(super) struct InnerInMemory {
     //   for this, in some form, perhaps alternate root zones...
     #[cfg(feature = "__dnssec")]
     pub(super) secure_keys: Vec<DnssecSigner>,
+    pub(super) history: History,
 }
 
 impl InnerInMemory {
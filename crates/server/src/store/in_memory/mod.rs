@@ -30,7 +30,9 @@ use crate::{
     net::runtime::{RuntimeProvider, TokioRuntimeProvider},
     proto::{
         op::ResponseCode,
-        rr::{DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey},
+        rr::{
+            DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey, SerialNumber,
+        },
         serialize::txt::Parser,
     },
     server::{Request, RequestInfo},
@@ -47,6 +49,8 @@ use tokio::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tracing::warn;
 use tracing::{debug, info};
 
+mod history;
+pub use history::MAX_CHANGES;
 mod inner;
 use inner::InnerInMemory;
 
@@ -165,7 +169,9 @@ impl<P: RuntimeProvider + Send + Sync> InMemoryZoneHandler<P> {
 
     /// Clears all records (including SOA, etc)
     pub fn clear(&mut self) {
-        self.inner.get_mut().records.clear()
+        let inner = self.inner.get_mut();
+        inner.records.clear();
+        inner.history.clear();
     }
 
     /// Retrieve the Signer, which contains the private keys, for this zone
@@ -209,6 +215,17 @@ impl<P: RuntimeProvider + Send + Sync> InMemoryZoneHandler<P> {
             .increment_soa_serial(self.origin(), self.class)
     }
 
+    /// Keeps the changes from `before` to the current records of the zone for IXFR.
+    ///
+    /// `before` holds copies of the RRsets that the change may have touched, taken before the
+    /// change, with `None` for those that did not exist yet, and must include the SOA RRset of the
+    /// zone. Clients with that version of the zone are then sent only the differences, for up to
+    /// [`MAX_CHANGES`] versions.
+    pub async fn record_changes(&self, before: &BTreeMap<RrKey, Option<Arc<RecordSet>>>) {
+        let inner = &mut *self.inner.write().await;
+        inner.history.record(&self.origin, before, &inner.records);
+    }
+
     /// Inserts or updates a `Record` depending on its existence in the zone.
     ///
     /// Guarantees that SOA, CNAME only has one record, will implicitly update if they already exist.
@@ -230,6 +247,67 @@ impl<P: RuntimeProvider + Send + Sync> InMemoryZoneHandler<P> {
         self.inner.get_mut().upsert(record, serial, self.class)
     }
 
+    /// The response to an IXFR (RFC 1995) from the changes since the client's version of the zone
+    ///
+    /// Returns `None` if the request has no SOA record, or if the changes since its serial were not
+    /// kept, in which case the full zone is sent instead.
+    async fn incremental_transfer(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+    ) -> Option<ZoneTransfer> {
+        let client_serial =
+            request
+                .authorities()
+                .iter()
+                .find_map(|record| match record.data() {
+                    RData::SOA(soa) if LowerName::from(record.name()) == self.origin => {
+                        Some(soa.serial())
+                    }
+                    _ => None,
+                })?;
+
+        let inner = self.inner.read().await;
+        let soa = inner
+            .records
+            .get(&RrKey::new(self.origin.clone(), RecordType::SOA))?
+            .records_without_rrsigs()
+            .next()?
+            .clone();
+        let RData::SOA(current) = soa.data() else {
+            return None;
+        };
+        let serial = current.serial();
+
+        let records = AxfrRecords::new(lookup_options.dnssec_ok, Vec::new());
+        let start_soa = LookupRecords::Section(vec![soa.clone()]);
+        if SerialNumber::new(client_serial) >= SerialNumber::new(serial) {
+            // the client is up to date, the response is only the current SOA record
+            return Some(ZoneTransfer {
+                start_soa,
+                changes: Vec::new(),
+                records,
+                end_soa: LookupRecords::Empty,
+            });
+        }
+
+        let mut changes = inner.history.since(client_serial, serial)?;
+        if !lookup_options.dnssec_ok {
+            changes.retain(|record| record.record_type() != RecordType::RRSIG);
+        }
+
+        debug!(
+            origin = %self.origin,
+            client_serial, serial, "answering IXFR with the changes since the client's version"
+        );
+        Some(ZoneTransfer {
+            start_soa,
+            changes,
+            records,
+            end_soa: LookupRecords::Section(vec![soa]),
+        })
+    }
+
     /// By adding a secure key, this will implicitly enable dnssec for the zone.
     ///
     /// # Arguments
@@ -523,13 +601,21 @@ impl<P: RuntimeProvider + Send + Sync> ZoneHandler for InMemoryZoneHandler<P> {
             Err(e) => return Some((Err(e), None)),
         };
 
-        if request_info.query.query_type() == RecordType::AXFR {
+        let query_type = request_info.query.query_type();
+        if matches!(query_type, RecordType::AXFR | RecordType::IXFR) {
             // TODO: support more advanced AXFR options
             if !matches!(self.axfr_policy, AxfrPolicy::AllowAll) {
                 return Some((Err(LookupError::from(ResponseCode::Refused)), None));
             }
         }
 
+        if query_type == RecordType::IXFR {
+            if let Some(zone_transfer) = self.incremental_transfer(request, lookup_options).await {
+                return Some((Ok(zone_transfer), None));
+            }
+            debug!(origin = %self.origin, "answering IXFR with the full zone");
+        }
+
         let future = self.lookup(self.origin(), RecordType::SOA, None, lookup_options);
         let start_soa = if let LookupControlFlow::Continue(Ok(res)) = future.await {
             res.unwrap_records()
@@ -557,6 +643,7 @@ impl<P: RuntimeProvider + Send + Sync> ZoneHandler for InMemoryZoneHandler<P> {
         Some((
             Ok(ZoneTransfer {
                 start_soa,
+                changes: Vec::new(),
                 records,
                 end_soa,
             }),
@@ -8,9 +8,22 @@
 //! All persistent store implementations
 
 pub mod blocklist;
+mod client_subnet;
 pub mod file;
+mod fnv;
 pub mod forwarder;
+#[cfg(feature = "geo")]
+pub mod geo;
 pub mod in_memory;
+pub mod notify;
 pub mod recursor;
+pub mod secondary;
 #[cfg(feature = "sqlite")]
 pub mod sqlite;
+#[cfg(feature = "__dnssec")]
+mod tsig;
+pub mod weighted;
+
+pub use client_subnet::ClientSubnetPolicy;
+#[cfg(feature = "__dnssec")]
+pub use tsig::TsigKeyConfig;
@@ -0,0 +1,517 @@
+// Copyright 2015-2025 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! NOTIFY (RFC 1996) of the secondaries of a primary zone when its serial changes
+
+use std::{net::SocketAddr, sync::Arc, sync::Mutex, time::Duration};
+
+use serde::Deserialize;
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "__dnssec")]
+use crate::{dnssec::NxProofKind, zone_handler::Nsec3QueryInfo};
+use crate::{
+    net::{
+        NetError,
+        client::{Client, ClientHandle},
+        runtime::{RuntimeProvider, Spawn, Time, TokioRuntimeProvider},
+        udp::UdpClientStream,
+    },
+    proto::{
+        op::ResponseCode,
+        rr::{DNSClass, LowerName, Name, RData, RecordSet, RecordType, TSigResponseContext},
+    },
+    server::{Request, RequestInfo},
+    zone_handler::{
+        AuthLookup, AxfrPolicy, LookupControlFlow, LookupError, LookupOptions, ZoneHandler,
+        ZoneTransfer, ZoneType,
+    },
+};
+
+/// A zone handler that sends NOTIFY to the secondaries of the zone it wraps when its serial
+/// changes
+///
+/// The serial is checked after every successful dynamic update, and when the zone is loaded
+/// with [`Self::notify_changed`]. Each secondary is sent NOTIFY over UDP until it answers, up to
+/// the configured number of attempts (RFC 1996 section 3.6). A secondary answering NOTIMP is
+/// considered notified (section 3.12).
+pub struct NotifyZoneHandler<P = TokioRuntimeProvider> {
+    inner: Arc<dyn ZoneHandler>,
+    targets: Arc<[SocketAddr]>,
+    attempts: u8,
+    retry_interval: Duration,
+    /// Serial of the last NOTIFY
+    serial: Mutex<Option<u32>>,
+    provider: P,
+}
+
+impl<P: RuntimeProvider + Send + Sync> NotifyZoneHandler<P> {
+    /// Wrap a zone handler, to notify the secondaries of `config` of its changes
+    pub fn new(inner: Arc<dyn ZoneHandler>, config: &NotifyConfig, provider: P) -> Self {
+        Self {
+            inner,
+            targets: config
+                .secondaries
+                .iter()
+                .chain(&config.also_notify)
+                .copied()
+                .collect(),
+            attempts: config.attempts.max(1),
+            retry_interval: Duration::from_secs(config.retry_interval),
+            serial: Mutex::new(None),
+            provider,
+        }
+    }
+
+    /// Set the number of NOTIFY sent to each secondary, and how long to wait for an answer
+    /// before sending the next one
+    pub fn with_retry(mut self, attempts: u8, retry_interval: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Send NOTIFY to the secondaries if the serial of the zone changed since the last NOTIFY
+    ///
+    /// The NOTIFY are sent in the background. Returns whether they were sent.
+    pub async fn notify_changed(&self) -> bool {
+        let Some(serial) = self.serial().await else {
+            warn!("no SOA to notify secondaries of in {}", self.origin());
+            return false;
+        };
+
+        {
+            let mut last = self.serial.lock().expect("poisoned");
+            if *last == Some(serial) {
+                return false;
+            }
+            *last = Some(serial);
+        }
+
+        info!(
+            "notifying {} secondaries of {} at serial {serial}",
+            self.targets.len(),
+            self.origin()
+        );
+        let origin = Name::from(self.origin());
+        for target in self.targets.iter().copied() {
+            self.provider.create_handle().spawn_bg(notify::<P>(
+                origin.clone(),
+                target,
+                self.attempts,
+                self.retry_interval,
+                self.provider.clone(),
+            ));
+        }
+        true
+    }
+
+    async fn serial(&self) -> Option<u32> {
+        let lookup = self
+            .inner
+            .lookup(
+                self.origin(),
+                RecordType::SOA,
+                None,
+                LookupOptions::default(),
+            )
+            .await
+            .map_result()?
+            .ok()?;
+        lookup.iter().find_map(|record| match record.data() {
+            RData::SOA(soa) => Some(soa.serial()),
+            _ => None,
+        })
+    }
+}
+
+/// Send NOTIFY to a secondary until it answers
+async fn notify<P: RuntimeProvider>(
+    origin: Name,
+    target: SocketAddr,
+    attempts: u8,
+    retry_interval: Duration,
+    provider: P,
+) {
+    for attempt in 1..=attempts {
+        match notify_once(&origin, target, retry_interval, &provider).await {
+            Ok(ResponseCode::NoError | ResponseCode::NotImp) => {
+                debug!("notified {target} of {origin}");
+                return;
+            }
+            Ok(code) => debug!("NOTIFY {attempt} of {origin} to {target}: {code}"),
+            Err(e) => debug!("NOTIFY {attempt} of {origin} to {target} failed: {e}"),
+        }
+
+        if attempt < attempts {
+            P::Timer::delay_for(retry_interval).await;
+        }
+    }
+
+    warn!("{target} did not answer NOTIFY of {origin} after {attempts} attempts");
+}
+
+async fn notify_once<P: RuntimeProvider>(
+    origin: &Name,
+    target: SocketAddr,
+    timeout: Duration,
+    provider: &P,
+) -> Result<ResponseCode, NetError> {
+    let stream = UdpClientStream::builder(target, provider.clone())
+        .with_timeout(Some(timeout))
+        .build();
+    let (mut client, bg) = Client::<P>::from_sender(stream);
+    provider.create_handle().spawn_bg(bg);
+
+    let response = client
+        .notify(
+            origin.clone(),
+            DNSClass::IN,
+            RecordType::SOA,
+            None::<RecordSet>,
+        )
+        .await?;
+    Ok(response.response_code())
+}
+
+#[async_trait::async_trait]
+impl<P: RuntimeProvider + Send + Sync> ZoneHandler for NotifyZoneHandler<P> {
+    fn zone_type(&self) -> ZoneType {
+        self.inner.zone_type()
+    }
+
+    fn axfr_policy(&self) -> AxfrPolicy {
+        self.inner.axfr_policy()
+    }
+
+    fn can_validate_dnssec(&self) -> bool {
+        self.inner.can_validate_dnssec()
+    }
+
+    /// Updates the wrapped zone handler, and notifies the secondaries if the serial changed
+    async fn update(
+        &self,
+        update: &Request,
+        now: u64,
+    ) -> (Result<bool, ResponseCode>, Option<TSigResponseContext>) {
+        let result = self.inner.update(update, now).await;
+        if let (Ok(true), _) = result {
+            self.notify_changed().await;
+        }
+        result
+    }
+
+    async fn notify(&self, notify: &Request) -> Result<(), ResponseCode> {
+        self.inner.notify(notify).await
+    }
+
+    fn origin(&self) -> &LowerName {
+        self.inner.origin()
+    }
+
+    async fn lookup(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner
+            .lookup(name, rtype, request_info, lookup_options)
+            .await
+    }
+
+    async fn consult(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+        last_result: LookupControlFlow<AuthLookup>,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        self.inner
+            .consult(name, rtype, request_info, lookup_options, last_result)
+            .await
+    }
+
+    async fn search(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        self.inner.search(request, lookup_options).await
+    }
+
+    async fn nsec_records(
+        &self,
+        name: &LowerName,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner.nsec_records(name, lookup_options).await
+    }
+
+    #[cfg(feature = "__dnssec")]
+    async fn nsec3_records(
+        &self,
+        info: Nsec3QueryInfo<'_>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner.nsec3_records(info, lookup_options).await
+    }
+
+    async fn zone_transfer(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+        now: u64,
+    ) -> Option<(
+        Result<ZoneTransfer, LookupError>,
+        Option<TSigResponseContext>,
+    )> {
+        self.inner.zone_transfer(request, lookup_options, now).await
+    }
+
+    #[cfg(feature = "__dnssec")]
+    fn nx_proof_kind(&self) -> Option<&NxProofKind> {
+        self.inner.nx_proof_kind()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn metrics_label(&self) -> &'static str {
+        self.inner.metrics_label()
+    }
+}
+
+/// Servers notified of the changes of a primary zone
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Addresses of the secondaries of the zone, e.g. `"192.0.2.2:53"`
+    #[serde(default)]
+    pub secondaries: Vec<SocketAddr>,
+    /// Addresses of other servers to notify, such as secondaries that are not listed in the NS
+    /// records of the zone
+    #[serde(default)]
+    pub also_notify: Vec<SocketAddr>,
+    /// Number of NOTIFY sent to a secondary that does not answer
+    #[serde(default = "default_attempts")]
+    pub attempts: u8,
+    /// Seconds to wait for the answer of a secondary before sending NOTIFY again
+    #[serde(default = "default_retry_interval")]
+    pub retry_interval: u64,
+}
+
+impl NotifyConfig {
+    /// Whether any server is notified
+    pub fn is_empty(&self) -> bool {
+        self.secondaries.is_empty() && self.also_notify.is_empty()
+    }
+}
+
+impl Default for NotifyConfig {
+    fn default() -> Self {
+        Self {
+            secondaries: Vec::new(),
+            also_notify: Vec::new(),
+            attempts: default_attempts(),
+            retry_interval: default_retry_interval(),
+        }
+    }
+}
+
+fn default_attempts() -> u8 {
+    5
+}
+
+fn default_retry_interval() -> u64 {
+    15
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use test_support::subscribe;
+    use tokio::{
+        net::{TcpListener, UdpSocket},
+        time::timeout,
+    };
+
+    use super::*;
+    use crate::{
+        proto::{
+            op::{Message, MessageType, OpCode},
+            rr::{
+                Record,
+                rdata::{A, SOA},
+            },
+        },
+        server::Server,
+        store::{in_memory::InMemoryZoneHandler, secondary::SecondaryZoneHandler},
+        zone_handler::Catalog,
+    };
+
+    fn origin() -> Name {
+        Name::from_str("example.com.").unwrap()
+    }
+
+    fn soa(serial: u32) -> Record {
+        let soa = SOA::new(
+            Name::from_str("ns.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            serial,
+            3600,
+            600,
+            86_400,
+            300,
+        );
+        Record::from_rdata(origin(), 3600, RData::SOA(soa))
+    }
+
+    fn primary() -> Arc<InMemoryZoneHandler> {
+        let mut zone = InMemoryZoneHandler::empty(
+            origin(),
+            ZoneType::Primary,
+            AxfrPolicy::AllowAll,
+            #[cfg(feature = "__dnssec")]
+            None,
+        );
+        zone.upsert_mut(soa(1), 1);
+        Arc::new(zone)
+    }
+
+    fn handler(
+        zone: Arc<InMemoryZoneHandler>,
+        secondary: SocketAddr,
+    ) -> NotifyZoneHandler<TokioRuntimeProvider> {
+        let config = NotifyConfig {
+            secondaries: vec![secondary],
+            ..NotifyConfig::default()
+        };
+        NotifyZoneHandler::new(zone, &config, TokioRuntimeProvider::new())
+            .with_retry(3, Duration::from_millis(200))
+    }
+
+    /// Receive a NOTIFY, and answer it if `answer` is set
+    async fn receive(socket: &UdpSocket, answer: bool) -> Message {
+        let mut buf = [0; 512];
+        let (len, src) = timeout(Duration::from_secs(5), socket.recv_from(&mut buf))
+            .await
+            .expect("no NOTIFY received")
+            .unwrap();
+        let notify = Message::from_vec(&buf[..len]).unwrap();
+        if answer {
+            let mut response = Message::response(notify.id(), OpCode::Notify);
+            response.set_authoritative(true);
+            response.add_queries(notify.queries().to_vec());
+            socket
+                .send_to(&response.to_vec().unwrap(), src)
+                .await
+                .unwrap();
+        }
+        notify
+    }
+
+    #[tokio::test]
+    async fn test_notify_on_serial_change() {
+        subscribe();
+        let secondary = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let zone = primary();
+        let handler = handler(zone.clone(), secondary.local_addr().unwrap());
+
+        assert!(handler.notify_changed().await);
+        let notify = receive(&secondary, true).await;
+        assert_eq!(notify.message_type(), MessageType::Query);
+        assert_eq!(notify.op_code(), OpCode::Notify);
+        assert_eq!(notify.queries()[0].name(), &origin());
+        assert_eq!(notify.queries()[0].query_type(), RecordType::SOA);
+
+        // the serial did not change
+        assert!(!handler.notify_changed().await);
+
+        zone.upsert(soa(2), 2).await;
+        assert!(handler.notify_changed().await);
+        receive(&secondary, true).await;
+    }
+
+    #[tokio::test]
+    async fn test_notify_retried() {
+        subscribe();
+        let secondary = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let handler = handler(primary(), secondary.local_addr().unwrap());
+
+        assert!(handler.notify_changed().await);
+        let first = receive(&secondary, false).await;
+        let second = receive(&secondary, true).await;
+        assert_eq!(first.queries(), second.queries());
+    }
+
+    #[tokio::test]
+    async fn test_notify_refreshes_secondary() {
+        subscribe();
+        let www = Name::from_str("www.example.com.").unwrap();
+
+        // the primary serves transfers over TCP
+        let zone = primary();
+        let mut catalog = Catalog::new();
+        catalog.upsert(LowerName::new(&origin()), vec![zone.clone()]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let primary_addr = listener.local_addr().unwrap();
+        let mut primary_server = Server::new(catalog);
+        primary_server.register_listener(listener, Duration::from_secs(5));
+
+        // the secondary receives NOTIFY over UDP
+        let secondary = Arc::new(
+            SecondaryZoneHandler::new(
+                origin(),
+                AxfrPolicy::Deny,
+                vec![primary_addr],
+                None,
+                #[cfg(feature = "__dnssec")]
+                None,
+                TokioRuntimeProvider::new(),
+            )
+            .unwrap(),
+        );
+        secondary.spawn_refresh();
+        let mut catalog = Catalog::new();
+        catalog.upsert(LowerName::new(&origin()), vec![secondary.clone()]);
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let secondary_addr = socket.local_addr().unwrap();
+        let mut secondary_server = Server::new(catalog);
+        secondary_server.register_socket(socket);
+
+        let lookup = || async {
+            secondary
+                .lookup(
+                    &LowerName::new(&www),
+                    RecordType::A,
+                    None,
+                    LookupOptions::default(),
+                )
+                .await
+                .map_result()
+                .and_then(Result::ok)
+                .is_some()
+        };
+
+        let handler = handler(zone.clone(), secondary_addr);
+        let a = Record::from_rdata(www.clone(), 300, RData::A(A::new(192, 0, 2, 1)));
+        zone.upsert(a, 2).await;
+        zone.upsert(soa(2), 2).await;
+        assert!(handler.notify_changed().await);
+
+        // long before the refresh interval of the zone
+        timeout(Duration::from_secs(5), async {
+            while !lookup().await {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("the secondary did not refresh after NOTIFY");
+    }
+}
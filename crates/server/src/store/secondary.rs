@@ -0,0 +1,918 @@
+// Copyright 2015-2025 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Secondary zones, transferred from their primaries and kept up to date with the SOA timers
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::pin,
+    sync::{Arc, RwLock, Weak},
+    time::{Duration, SystemTime},
+};
+
+use futures_util::{
+    StreamExt,
+    future::{Either, select},
+};
+use serde::Deserialize;
+use tokio::sync::Notify;
+use tracing::{debug, info, warn};
+
+#[cfg(feature = "__dnssec")]
+use crate::zone_handler::Nsec3QueryInfo;
+#[cfg(feature = "__dnssec")]
+use crate::{dnssec::NxProofKind, proto::rr::TSigner, store::TsigKeyConfig};
+use crate::{
+    net::{
+        client::{Client, ClientHandle},
+        runtime::{RuntimeProvider, Spawn, Time, TokioRuntimeProvider},
+        tcp::TcpClientStream,
+        xfer::DnsMultiplexer,
+    },
+    proto::{
+        op::ResponseCode,
+        rr::{
+            DNSClass, LowerName, Name, RData, Record, RecordSet, RecordType, RrKey, RrsetRecords,
+            TSigResponseContext, rdata::SOA,
+        },
+    },
+    server::{Request, RequestInfo},
+    store::{
+        file::rooted,
+        in_memory::{InMemoryZoneHandler, zone_from_path},
+    },
+    zone_handler::{
+        AuthLookup, AxfrPolicy, LookupControlFlow, LookupError, LookupOptions, ZoneHandler,
+        ZoneTransfer, ZoneType,
+    },
+};
+
+/// A secondary zone, transferred from its primaries
+///
+/// The zone is transferred with AXFR, then refreshed with IXFR when the SOA serial of a primary is
+/// newer than its own, following the refresh, retry and expire timers of its SOA record (RFC 1034
+/// section 4.3.5). Primaries are tried in the order they are configured. Once the zone has not
+/// been refreshed for the expire time of its SOA, or until it has first been transferred, queries
+/// for it are answered with SERVFAIL.
+///
+/// A NOTIFY (RFC 1996) from one of the primaries refreshes the zone immediately; NOTIFY from
+/// other addresses is refused.
+///
+/// When a zone path is configured, the zone is saved there as a zone file after every transfer
+/// and loaded from it at startup. The time of its last successful refresh is kept as the
+/// modification time of the file, so that the expire timer survives restarts.
+pub struct SecondaryZoneHandler<P = TokioRuntimeProvider> {
+    origin: LowerName,
+    axfr_policy: AxfrPolicy,
+    primaries: Vec<SocketAddr>,
+    zone_path: Option<PathBuf>,
+    timeout: Duration,
+    #[cfg(feature = "__dnssec")]
+    signer: Option<TSigner>,
+    #[cfg(feature = "__dnssec")]
+    nx_proof_kind: Option<NxProofKind>,
+    provider: P,
+    state: RwLock<State<P>>,
+    /// Wakes the refresh loop when a primary sends NOTIFY
+    refresh_now: Arc<Notify>,
+}
+
+struct State<P> {
+    zone: Option<Arc<InMemoryZoneHandler<P>>>,
+    /// SOA record of the zone, for its serial and timers
+    soa: Option<SOA>,
+    /// When the zone was last transferred or found to be up to date
+    refreshed: Option<SystemTime>,
+}
+
+impl<P: RuntimeProvider + Send + Sync> SecondaryZoneHandler<P> {
+    /// Create the zone handler from its configuration and start refreshing the zone in the
+    /// background, until the handler is dropped
+    ///
+    /// A relative `zone_path` or TSIG key file is resolved against `root_dir`. If the zone file
+    /// exists, the zone is loaded from it and answered until it expires.
+    pub fn try_from_config(
+        origin: Name,
+        axfr_policy: AxfrPolicy,
+        config: &SecondaryConfig,
+        root_dir: Option<&Path>,
+        #[cfg(feature = "__dnssec")] nx_proof_kind: Option<NxProofKind>,
+        provider: P,
+    ) -> Result<Arc<Self>, String> {
+        if config.primaries.is_empty() {
+            return Err(format!("secondary zone {origin} has no primaries"));
+        }
+
+        let mut handler = Self::new(
+            origin.clone(),
+            axfr_policy,
+            config.primaries.clone(),
+            config
+                .zone_path
+                .as_deref()
+                .map(|path| rooted(path, root_dir)),
+            #[cfg(feature = "__dnssec")]
+            nx_proof_kind,
+            provider,
+        )?;
+        handler.timeout = Duration::from_secs(config.transfer_timeout);
+        #[cfg(feature = "__dnssec")]
+        if let Some(key) = &config.tsig_key {
+            handler.signer = Some(key.to_signer(&origin, root_dir)?);
+        }
+
+        let handler = Arc::new(handler);
+        handler.spawn_refresh();
+        Ok(handler)
+    }
+
+    /// Create the zone handler, loading the zone from `zone_path` if the file exists
+    ///
+    /// The zone is not refreshed until [`Self::refresh`] is called or
+    /// [`Self::spawn_refresh`] starts refreshing it in the background.
+    pub fn new(
+        origin: Name,
+        axfr_policy: AxfrPolicy,
+        primaries: Vec<SocketAddr>,
+        zone_path: Option<PathBuf>,
+        #[cfg(feature = "__dnssec")] nx_proof_kind: Option<NxProofKind>,
+        provider: P,
+    ) -> Result<Self, String> {
+        let mut state = State {
+            zone: None,
+            soa: None,
+            refreshed: None,
+        };
+
+        if let Some(path) = zone_path.as_deref().filter(|path| path.exists()) {
+            let records = zone_from_path(path, origin.clone())
+                .map_err(|e| format!("failed to load secondary zone file: {e}"))?;
+            let soa = soa(records.get(&RrKey::new(LowerName::new(&origin), RecordType::SOA)))
+                .ok_or_else(|| format!("no SOA record in {}", path.display()))?;
+            let zone = InMemoryZoneHandler::new(
+                origin.clone(),
+                records,
+                ZoneType::Secondary,
+                axfr_policy,
+                #[cfg(feature = "__dnssec")]
+                nx_proof_kind.clone(),
+            )?;
+            state.zone = Some(Arc::new(zone));
+            state.soa = Some(soa);
+            state.refreshed = fs::metadata(path).and_then(|m| m.modified()).ok();
+        }
+
+        Ok(Self {
+            origin: LowerName::new(&origin),
+            axfr_policy,
+            primaries,
+            zone_path,
+            timeout: Duration::from_secs(default_transfer_timeout()),
+            #[cfg(feature = "__dnssec")]
+            signer: None,
+            #[cfg(feature = "__dnssec")]
+            nx_proof_kind,
+            provider,
+            state: RwLock::new(state),
+            refresh_now: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Refresh the zone in the background, at the intervals of its SOA, until the handler is
+    /// dropped
+    pub fn spawn_refresh(self: &Arc<Self>) {
+        let handler = Arc::downgrade(self);
+        self.provider
+            .create_handle()
+            .spawn_bg(Self::refresh_loop(handler));
+    }
+
+    async fn refresh_loop(handler: Weak<Self>) {
+        let Some(refresh_now) = handler.upgrade().map(|this| this.refresh_now.clone()) else {
+            return;
+        };
+
+        loop {
+            let Some(this) = handler.upgrade() else {
+                return;
+            };
+            let refreshed = this.refresh().await;
+            let (refresh, retry) = this.intervals();
+            let delay = match refreshed {
+                Ok(_) => refresh,
+                Err(e) => {
+                    warn!("failed to refresh secondary zone {}: {e}", this.origin);
+                    retry
+                }
+            };
+            drop(this);
+
+            debug!("next refresh in {}s", delay.as_secs());
+            let delay = pin!(P::Timer::delay_for(delay));
+            let notified = pin!(refresh_now.notified());
+            if let Either::Right(_) = select(delay, notified).await {
+                debug!("refreshing after NOTIFY");
+            }
+        }
+    }
+
+    /// Check the serial of the primaries, and transfer the zone from the first one that answers
+    /// if it has a newer serial
+    ///
+    /// Returns whether the zone was transferred.
+    pub async fn refresh(&self) -> Result<bool, String> {
+        let mut errors = Vec::new();
+        for primary in &self.primaries {
+            let refresh = pin!(self.refresh_from(*primary));
+            let timeout = pin!(P::Timer::delay_for(self.timeout));
+            match select(refresh, timeout).await {
+                Either::Left((Ok(transferred), _)) => return Ok(transferred),
+                Either::Left((Err(e), _)) => errors.push(format!("{primary}: {e}")),
+                Either::Right(_) => errors.push(format!("{primary}: timed out")),
+            }
+        }
+        Err(errors.join(", "))
+    }
+
+    async fn refresh_from(&self, primary: SocketAddr) -> Result<bool, String> {
+        let (current, last_soa) = {
+            let state = self.state.read().expect("poisoned");
+            (state.zone.clone(), state.soa.clone())
+        };
+        let origin = Name::from(&self.origin);
+        let mut client = self.connect(primary).await?;
+
+        if let Some(last_soa) = &last_soa {
+            let response = client
+                .query(origin.clone(), DNSClass::IN, RecordType::SOA)
+                .await
+                .map_err(|e| format!("SOA query failed: {e}"))?;
+            let Some(RData::SOA(primary_soa)) = response.answers().first().map(Record::data) else {
+                return Err(format!("no SOA in response: {}", response.response_code()));
+            };
+            if !serial_newer(primary_soa.serial(), last_soa.serial()) {
+                debug!(
+                    "secondary zone {origin} is up to date at serial {}",
+                    last_soa.serial()
+                );
+                self.refreshed(None);
+                return Ok(false);
+            }
+        }
+
+        let mut records = transfer(&mut client, &origin, last_soa.clone()).await;
+        if last_soa.is_some() && !records.as_ref().is_ok_and(|records| !records.is_empty()) {
+            // the primary does not serve IXFR for this zone
+            debug!("incremental transfer of {origin} failed, falling back to AXFR");
+            records = transfer(&mut client, &origin, None).await;
+        }
+        let records = records?;
+
+        let current = match &current {
+            Some(zone) => Some(flatten(&*zone.records().await)),
+            None => None,
+        };
+        let Some(records) = transferred(current, records)? else {
+            self.refreshed(None);
+            return Ok(false);
+        };
+
+        let mut zone = InMemoryZoneHandler::empty(
+            origin.clone(),
+            ZoneType::Secondary,
+            self.axfr_policy,
+            #[cfg(feature = "__dnssec")]
+            self.nx_proof_kind.clone(),
+        );
+        let soa = records
+            .get(&RrKey::new(self.origin.clone(), RecordType::SOA))
+            .and_then(|records| soa_data(records.first()?))
+            .ok_or_else(|| format!("no SOA record for {origin} in transfer"))?;
+        let serial = soa.serial();
+        for record in records.into_values().flatten() {
+            zone.upsert_mut(record, serial);
+        }
+        info!("transferred secondary zone {origin} at serial {serial} from {primary}");
+
+        let zone = Arc::new(zone);
+        if let Some(path) = &self.zone_path {
+            if let Err(e) = persist(path, &zone).await {
+                warn!(
+                    "failed to save secondary zone {origin} to {}: {e}",
+                    path.display()
+                );
+            }
+        }
+        self.refreshed(Some((zone, soa)));
+        Ok(true)
+    }
+
+    async fn connect(&self, primary: SocketAddr) -> Result<Client<P>, String> {
+        let (stream, sender) = TcpClientStream::<P::Tcp>::new(
+            primary,
+            None,
+            Some(self.timeout),
+            self.provider.clone(),
+        );
+        let stream = stream
+            .await
+            .map_err(|e| format!("failed to connect: {e}"))?;
+
+        #[cfg_attr(not(feature = "__dnssec"), allow(unused_mut))]
+        let mut multiplexer = DnsMultiplexer::new(stream, sender).with_timeout(self.timeout);
+        #[cfg(feature = "__dnssec")]
+        if let Some(signer) = &self.signer {
+            multiplexer = multiplexer.with_signer(signer.clone());
+        }
+
+        let (client, bg) = Client::<P>::from_sender(multiplexer);
+        self.provider.create_handle().spawn_bg(bg);
+        Ok(client)
+    }
+
+    /// Record a successful refresh, replacing the zone if it was transferred
+    fn refreshed(&self, zone: Option<(Arc<InMemoryZoneHandler<P>>, SOA)>) {
+        let now = SystemTime::now();
+        let mut state = self.state.write().expect("poisoned");
+        if let Some((zone, soa)) = zone {
+            state.zone = Some(zone);
+            state.soa = Some(soa);
+        } else if let Some(path) = &self.zone_path {
+            let touched = File::options()
+                .write(true)
+                .open(path)
+                .and_then(|file| file.set_modified(now));
+            if let Err(e) = touched {
+                debug!("failed to update the time of {}: {e}", path.display());
+            }
+        }
+        state.refreshed = Some(now);
+    }
+
+    /// Refresh and retry intervals of the zone's SOA
+    fn intervals(&self) -> (Duration, Duration) {
+        let state = self.state.read().expect("poisoned");
+        let Some(soa) = &state.soa else {
+            let retry = Duration::from_secs(DEFAULT_RETRY);
+            return (retry, retry);
+        };
+        let secs = |interval: i32| Duration::from_secs(interval.max(MIN_INTERVAL) as u64);
+        (secs(soa.refresh()), secs(soa.retry()))
+    }
+
+    /// The zone, unless it has not been transferred yet or has expired
+    fn zone(&self) -> Option<Arc<InMemoryZoneHandler<P>>> {
+        let state = self.state.read().expect("poisoned");
+        let zone = state.zone.as_ref()?;
+        let expire = Duration::from_secs(state.soa.as_ref()?.expire().max(0) as u64);
+        let refreshed = state.refreshed?;
+        match refreshed.elapsed() {
+            Ok(elapsed) if elapsed >= expire => {
+                debug!("secondary zone {} has expired", self.origin);
+                None
+            }
+            _ => Some(zone.clone()),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl<P: RuntimeProvider + Send + Sync> ZoneHandler for SecondaryZoneHandler<P> {
+    /// Always Secondary
+    fn zone_type(&self) -> ZoneType {
+        ZoneType::Secondary
+    }
+
+    fn axfr_policy(&self) -> AxfrPolicy {
+        self.axfr_policy
+    }
+
+    fn origin(&self) -> &LowerName {
+        &self.origin
+    }
+
+    async fn lookup(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        match self.zone() {
+            Some(zone) => zone.lookup(name, rtype, request_info, lookup_options).await,
+            None => LookupControlFlow::Break(Err(LookupError::from(ResponseCode::ServFail))),
+        }
+    }
+
+    async fn search(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        match self.zone() {
+            Some(zone) => zone.search(request, lookup_options).await,
+            None => (
+                LookupControlFlow::Break(Err(LookupError::from(ResponseCode::ServFail))),
+                None,
+            ),
+        }
+    }
+
+    async fn zone_transfer(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+        now: u64,
+    ) -> Option<(
+        Result<ZoneTransfer, LookupError>,
+        Option<TSigResponseContext>,
+    )> {
+        match self.zone() {
+            Some(zone) => zone.zone_transfer(request, lookup_options, now).await,
+            None => Some((Err(LookupError::from(ResponseCode::ServFail)), None)),
+        }
+    }
+
+    async fn nsec_records(
+        &self,
+        name: &LowerName,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        match self.zone() {
+            Some(zone) => zone.nsec_records(name, lookup_options).await,
+            None => LookupControlFlow::Break(Err(LookupError::from(ResponseCode::ServFail))),
+        }
+    }
+
+    #[cfg(feature = "__dnssec")]
+    async fn nsec3_records(
+        &self,
+        info: Nsec3QueryInfo<'_>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        match self.zone() {
+            Some(zone) => zone.nsec3_records(info, lookup_options).await,
+            None => LookupControlFlow::Break(Err(LookupError::from(ResponseCode::ServFail))),
+        }
+    }
+
+    async fn notify(&self, notify: &Request) -> Result<(), ResponseCode> {
+        let src = notify.src().ip().to_canonical();
+        if !self
+            .primaries
+            .iter()
+            .any(|primary| primary.ip().to_canonical() == src)
+        {
+            warn!("refusing NOTIFY for {} from {src}", self.origin);
+            return Err(ResponseCode::Refused);
+        }
+
+        debug!("NOTIFY for {} from {src}", self.origin);
+        self.refresh_now.notify_one();
+        Ok(())
+    }
+
+    #[cfg(feature = "__dnssec")]
+    fn nx_proof_kind(&self) -> Option<&NxProofKind> {
+        self.nx_proof_kind.as_ref()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn metrics_label(&self) -> &'static str {
+        "secondary"
+    }
+}
+
+/// Configuration for a secondary zone
+#[derive(Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SecondaryConfig {
+    /// Addresses of the primaries the zone is transferred from, tried in order, e.g.
+    /// `"192.0.2.1:53"`
+    pub primaries: Vec<SocketAddr>,
+    /// Path of the zone file the zone is saved to after every transfer and loaded from at
+    /// startup
+    #[serde(default)]
+    pub zone_path: Option<PathBuf>,
+    /// Seconds to wait for a primary to answer a refresh, including the transfer
+    #[serde(default = "default_transfer_timeout")]
+    pub transfer_timeout: u64,
+    /// TSIG key the transfer requests are signed with, and the responses verified with
+    #[cfg(feature = "__dnssec")]
+    #[serde(default)]
+    pub tsig_key: Option<TsigKeyConfig>,
+}
+
+fn default_transfer_timeout() -> u64 {
+    30
+}
+
+/// Seconds between attempts to transfer a zone that has never been transferred
+const DEFAULT_RETRY: u64 = 60;
+/// Lower bound of the refresh and retry intervals, in seconds
+const MIN_INTERVAL: i32 = 5;
+
+/// Whether `serial` is newer than `than`, in serial number arithmetic (RFC 1982)
+fn serial_newer(serial: u32, than: u32) -> bool {
+    serial != than && serial.wrapping_sub(than) < 1 << 31
+}
+
+/// Transfer a zone with AXFR, or with IXFR from the version of `last_soa`
+async fn transfer<P: RuntimeProvider>(
+    client: &mut Client<P>,
+    origin: &Name,
+    last_soa: Option<SOA>,
+) -> Result<Vec<Record>, String> {
+    let mut stream = client.zone_transfer(origin.clone(), last_soa);
+    let mut records = Vec::new();
+    while let Some(response) = stream.next().await {
+        let response = response.map_err(|e| format!("transfer failed: {e}"))?;
+        if response.response_code() != ResponseCode::NoError {
+            return Err(format!("transfer refused: {}", response.response_code()));
+        }
+        if response.answers().is_empty() {
+            // not a transfer, e.g. the answer of a primary that does not serve IXFR
+            break;
+        }
+        records.extend(response.answers().iter().cloned());
+    }
+    Ok(records)
+}
+
+/// The records of a zone after a transfer, or None if the zone is already up to date
+///
+/// `current` is the zone before the transfer, if there is one. `answers` are the records of the
+/// transfer, which starts and ends with the SOA of the new version: either all the records of the
+/// zone (AXFR), or the differences from the current version (IXFR, RFC 1995), as sequences of the
+/// old SOA and the deleted records, then the new SOA and the added records.
+fn transferred(
+    current: Option<BTreeMap<RrKey, Vec<Record>>>,
+    mut answers: Vec<Record>,
+) -> Result<Option<BTreeMap<RrKey, Vec<Record>>>, String> {
+    let is_soa = |record: &Record| record.record_type() == RecordType::SOA;
+    if !answers.first().is_some_and(is_soa) {
+        return Err("transfer does not start with a SOA record".to_string());
+    }
+    if answers.len() == 1 {
+        // the response to an IXFR when the zone is up to date
+        return Ok(None);
+    }
+    if !answers.last().is_some_and(is_soa) {
+        return Err("transfer does not end with a SOA record".to_string());
+    }
+
+    answers.pop();
+    let incremental = match current {
+        Some(current) if answers.get(1).is_some_and(is_soa) => current,
+        _ => {
+            let mut zone = BTreeMap::<_, Vec<_>>::new();
+            for record in answers {
+                zone.entry(key(&record)).or_default().push(record);
+            }
+            return Ok(Some(zone));
+        }
+    };
+
+    let mut zone = incremental;
+    let mut adding = true;
+    for record in answers.into_iter().skip(1) {
+        if is_soa(&record) {
+            adding = !adding;
+            if adding {
+                zone.insert(key(&record), vec![record]);
+            }
+            continue;
+        }
+
+        let key = key(&record);
+        match adding {
+            true => {
+                let records = zone.entry(key).or_default();
+                if !records.contains(&record) {
+                    records.push(record);
+                }
+            }
+            false => {
+                if let Some(records) = zone.get_mut(&key) {
+                    records.retain(|r| r != &record);
+                    if records.is_empty() {
+                        zone.remove(&key);
+                    }
+                }
+            }
+        }
+    }
+    if !adding {
+        return Err("incremental transfer ends with deletions".to_string());
+    }
+    Ok(Some(zone))
+}
+
+fn key(record: &Record) -> RrKey {
+    RrKey::new(LowerName::new(record.name()), record.record_type())
+}
+
+/// Every record of a zone, RRSIGs included, by name and type
+fn flatten(records: &BTreeMap<RrKey, Arc<RecordSet>>) -> BTreeMap<RrKey, Vec<Record>> {
+    let mut zone = BTreeMap::<_, Vec<_>>::new();
+    for record in records.values().flat_map(|rrset| all_records(rrset)) {
+        zone.entry(key(record)).or_default().push(record.clone());
+    }
+    zone
+}
+
+/// The records of a set, with their RRSIGs if DNSSEC is enabled
+fn all_records(rrset: &RecordSet) -> RrsetRecords<'_> {
+    #[cfg(feature = "__dnssec")]
+    return rrset.records_with_rrsigs();
+    #[cfg(not(feature = "__dnssec"))]
+    rrset.records_without_rrsigs()
+}
+
+fn soa(rrset: Option<&RecordSet>) -> Option<SOA> {
+    soa_data(rrset?.records_without_rrsigs().next()?)
+}
+
+fn soa_data(record: &Record) -> Option<SOA> {
+    match record.data() {
+        RData::SOA(soa) => Some(soa.clone()),
+        _ => None,
+    }
+}
+
+/// Save a zone as a zone file, replacing the file only once it is completely written
+async fn persist<P: RuntimeProvider + Send + Sync>(
+    path: &Path,
+    zone: &InMemoryZoneHandler<P>,
+) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+
+    let records = zone.records().await;
+    let mut file = BufWriter::new(File::create(&tmp)?);
+    let soa = RrKey::new(zone.origin().clone(), RecordType::SOA);
+    // the SOA record comes first in a zone file
+    let ordered = records.get(&soa).into_iter().chain(
+        records
+            .iter()
+            .filter(|(key, _)| **key != soa)
+            .map(|(_, rrset)| rrset),
+    );
+    for rrset in ordered {
+        for record in all_records(rrset) {
+            writeln!(file, "{record}")?;
+        }
+    }
+    file.into_inner()?.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env::temp_dir, process, str::FromStr};
+
+    use test_support::subscribe;
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::{
+        net::xfer::Protocol,
+        proto::{
+            op::{Message, OpCode, Query},
+            rr::rdata::A,
+        },
+        server::Server,
+        zone_handler::Catalog,
+    };
+
+    fn origin() -> Name {
+        Name::from_str("example.com.").unwrap()
+    }
+
+    fn www() -> Name {
+        Name::from_str("www.example.com.").unwrap()
+    }
+
+    fn soa(serial: u32) -> Record {
+        let soa = SOA::new(
+            Name::from_str("ns.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            serial,
+            3600,
+            600,
+            86_400,
+            300,
+        );
+        Record::from_rdata(origin(), 3600, RData::SOA(soa))
+    }
+
+    fn a(name: Name, last: u8) -> Record {
+        Record::from_rdata(name, 300, RData::A(A::new(192, 0, 2, last)))
+    }
+
+    /// Serve a primary zone over TCP
+    async fn primary() -> (Arc<InMemoryZoneHandler>, SocketAddr, Server<Catalog>) {
+        let mut zone = InMemoryZoneHandler::empty(
+            origin(),
+            ZoneType::Primary,
+            AxfrPolicy::AllowAll,
+            #[cfg(feature = "__dnssec")]
+            None,
+        );
+        zone.upsert_mut(soa(1), 1);
+        zone.upsert_mut(a(www(), 1), 1);
+        let zone = Arc::new(zone);
+
+        let mut catalog = Catalog::new();
+        catalog.upsert(LowerName::new(&origin()), vec![zone.clone()]);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut server = Server::new(catalog);
+        server.register_listener(listener, Duration::from_secs(5));
+        (zone, addr, server)
+    }
+
+    fn secondary(primary: SocketAddr, zone_path: Option<PathBuf>) -> SecondaryZoneHandler {
+        SecondaryZoneHandler::new(
+            origin(),
+            AxfrPolicy::Deny,
+            vec![primary],
+            zone_path,
+            #[cfg(feature = "__dnssec")]
+            None,
+            TokioRuntimeProvider::new(),
+        )
+        .unwrap()
+    }
+
+    fn zone_path(test: &str) -> PathBuf {
+        let path = temp_dir().join(format!("secondary-{test}-{}.zone", process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    async fn lookup(handler: &SecondaryZoneHandler, name: Name) -> Result<Vec<RData>, ()> {
+        let result = handler
+            .lookup(
+                &LowerName::new(&name),
+                RecordType::A,
+                None,
+                LookupOptions::default(),
+            )
+            .await;
+        match result {
+            LookupControlFlow::Continue(Ok(lookup)) => {
+                Ok(lookup.iter().map(|r| r.data().clone()).collect())
+            }
+            _ => Err(()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transfer_and_refresh() {
+        subscribe();
+        let (primary, addr, _server) = primary().await;
+        let handler = secondary(addr, None);
+
+        // nothing is answered before the first transfer
+        assert!(lookup(&handler, www()).await.is_err());
+
+        assert!(handler.refresh().await.unwrap());
+        assert_eq!(
+            lookup(&handler, www()).await.unwrap(),
+            vec![RData::A(A::new(192, 0, 2, 1))]
+        );
+
+        // the serial of the primary has not changed
+        assert!(!handler.refresh().await.unwrap());
+
+        let mail = Name::from_str("mail.example.com.").unwrap();
+        primary.upsert(a(mail.clone(), 25), 2).await;
+        primary.upsert(soa(2), 2).await;
+        assert!(handler.refresh().await.unwrap());
+        assert_eq!(
+            lookup(&handler, mail).await.unwrap(),
+            vec![RData::A(A::new(192, 0, 2, 25))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_primary() {
+        subscribe();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let handler = secondary(addr, None);
+        assert!(handler.refresh().await.is_err());
+        assert!(lookup(&handler, www()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_zone_persisted() {
+        subscribe();
+        let (_primary, addr, server) = primary().await;
+        let path = zone_path("persisted");
+        let handler = secondary(addr, Some(path.clone()));
+        assert!(handler.refresh().await.unwrap());
+        drop(server);
+
+        // a restarted secondary answers from the saved zone, without its primary
+        let handler = secondary(addr, Some(path.clone()));
+        assert_eq!(
+            lookup(&handler, www()).await.unwrap(),
+            vec![RData::A(A::new(192, 0, 2, 1))]
+        );
+
+        // until the zone expires
+        let expired = SystemTime::now() - Duration::from_secs(86_400 + 60);
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(expired)
+            .unwrap();
+        let handler = secondary(addr, Some(path.clone()));
+        assert!(lookup(&handler, www()).await.is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_notify_from_primaries_only() {
+        subscribe();
+        let primary = SocketAddr::from(([192, 0, 2, 1], 53));
+        let handler = secondary(primary, None);
+
+        let mut message = Message::query();
+        message
+            .set_op_code(OpCode::Notify)
+            .add_query(Query::query(origin(), RecordType::SOA));
+        let notify = |src: SocketAddr| {
+            Request::from_bytes(message.to_vec().unwrap(), src, Protocol::Udp).unwrap()
+        };
+
+        // from any port of a primary
+        let from_primary = notify(SocketAddr::from(([192, 0, 2, 1], 33333)));
+        assert_eq!(handler.notify(&from_primary).await, Ok(()));
+        let from_other = notify(SocketAddr::from(([192, 0, 2, 2], 53)));
+        assert_eq!(
+            handler.notify(&from_other).await,
+            Err(ResponseCode::Refused)
+        );
+    }
+
+    #[test]
+    fn test_incremental_transfer() {
+        let current = transferred(None, vec![soa(1), a(www(), 1), a(www(), 2), soa(1)])
+            .unwrap()
+            .unwrap();
+
+        let mail = Name::from_str("mail.example.com.").unwrap();
+        let ixfr = vec![
+            soa(3),
+            // 1 -> 2: delete www 192.0.2.1, add mail
+            soa(1),
+            a(www(), 1),
+            soa(2),
+            a(mail.clone(), 25),
+            // 2 -> 3: delete www 192.0.2.2
+            soa(2),
+            a(www(), 2),
+            soa(3),
+            soa(3),
+        ];
+        let zone = transferred(Some(current), ixfr).unwrap().unwrap();
+
+        assert_eq!(zone.len(), 2);
+        assert_eq!(zone[&key(&soa(3))], vec![soa(3)]);
+        assert_eq!(zone[&key(&a(mail.clone(), 25))], vec![a(mail, 25)]);
+        assert!(!zone.contains_key(&key(&a(www(), 1))));
+    }
+
+    #[test]
+    fn test_up_to_date_transfer() {
+        assert!(transferred(None, vec![soa(1)]).unwrap().is_none());
+        assert!(transferred(None, vec![a(www(), 1)]).is_err());
+        assert!(transferred(None, vec![soa(1), a(www(), 1)]).is_err());
+    }
+
+    #[test]
+    fn test_serial_newer() {
+        assert!(serial_newer(2, 1));
+        assert!(!serial_newer(1, 1));
+        assert!(!serial_newer(1, 2));
+        // serials wrap around
+        assert!(serial_newer(1, u32::MAX));
+        assert!(!serial_newer(u32::MAX, 1));
+    }
+}
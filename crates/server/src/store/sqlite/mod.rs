@@ -7,14 +7,12 @@
 
 //! SQLite serving with Dynamic DNS and journaling support
 
-#[cfg(feature = "__dnssec")]
-use std::fs;
 use std::marker::PhantomData;
-#[cfg(feature = "__dnssec")]
-use std::str::FromStr;
 use std::{
+    collections::BTreeMap,
     ops::{Deref, DerefMut},
     path::{Path, PathBuf},
+    slice,
     sync::Arc,
 };
 
@@ -27,7 +25,7 @@ use crate::metrics::PersistentStoreMetrics;
 #[cfg(feature = "__dnssec")]
 use crate::proto::rr::{
     TSigner,
-    rdata::tsig::{TSIG, TsigAlgorithm, TsigError},
+    rdata::tsig::{TSIG, TsigError},
 };
 #[cfg(feature = "__dnssec")]
 use crate::{
@@ -47,7 +45,7 @@ use crate::{
     server::{Request, RequestInfo},
     store::{
         file::rooted,
-        in_memory::{InMemoryZoneHandler, zone_from_path},
+        in_memory::{InMemoryZoneHandler, MAX_CHANGES, zone_from_path},
     },
     zone_handler::{
         AuthLookup, AxfrPolicy, LookupControlFlow, LookupError, LookupOptions, ZoneHandler,
@@ -58,6 +56,9 @@ use crate::{
 pub mod persistence;
 pub use persistence::{Journal, PersistenceError};
 
+#[cfg(feature = "__dnssec")]
+pub use crate::store::TsigKeyConfig;
+
 /// SqliteZoneHandler is responsible for storing the resource records for a particular zone.
 ///
 /// Zone handlers default to DNSClass IN. The ZoneType specifies if this should be treated as the
@@ -213,13 +214,32 @@ impl<P: RuntimeProvider + Send + Sync> SqliteZoneHandler<P> {
         );
 
         info!("recovering from journal");
+
+        // Every update in the journal ends with the new SOA record of the zone. Copies of the
+        //  RRsets touched after the last of these records rebuild the changes between them for
+        //  IXFR. The first update after a dump of the zone has nothing to start from, older
+        //  clients get the zone.
+        let origin = self.origin().clone();
+        let soa_key = RrKey::new(origin.clone(), RecordType::SOA);
+        let is_soa = |record: &Record| {
+            record.record_type() == RecordType::SOA && LowerName::from(record.name()) == origin
+        };
+        let mut remaining = journal.iter().filter(is_soa).count();
+        let mut before = None;
+
         for record in journal.iter() {
             // AXFR is special, it is used to mark the dump of a full zone.
             //  when recovering, if an AXFR is encountered, we should remove all the records in the
             //  zone.
             if record.record_type() == RecordType::AXFR {
                 self.in_memory.clear();
+                before = None;
             } else {
+                let soa = is_soa(&record);
+                let new_version = soa && self.in_memory.records().await.contains_key(&soa_key);
+                if let Some(before) = &mut before {
+                    self.copy_rrsets(slice::from_ref(&record), before).await;
+                }
                 match self.update_records(&[record], false).await {
                     Ok(_) => {
                         #[cfg(feature = "metrics")]
@@ -227,12 +247,53 @@ impl<P: RuntimeProvider + Send + Sync> SqliteZoneHandler<P> {
                     }
                     Err(error) => return Err(PersistenceError::Recovery(error.to_str())),
                 }
+
+                if soa {
+                    remaining = remaining.saturating_sub(1);
+                }
+                if new_version && remaining <= MAX_CHANGES {
+                    if let Some(before) = before.take() {
+                        self.in_memory.record_changes(&before).await;
+                    }
+                    before = Some(BTreeMap::new());
+                }
             }
         }
 
         Ok(())
     }
 
+    /// Copies the RRsets that `records` may change, and the SOA RRset, into `before`
+    ///
+    /// RRsets already in `before` are kept as they are, missing ones are recorded as `None`.
+    async fn copy_rrsets(
+        &self,
+        records: &[Record],
+        before: &mut BTreeMap<RrKey, Option<Arc<RecordSet>>>,
+    ) {
+        let zone = self.in_memory.records().await;
+        let mut copy = |key: &RrKey| {
+            if !before.contains_key(key) {
+                before.insert(key.clone(), zone.get(key).cloned());
+            }
+        };
+
+        copy(&RrKey::new(self.origin().clone(), RecordType::SOA));
+        for rr in records {
+            let name = LowerName::from(rr.name());
+            if rr.dns_class() == DNSClass::ANY && rr.record_type() == RecordType::ANY {
+                // deletes all RRsets at the name
+                let start = RrKey::new(name.clone(), RecordType::Unknown(u16::MIN));
+                zone.range(start..)
+                    .map(|(key, _)| key)
+                    .take_while(|key| key.name == name)
+                    .for_each(&mut copy);
+            } else {
+                copy(&RrKey::new(name, rr.record_type()));
+            }
+        }
+    }
+
     /// Persist the state of the current zone to the journal, does nothing if there is no associated
     ///  Journal.
     ///
@@ -711,6 +772,24 @@ impl<P: RuntimeProvider + Send + Sync> SqliteZoneHandler<P> {
     ) -> Result<bool, ResponseCode> {
         let mut updated = false;
         let serial: u32 = self.in_memory.serial().await;
+        // the RRsets before the update, to keep its changes for IXFR. Signing the zone touches
+        //  all of it, otherwise only the RRsets named by the update can change.
+        let before = match auto_signing_and_increment {
+            true if self.is_dnssec_enabled => Some(
+                self.in_memory
+                    .records()
+                    .await
+                    .iter()
+                    .map(|(key, rrset)| (key.clone(), Some(rrset.clone())))
+                    .collect(),
+            ),
+            true => {
+                let mut before = BTreeMap::new();
+                self.copy_rrsets(records, &mut before).await;
+                Some(before)
+            }
+            false => None,
+        };
 
         // the persistence act as a write-ahead log. The WAL will also be used for recovery of a zone
         //  subsequent to a failure of the server.
@@ -907,6 +986,16 @@ impl<P: RuntimeProvider + Send + Sync> SqliteZoneHandler<P> {
             self.in_memory.increment_soa_serial().await
         };
 
+        if let Some(mut before) = before {
+            if self.is_dnssec_enabled {
+                // signing adds RRsets anywhere in the zone, e.g. NSEC records
+                for key in self.in_memory.records().await.keys() {
+                    before.entry(key.clone()).or_insert(None);
+                }
+            }
+            self.in_memory.record_changes(&before).await;
+        }
+
         // Persist the post-update SOA record (including the incremented serial) so journal
         // replay reconstructs the monotonic SOA serial across restarts.
         //
@@ -1213,51 +1302,6 @@ pub struct SqliteConfig {
     pub tsig_keys: Vec<TsigKeyConfig>,
 }
 
-/// Configuration for a TSIG authentication signer key
-#[derive(Deserialize, PartialEq, Eq, Debug)]
-#[serde(deny_unknown_fields)]
-#[cfg(feature = "__dnssec")]
-pub struct TsigKeyConfig {
-    /// The key name
-    pub name: String,
-    /// A path to the unencoded symmetric HMAC key data
-    pub key_file: PathBuf,
-    /// The key algorithm
-    pub algorithm: TsigAlgorithm,
-    /// Allowed +/- difference (in seconds) between the time a TSIG request was signed
-    /// and when it is verified.
-    ///
-    /// A fudge value that is too large may leave the server open to replay attacks.
-    /// A fudge value that is too small may cause failures from latency and clock
-    /// desynchronization.
-    ///
-    /// RFC 8945 recommends a fudge value of 300 seconds (the default if not specified).
-    #[serde(default = "default_fudge")]
-    pub fudge: u16,
-}
-
-#[cfg(feature = "__dnssec")]
-impl TsigKeyConfig {
-    fn to_signer(&self, zone_name: &Name, root_dir: Option<&Path>) -> Result<TSigner, String> {
-        let key_file = rooted(&self.key_file, root_dir);
-        let key_data = fs::read(&key_file)
-            .map_err(|e| format!("error reading TSIG key file: {}: {e}", key_file.display()))?;
-        let signer_name = Name::from_str(&self.name).unwrap_or_else(|_| zone_name.clone());
-
-        TSigner::new(key_data, self.algorithm.clone(), signer_name, self.fudge)
-            .map_err(|e| format!("invalid TSIG key configuration: {e}"))
-    }
-}
-
-/// Default TSIG fudge value (seconds).
-///
-/// Per RFC 8945 §10:
-///   "The RECOMMENDED value in most situations is 300 seconds."
-#[cfg(feature = "__dnssec")]
-pub(crate) fn default_fudge() -> u16 {
-    300
-}
-
 #[cfg(test)]
 #[allow(clippy::extra_unused_type_parameters)]
 mod tests {
@@ -0,0 +1,757 @@
+// Copyright 2015-2025 Benjamin Fry <benjaminfry@me.com>
+//
+// Licensed under the Apache License, Version 2.0, <LICENSE-APACHE or
+// https://apache.org/licenses/LICENSE-2.0> or the MIT license <LICENSE-MIT or
+// https://opensource.org/licenses/MIT>, at your option. This file may not be
+// copied, modified, or distributed except according to those terms.
+
+//! Weighted answer selection for traffic splitting
+
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
+
+use ipnet::IpNet;
+use serde::Deserialize;
+use tracing::{debug, trace};
+
+#[cfg(feature = "__dnssec")]
+use crate::{dnssec::NxProofKind, zone_handler::Nsec3QueryInfo};
+use crate::{
+    proto::{
+        op::ResponseCode,
+        rr::{LowerName, Name, RData, Record, RecordSet, RecordType, TSigResponseContext},
+        serialize::txt::RDataParser,
+    },
+    server::{Request, RequestInfo},
+    store::{ClientSubnetPolicy, client_subnet::full_prefix, fnv::Fnv1a},
+    zone_handler::{
+        AuthLookup, AxfrPolicy, LookupControlFlow, LookupError, LookupOptions, LookupRecords,
+        ZoneHandler, ZoneTransfer, ZoneType,
+    },
+};
+
+/// A zone handler wrapper which answers with a weighted subset of each configured RRset.
+///
+/// Lookups are passed to the wrapped zone handler. Any RRset in the result which has weights
+/// configured is then cut down to at most `answers` records, chosen per query with a probability
+/// proportional to their weight. Records with a weight of zero are never returned, unless every
+/// record in the set has a weight of zero. Zone transfers and updates are passed through
+/// unchanged.
+///
+/// Signed RRsets are never modified, since a subset would no longer match its RRSIG.
+pub struct WeightedZoneHandler {
+    inner: Arc<dyn ZoneHandler>,
+    selection: WeightedSelection,
+    client_subnet: ClientSubnetPolicy,
+    answers: usize,
+    ipv4_prefix_len: u8,
+    ipv6_prefix_len: u8,
+    weights: HashMap<(LowerName, RecordType), Vec<(RData, u32)>>,
+}
+
+impl WeightedZoneHandler {
+    /// Wrap `inner` with the weights from the given configuration
+    ///
+    /// Relative names in the configuration are relative to the origin of `inner`, and values
+    /// are parsed as rdata of the configured record type.
+    pub fn try_from_config(
+        inner: Arc<dyn ZoneHandler>,
+        config: &WeightedConfig,
+    ) -> Result<Self, String> {
+        if config.answers == 0 {
+            return Err("weighted answers must be at least 1".to_string());
+        }
+        if config.ipv4_prefix_len > 32 || config.ipv6_prefix_len > 128 {
+            return Err("invalid client subnet prefix length".to_string());
+        }
+
+        let origin = Name::from(inner.origin());
+        let mut weights = HashMap::<_, Vec<_>>::new();
+        for record in &config.records {
+            let name =
+                match record.name.is_fqdn() {
+                    true => record.name.clone(),
+                    false => record.name.clone().append_domain(&origin).map_err(|e| {
+                        format!("invalid weighted record name {}: {e}", record.name)
+                    })?,
+                };
+            let rdata = RData::try_from_str(record.record_type, &record.value).map_err(|e| {
+                format!(
+                    "invalid {} value for weighted record {name}: {e}",
+                    record.record_type
+                )
+            })?;
+
+            weights
+                .entry((LowerName::new(&name), record.record_type))
+                .or_default()
+                .push((rdata, record.weight));
+        }
+
+        Ok(Self {
+            inner,
+            selection: config.selection,
+            client_subnet: config.client_subnet.clone(),
+            answers: config.answers,
+            ipv4_prefix_len: config.ipv4_prefix_len,
+            ipv6_prefix_len: config.ipv6_prefix_len,
+            weights,
+        })
+    }
+
+    /// Selects from the weighted RRsets of `lookup` for `client`, an address and the number of
+    /// significant bits in it
+    ///
+    /// `scope` is set to the prefix length of the client subnet the selection holds for.
+    fn select_lookup(
+        &self,
+        lookup: LookupControlFlow<AuthLookup>,
+        client: Option<(IpAddr, u8)>,
+        scope: &mut u8,
+    ) -> LookupControlFlow<AuthLookup> {
+        lookup.map(|lookup| match lookup {
+            AuthLookup::Records {
+                answers,
+                additionals,
+                client_subnet,
+            } => AuthLookup::Records {
+                answers: self.select_records(answers, client, scope),
+                additionals,
+                client_subnet,
+            },
+            other => other,
+        })
+    }
+
+    fn select_records(
+        &self,
+        records: LookupRecords,
+        client: Option<(IpAddr, u8)>,
+        scope: &mut u8,
+    ) -> LookupRecords {
+        match records {
+            LookupRecords::Records {
+                lookup_options,
+                records,
+            } => LookupRecords::Records {
+                lookup_options,
+                records: self.select_rrset(records, client, scope),
+            },
+            LookupRecords::ManyRecords(lookup_options, rrsets) => LookupRecords::ManyRecords(
+                lookup_options,
+                rrsets
+                    .into_iter()
+                    .map(|rrset| self.select_rrset(rrset, client, scope))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    fn select_rrset(
+        &self,
+        rrset: Arc<RecordSet>,
+        client: Option<(IpAddr, u8)>,
+        scope: &mut u8,
+    ) -> Arc<RecordSet> {
+        let name = LowerName::new(rrset.name());
+        let Some(weights) = self.weights.get(&(name, rrset.record_type())) else {
+            return rrset;
+        };
+        if !rrset.rrsigs().is_empty() {
+            trace!("not selecting from signed rrset {}", rrset.name());
+            return rrset;
+        }
+
+        let candidates = rrset
+            .records_without_rrsigs()
+            .map(|record| {
+                let weight = weights
+                    .iter()
+                    .find(|(rdata, _)| rdata == record.data())
+                    .map_or(DEFAULT_WEIGHT, |(_, weight)| *weight);
+                (record, weight)
+            })
+            .collect::<Vec<_>>();
+
+        let mut rng = match (self.selection, client) {
+            (WeightedSelection::ClientSubnet, Some((ip, source_prefix))) => {
+                let (seed, prefix_len) =
+                    self.client_subnet_seed(ip, source_prefix, rrset.name(), rrset.record_type());
+                *scope = prefix_len;
+                SelectionRng::Seeded(seed)
+            }
+            _ => SelectionRng::Random,
+        };
+
+        let Some(selected) = select(&candidates, self.answers, &mut rng) else {
+            return rrset;
+        };
+
+        debug!(
+            "selected {} of {} records for {} {}",
+            selected.len(),
+            candidates.len(),
+            rrset.name(),
+            rrset.record_type()
+        );
+        let mut subset = RecordSet::new(rrset.name().clone(), rrset.record_type(), rrset.serial());
+        for record in selected {
+            subset.insert(record.clone(), rrset.serial());
+        }
+        Arc::new(subset)
+    }
+
+    /// A stable seed for the client's subnet and the queried RRset, and the prefix length of that
+    /// subnet
+    ///
+    /// The subnet is no longer than the `source_prefix` significant bits of `ip`.
+    fn client_subnet_seed(
+        &self,
+        ip: IpAddr,
+        source_prefix: u8,
+        name: &Name,
+        record_type: RecordType,
+    ) -> (u64, u8) {
+        let prefix_len = match ip {
+            IpAddr::V4(_) => self.ipv4_prefix_len,
+            IpAddr::V6(_) => self.ipv6_prefix_len,
+        }
+        .min(source_prefix);
+        // prefix lengths are validated on construction
+        let subnet = IpNet::new(ip, prefix_len).map_or(ip, |net| net.network());
+
+        let mut hash = Fnv1a::default();
+        hash.write_ip(subnet);
+        hash.write(&[prefix_len]);
+        hash.write(name.to_lowercase().to_ascii().as_bytes());
+        hash.write(&u16::from(record_type).to_be_bytes());
+        (hash.finish(), prefix_len)
+    }
+}
+
+#[async_trait::async_trait]
+impl ZoneHandler for WeightedZoneHandler {
+    fn zone_type(&self) -> ZoneType {
+        self.inner.zone_type()
+    }
+
+    fn axfr_policy(&self) -> AxfrPolicy {
+        self.inner.axfr_policy()
+    }
+
+    fn can_validate_dnssec(&self) -> bool {
+        self.inner.can_validate_dnssec()
+    }
+
+    async fn update(
+        &self,
+        update: &Request,
+        now: u64,
+    ) -> (Result<bool, ResponseCode>, Option<TSigResponseContext>) {
+        self.inner.update(update, now).await
+    }
+
+    async fn notify(&self, notify: &Request) -> Result<(), ResponseCode> {
+        self.inner.notify(notify).await
+    }
+
+    fn origin(&self) -> &LowerName {
+        self.inner.origin()
+    }
+
+    /// Looks up the records in the wrapped zone handler and selects from the weighted RRsets.
+    ///
+    /// Without `request_info` there is no client to select for, and the selection is random.
+    async fn lookup(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        let lookup = self
+            .inner
+            .lookup(name, rtype, request_info, lookup_options)
+            .await;
+        let client = request_info.map(|info| (info.src.ip(), full_prefix(info.src.ip())));
+        self.select_lookup(lookup, client, &mut 0)
+    }
+
+    async fn consult(
+        &self,
+        name: &LowerName,
+        rtype: RecordType,
+        request_info: Option<&RequestInfo<'_>>,
+        lookup_options: LookupOptions,
+        last_result: LookupControlFlow<AuthLookup>,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        self.inner
+            .consult(name, rtype, request_info, lookup_options, last_result)
+            .await
+    }
+
+    async fn search(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+    ) -> (LookupControlFlow<AuthLookup>, Option<TSigResponseContext>) {
+        let (lookup, context) = self.inner.search(request, lookup_options).await;
+        let subnet = match self.selection {
+            WeightedSelection::ClientSubnet => self.client_subnet.client_subnet(request),
+            WeightedSelection::Random => None,
+        };
+        let src = request.src().ip();
+        let client = match subnet {
+            Some(subnet) => (subnet.addr(), subnet.source_prefix()),
+            None => (src, full_prefix(src)),
+        };
+
+        let mut scope = 0;
+        let lookup = self.select_lookup(lookup, Some(client), &mut scope);
+        let lookup = match subnet {
+            // the answer holds for the subnet it was selected for, and for the one the wrapped
+            // zone handler answered for
+            Some(mut subnet) => lookup.map(|lookup| {
+                let scope = lookup
+                    .client_subnet()
+                    .map_or(scope, |inner| inner.scope_prefix().max(scope));
+                subnet.set_scope_prefix(scope);
+                lookup.with_client_subnet(subnet)
+            }),
+            None => lookup,
+        };
+        (lookup, context)
+    }
+
+    async fn nsec_records(
+        &self,
+        name: &LowerName,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner.nsec_records(name, lookup_options).await
+    }
+
+    #[cfg(feature = "__dnssec")]
+    async fn nsec3_records(
+        &self,
+        info: Nsec3QueryInfo<'_>,
+        lookup_options: LookupOptions,
+    ) -> LookupControlFlow<AuthLookup> {
+        self.inner.nsec3_records(info, lookup_options).await
+    }
+
+    async fn zone_transfer(
+        &self,
+        request: &Request,
+        lookup_options: LookupOptions,
+        now: u64,
+    ) -> Option<(
+        Result<ZoneTransfer, LookupError>,
+        Option<TSigResponseContext>,
+    )> {
+        self.inner.zone_transfer(request, lookup_options, now).await
+    }
+
+    #[cfg(feature = "__dnssec")]
+    fn nx_proof_kind(&self) -> Option<&NxProofKind> {
+        self.inner.nx_proof_kind()
+    }
+
+    #[cfg(feature = "metrics")]
+    fn metrics_label(&self) -> &'static str {
+        self.inner.metrics_label()
+    }
+}
+
+/// Choose up to `count` records, without replacement, with a probability proportional to weight.
+///
+/// Returns `None` if the RRset should be served unchanged, i.e. when there is nothing to drop or
+/// every record has a weight of zero.
+fn select<'r>(
+    candidates: &[(&'r Record, u32)],
+    count: usize,
+    rng: &mut SelectionRng,
+) -> Option<Vec<&'r Record>> {
+    let mut remaining = candidates
+        .iter()
+        .filter(|(_, weight)| *weight > 0)
+        .copied()
+        .collect::<Vec<_>>();
+    if remaining.is_empty() || (remaining.len() == candidates.len() && remaining.len() <= count) {
+        return None;
+    }
+
+    let mut selected = Vec::with_capacity(count.min(remaining.len()));
+    while selected.len() < count && !remaining.is_empty() {
+        let total = remaining.iter().map(|(_, w)| u64::from(*w)).sum::<u64>();
+        let mut point = rng.next_u64() % total;
+        let idx = remaining
+            .iter()
+            .position(|(_, weight)| match point.checked_sub(u64::from(*weight)) {
+                Some(rest) => {
+                    point = rest;
+                    false
+                }
+                None => true,
+            })
+            .unwrap_or(remaining.len() - 1);
+        selected.push(remaining.remove(idx).0);
+    }
+
+    Some(selected)
+}
+
+enum SelectionRng {
+    Random,
+    /// SplitMix64, so that the same seed always yields the same selection
+    Seeded(u64),
+}
+
+impl SelectionRng {
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            Self::Random => rand::random(),
+            Self::Seeded(state) => {
+                *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+                z ^ (z >> 31)
+            }
+        }
+    }
+}
+
+const DEFAULT_WEIGHT: u32 = 1;
+
+/// How the weighted subset of an RRset is chosen for each query
+#[derive(Clone, Copy, Default, Deserialize, PartialEq, Eq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum WeightedSelection {
+    /// Choose independently for every query
+    #[default]
+    Random,
+    /// Choose deterministically from a hash of the client's subnet, so that a client keeps
+    /// getting the same answer; the subnet is taken from the EDNS Client Subnet of the query if
+    /// there is one, or else from its source address
+    ClientSubnet,
+}
+
+/// Configuration for weighted answer selection
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedConfig {
+    /// How records are chosen
+    #[serde(default)]
+    pub selection: WeightedSelection,
+    /// The maximum number of records returned from a weighted RRset
+    #[serde(default = "default_answers")]
+    pub answers: usize,
+    /// Prefix length of the IPv4 client subnet for `client_subnet` selection
+    #[serde(default = "default_ipv4_prefix_len")]
+    pub ipv4_prefix_len: u8,
+    /// Prefix length of the IPv6 client subnet for `client_subnet` selection
+    #[serde(default = "default_ipv6_prefix_len")]
+    pub ipv6_prefix_len: u8,
+    /// Whether the EDNS Client Subnet of queries is used for `client_subnet` selection
+    #[serde(default)]
+    pub client_subnet: ClientSubnetPolicy,
+    /// The weights of individual records; records in a weighted RRset without an entry here
+    /// have a weight of 1
+    #[serde(default)]
+    pub records: Vec<WeightedRecordConfig>,
+}
+
+/// The weight of a single record
+#[derive(Clone, Deserialize, PartialEq, Eq, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct WeightedRecordConfig {
+    /// Owner name of the record, relative to the zone origin unless fully qualified
+    pub name: Name,
+    /// Type of the record
+    pub record_type: RecordType,
+    /// Record data in presentation format, e.g. `192.0.2.1` or `pop1.example.com.`
+    pub value: String,
+    /// Relative weight of the record
+    pub weight: u32,
+}
+
+fn default_answers() -> usize {
+    1
+}
+
+fn default_ipv4_prefix_len() -> u8 {
+    24
+}
+
+fn default_ipv6_prefix_len() -> u8 {
+    56
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, SocketAddr},
+        str::FromStr,
+    };
+
+    use test_support::subscribe;
+
+    use super::*;
+    use crate::{
+        net::{runtime::TokioRuntimeProvider, xfer::Protocol},
+        proto::{
+            op::{Edns, Header, LowerQuery, Message, MessageType, OpCode, Query},
+            rr::rdata::{
+                A, SOA,
+                opt::{ClientSubnet, EdnsOption},
+            },
+        },
+        store::in_memory::InMemoryZoneHandler,
+    };
+
+    fn origin() -> Name {
+        Name::from_str("example.com.").unwrap()
+    }
+
+    fn inner() -> Arc<dyn ZoneHandler> {
+        let mut handler = InMemoryZoneHandler::<TokioRuntimeProvider>::empty(
+            origin(),
+            ZoneType::Primary,
+            AxfrPolicy::Deny,
+            #[cfg(feature = "__dnssec")]
+            None,
+        );
+        let soa = SOA::new(
+            Name::from_str("ns.example.com.").unwrap(),
+            Name::from_str("hostmaster.example.com.").unwrap(),
+            1,
+            3600,
+            600,
+            86_400,
+            300,
+        );
+        handler.upsert_mut(Record::from_rdata(origin(), 3600, RData::SOA(soa)), 1);
+
+        let www = Name::from_str("www.example.com.").unwrap();
+        let other = Name::from_str("other.example.com.").unwrap();
+        for last in 1..=3 {
+            let a = RData::A(A::new(192, 0, 2, last));
+            handler.upsert_mut(Record::from_rdata(www.clone(), 300, a.clone()), 1);
+            handler.upsert_mut(Record::from_rdata(other.clone(), 300, a), 1);
+        }
+        Arc::new(handler)
+    }
+
+    fn config(selection: WeightedSelection, weights: &[(&str, u32)]) -> WeightedConfig {
+        WeightedConfig {
+            selection,
+            answers: 1,
+            ipv4_prefix_len: 24,
+            ipv6_prefix_len: 56,
+            client_subnet: ClientSubnetPolicy::Trust,
+            records: weights
+                .iter()
+                .map(|(value, weight)| WeightedRecordConfig {
+                    name: Name::from_str("www").unwrap(),
+                    record_type: RecordType::A,
+                    value: value.to_string(),
+                    weight: *weight,
+                })
+                .collect(),
+        }
+    }
+
+    async fn lookup_from(handler: &WeightedZoneHandler, name: &str, src: IpAddr) -> Vec<RData> {
+        let name = LowerName::from_str(name).unwrap();
+        let header = Header::new(0, MessageType::Query, OpCode::Query);
+        let query = LowerQuery::query(Query::query(Name::from(&name), RecordType::A));
+        let info = RequestInfo::new(SocketAddr::new(src, 53000), Protocol::Udp, &header, &query);
+        handler
+            .lookup(&name, RecordType::A, Some(&info), LookupOptions::default())
+            .await
+            .unwrap()
+            .iter()
+            .map(|record| record.data().clone())
+            .collect()
+    }
+
+    fn a(last: u8) -> RData {
+        RData::A(A::new(192, 0, 2, last))
+    }
+
+    #[tokio::test]
+    async fn test_zero_weight_never_served() {
+        subscribe();
+        let config = config(
+            WeightedSelection::Random,
+            &[("192.0.2.1", 1), ("192.0.2.2", 0), ("192.0.2.3", 0)],
+        );
+        let handler = WeightedZoneHandler::try_from_config(inner(), &config).unwrap();
+
+        let client = IpAddr::from([198, 51, 100, 1]);
+        for _ in 0..50 {
+            assert_eq!(
+                lookup_from(&handler, "www.example.com.", client).await,
+                vec![a(1)]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unweighted_rrset_unchanged() {
+        subscribe();
+        let config = config(WeightedSelection::Random, &[("192.0.2.1", 5)]);
+        let handler = WeightedZoneHandler::try_from_config(inner(), &config).unwrap();
+
+        let client = IpAddr::from([198, 51, 100, 1]);
+        let answers = lookup_from(&handler, "other.example.com.", client).await;
+        assert_eq!(answers.len(), 3);
+        // records without a configured weight default to 1, so all stay eligible
+        let answers = lookup_from(&handler, "www.example.com.", client).await;
+        assert_eq!(answers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet_is_stable() {
+        subscribe();
+        let config = config(
+            WeightedSelection::ClientSubnet,
+            &[("192.0.2.1", 1), ("192.0.2.2", 1), ("192.0.2.3", 1)],
+        );
+        let handler = WeightedZoneHandler::try_from_config(inner(), &config).unwrap();
+
+        for subnet in 0..32u8 {
+            let first = lookup_from(
+                &handler,
+                "www.example.com.",
+                IpAddr::from([10, 0, subnet, 1]),
+            )
+            .await;
+            assert_eq!(first.len(), 1);
+            for host in [2, 100, 254] {
+                let client = IpAddr::from([10, 0, subnet, host]);
+                assert_eq!(
+                    lookup_from(&handler, "www.example.com.", client).await,
+                    first
+                );
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet_split() {
+        subscribe();
+        let config = config(
+            WeightedSelection::ClientSubnet,
+            &[("192.0.2.1", 95), ("192.0.2.2", 5), ("192.0.2.3", 0)],
+        );
+        let handler = WeightedZoneHandler::try_from_config(inner(), &config).unwrap();
+
+        let mut canary = 0;
+        for subnet in 0..2000u32 {
+            let client = IpAddr::from(Ipv4Addr::from(0x0a00_0001 + (subnet << 8)));
+            let answers = lookup_from(&handler, "www.example.com.", client).await;
+            assert_ne!(answers, vec![a(3)]);
+            if answers == vec![a(2)] {
+                canary += 1;
+            }
+        }
+        // 5% of 2000 is 100
+        assert!((50..150).contains(&canary), "canary served {canary} times");
+    }
+
+    async fn search_from(
+        handler: &WeightedZoneHandler,
+        src: IpAddr,
+        subnet: &str,
+    ) -> (Vec<RData>, Option<ClientSubnet>) {
+        let mut message = Message::query();
+        message.add_query(Query::query(
+            Name::from_str("www.example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let mut edns = Edns::new();
+        let subnet = ClientSubnet::from_str(subnet).unwrap();
+        edns.options_mut().insert(EdnsOption::Subnet(subnet));
+        message.set_edns(edns);
+
+        let src = SocketAddr::new(src, 53000);
+        let request = Request::from_bytes(message.to_vec().unwrap(), src, Protocol::Udp).unwrap();
+        let lookup = handler
+            .search(&request, LookupOptions::default())
+            .await
+            .0
+            .unwrap();
+        let answers = lookup.iter().map(|record| record.data().clone()).collect();
+        (answers, lookup.client_subnet())
+    }
+
+    #[tokio::test]
+    async fn test_client_subnet_option() {
+        subscribe();
+        let mut config = config(
+            WeightedSelection::ClientSubnet,
+            &[("192.0.2.1", 95), ("192.0.2.2", 5), ("192.0.2.3", 0)],
+        );
+        let handler = WeightedZoneHandler::try_from_config(inner(), &config).unwrap();
+        let resolver = IpAddr::from([198, 51, 100, 53]);
+
+        // the clients behind a single resolver are still split
+        let mut canary = 0;
+        for subnet in 0..2000u32 {
+            let client = Ipv4Addr::from(0x0a00_0000 + (subnet << 8));
+            let (answers, subnet) = search_from(&handler, resolver, &format!("{client}/24")).await;
+            if answers == vec![a(2)] {
+                canary += 1;
+            }
+            let subnet = subnet.unwrap();
+            assert_eq!(subnet.addr(), IpAddr::from(client));
+            assert_eq!((subnet.source_prefix(), subnet.scope_prefix()), (24, 24));
+        }
+        assert!((50..150).contains(&canary), "canary served {canary} times");
+
+        // the answer for a shorter client subnet holds for all of it
+        let (_, subnet) = search_from(&handler, resolver, "10.0.0.0/16").await;
+        assert_eq!(subnet.unwrap().scope_prefix(), 16);
+
+        // without trusting the resolver, its address is used
+        config.client_subnet = ClientSubnetPolicy::Ignore;
+        let handler = WeightedZoneHandler::try_from_config(inner(), &config).unwrap();
+        let expected = lookup_from(&handler, "www.example.com.", resolver).await;
+        for subnet in ["10.0.0.0/24", "10.1.0.0/24", "10.2.0.0/24"] {
+            let (answers, subnet) = search_from(&handler, resolver, subnet).await;
+            assert_eq!(answers, expected);
+            assert!(subnet.is_none());
+        }
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        subscribe();
+        let mut bad_value = config(WeightedSelection::Random, &[("not-an-ip", 1)]);
+        assert!(WeightedZoneHandler::try_from_config(inner(), &bad_value).is_err());
+
+        bad_value.records.clear();
+        bad_value.answers = 0;
+        assert!(WeightedZoneHandler::try_from_config(inner(), &bad_value).is_err());
+    }
+
+    #[test]
+    fn test_select_without_replacement() {
+        let records = (1..=4)
+            .map(|last| Record::from_rdata(origin(), 60, a(last)))
+            .collect::<Vec<_>>();
+        let candidates = records.iter().map(|r| (r, 1)).collect::<Vec<_>>();
+
+        let mut rng = SelectionRng::Seeded(7);
+        let selected = select(&candidates, 3, &mut rng).unwrap();
+        assert_eq!(selected.len(), 3);
+        for (i, record) in selected.iter().enumerate() {
+            assert!(!selected[i + 1..].contains(record));
+        }
+
+        // nothing to drop
+        assert!(select(&candidates, 4, &mut rng).is_none());
+    }
+}
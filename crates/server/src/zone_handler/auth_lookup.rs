@@ -10,7 +10,7 @@ use std::sync::Arc;
 
 use crate::proto::{
     op::Message,
-    rr::{Record, RecordSet, RecordType, RrsetRecords},
+    rr::{Record, RecordSet, RecordType, RrsetRecords, rdata::opt::ClientSubnet},
 };
 #[cfg(feature = "resolver")]
 use crate::resolver::lookup::Lookup;
@@ -31,6 +31,9 @@ pub enum AuthLookup {
         answers: LookupRecords,
         /// Optional set of LookupRecords
         additionals: Option<LookupRecords>,
+        /// The EDNS Client Subnet to echo in the response, with the scope the answers are valid
+        /// for, if they were chosen for the client's subnet
+        client_subnet: Option<ClientSubnet>,
     },
     /// Records resulting from a resolver lookup
     #[cfg(feature = "resolver")]
@@ -45,6 +48,31 @@ impl AuthLookup {
         Self::Records {
             answers,
             additionals,
+            client_subnet: None,
+        }
+    }
+
+    /// Mark the answers as chosen for the given client subnet, if there are any records
+    pub fn with_client_subnet(self, client_subnet: ClientSubnet) -> Self {
+        match self {
+            Self::Records {
+                answers,
+                additionals,
+                ..
+            } => Self::Records {
+                answers,
+                additionals,
+                client_subnet: Some(client_subnet),
+            },
+            other => other,
+        }
+    }
+
+    /// The EDNS Client Subnet the answers were chosen for
+    pub fn client_subnet(&self) -> Option<ClientSubnet> {
+        match self {
+            Self::Records { client_subnet, .. } => *client_subnet,
+            _ => None,
         }
     }
 
@@ -158,6 +186,7 @@ impl From<LookupRecords> for AuthLookup {
         Self::Records {
             answers: lookup,
             additionals: None,
+            client_subnet: None,
         }
     }
 }
@@ -337,16 +366,22 @@ impl<'r> Iterator for LookupRecordsIter<'r> {
     }
 }
 
-/// A copy of all data in a zone.
+/// A copy of all data in a zone, or of the changes to it.
 ///
-/// This is used in the AXFR sub-protocol.
+/// This is used in the AXFR and IXFR sub-protocols.
 #[derive(Debug)]
 pub struct ZoneTransfer {
     /// The SOA record, plus its RRSIG.
     ///
     /// This is sent at the start of the first message of the response.
     pub start_soa: LookupRecords,
-    /// All the records in the zone.
+    /// The changes since the version of the zone that the client has, for an IXFR.
+    ///
+    /// For every version in between, this is the old SOA record followed by the deleted records,
+    /// then the new SOA record followed by the added records (RFC 1995, section 4). This is empty
+    /// when the whole zone is sent.
+    pub changes: Vec<Record>,
+    /// All the records in the zone, empty when only the changes are sent.
     pub records: AxfrRecords,
     /// The SOA record again.
     ///
@@ -359,6 +394,7 @@ impl ZoneTransfer {
     pub fn iter(&self) -> impl Iterator<Item = &Record> {
         self.start_soa
             .iter()
+            .chain(self.changes.iter())
             .chain(self.records.iter())
             .chain(self.end_soa.iter())
     }
@@ -140,6 +140,10 @@ impl RequestHandler for Catalog {
                     self.update(request, response_edns, now, response_handle)
                         .await
                 }
+                OpCode::Notify => {
+                    debug!("notify received: {}", request.id());
+                    self.notify(request, response_edns, response_handle).await
+                }
                 c => {
                     warn!("unimplemented op_code: {:?}", c);
                     send_error_response(
@@ -384,6 +388,97 @@ impl Catalog {
         .await
     }
 
+    /// Handle a NOTIFY that a zone has changed on one of its primaries
+    ///
+    /// [RFC 1996](https://tools.ietf.org/html/rfc1996), DNS NOTIFY, August 1996
+    ///
+    /// ```text
+    /// 3.7. A NOTIFY request has QDCOUNT>0, ANCOUNT>=0, AUCOUNT>=0,
+    /// ADCOUNT>=0.  If ANCOUNT>0, then the answer section represents an
+    /// unsecure hint at the new RRset for this <QNAME,QCLASS,QTYPE>.
+    ///
+    /// 3.10. If a Secondary receives a NOTIFY request from a host that is not
+    /// a known Primary for the zone containing the QNAME, it should ignore
+    /// the request and produce an error message in its operations log.
+    ///
+    /// 4.7 Response
+    ///
+    ///    The response has QR set, the same ID, and the AA bit set.
+    /// ```
+    ///
+    /// The zone handlers of the zone decide whether to accept the NOTIFY, the first one that
+    /// implements it answers.
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - the NOTIFY message
+    /// * `response_edns` an optional `Edns` value for the response message
+    /// * `response_handle` - sink for the response message to be sent
+    pub async fn notify<R: ResponseHandler>(
+        &self,
+        request: &Request,
+        response_edns: Option<&Edns>,
+        mut response_handle: R,
+    ) -> ResponseInfo {
+        let Ok(request_info) = request.request_info() else {
+            warn!("invalid notify request, query count must be one");
+            return send_error_response(
+                request,
+                ResponseCode::FormErr,
+                response_edns,
+                response_handle,
+            )
+            .await;
+        };
+
+        let name = request_info.query.name();
+        let handlers = self
+            .find(name)
+            .filter(|handlers| handlers.iter().any(|handler| handler.origin() == name));
+        let Some(handlers) = handlers else {
+            warn!("notify for unknown zone: {name}");
+            return send_error_response(
+                request,
+                ResponseCode::NotAuth,
+                response_edns,
+                response_handle,
+            )
+            .await;
+        };
+
+        let mut response_code = ResponseCode::NotImp;
+        for handler in handlers {
+            match handler.notify(request).await {
+                Ok(()) => {
+                    response_code = ResponseCode::NoError;
+                    break;
+                }
+                Err(ResponseCode::NotImp) => continue,
+                Err(code) => {
+                    response_code = code;
+                    break;
+                }
+            }
+        }
+
+        if response_code != ResponseCode::NoError {
+            return send_error_response(request, response_code, response_edns, response_handle)
+                .await;
+        }
+
+        let mut response_header = Header::new(request.id(), MessageType::Response, OpCode::Notify);
+        response_header.set_authoritative(true);
+        let response = MessageResponseBuilder::new(request.raw_queries(), response_edns)
+            .build_no_records(response_header);
+        match response_handle.send_response(response).await {
+            Err(error) => {
+                error!(%error, "error sending message");
+                ResponseInfo::serve_failed(request)
+            }
+            Ok(response_info) => response_info,
+        }
+    }
+
     /// Checks whether the `Catalog` contains DNS records for `name`
     ///
     /// Use this when you know the exact `LowerName` that was used when
@@ -434,7 +529,10 @@ impl Catalog {
             .await;
         };
 
-        if request_info.query.query_type() == RecordType::AXFR {
+        if matches!(
+            request_info.query.query_type(),
+            RecordType::AXFR | RecordType::IXFR
+        ) {
             zone_transfer(
                 request_info,
                 handlers,
@@ -551,6 +649,21 @@ async fn lookup<R: ResponseHandler + Unpin>(
             .await;
         };
 
+        // echo EDNS Client Subnet with the scope the answers were chosen for, RFC 7871
+        let scoped_edns: Edns;
+        let response_edns = match (response_edns, result.as_ref().ok()) {
+            (Some(edns), Some(lookup)) => match lookup.client_subnet() {
+                Some(client_subnet) => {
+                    let mut edns = edns.clone();
+                    edns.options_mut().insert(EdnsOption::Subnet(client_subnet));
+                    scoped_edns = edns;
+                    Some(&scoped_edns)
+                }
+                None => Some(edns),
+            },
+            (response_edns, _) => response_edns,
+        };
+
         let response_message = build_response(
             result,
             &**handler,
@@ -1009,6 +1122,7 @@ async fn build_authoritative_response(
                 AuthLookup::Records {
                     answers: additionals,
                     additionals: None,
+                    client_subnet: None,
                 },
             ),
             None => (answers, AuthLookup::default()),
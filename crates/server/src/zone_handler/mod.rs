@@ -67,6 +67,11 @@ pub trait ZoneHandler: Send + Sync {
         (Err(ResponseCode::NotImp), None)
     }
 
+    /// Handle a NOTIFY (RFC 1996) that the zone has changed on one of its primaries
+    async fn notify(&self, _notify: &Request) -> Result<(), ResponseCode> {
+        Err(ResponseCode::NotImp)
+    }
+
     /// Get the origin of this zone, i.e. example.com is the origin for www.example.com
     fn origin(&self) -> &LowerName;
 
@@ -176,7 +181,7 @@ pub trait ZoneHandler: Send + Sync {
         lookup_options: LookupOptions,
     ) -> LookupControlFlow<AuthLookup>;
 
-    /// Returns all records in the zone.
+    /// Returns all records in the zone (AXFR), or the changes since the client's version (IXFR).
     ///
     /// This will return `None` if the next zone handler in the zone handler chain should be used instead.
     async fn zone_transfer(
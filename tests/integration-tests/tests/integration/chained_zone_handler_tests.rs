@@ -308,6 +308,7 @@ fn inner_lookup(
             let lookup = AuthLookup::Records {
                 answers: LookupRecords::new(*lookup_options, rset.into()),
                 additionals: None,
+                client_subnet: None,
             };
 
             use LookupControlFlow::*;
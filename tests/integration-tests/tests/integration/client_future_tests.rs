@@ -229,18 +229,23 @@ async fn test_notify() {
     let (mut client, bg) = Client::<TokioRuntimeProvider>::new(stream, sender);
     tokio::spawn(bg);
 
-    let name = Name::from_str("ping.example.com.").unwrap();
+    // a primary zone is not refreshed from elsewhere
+    let name = Name::from_str("example.com.").unwrap();
+    let message = client
+        .notify(name, DNSClass::IN, RecordType::SOA, None::<RecordSet>)
+        .await;
+    assert!(message.is_ok());
+    let message = message.unwrap();
+    assert_eq!(message.response_code(), ResponseCode::NotImp);
 
+    // NOTIFY is for zones, not names in them
+    let name = Name::from_str("ping.example.com.").unwrap();
     let message = client
         .notify(name, DNSClass::IN, RecordType::A, None::<RecordSet>)
         .await;
     assert!(message.is_ok());
     let message = message.unwrap();
-    assert_eq!(
-        message.response_code(),
-        ResponseCode::NotImp,
-        "the catalog must support Notify now, update this"
-    );
+    assert_eq!(message.response_code(), ResponseCode::NotAuth);
 }
 
 // update tests
@@ -13,8 +13,8 @@ use rusqlite::*;
 use hickory_net::runtime::{Time, TokioRuntimeProvider, TokioTime};
 use hickory_net::xfer::Protocol;
 #[cfg(feature = "__dnssec")]
-use hickory_proto::op::{Edns, LowerQuery, Message};
-use hickory_proto::op::{Header, MessageType, OpCode, Query, ResponseCode};
+use hickory_proto::op::{Edns, LowerQuery};
+use hickory_proto::op::{Header, Message, MessageType, OpCode, Query, ResponseCode};
 #[cfg(feature = "__dnssec")]
 use hickory_proto::rr::TSigner;
 #[cfg(feature = "__dnssec")]
@@ -1602,6 +1602,161 @@ async fn test_axfr_deny_all() {
     ))
 }
 
+#[tokio::test]
+async fn test_ixfr() {
+    subscribe();
+    let new_name = Name::from_str("new.example.com.").unwrap();
+    let www_name = Name::from_str("www.example.com.").unwrap();
+    let handler = create_example();
+    let old_soa = soa_record(&handler).await;
+
+    let new_record = Record::from_rdata(new_name, 0, RData::A(A::new(10, 11, 12, 13)));
+    let deleted_record = Record::from_rdata(www_name, 86400, RData::A(A::new(93, 184, 215, 14)));
+    let delete_record = deleted_record
+        .clone()
+        .set_dns_class(DNSClass::NONE)
+        .set_ttl(0)
+        .clone();
+    handler
+        .update_records(&[new_record.clone(), delete_record], true)
+        .await
+        .unwrap();
+    let new_soa = soa_record(&handler).await;
+
+    // the differences since the old version
+    let changes = ixfr(&handler, &old_soa).await;
+    assert_eq!(
+        changes,
+        vec![
+            new_soa.clone(),
+            old_soa.clone(),
+            deleted_record,
+            new_soa.clone(),
+            new_record,
+            new_soa.clone(),
+        ]
+    );
+
+    // only the SOA record when the client is up to date
+    assert_eq!(ixfr(&handler, &new_soa).await, vec![new_soa]);
+}
+
+#[tokio::test]
+async fn test_ixfr_after_recovery() {
+    subscribe();
+    let conn = Connection::open_in_memory().expect("could not create in memory DB");
+    let mut journal = Journal::new(conn).unwrap();
+    journal.schema_up().unwrap();
+
+    let mut handler = create_example();
+    handler.set_journal(journal).await;
+    handler.persist_to_journal().await.unwrap();
+    let first_soa = soa_record(&handler).await;
+
+    let first_record = Record::from_rdata(
+        Name::from_str("first.example.com.").unwrap(),
+        0,
+        RData::A(A::new(10, 0, 0, 1)),
+    );
+    handler
+        .update_records(&[first_record.clone()], true)
+        .await
+        .unwrap();
+    let second_soa = soa_record(&handler).await;
+
+    let second_record = Record::from_rdata(
+        Name::from_str("second.example.com.").unwrap(),
+        0,
+        RData::A(A::new(10, 0, 0, 2)),
+    );
+    handler
+        .update_records(&[second_record.clone()], true)
+        .await
+        .unwrap();
+    let third_soa = soa_record(&handler).await;
+
+    let in_memory = InMemoryZoneHandler::empty(
+        handler.origin().clone().into(),
+        ZoneType::Primary,
+        AxfrPolicy::AllowAll,
+        #[cfg(feature = "__dnssec")]
+        Some(NxProofKind::Nsec),
+    );
+    let mut recovered_handler = SqliteZoneHandler::<TokioRuntimeProvider>::new(
+        in_memory,
+        AxfrPolicy::AllowAll,
+        false,
+        false,
+    );
+    recovered_handler
+        .recover_with_journal(handler.journal().await.as_ref().expect("journal not Some"))
+        .await
+        .expect("recovery");
+
+    // the changes of the second update are rebuilt from the journal
+    assert_eq!(
+        ixfr(&recovered_handler, &second_soa).await,
+        vec![
+            third_soa.clone(),
+            second_soa,
+            third_soa.clone(),
+            second_record.clone(),
+            third_soa.clone(),
+        ]
+    );
+
+    // the version before the first update was a dump of the zone, so the whole zone is sent
+    let records = ixfr(&recovered_handler, &first_soa).await;
+    assert_eq!(records.first(), Some(&third_soa));
+    assert_eq!(records.last(), Some(&third_soa));
+    assert_ne!(records[1].record_type(), RecordType::SOA);
+    assert!(records.contains(&first_record));
+    assert!(records.contains(&second_record));
+}
+
+async fn soa_record(handler: &SqliteZoneHandler) -> Record {
+    handler
+        .lookup(
+            handler.origin(),
+            RecordType::SOA,
+            None,
+            LookupOptions::default(),
+        )
+        .await
+        .unwrap()
+        .iter()
+        .next()
+        .unwrap()
+        .clone()
+}
+
+async fn ixfr(handler: &SqliteZoneHandler, soa: &Record) -> Vec<Record> {
+    let mut message = Message::new(10, MessageType::Query, OpCode::Query);
+    message
+        .add_query(Query::query(soa.name().clone(), RecordType::IXFR))
+        .add_authority(soa.clone());
+    let request = Request::from_bytes(
+        message.to_vec().unwrap(),
+        SocketAddr::from((Ipv4Addr::LOCALHOST, 53)),
+        Protocol::Tcp,
+    )
+    .unwrap();
+
+    handler
+        .zone_transfer(
+            &request,
+            LookupOptions::default(),
+            TokioTime::current_time(),
+        )
+        .await
+        .unwrap()
+        .0
+        .unwrap()
+        .iter()
+        .cloned()
+        .collect()
+}
+
 #[cfg(feature = "__dnssec")]
 #[tokio::test]
 async fn test_axfr_deny_unsigned() {